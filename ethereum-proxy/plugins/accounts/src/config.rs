@@ -17,8 +17,10 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 //! CLI configuration for accounts.
 
+use crate::hw;
 use cli_params;
 use ethsign::{KeyFile, Protected};
+use std::{fs, path::Path};
 
 /// A configuration option to apply.
 pub enum Param {
@@ -26,6 +28,12 @@ pub enum Param {
     Account(Option<KeyFile>),
     /// Password to the keyfile.
     Pass(Protected),
+    /// Keyfiles found in the keystore directory.
+    Keystore(Vec<KeyFile>),
+    /// Passwords to try when unlocking keystore accounts.
+    Passwords(Vec<Protected>),
+    /// Hardware wallet to sign transactions with.
+    HardwareWallet(Option<hw::Kind>),
 }
 
 /// Returns a list of supported configuration parameters.
@@ -53,9 +61,133 @@ pub fn params() -> Vec<cli_params::Param<Param>> {
                 Ok(Param::Account(Some(key)))
             },
         ),
+        cli_params::Param::new(
+            "Accounts keystore",
+            "accounts-keystore-dir",
+            "A path to a directory containing JSON wallets. All of them will be unlocked.",
+            "-",
+            |path: String| {
+                if path == "-" {
+                    return Ok(Param::Keystore(vec![]));
+                }
+
+                read_keystore(Path::new(&path)).map(Param::Keystore)
+            },
+        ),
+        cli_params::Param::new(
+            "Accounts passwords",
+            "accounts-password-file",
+            "A path to a file with newline-separated passwords used to unlock keystore accounts.",
+            "-",
+            |path: String| {
+                if path == "-" {
+                    return Ok(Param::Passwords(vec![]));
+                }
+
+                let content = fs::read_to_string(&path).map_err(to_str)?;
+                Ok(Param::Passwords(content.lines().map(Into::into).collect()))
+            },
+        ),
+        cli_params::Param::new(
+            "Hardware wallet",
+            "account-hw-wallet",
+            "Sign transactions using a connected hardware wallet. Possible options: \"ledger\", \"none\".",
+            "none",
+            |kind: String| match kind.as_str() {
+                "none" => Ok(Param::HardwareWallet(None)),
+                "ledger" => Ok(Param::HardwareWallet(Some(hw::Kind::Ledger))),
+                _ => Err(format!("Unsupported hardware wallet: {}", kind)),
+            },
+        ),
     ]
 }
 
+/// Parses the `account` section of a YAML configuration file.
+///
+/// The section may contain `file` and `password` keys, equivalent to `--account-file` and `--account-password`.
+pub fn from_yaml(value: &serde_json::Value) -> Result<Vec<Param>, String> {
+    let account = match value.get("account") {
+        Some(account) => account,
+        None => return Ok(vec![]),
+    };
+    let all_params = params();
+
+    [("file", "account-file"), ("password", "account-password")]
+        .iter()
+        .filter_map(|(key, name)| account.get(key).map(|value| (key, name, value)))
+        .map(|(key, name, value)| {
+            let value = value
+                .as_str()
+                .ok_or_else(|| format!("Invalid value of account.{}: expected a string", key))?;
+            all_params
+                .iter()
+                .find(|param| param.name == *name)
+                .expect("account params are defined above; qed")
+                .parse(Some(value.into()))
+        })
+        .collect()
+}
+
+/// Reads all keyfiles from given directory.
+///
+/// Files that are not valid JSON wallets are skipped.
+fn read_keystore(dir: &Path) -> Result<Vec<KeyFile>, String> {
+    let mut paths = fs::read_dir(dir)
+        .map_err(|e| format!("Can't read keystore directory at {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    // make sure the accounts order is deterministic
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let file = fs::File::open(&path).ok()?;
+            match serde_json::from_reader(file) {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    log::warn!("Skipping invalid keyfile at {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
 fn to_str<E: std::fmt::Display>(e: E) -> String {
     format!("{}", e)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_account_from_yaml() {
+        // given
+        let config = serde_json::json!({
+            "account": { "password": "secret" },
+        });
+
+        // when
+        let params = from_yaml(&config).unwrap();
+
+        // then
+        assert_eq!(params.len(), 1);
+        match params[0] {
+            Param::Pass(ref pass) => {
+                let pass: &[u8] = pass.as_ref();
+                assert_eq!(pass, b"secret")
+            }
+            _ => panic!("Expected the password."),
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_account_config() {
+        assert!(from_yaml(&serde_json::json!({})).unwrap().is_empty());
+        assert!(from_yaml(&serde_json::json!({ "account": { "password": 5 } })).is_err());
+        assert!(from_yaml(&serde_json::json!({ "account": { "file": "/non-existent.json" } })).is_err());
+    }
+}
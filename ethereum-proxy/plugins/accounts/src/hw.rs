@@ -0,0 +1,395 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Hardware wallet signing backend.
+//!
+//! Talks to the Ethereum app on a Ledger device using APDU commands over HID.
+
+use ethereum_transaction::SignTransaction;
+use ethsign::Signature;
+use std::sync::Mutex;
+
+/// Supported hardware wallets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Ledger Nano S/X with Ethereum app.
+    Ledger,
+}
+
+/// Raw HID device.
+///
+/// Reads and writes single HID reports (without the report id).
+pub trait HidDevice: Send {
+    /// Write a single report to the device.
+    fn write(&self, report: &[u8]) -> Result<usize, String>;
+
+    /// Read a single report from the device.
+    fn read(&self, report: &mut [u8]) -> Result<usize, String>;
+}
+
+#[cfg(feature = "ledger")]
+const LEDGER_VENDOR_ID: u16 = 0x2c97;
+#[cfg(feature = "ledger")]
+const LEDGER_USAGE_PAGE: u16 = 0xffa0;
+const LEDGER_CHANNEL: u16 = 0x0101;
+const LEDGER_TAG: u8 = 0x05;
+const REPORT_SIZE: usize = 64;
+const HEADER_SIZE: usize = 5;
+const MAX_CHUNK_SIZE: usize = 255;
+
+const CLA: u8 = 0xe0;
+const INS_GET_ADDRESS: u8 = 0x02;
+const INS_SIGN: u8 = 0x04;
+const P1_FIRST: u8 = 0x00;
+const P1_MORE: u8 = 0x80;
+const SW_OK: u16 = 0x9000;
+
+const HARDENED: u32 = 0x8000_0000;
+/// Default derivation path: `m/44'/60'/0'/0/0`.
+const DEFAULT_PATH: [u32; 5] = [44 | HARDENED, 60 | HARDENED, HARDENED, 0, 0];
+
+/// Ledger hardware wallet.
+pub struct Ledger {
+    device: Mutex<Box<dyn HidDevice>>,
+    path: Vec<u32>,
+}
+
+impl Ledger {
+    /// Create new Ledger wallet using given HID device.
+    pub fn new(device: Box<dyn HidDevice>) -> Self {
+        Ledger {
+            device: Mutex::new(device),
+            path: DEFAULT_PATH.to_vec(),
+        }
+    }
+
+    /// Probe for a connected Ledger device.
+    #[cfg(feature = "ledger")]
+    pub fn connect() -> Result<Self, String> {
+        let api = hidapi::HidApi::new().map_err(|e| format!("Unable to initialize HID: {}", e))?;
+        let info = api
+            .device_list()
+            .find(|info| {
+                info.vendor_id() == LEDGER_VENDOR_ID
+                    && (info.usage_page() == LEDGER_USAGE_PAGE || info.interface_number() == 0)
+            })
+            .ok_or_else(|| "No Ledger device found.".to_owned())?;
+        let device = info
+            .open_device(&api)
+            .map_err(|e| format!("Unable to open Ledger device: {}", e))?;
+
+        Ok(Self::new(Box::new(device)))
+    }
+
+    /// Probe for a connected Ledger device.
+    #[cfg(not(feature = "ledger"))]
+    pub fn connect() -> Result<Self, String> {
+        Err("The proxy was compiled without `ledger` feature.".into())
+    }
+
+    /// Retrieve the address of the account.
+    pub fn address(&self) -> Result<[u8; 20], String> {
+        let response = self.exchange(INS_GET_ADDRESS, P1_FIRST, &self.encoded_path())?;
+        // [pubkey_len, pubkey.., address_len, address (hex ascii)..]
+        let pubkey_len = *response.first().ok_or("Empty response")? as usize;
+        let address_len = *response.get(1 + pubkey_len).ok_or("Missing address")? as usize;
+        let address = response
+            .get(2 + pubkey_len..2 + pubkey_len + address_len)
+            .ok_or("Address truncated")?;
+        let address = std::str::from_utf8(address).map_err(|e| format!("Invalid address: {}", e))?;
+
+        let mut result = [0u8; 20];
+        if address.len() != 40 {
+            return Err(format!("Invalid address length: {}", address.len()));
+        }
+        for (i, byte) in result.iter_mut().enumerate() {
+            *byte =
+                u8::from_str_radix(&address[2 * i..2 * i + 2], 16).map_err(|e| format!("Invalid address: {}", e))?;
+        }
+        Ok(result)
+    }
+
+    /// Sign given transaction.
+    ///
+    /// This call blocks until the user confirms the transaction on the device.
+    pub fn sign(&self, transaction: &SignTransaction) -> Result<Signature, String> {
+        let mut data = self.encoded_path();
+        data.extend_from_slice(&transaction.to_rlp());
+
+        let mut response = vec![];
+        for (i, chunk) in data.chunks(MAX_CHUNK_SIZE).enumerate() {
+            let p1 = if i == 0 { P1_FIRST } else { P1_MORE };
+            response = self.exchange(INS_SIGN, p1, chunk)?;
+        }
+
+        if response.len() != 65 {
+            return Err(format!("Invalid signature length: {}", response.len()));
+        }
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&response[1..33]);
+        s.copy_from_slice(&response[33..65]);
+
+        let v = match *transaction {
+            SignTransaction::Legacy { chain_id, .. } => recovery_id(response[0], chain_id),
+            // typed transactions return y-parity directly
+            SignTransaction::AccessList(_) => response[0] & 1,
+        };
+        Ok(Signature { v, r, s })
+    }
+
+    fn encoded_path(&self) -> Vec<u8> {
+        let mut encoded = vec![self.path.len() as u8];
+        for index in &self.path {
+            encoded.extend_from_slice(&index.to_be_bytes());
+        }
+        encoded
+    }
+
+    fn exchange(&self, ins: u8, p1: u8, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut apdu = vec![CLA, ins, p1, 0x00, data.len() as u8];
+        apdu.extend_from_slice(data);
+
+        let device = self.device.lock().map_err(|_| "Ledger device lock poisoned.")?;
+        for report in frame(&apdu) {
+            device.write(&report)?;
+        }
+
+        let mut response = vec![];
+        let mut expected = None;
+        let mut sequence = 0u16;
+        loop {
+            let mut report = [0u8; REPORT_SIZE];
+            let read = device.read(&mut report)?;
+            if read < HEADER_SIZE
+                || u16::from_be_bytes([report[0], report[1]]) != LEDGER_CHANNEL
+                || report[2] != LEDGER_TAG
+                || u16::from_be_bytes([report[3], report[4]]) != sequence
+            {
+                return Err("Unexpected HID report".into());
+            }
+
+            let mut payload = &report[HEADER_SIZE..read];
+            if expected.is_none() {
+                if payload.len() < 2 {
+                    return Err("Missing response length".into());
+                }
+                expected = Some(u16::from_be_bytes([payload[0], payload[1]]) as usize);
+                payload = &payload[2..];
+            }
+            let expected = expected.expect("Set above; qed");
+            let remaining = expected - response.len();
+            response.extend_from_slice(&payload[..remaining.min(payload.len())]);
+
+            if response.len() == expected {
+                break;
+            }
+            sequence += 1;
+        }
+
+        if response.len() < 2 {
+            return Err("Response too short".into());
+        }
+        let status = response.split_off(response.len() - 2);
+        match u16::from_be_bytes([status[0], status[1]]) {
+            SW_OK => Ok(response),
+            sw => Err(format!("Ledger returned error status: {:#06x}", sw)),
+        }
+    }
+}
+
+/// Split APDU into HID reports.
+fn frame(apdu: &[u8]) -> Vec<[u8; REPORT_SIZE]> {
+    let mut data = (apdu.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(apdu);
+
+    data.chunks(REPORT_SIZE - HEADER_SIZE)
+        .enumerate()
+        .map(|(sequence, chunk)| {
+            let mut report = [0u8; REPORT_SIZE];
+            report[0..2].copy_from_slice(&LEDGER_CHANNEL.to_be_bytes());
+            report[2] = LEDGER_TAG;
+            report[3..5].copy_from_slice(&(sequence as u16).to_be_bytes());
+            report[HEADER_SIZE..HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            report
+        })
+        .collect()
+}
+
+/// Ledger returns the lowest byte of EIP-155 `v`, convert it back to recovery id.
+fn recovery_id(v: u8, chain_id: u64) -> u8 {
+    let base = ((chain_id * 2 + 35) % 256) as u8;
+    v.wrapping_sub(base) & 1
+}
+
+#[cfg(feature = "ledger")]
+impl HidDevice for hidapi::HidDevice {
+    fn write(&self, report: &[u8]) -> Result<usize, String> {
+        // prepend the report id
+        let mut data = vec![0u8];
+        data.extend_from_slice(report);
+        hidapi::HidDevice::write(self, &data).map_err(|e| format!("HID write error: {}", e))
+    }
+
+    fn read(&self, report: &mut [u8]) -> Result<usize, String> {
+        hidapi::HidDevice::read(self, report).map_err(|e| format!("HID read error: {}", e))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+    };
+
+    /// HID device replying with pre-defined responses.
+    #[derive(Clone, Default)]
+    pub struct MockDevice {
+        pub written: Arc<Mutex<Vec<Vec<u8>>>>,
+        pub responses: Arc<Mutex<VecDeque<[u8; REPORT_SIZE]>>>,
+    }
+
+    impl MockDevice {
+        pub fn respond(&self, data: &[u8]) {
+            let mut response = data.to_vec();
+            response.extend_from_slice(&SW_OK.to_be_bytes());
+            self.respond_raw(&response);
+        }
+
+        pub fn respond_raw(&self, response: &[u8]) {
+            // responses are framed the same way as requests.
+            for report in frame(response) {
+                self.responses.lock().unwrap().push_back(report);
+            }
+        }
+
+        pub fn apdus(&self) -> Vec<Vec<u8>> {
+            let written = self.written.lock().unwrap();
+            let mut apdus = vec![];
+            let mut current: Vec<u8> = vec![];
+            let mut expected = 0;
+            for report in written.iter() {
+                let payload = &report[HEADER_SIZE..];
+                if current.is_empty() {
+                    expected = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                    current.extend_from_slice(&payload[2..]);
+                } else {
+                    current.extend_from_slice(payload);
+                }
+                if current.len() >= expected {
+                    current.truncate(expected);
+                    apdus.push(std::mem::take(&mut current));
+                }
+            }
+            apdus
+        }
+    }
+
+    impl HidDevice for MockDevice {
+        fn write(&self, report: &[u8]) -> Result<usize, String> {
+            self.written.lock().unwrap().push(report.to_vec());
+            Ok(report.len())
+        }
+
+        fn read(&self, report: &mut [u8]) -> Result<usize, String> {
+            let next = self.responses.lock().unwrap().pop_front().ok_or("No more responses")?;
+            report.copy_from_slice(&next);
+            Ok(REPORT_SIZE)
+        }
+    }
+
+    pub fn address_response(address: &[u8; 20]) -> Vec<u8> {
+        let mut response = vec![65];
+        response.extend_from_slice(&[4; 65]);
+        response.push(40);
+        for byte in address {
+            response.extend_from_slice(format!("{:02x}", byte).as_bytes());
+        }
+        response
+    }
+
+    #[test]
+    fn should_read_address() {
+        // given
+        let device = MockDevice::default();
+        device.respond(&address_response(&[0xab; 20]));
+        let ledger = Ledger::new(Box::new(device.clone()));
+
+        // when
+        let address = ledger.address();
+
+        // then
+        assert_eq!(address, Ok([0xab; 20]));
+        let apdus = device.apdus();
+        assert_eq!(apdus.len(), 1);
+        assert_eq!(&apdus[0][..6], &[CLA, INS_GET_ADDRESS, 0, 0, 21, 5]);
+    }
+
+    #[test]
+    fn should_sign_in_chunks() {
+        // given
+        let device = MockDevice::default();
+        device.respond(&[]);
+        let mut signature = vec![37];
+        signature.extend_from_slice(&[1; 32]);
+        signature.extend_from_slice(&[2; 32]);
+        device.respond(&signature);
+        let ledger = Ledger::new(Box::new(device.clone()));
+
+        let transaction = ethereum_transaction::Transaction {
+            data: vec![0xcc; 300].into(),
+            ..Default::default()
+        };
+
+        // when
+        let result = ledger.sign(&SignTransaction::owned(transaction, 1)).unwrap();
+
+        // then
+        assert_eq!(result.v, 0);
+        assert_eq!(result.r, [1; 32]);
+        assert_eq!(result.s, [2; 32]);
+        let apdus = device.apdus();
+        assert_eq!(apdus.len(), 2);
+        assert_eq!(&apdus[0][..5], &[CLA, INS_SIGN, P1_FIRST, 0, 255]);
+        assert_eq!(&apdus[1][..5], &[CLA, INS_SIGN, P1_MORE, 0, 80]);
+    }
+
+    #[test]
+    fn should_return_error_status() {
+        // given
+        let device = MockDevice::default();
+        device.respond_raw(&[0x69, 0x85]);
+        let ledger = Ledger::new(Box::new(device));
+
+        // when
+        let result = ledger.address();
+
+        // then
+        assert_eq!(result, Err("Ledger returned error status: 0x6985".into()));
+    }
+
+    #[test]
+    fn should_convert_v_to_recovery_id() {
+        assert_eq!(recovery_id(37, 1), 0);
+        assert_eq!(recovery_id(38, 1), 1);
+        // chain id 1337: 2709 = 0x0a95
+        assert_eq!(recovery_id(0x95, 1337), 0);
+        assert_eq!(recovery_id(0x96, 1337), 1);
+    }
+}
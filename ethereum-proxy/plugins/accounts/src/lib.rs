@@ -22,32 +22,51 @@
 
 #![warn(missing_docs)]
 
-use ethereum_transaction::{Bytes, SignTransaction, SignedTransaction, Transaction, U256};
+use ethereum_transaction::{AccessListEntry, Bytes, SignTransaction, Transaction, U256};
 use ethsign::{KeyFile, Protected, SecretKey};
 use jsonrpc_core::{
     self as rpc,
     futures::{
-        channel::oneshot,
         future::{self, Either},
         Future,
     },
 };
 use std::sync::{
     atomic::{self, AtomicUsize},
-    Arc, Mutex,
+    Arc,
 };
+use upstream::helpers::build_error_response;
 
 pub mod config;
+pub mod hw;
 
 type Upstream = Box<dyn Fn(rpc::Call) -> Box<dyn Future<Output = Option<rpc::Output>> + Send + Unpin> + Send + Sync>;
 
+/// An account that is able to sign transactions.
+#[derive(Clone)]
+enum Signer {
+    /// Unlocked local key.
+    Key(SecretKey),
+    /// Hardware wallet together with its address.
+    Hardware(Arc<hw::Ledger>, [u8; 20]),
+}
+
+impl Signer {
+    fn address(&self) -> [u8; 20] {
+        match *self {
+            Signer::Key(ref secret) => *secret.public().address(),
+            Signer::Hardware(_, address) => address,
+        }
+    }
+}
+
 /// A middleware intercepting transaction requests and signing them locally.
 #[derive(Clone)]
 pub struct Middleware {
-    secret: Option<SecretKey>,
+    signers: Arc<Vec<Signer>>,
     upstream: Arc<Upstream>,
     id: Arc<AtomicUsize>,
-    lock: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+    lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl Middleware {
@@ -58,21 +77,45 @@ impl Middleware {
     pub fn new(upstream: Arc<Upstream>, params: &[config::Param]) -> Self {
         let mut key = None;
         let mut pass: Protected = "".into();
+        let mut keystore = vec![];
+        let mut passwords = vec![];
+        let mut hw_wallet = None;
 
         for p in params {
             match p {
                 config::Param::Account(k) => key = k.clone(),
                 config::Param::Pass(p) => pass = p.clone(),
+                config::Param::Keystore(k) => keystore = k.clone(),
+                config::Param::Passwords(p) => passwords = p.clone(),
+                config::Param::HardwareWallet(kind) => hw_wallet = *kind,
             }
         }
 
-        let secret = key.map(|key: KeyFile| {
+        let mut signers = vec![];
+        if let Some(key) = key {
             // TODO [ToDr] Panicking here is crap.
-            key.to_secret_key(&pass).unwrap()
-        });
+            signers.push(Signer::Key(key.to_secret_key(&pass).unwrap()));
+        }
+        signers.extend(keystore.iter().filter_map(|key: &KeyFile| {
+            let secret = passwords.iter().find_map(|pass| key.to_secret_key(pass).ok());
+            if secret.is_none() {
+                log::warn!("Unable to unlock keystore account {}: no matching password.", key.id);
+            }
+            secret.map(Signer::Key)
+        }));
+        if let Some(hw::Kind::Ledger) = hw_wallet {
+            let ledger = hw::Ledger::connect().and_then(|ledger| {
+                let address = ledger.address()?;
+                Ok(Signer::Hardware(Arc::new(ledger), address))
+            });
+            match ledger {
+                Ok(signer) => signers.push(signer),
+                Err(e) => log::error!("Unable to use Ledger hardware wallet: {}", e),
+            }
+        }
 
         Self {
-            secret,
+            signers: Arc::new(signers),
             upstream,
             id: Arc::new(AtomicUsize::new(10_000)),
             lock: Default::default(),
@@ -93,36 +136,50 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
     {
         use rpc::futures::FutureExt;
 
-        let secret = match self.secret.as_ref() {
-            Some(secret) => secret.clone(),
-            None => return Either::Right(next(call, meta)),
-        };
-        let address = secret.public().address().to_vec();
+        if self.signers.is_empty() {
+            return Either::Right(next(call, meta));
+        }
+        let signers = self.signers.clone();
         let next_id = || {
             let id = self.id.fetch_add(1, atomic::Ordering::SeqCst);
             rpc::Id::Num(id as u64)
         };
 
         log::trace!("Parsing call: {:?}", call);
-        let (jsonrpc, id) = match call {
+        let (jsonrpc, id, access_list) = match call {
             rpc::Call::MethodCall(rpc::MethodCall {
                 ref mut method,
                 ref jsonrpc,
                 ref mut id,
-                ..
+                ref mut params,
             }) if method == "eth_sendTransaction" || method == "parity_postTransaction" => {
+                // EIP-2930 transactions are detected by the presence of `accessList`.
+                let access_list = match take_access_list(params) {
+                    Ok(access_list) => access_list,
+                    Err(e) => {
+                        log::error!("Invalid access list: {:?}", e);
+                        return Either::Left(Either::Right(future::ready(build_error_response(
+                            &call,
+                            rpc::ErrorCode::InvalidParams.code(),
+                            &format!("Invalid access list: {}", e),
+                        ))));
+                    }
+                };
                 let orig_id = id.clone();
                 *method = "parity_composeTransaction".into();
                 *id = next_id();
-                (*jsonrpc, orig_id)
+                (*jsonrpc, orig_id, access_list)
             }
-            // prepend signing account to the accounts list.
+            // prepend signing accounts to the accounts list.
             rpc::Call::MethodCall(rpc::MethodCall { ref mut method, .. }) if method == "eth_accounts" => {
-                let res = next(call, meta).map(|mut output| {
+                let res = next(call, meta).map(move |mut output| {
                     if let Some(rpc::Output::Success(ref mut s)) = output {
                         let rpc::Success { ref mut result, .. } = s;
                         if let rpc::Value::Array(ref mut vec) = result {
-                            vec.insert(0, serde_json::to_value(Bytes(address)).unwrap());
+                            let addresses = signers
+                                .iter()
+                                .map(|signer| serde_json::to_value(Bytes(signer.address().to_vec())).unwrap());
+                            vec.splice(0..0, addresses);
                         }
                     }
                     log::debug!("Returning accounts: {:?}", output);
@@ -133,14 +190,9 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
             _ => return Either::Right(next(call, meta)),
         };
 
-        // Acquire lock to make sure we call it sequentially.
-        let (tx, previous) = {
-            let mut lock = self.lock.lock().unwrap();
-            let previous = lock.take();
-            let (tx, rx) = oneshot::channel();
-            *lock = Some(rx);
-            (tx, previous)
-        };
+        // Transactions are composed and sent one by one, so that they get subsequent nonces.
+        // The lock is released once the transaction is sent or the future is dropped.
+        let lock = self.lock.clone();
 
         // Get composed transaction
         let chain_id = (self.upstream)(rpc::Call::MethodCall(rpc::MethodCall {
@@ -150,14 +202,10 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
             params: rpc::Params::Array(vec![]),
         }));
         let upstream = self.upstream.clone();
-        let upstream2 = upstream.clone();
-        let transaction_request = match previous {
-            Some(prev) => Either::Left(prev.then(move |_| upstream2(call))),
-            None => Either::Right(upstream2(call)),
-        };
 
         let res = async move {
-            let request = transaction_request.await;
+            let _guard = lock.lock().await;
+            let request = upstream(call).await;
             let chain_id = chain_id.await;
 
             log::trace!("Got results, parsing composed transaction and chain_id");
@@ -198,31 +246,44 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
                 }
                 o => return Either::Left(future::ready(Some(o.into()))),
             };
-            // Verify from
-            let public = secret.public();
-            let address = public.address();
+            // Find the account to sign with
             let from = request.from;
-            if from.as_bytes() != address {
-                log::error!("Expected to send from {:?}, but only support {:?}", from, address);
-                return err(id, "Invalid `from` address");
-            }
-            // Calculate unsigned hash
-            let hash = SignTransaction {
-                transaction: std::borrow::Cow::Borrowed(&request),
-                chain_id,
-            }
-            .hash();
-            // Sign replay-protected hash.
-            let signature = secret.sign(&hash).unwrap();
+            let signer = match signers.iter().find(|signer| from.as_bytes() == signer.address()) {
+                Some(signer) => signer.clone(),
+                None => {
+                    log::error!("Expected to send from one of the configured accounts, got {:?}", from);
+                    return err(id, "Invalid `from` address");
+                }
+            };
+            let sign_transaction = match access_list {
+                None => SignTransaction::owned(request, chain_id),
+                Some(access_list) => SignTransaction::AccessList(std::borrow::Cow::Owned(
+                    request.with_access_list(chain_id, access_list),
+                )),
+            };
+            let signature = match signer {
+                // Sign replay-protected hash.
+                Signer::Key(secret) => secret.sign(&sign_transaction.hash()).map_err(|e| format!("{:?}", e)),
+                // Let the device sign the transaction, it blocks until confirmed by the user.
+                Signer::Hardware(ledger, _) => {
+                    let sign_transaction = sign_transaction.clone();
+                    tokio::task::spawn_blocking(move || ledger.sign(&sign_transaction))
+                        .await
+                        .map_err(|e| format!("{:?}", e))
+                        .and_then(|res| res)
+                }
+            };
+            let signature = match signature {
+                Ok(signature) => signature,
+                Err(e) => {
+                    log::error!("Unable to sign transaction: {}", e);
+                    return err(id, "Unable to sign transaction");
+                }
+            };
             // Construct signed RLP
-            let signed = SignedTransaction::new(
-                std::borrow::Cow::Owned(request),
-                chain_id,
-                signature.v,
-                signature.r,
-                signature.s,
-            );
+            let signed = sign_transaction.into_signed(signature.v, signature.r, signature.s);
             let rlp = Bytes(signed.to_rlp());
+            log::debug!("Sending signed transaction: {}", rlp);
 
             Either::Right((upstream)(rpc::Call::MethodCall(rpc::MethodCall {
                 jsonrpc,
@@ -231,10 +292,369 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
                 params: rpc::Params::Array(vec![serde_json::to_value(rlp).unwrap()]),
             })))
         }
-        .then(move |x| {
-            let _ = tx.send(());
-            x
-        });
+        .flatten();
         Either::Left(Either::Left(Box::pin(res)))
     }
 }
+
+/// Removes `accessList` from transaction request params.
+fn take_access_list(params: &mut rpc::Params) -> Result<Option<Vec<AccessListEntry>>, serde_json::Error> {
+    let access_list = match *params {
+        rpc::Params::Array(ref mut vec) => match vec.first_mut() {
+            Some(rpc::Value::Object(ref mut request)) => request.remove("accessList"),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    access_list.map(serde_json::from_value).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware as MiddlewareTrait;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Mutex,
+    };
+
+    trait FutExt: std::future::Future {
+        fn wait(self) -> Self::Output;
+    }
+
+    impl<F> FutExt for F
+    where
+        F: std::future::Future,
+    {
+        fn wait(self) -> Self::Output {
+            rpc::futures::executor::block_on(self)
+        }
+    }
+
+    fn success(id: rpc::Id, result: rpc::Value) -> Option<rpc::Output> {
+        Some(rpc::Output::Success(rpc::Success {
+            jsonrpc: Some(rpc::Version::V2),
+            result,
+            id,
+        }))
+    }
+
+    fn method_call(method: &str, params: Vec<rpc::Value>) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: method.into(),
+            params: rpc::Params::Array(params),
+        })
+    }
+
+    fn keystore(name: &str, secrets: &[SecretKey], password: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("accounts-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (i, secret) in secrets.iter().enumerate() {
+            let key = KeyFile {
+                id: format!("key-{}", i),
+                version: 3,
+                crypto: secret.to_crypto(&password.into(), 1024).unwrap(),
+                address: None,
+            };
+            fs::write(dir.join(format!("key{}.json", i)), serde_json::to_vec(&key).unwrap()).unwrap();
+        }
+        fs::write(dir.join("passwords"), format!("invalid\n{}\n", password)).unwrap();
+        dir
+    }
+
+    fn middleware(dir: &Path, upstream: Upstream) -> Middleware {
+        let params = config::params();
+        let params = params
+            .iter()
+            .map(|p| match p.name.as_str() {
+                "accounts-keystore-dir" => p.parse(Some(dir.display().to_string())),
+                "accounts-password-file" => p.parse(Some(dir.join("passwords").display().to_string())),
+                _ => p.parse(None),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        Middleware::new(Arc::new(upstream), &params)
+    }
+
+    fn two_secrets() -> Vec<SecretKey> {
+        vec![
+            SecretKey::from_raw(&[1; 32]).unwrap(),
+            SecretKey::from_raw(&[2; 32]).unwrap(),
+        ]
+    }
+
+    fn address(secret: &SecretKey) -> rpc::Value {
+        serde_json::to_value(Bytes(secret.public().address().to_vec())).unwrap()
+    }
+
+    #[test]
+    fn should_return_all_keystore_accounts() {
+        // given
+        let secrets = two_secrets();
+        let dir = keystore("eth-accounts", &secrets, "pass");
+        let middleware = middleware(
+            &dir,
+            Box::new(|_| unreachable!("Upstream is not used for eth_accounts.")),
+        );
+        let remote = rpc::Value::String("0x0000000000000000000000000000000000000005".into());
+        let remote2 = remote.clone();
+
+        // when
+        let result = middleware
+            .on_call(method_call("eth_accounts", vec![]), (), move |call, _| {
+                future::ready(success(call_id(&call), rpc::Value::Array(vec![remote2.clone()])))
+            })
+            .wait();
+
+        // then
+        assert_eq!(
+            result,
+            success(
+                rpc::Id::Num(1),
+                rpc::Value::Array(vec![address(&secrets[0]), address(&secrets[1]), remote])
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Methods called on the upstream together with their parameters.
+    type Calls = Arc<Mutex<Vec<(String, rpc::Params)>>>;
+
+    /// Upstream composing a transaction from given address and recording called methods.
+    fn compose_upstream(from: rpc::Value) -> (Upstream, Calls) {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let sent2 = sent.clone();
+        let upstream = Box::new(move |call| {
+            let id = call_id(&call);
+            let (method, params) = match call {
+                rpc::Call::MethodCall(rpc::MethodCall { method, params, .. }) => (method, params),
+                _ => unreachable!(),
+            };
+            sent2.lock().unwrap().push((method.clone(), params));
+            let result = match method.as_str() {
+                "parity_composeTransaction" => serde_json::json!({
+                    "from": from,
+                    "to": null,
+                    "nonce": "0x0",
+                    "gas": "0x5208",
+                    "gasPrice": "0x1",
+                    "value": "0x0",
+                    "data": "0x",
+                }),
+                "eth_chainId" => "0x1".into(),
+                _ => "0x00".into(),
+            };
+            Box::new(future::ready(success(id, result))) as _
+        });
+
+        (upstream, sent)
+    }
+
+    fn send_transaction(middleware: &Middleware, params: Vec<rpc::Value>) -> impl Future<Output = Option<rpc::Output>> {
+        let res = middleware.on_call(
+            method_call("eth_sendTransaction", params),
+            (),
+            |_, _| -> future::Ready<Option<rpc::Output>> { unreachable!("Transaction should not be forwarded.") },
+        );
+        match res {
+            Either::Left(res) => res,
+            Either::Right(_) => unreachable!("Transaction should not be forwarded."),
+        }
+    }
+
+    fn assert_signed(result: Option<rpc::Output>, sent: Calls) {
+        assert_eq!(result, success(rpc::Id::Num(1), "0x00".into()));
+        let mut sent = sent.lock().unwrap().iter().map(|x| x.0.clone()).collect::<Vec<_>>();
+        sent.sort();
+        assert_eq!(
+            sent,
+            vec!["eth_chainId", "eth_sendRawTransaction", "parity_composeTransaction"]
+        );
+    }
+
+    #[test]
+    fn should_sign_transaction_from_any_keystore_account() {
+        // given
+        let secrets = two_secrets();
+        let dir = keystore("send-transaction", &secrets, "pass");
+        let (upstream, sent) = compose_upstream(address(&secrets[1]));
+        let middleware = middleware(&dir, upstream);
+
+        // when
+        let result = send_transaction(&middleware, vec![]).wait();
+
+        // then
+        assert_signed(result, sent);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_sign_access_list_transaction() {
+        // given
+        let secrets = two_secrets();
+        let dir = keystore("access-list", &secrets, "pass");
+        let (upstream, sent) = compose_upstream(address(&secrets[0]));
+        let middleware = middleware(&dir, upstream);
+        let request = serde_json::json!({
+            "from": address(&secrets[0]),
+            "accessList": [{
+                "address": "0x0707070707070707070707070707070707070707",
+                "storageKeys": [],
+            }],
+        });
+
+        // when
+        let result = send_transaction(&middleware, vec![request]).wait();
+
+        // then
+        assert_signed(result, sent.clone());
+        let sent = sent.lock().unwrap();
+        let compose = sent.iter().find(|x| x.0 == "parity_composeTransaction").unwrap();
+        assert_eq!(
+            compose.1,
+            rpc::Params::Array(vec![serde_json::json!({ "from": address(&secrets[0]) })])
+        );
+        let raw = sent.iter().find(|x| x.0 == "eth_sendRawTransaction").unwrap();
+        let raw = match raw.1 {
+            rpc::Params::Array(ref vec) => serde_json::from_value::<Bytes>(vec[0].clone()).unwrap(),
+            _ => unreachable!(),
+        };
+        let signed = ethereum_transaction::SignedTransaction::from_raw(&raw).unwrap();
+        match signed {
+            ethereum_transaction::SignedTransaction::AccessList { ref transaction, .. } => {
+                assert_eq!(transaction.chain_id, 1);
+                assert_eq!(transaction.access_list.len(), 1);
+            }
+            _ => panic!("Expected access list transaction, got: {:?}", signed),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Upstream composing transactions with nonces equal to the number of transactions sent so far.
+    ///
+    /// Responses are delayed, so that concurrent calls can interleave.
+    fn nonce_upstream(from: rpc::Value) -> (Upstream, Arc<Mutex<Vec<u64>>>) {
+        let nonces = Arc::new(Mutex::new(vec![]));
+        let nonces2 = nonces.clone();
+        let upstream = Box::new(move |call| {
+            let id = call_id(&call);
+            let (method, params) = match call {
+                rpc::Call::MethodCall(rpc::MethodCall { method, params, .. }) => (method, params),
+                _ => unreachable!(),
+            };
+            let mut nonces = nonces2.lock().unwrap();
+            let result = match method.as_str() {
+                "parity_composeTransaction" => serde_json::json!({
+                    "from": from,
+                    "to": null,
+                    "nonce": format!("{:#x}", nonces.len()),
+                    "gas": "0x5208",
+                    "gasPrice": "0x1",
+                    "value": "0x0",
+                    "data": "0x",
+                }),
+                "eth_chainId" => "0x1".into(),
+                _ => {
+                    let raw = match params {
+                        rpc::Params::Array(ref vec) => serde_json::from_value::<Bytes>(vec[0].clone()).unwrap(),
+                        _ => unreachable!(),
+                    };
+                    match ethereum_transaction::SignedTransaction::from_raw(&raw).unwrap() {
+                        ethereum_transaction::SignedTransaction::Legacy { ref transaction, .. } => {
+                            nonces.push(transaction.nonce.as_u64())
+                        }
+                        signed => panic!("Expected legacy transaction, got: {:?}", signed),
+                    }
+                    "0x00".into()
+                }
+            };
+            Box::new(Box::pin(async move {
+                tokio::task::yield_now().await;
+                success(id, result)
+            })) as _
+        });
+
+        (upstream, nonces)
+    }
+
+    #[tokio::test]
+    async fn should_sign_concurrent_transactions_with_subsequent_nonces() {
+        // given
+        let secrets = two_secrets();
+        let dir = keystore("concurrent", &secrets, "pass");
+        let (upstream, nonces) = nonce_upstream(address(&secrets[0]));
+        let middleware = middleware(&dir, upstream);
+
+        // when
+        let results = future::join_all(vec![
+            send_transaction(&middleware, vec![]),
+            send_transaction(&middleware, vec![]),
+            send_transaction(&middleware, vec![]),
+        ])
+        .await;
+
+        // then
+        assert_eq!(results, vec![success(rpc::Id::Num(1), "0x00".into()); 3]);
+        let mut nonces = nonces.lock().unwrap().clone();
+        nonces.sort();
+        assert_eq!(nonces, vec![0, 1, 2]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_release_lock_if_transaction_is_dropped() {
+        // given
+        let secrets = two_secrets();
+        let dir = keystore("dropped", &secrets, "pass");
+        let (upstream, nonces) = nonce_upstream(address(&secrets[0]));
+        let middleware = middleware(&dir, upstream);
+        let mut dropped = Box::pin(send_transaction(&middleware, vec![]));
+        assert_eq!(rpc::futures::FutureExt::now_or_never(dropped.as_mut()), None);
+
+        // when
+        drop(dropped);
+        let result = send_transaction(&middleware, vec![]).await;
+
+        // then
+        assert_eq!(result, success(rpc::Id::Num(1), "0x00".into()));
+        assert_eq!(*nonces.lock().unwrap(), vec![0]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_sign_transaction_with_hardware_wallet() {
+        // given
+        let hw_address = [5u8; 20];
+        let device = hw::tests::MockDevice::default();
+        device.respond(&hw::tests::address_response(&hw_address));
+        let mut signature = vec![37];
+        signature.extend_from_slice(&[1; 32]);
+        signature.extend_from_slice(&[2; 32]);
+        device.respond(&signature);
+        let ledger = hw::Ledger::new(Box::new(device));
+        let address = ledger.address().unwrap();
+        let (upstream, sent) = compose_upstream(serde_json::to_value(Bytes(hw_address.to_vec())).unwrap());
+        let middleware = Middleware {
+            signers: Arc::new(vec![Signer::Hardware(Arc::new(ledger), address)]),
+            ..Middleware::new(Arc::new(upstream), &[])
+        };
+
+        // when
+        let result = send_transaction(&middleware, vec![]).await;
+
+        // then
+        assert_signed(result, sent);
+    }
+
+    fn call_id(call: &rpc::Call) -> rpc::Id {
+        match *call {
+            rpc::Call::MethodCall(rpc::MethodCall { ref id, .. }) => id.clone(),
+            _ => rpc::Id::Null,
+        }
+    }
+}
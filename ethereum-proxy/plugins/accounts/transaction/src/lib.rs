@@ -20,7 +20,7 @@
 use impl_serde::serialize as bytes;
 use rlp::RlpStream;
 use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt};
 use tiny_keccak::{Hasher, Keccak};
 
 pub use ethereum_types::{Address, U256};
@@ -41,76 +41,286 @@ impl std::ops::Deref for Bytes {
     }
 }
 
+/// Number of bytes printed by the `Display` implementation of `Bytes`.
+const DISPLAY_BYTES: usize = 8;
+
+/// Hex representation for logging, truncated to `DISPLAY_BYTES` followed by the total length.
+impl fmt::Display for Bytes {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.len() <= DISPLAY_BYTES {
+            return write!(fmt, "{}", bytes::to_hex(&self.0, false));
+        }
+
+        write!(
+            fmt,
+            "{}...({} bytes)",
+            bytes::to_hex(&self.0[..DISPLAY_BYTES], false),
+            self.0.len()
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
     pub from: Address,
     pub to: Option<Address>,
     pub nonce: U256,
+    #[serde(alias = "gasLimit")]
     pub gas: U256,
     pub gas_price: U256,
     pub value: U256,
+    #[serde(alias = "input")]
     pub data: Bytes,
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+impl Transaction {
+    /// Turn the transaction into EIP-2930 one.
+    pub fn with_access_list(self, chain_id: u64, access_list: Vec<AccessListEntry>) -> Transaction2930 {
+        Transaction2930 {
+            from: self.from,
+            chain_id,
+            nonce: self.nonce,
+            gas_price: self.gas_price,
+            gas: self.gas,
+            to: self.to,
+            value: self.value,
+            data: self.data,
+            access_list,
+        }
+    }
+}
+
+/// EIP-2930 transaction type.
+pub const ACCESS_LIST_TX_TYPE: u8 = 0x01;
+
+/// An address and storage keys the transaction plans to access.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessListEntry {
+    pub address: Address,
+    #[serde(with = "storage_keys")]
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
+impl rlp::Encodable for AccessListEntry {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(2);
+        s.append(&self.address);
+        s.begin_list(self.storage_keys.len());
+        for key in &self.storage_keys {
+            s.append(&key.as_ref());
+        }
+    }
+}
+
+impl rlp::Decodable for AccessListEntry {
+    fn decode(d: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if d.item_count()? != 2 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        Ok(AccessListEntry {
+            address: d.val_at(0).map_err(|e| debug("address", e))?,
+            storage_keys: d
+                .at(1)?
+                .iter()
+                .map(|key| {
+                    key.decoder().decode_value(|bytes| {
+                        let mut key = [0u8; 32];
+                        if bytes.len() != key.len() {
+                            return Err(rlp::DecoderError::RlpInvalidLength);
+                        }
+                        key.copy_from_slice(bytes);
+                        Ok(key)
+                    })
+                })
+                .collect::<Result<_, _>>()
+                .map_err(|e| debug("storage_keys", e))?,
+        })
+    }
+}
+
+/// Access list transaction (EIP-2930).
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug, Default)]
 #[serde(rename_all = "camelCase")]
-pub struct SignTransaction<'a> {
-    pub transaction: Cow<'a, Transaction>,
+pub struct Transaction2930 {
+    pub from: Address,
+    #[serde(default)]
     pub chain_id: u64,
+    pub nonce: U256,
+    pub gas_price: U256,
+    #[serde(alias = "gasLimit")]
+    pub gas: U256,
+    pub to: Option<Address>,
+    pub value: U256,
+    #[serde(alias = "input")]
+    pub data: Bytes,
+    #[serde(default)]
+    pub access_list: Vec<AccessListEntry>,
+}
+
+impl Transaction2930 {
+    fn append_fields(&self, s: &mut RlpStream) {
+        s.append(&self.chain_id);
+        s.append(&self.nonce);
+        s.append(&self.gas_price);
+        s.append(&self.gas);
+        append_to(s, &self.to);
+        s.append(&self.value);
+        s.append(&self.data.0);
+        s.append_list(&self.access_list);
+    }
+
+    fn decode_fields(d: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        Ok(Transaction2930 {
+            from: Default::default(),
+            chain_id: d.val_at(0).map_err(|e| debug("chain_id", e))?,
+            nonce: d.val_at(1).map_err(|e| debug("nonce", e))?,
+            gas_price: d.val_at(2).map_err(|e| debug("gas_price", e))?,
+            gas: d.val_at(3).map_err(|e| debug("gas", e))?,
+            to: decode_to(d, 4)?,
+            value: d.val_at(5).map_err(|e| debug("value", e))?,
+            data: d.val_at::<Vec<u8>>(6).map_err(|e| debug("data", e))?.into(),
+            access_list: d.list_at(7).map_err(|e| debug("access_list", e))?,
+        })
+    }
+}
+
+impl rlp::Encodable for Transaction2930 {
+    fn rlp_append(&self, s: &mut RlpStream) {
+        s.begin_list(8);
+        self.append_fields(s);
+    }
+}
+
+impl rlp::Decodable for Transaction2930 {
+    fn decode(d: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        if d.item_count()? != 8 {
+            return Err(rlp::DecoderError::RlpIncorrectListLen);
+        }
+
+        Self::decode_fields(d)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum SignTransaction<'a> {
+    /// Legacy transaction with EIP-155 replay protection.
+    #[serde(rename_all = "camelCase")]
+    Legacy {
+        transaction: Cow<'a, Transaction>,
+        chain_id: u64,
+    },
+    /// EIP-2930 access list transaction.
+    AccessList(Cow<'a, Transaction2930>),
 }
 
 impl<'a> SignTransaction<'a> {
     pub fn owned(tx: Transaction, chain_id: u64) -> Self {
-        Self {
+        SignTransaction::Legacy {
             transaction: Cow::Owned(tx),
             chain_id,
         }
     }
 
+    pub fn chain_id(&self) -> u64 {
+        match *self {
+            SignTransaction::Legacy { chain_id, .. } => chain_id,
+            SignTransaction::AccessList(ref tx) => tx.chain_id,
+        }
+    }
+
+    /// Hash to sign.
     pub fn hash(&self) -> [u8; 32] {
-        SignedTransaction {
-            transaction: Cow::Borrowed(&*self.transaction),
-            v: self.chain_id,
-            r: 0.into(),
-            s: 0.into(),
+        keccak(&self.to_rlp())
+    }
+
+    /// Encoded payload to sign.
+    pub fn to_rlp(&self) -> Vec<u8> {
+        match *self {
+            SignTransaction::Legacy { .. } => self.unsigned().to_rlp(),
+            SignTransaction::AccessList(ref tx) => {
+                let mut out = vec![ACCESS_LIST_TX_TYPE];
+                out.extend_from_slice(&rlp::encode(&**tx));
+                out
+            }
+        }
+    }
+
+    /// Attach signature to the transaction.
+    ///
+    /// `v` is expected to be the recovery id (`0` or `1`).
+    pub fn into_signed(self, v: u8, r: [u8; 32], s: [u8; 32]) -> SignedTransaction<'a> {
+        match self {
+            SignTransaction::Legacy { transaction, chain_id } => SignedTransaction::new(transaction, chain_id, v, r, s),
+            SignTransaction::AccessList(transaction) => SignedTransaction::AccessList {
+                transaction,
+                v: v as u64,
+                r: U256::from_big_endian(&r),
+                s: U256::from_big_endian(&s),
+            },
+        }
+    }
+
+    fn unsigned(&self) -> SignedTransaction<'_> {
+        match *self {
+            SignTransaction::Legacy {
+                ref transaction,
+                chain_id,
+            } => SignedTransaction::Legacy {
+                transaction: Cow::Borrowed(&**transaction),
+                v: chain_id,
+                r: 0.into(),
+                s: 0.into(),
+            },
+            SignTransaction::AccessList(ref transaction) => SignedTransaction::AccessList {
+                transaction: Cow::Borrowed(&**transaction),
+                v: 0,
+                r: 0.into(),
+                s: 0.into(),
+            },
         }
-        .hash()
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct SignedTransaction<'a> {
-    pub transaction: Cow<'a, Transaction>,
-    pub v: u64,
-    pub r: U256,
-    pub s: U256,
+pub enum SignedTransaction<'a> {
+    /// Legacy transaction, `v` contains replay protection.
+    Legacy {
+        transaction: Cow<'a, Transaction>,
+        v: u64,
+        r: U256,
+        s: U256,
+    },
+    /// EIP-2930 access list transaction, `v` is the signature y-parity.
+    AccessList {
+        transaction: Cow<'a, Transaction2930>,
+        v: u64,
+        r: U256,
+        s: U256,
+    },
 }
 
 impl<'a> rlp::Decodable for SignedTransaction<'a> {
     fn decode(d: &rlp::Rlp) -> Result<Self, rlp::DecoderError> {
+        // Typed transactions are wrapped in an RLP string.
+        if d.is_data() {
+            return d.decoder().decode_value(Self::decode_typed);
+        }
+
         if d.item_count()? != 9 {
             return Err(rlp::DecoderError::RlpIncorrectListLen);
         }
 
-        Ok(SignedTransaction {
+        Ok(SignedTransaction::Legacy {
             transaction: Cow::Owned(Transaction {
                 nonce: d.val_at(0).map_err(|e| debug("nonce", e))?,
                 gas_price: d.val_at(1).map_err(|e| debug("gas_price", e))?,
                 gas: d.val_at(2).map_err(|e| debug("gas", e))?,
-                to: {
-                    let to = d.at(3).map_err(|e| debug("to", e))?;
-                    if to.is_empty() {
-                        if to.is_data() {
-                            None
-                        } else {
-                            return Err(rlp::DecoderError::RlpExpectedToBeData);
-                        }
-                    } else {
-                        Some(to.as_val().map_err(|e| debug("to", e))?)
-                    }
-                },
+                to: decode_to(d, 3)?,
                 from: Default::default(),
                 value: d.val_at(4).map_err(|e| debug("value", e))?,
                 data: d.val_at::<Vec<u8>>(5).map_err(|e| debug("data", e))?.into(),
@@ -127,21 +337,59 @@ fn debug(s: &str, err: rlp::DecoderError) -> rlp::DecoderError {
     err
 }
 
+fn append_to(s: &mut RlpStream, to: &Option<Address>) {
+    match to.as_ref() {
+        None => s.append(&""),
+        Some(addr) => s.append(addr),
+    };
+}
+
+fn decode_to(d: &rlp::Rlp, index: usize) -> Result<Option<Address>, rlp::DecoderError> {
+    let to = d.at(index).map_err(|e| debug("to", e))?;
+    if to.is_empty() {
+        if to.is_data() {
+            Ok(None)
+        } else {
+            Err(rlp::DecoderError::RlpExpectedToBeData)
+        }
+    } else {
+        Ok(Some(to.as_val().map_err(|e| debug("to", e))?))
+    }
+}
+
+fn keccak(data: &[u8]) -> [u8; 32] {
+    let mut output = [0_u8; 32];
+    let mut k = Keccak::v256();
+    k.update(data);
+    k.finalize(&mut output);
+    output
+}
+
 impl<'a> rlp::Encodable for SignedTransaction<'a> {
     fn rlp_append(&self, s: &mut RlpStream) {
-        s.begin_list(9);
-        s.append(&self.transaction.nonce);
-        s.append(&self.transaction.gas_price);
-        s.append(&self.transaction.gas);
-        match self.transaction.to.as_ref() {
-            None => s.append(&""),
-            Some(addr) => s.append(addr),
-        };
-        s.append(&self.transaction.value);
-        s.append(&self.transaction.data.0);
-        s.append(&self.v);
-        s.append(&self.r);
-        s.append(&self.s);
+        match *self {
+            SignedTransaction::Legacy {
+                ref transaction,
+                v,
+                ref r,
+                s: ref sig_s,
+            } => {
+                s.begin_list(9);
+                s.append(&transaction.nonce);
+                s.append(&transaction.gas_price);
+                s.append(&transaction.gas);
+                append_to(s, &transaction.to);
+                s.append(&transaction.value);
+                s.append(&transaction.data.0);
+                s.append(&v);
+                s.append(r);
+                s.append(sig_s);
+            }
+            // Typed transactions are wrapped in an RLP string.
+            SignedTransaction::AccessList { .. } => {
+                s.append(&self.to_rlp());
+            }
+        }
     }
 }
 
@@ -151,50 +399,151 @@ impl<'a> SignedTransaction<'a> {
         let r = U256::from_big_endian(&r);
         let s = U256::from_big_endian(&s);
 
-        Self { transaction, v, r, s }
+        SignedTransaction::Legacy { transaction, v, r, s }
     }
 
     pub fn standard_v(&self) -> u8 {
-        match self.v {
-            v if v == 27 => 0,
-            v if v == 28 => 1,
-            v if v >= 35 => ((v - 1) % 2) as u8,
-            _ => 4,
+        match *self {
+            SignedTransaction::Legacy { v, .. } => match v {
+                v if v == 27 => 0,
+                v if v == 28 => 1,
+                v if v >= 35 => ((v - 1) % 2) as u8,
+                _ => 4,
+            },
+            SignedTransaction::AccessList { v, .. } => v as u8,
         }
     }
 
     pub fn chain_id(&self) -> Option<u64> {
-        replay_protection::chain_id(self.v)
+        match *self {
+            SignedTransaction::Legacy { v, .. } => replay_protection::chain_id(v),
+            SignedTransaction::AccessList { ref transaction, .. } => Some(transaction.chain_id),
+        }
     }
 
     pub fn hash(&self) -> [u8; 32] {
-        self.with_rlp(|s| {
-            let mut output = [0_u8; 32];
-            let mut k = Keccak::v256();
-            k.update(s.as_raw());
-            k.finalize(&mut output);
-            output
-        })
+        keccak(&self.to_rlp())
     }
 
     pub fn bare_hash(&self) -> [u8; 32] {
-        let chain_id = self.chain_id().unwrap_or_default();
-
-        SignTransaction {
-            transaction: std::borrow::Cow::Borrowed(&self.transaction),
-            chain_id,
+        match *self {
+            SignedTransaction::Legacy { ref transaction, v, .. } => match replay_protection::chain_id(v) {
+                Some(chain_id) => SignTransaction::Legacy {
+                    transaction: Cow::Borrowed(&**transaction),
+                    chain_id,
+                }
+                .hash(),
+                // Transactions without replay protection sign only the transaction fields.
+                None => {
+                    let mut s = RlpStream::new_list(6);
+                    s.append(&transaction.nonce);
+                    s.append(&transaction.gas_price);
+                    s.append(&transaction.gas);
+                    append_to(&mut s, &transaction.to);
+                    s.append(&transaction.value);
+                    s.append(&transaction.data.0);
+                    keccak(&s.out())
+                }
+            },
+            SignedTransaction::AccessList { ref transaction, .. } => {
+                SignTransaction::AccessList(Cow::Borrowed(&**transaction)).hash()
+            }
         }
-        .hash()
     }
 
+    /// Recovers the address of the account that signed the transaction.
+    ///
+    /// Returns `None` if the signature is invalid.
+    pub fn recover_sender(&self) -> Option<Address> {
+        let (r, s) = match *self {
+            SignedTransaction::Legacy { ref r, ref s, .. } | SignedTransaction::AccessList { ref r, ref s, .. } => {
+                (r, s)
+            }
+        };
+        let mut signature = ethsign::Signature {
+            v: self.standard_v(),
+            r: [0; 32],
+            s: [0; 32],
+        };
+        r.to_big_endian(&mut signature.r);
+        s.to_big_endian(&mut signature.s);
+
+        let public = signature.recover(&self.bare_hash()).ok()?;
+        Some(Address::from_slice(public.address()))
+    }
+
+    /// Raw transaction bytes (as accepted by `eth_sendRawTransaction`).
     pub fn to_rlp(&self) -> Vec<u8> {
-        self.with_rlp(|s| s.out().to_vec())
+        match *self {
+            SignedTransaction::Legacy { .. } => rlp::encode(self).to_vec(),
+            SignedTransaction::AccessList {
+                ref transaction,
+                v,
+                ref r,
+                s: ref sig_s,
+            } => {
+                let mut s = RlpStream::new_list(11);
+                transaction.append_fields(&mut s);
+                s.append(&v);
+                s.append(r);
+                s.append(sig_s);
+
+                let mut out = vec![ACCESS_LIST_TX_TYPE];
+                out.extend_from_slice(&s.out());
+                out
+            }
+        }
+    }
+
+    /// Decode raw transaction bytes (either legacy RLP or typed envelope).
+    pub fn from_raw(bytes: &[u8]) -> Result<Self, rlp::DecoderError> {
+        match bytes.first() {
+            Some(&first) if first <= 0x7f => Self::decode_typed(bytes),
+            _ => rlp::decode(bytes),
+        }
     }
 
-    fn with_rlp<R>(&self, f: impl FnOnce(RlpStream) -> R) -> R {
-        let mut s = RlpStream::new();
-        rlp::Encodable::rlp_append(self, &mut s);
-        f(s)
+    fn decode_typed(bytes: &[u8]) -> Result<Self, rlp::DecoderError> {
+        match bytes.split_first() {
+            Some((&ACCESS_LIST_TX_TYPE, payload)) => {
+                let d = rlp::Rlp::new(payload);
+                if d.item_count()? != 11 {
+                    return Err(rlp::DecoderError::RlpIncorrectListLen);
+                }
+
+                Ok(SignedTransaction::AccessList {
+                    transaction: Cow::Owned(Transaction2930::decode_fields(&d)?),
+                    v: d.val_at(8).map_err(|e| debug("v", e))?,
+                    r: d.val_at(9).map_err(|e| debug("r", e))?,
+                    s: d.val_at(10).map_err(|e| debug("s", e))?,
+                })
+            }
+            _ => Err(rlp::DecoderError::Custom("Unsupported transaction type")),
+        }
+    }
+}
+
+mod storage_keys {
+    use impl_serde::serialize as bytes;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(keys: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keys.iter().map(|key| bytes::to_hex(key, false)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|key| {
+                let bytes = bytes::from_hex(&key).map_err(de::Error::custom)?;
+                let mut key = [0u8; 32];
+                if bytes.len() != key.len() {
+                    return Err(de::Error::invalid_length(bytes.len(), &"32 bytes"));
+                }
+                key.copy_from_slice(&bytes);
+                Ok(key)
+            })
+            .collect()
     }
 }
 
@@ -217,6 +566,45 @@ mod replay_protection {
 mod tests {
     use super::*;
 
+    #[test]
+    fn transaction_should_accept_gas_limit_and_input_aliases() {
+        let canonical = r#"{"from":"0x0000000000000000000000000000000000000001","to":null,"nonce":"0x5","gas":"0x45","gasPrice":"0xf","value":"0x3e8","data":"0x1234"}"#;
+        let aliased = r#"{"from":"0x0000000000000000000000000000000000000001","to":null,"nonce":"0x5","gasLimit":"0x45","gasPrice":"0xf","value":"0x3e8","input":"0x1234"}"#;
+
+        let transaction: Transaction = serde_json::from_str(canonical).unwrap();
+        let aliased: Transaction = serde_json::from_str(aliased).unwrap();
+
+        assert_eq!(transaction.gas, 69.into());
+        assert_eq!(transaction.data.0, vec![0x12, 0x34]);
+        assert_eq!(aliased, transaction);
+        assert_eq!(serde_json::to_string(&aliased).unwrap(), canonical);
+    }
+
+    #[test]
+    fn access_list_transaction_should_accept_gas_limit_and_input_aliases() {
+        let canonical = r#"{"from":"0x0000000000000000000000000000000000000001","nonce":"0x5","gasPrice":"0xf","gas":"0x45","to":null,"value":"0x3e8","data":"0x1234"}"#;
+        let aliased = r#"{"from":"0x0000000000000000000000000000000000000001","nonce":"0x5","gasPrice":"0xf","gasLimit":"0x45","to":null,"value":"0x3e8","input":"0x1234"}"#;
+
+        let transaction: Transaction2930 = serde_json::from_str(canonical).unwrap();
+        let aliased: Transaction2930 = serde_json::from_str(aliased).unwrap();
+
+        assert_eq!(transaction.gas, 69.into());
+        assert_eq!(transaction.data.0, vec![0x12, 0x34]);
+        assert_eq!(aliased, transaction);
+    }
+
+    #[test]
+    fn should_display_truncated_bytes() {
+        let short = Bytes(vec![0x12, 0x34]);
+        let exact = Bytes(vec![0xab; 8]);
+        let long = Bytes((0..100).collect());
+
+        assert_eq!(short.to_string(), "0x1234");
+        assert_eq!(exact.to_string(), "0xabababababababab");
+        assert_eq!(long.to_string(), "0x0001020304050607...(100 bytes)");
+        assert_eq!(Bytes::default().to_string(), "0x");
+    }
+
     #[test]
     fn transaction_rlp_round_trip() {
         let transaction = Transaction {
@@ -254,4 +642,125 @@ mod tests {
 
         assert_eq!(t, decoded);
     }
+
+    fn access_list_transaction() -> Transaction2930 {
+        Transaction {
+            from: Default::default(),
+            to: Some(ethereum_types::H160::repeat_byte(5)),
+            nonce: 5.into(),
+            gas_price: 15.into(),
+            gas: 69.into(),
+            data: vec![1, 2, 3].into(),
+            value: 1_000.into(),
+        }
+        .with_access_list(
+            105,
+            vec![AccessListEntry {
+                address: ethereum_types::H160::repeat_byte(7),
+                storage_keys: vec![[1; 32], [2; 32]],
+            }],
+        )
+    }
+
+    #[test]
+    fn access_list_transaction_rlp_round_trip() {
+        let transaction = access_list_transaction();
+
+        let encoded = rlp::encode(&transaction);
+        let decoded: Transaction2930 = rlp::decode(&encoded).unwrap();
+
+        assert_eq!(transaction, decoded);
+    }
+
+    #[test]
+    fn signed_access_list_transaction_round_trip() {
+        let t = SignTransaction::AccessList(Cow::Owned(access_list_transaction())).into_signed(1, [1; 32], [1; 32]);
+
+        let raw = t.to_rlp();
+        assert_eq!(raw[0], ACCESS_LIST_TX_TYPE);
+        let decoded = SignedTransaction::from_raw(&raw).unwrap();
+        assert_eq!(t, decoded);
+        assert_eq!(decoded.standard_v(), 1);
+        assert_eq!(decoded.chain_id(), Some(105));
+
+        // typed transactions are wrapped in RLP string
+        let encoded = rlp::encode(&t);
+        let decoded: SignedTransaction = rlp::decode(&encoded).unwrap();
+        assert_eq!(t, decoded);
+    }
+
+    #[test]
+    fn access_list_sign_payload_is_typed() {
+        let transaction = access_list_transaction();
+        let sign = SignTransaction::AccessList(Cow::Borrowed(&transaction));
+
+        let payload = sign.to_rlp();
+
+        assert_eq!(payload[0], ACCESS_LIST_TX_TYPE);
+        assert_eq!(&payload[1..], &*rlp::encode(&transaction));
+        assert_eq!(sign.hash(), sign.clone().into_signed(0, [1; 32], [1; 32]).bare_hash());
+    }
+
+    fn recover_sender(raw: &str) -> Option<Address> {
+        let raw = impl_serde::serialize::from_hex(raw).unwrap();
+        SignedTransaction::from_raw(&raw).unwrap().recover_sender()
+    }
+
+    #[test]
+    fn should_recover_sender_of_eip155_transaction() {
+        // Example transaction from EIP-155.
+        let sender = recover_sender("0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83");
+
+        assert_eq!(
+            sender,
+            Some("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn should_recover_sender_of_unprotected_transaction() {
+        let sender = recover_sender("0xf86380843b9aca0082520894353535353535353535353535353535353535353501801ba0f973a0b87062c389d125d8199e803b832b6ac6bf7867a4f6cd87506060fc4c58a010190a7072844e198b900595d4ff76344ec7e49e736ea1bb70c321e8e925acbf");
+
+        assert_eq!(
+            sender,
+            Some("1a642f0e3c3af545e7acbd38b07251b3990914f1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn should_recover_sender_of_access_list_transaction() {
+        let sender = recover_sender("0x01f8650101843b9aca008252089435353535353535353535353535353535353535350180c001a00fd109be4a9e07d64737926c7e2d8d7afa63ec5991d126b8abe7919d3c62a7bea04c48eec569517e252d262bb349949793e6ca39087aa4b64b994ddda92911c6e8");
+
+        assert_eq!(
+            sender,
+            Some("1a642f0e3c3af545e7acbd38b07251b3990914f1".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn should_not_recover_sender_of_invalid_signature() {
+        let transaction = Transaction::default();
+        let signed = SignedTransaction::Legacy {
+            transaction: Cow::Borrowed(&transaction),
+            v: 29,
+            r: 1.into(),
+            s: 1.into(),
+        };
+
+        assert_eq!(signed.recover_sender(), None);
+    }
+
+    #[test]
+    fn should_deserialize_access_list() {
+        let entry: AccessListEntry = serde_json::from_str(
+            r#"{
+                "address": "0x0707070707070707070707070707070707070707",
+                "storageKeys": ["0x0101010101010101010101010101010101010101010101010101010101010101"]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(entry.address, ethereum_types::H160::repeat_byte(7));
+        assert_eq!(entry.storage_keys, vec![[1; 32]]);
+    }
 }
@@ -0,0 +1,270 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Tracking of installed filters.
+//!
+//! Records filters created by `eth_newFilter` (and its block & pending transaction variants)
+//! for every client session and uninstalls them upstream once the session is dropped,
+//! so that disconnected clients don't leak filters on the node.
+
+use ethereum_types::U256;
+use jsonrpc_core::{
+    self as rpc,
+    futures::{
+        future::{BoxFuture, Either},
+        Future, FutureExt,
+    },
+};
+use jsonrpc_pubsub::Session;
+use parking_lot::Mutex;
+use std::{collections::HashMap, sync::Arc};
+
+/// Methods installing a new filter.
+pub const NEW_FILTER: &[&str] = &["eth_newFilter", "eth_newBlockFilter", "eth_newPendingTransactionFilter"];
+/// Method removing an installed filter.
+pub const UNINSTALL_FILTER: &str = "eth_uninstallFilter";
+
+/// Identifier of a client session.
+///
+/// Derived from the address of the session, which is stable for the lifetime of the session.
+pub type SessionId = usize;
+
+/// Upstream used to uninstall filters.
+pub type Upstream =
+    Box<dyn Fn(rpc::Call) -> Box<dyn Future<Output = Option<rpc::Output>> + Send + Unpin> + Send + Sync>;
+
+fn session_id(session: &Arc<Session>) -> SessionId {
+    Arc::as_ptr(session) as SessionId
+}
+
+/// Middleware tracking filters installed by client sessions.
+#[derive(Clone)]
+pub struct FilterTracker {
+    filters: Arc<Mutex<HashMap<SessionId, Vec<U256>>>>,
+    upstream: Arc<Upstream>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl FilterTracker {
+    /// Creates new tracker uninstalling filters via given upstream.
+    pub fn new(upstream: Arc<Upstream>, runtime: tokio::runtime::Handle) -> Self {
+        FilterTracker {
+            filters: Default::default(),
+            upstream,
+            runtime,
+        }
+    }
+
+    /// Returns filters currently tracked for given session.
+    #[cfg(test)]
+    pub fn filters(&self, session: &Arc<Session>) -> Vec<U256> {
+        self.filters
+            .lock()
+            .get(&session_id(session))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn track(&self, session: &Arc<Session>, filter: U256) {
+        let id = session_id(session);
+        let mut filters = self.filters.lock();
+        if let Some(list) = filters.get_mut(&id) {
+            list.push(filter);
+            return;
+        }
+
+        filters.insert(id, vec![filter]);
+        let tracker = self.clone();
+        session.on_drop(move || tracker.uninstall_all(id));
+    }
+
+    fn untrack(&self, session: &Arc<Session>, filter: U256) {
+        if let Some(list) = self.filters.lock().get_mut(&session_id(session)) {
+            list.retain(|f| *f != filter);
+        }
+    }
+
+    fn uninstall_all(&self, id: SessionId) {
+        let filters = self.filters.lock().remove(&id).unwrap_or_default();
+        for filter in filters {
+            let call = rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Str(format!("proxy_uninstallFilter_{:#x}", filter)),
+                method: UNINSTALL_FILTER.into(),
+                params: rpc::Params::Array(vec![
+                    serde_json::to_value(filter).expect("U256 serialization is infallible.")
+                ]),
+            });
+            let uninstall = (self.upstream)(call).map(move |output| match output {
+                Some(rpc::Output::Success(_)) => log::debug!("Uninstalled filter {:#x}", filter),
+                other => log::warn!("Unable to uninstall filter {:#x}: {:?}", filter, other),
+            });
+            self.runtime.spawn(uninstall);
+        }
+    }
+}
+
+impl rpc::Middleware<generic_proxy::ProxyMetadata> for FilterTracker {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = BoxFuture<'static, Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: generic_proxy::ProxyMetadata, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, generic_proxy::ProxyMetadata) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        // Without a session we are unable to tell when the client goes away.
+        let session = match meta.session {
+            Some(ref session) => session.clone(),
+            None => return Either::Right(next(call, meta)),
+        };
+
+        match call {
+            rpc::Call::MethodCall(ref request) if NEW_FILTER.contains(&request.method.as_str()) => {
+                let tracker = self.clone();
+                Either::Left(
+                    next(call, meta)
+                        .map(move |output| {
+                            if let Some(rpc::Output::Success(ref success)) = output {
+                                match serde_json::from_value(success.result.clone()) {
+                                    Ok(filter) => tracker.track(&session, filter),
+                                    Err(e) => log::warn!("Unable to track filter {:?}: {:?}", success.result, e),
+                                }
+                            }
+                            output
+                        })
+                        .boxed(),
+                )
+            }
+            rpc::Call::MethodCall(ref request) if request.method == UNINSTALL_FILTER => {
+                if let rpc::Params::Array(ref params) = request.params {
+                    if let Some(Ok(filter)) = params.first().cloned().map(serde_json::from_value) {
+                        self.untrack(&session, filter);
+                    }
+                }
+                Either::Right(next(call, meta))
+            }
+            _ => Either::Right(next(call, meta)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_core::{futures::future, Middleware};
+    use jsonrpc_pubsub::Session;
+
+    type Calls = Arc<Mutex<Vec<rpc::Call>>>;
+
+    fn tracker() -> (FilterTracker, Calls) {
+        let calls = Calls::default();
+        let recorded = calls.clone();
+        let upstream: Upstream = Box::new(move |call| {
+            recorded.lock().push(call);
+            Box::new(future::ready(None))
+        });
+        (
+            FilterTracker::new(Arc::new(upstream), tokio::runtime::Handle::current()),
+            calls,
+        )
+    }
+
+    fn session() -> Arc<Session> {
+        let (sender, _receiver) = rpc::futures::channel::mpsc::unbounded();
+        Arc::new(Session::new(sender))
+    }
+
+    fn meta(session: &Arc<Session>) -> generic_proxy::ProxyMetadata {
+        Some(session.clone()).into()
+    }
+
+    fn method_call(method: &str, params: Vec<rpc::Value>) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: method.into(),
+            params: rpc::Params::Array(params),
+        })
+    }
+
+    fn uninstall(filter: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Str(format!("proxy_uninstallFilter_{}", filter)),
+            method: UNINSTALL_FILTER.into(),
+            params: rpc::Params::Array(vec![filter.into()]),
+        })
+    }
+
+    async fn call(tracker: &FilterTracker, session: &Arc<Session>, call: rpc::Call, result: &str) {
+        let result = rpc::Value::String(result.into());
+        let response = tracker.on_call(call, meta(session), move |call, _| {
+            let id = match call {
+                rpc::Call::MethodCall(request) => request.id,
+                _ => unreachable!(),
+            };
+            future::ready(Some(rpc::Output::from(Ok(result.clone()), id, Some(rpc::Version::V2))))
+        });
+        match response {
+            Either::Left(fut) => fut.await,
+            Either::Right(fut) => fut.await,
+        };
+    }
+
+    #[tokio::test]
+    async fn should_uninstall_filters_when_session_is_dropped() {
+        // given
+        let (tracker, calls) = tracker();
+        let session = session();
+        call(&tracker, &session, method_call("eth_newFilter", vec![]), "0x1").await;
+        call(&tracker, &session, method_call("eth_newBlockFilter", vec![]), "0x2").await;
+        assert_eq!(tracker.filters(&session), vec![1.into(), 2.into()]);
+        assert!(calls.lock().is_empty());
+
+        // when
+        drop(session);
+
+        // then
+        let calls = calls.lock();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0], uninstall("0x1"));
+        assert_eq!(calls[1], uninstall("0x2"));
+        assert!(tracker.filters.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_not_uninstall_filters_removed_by_client() {
+        // given
+        let (tracker, calls) = tracker();
+        let session = session();
+        call(&tracker, &session, method_call("eth_newFilter", vec![]), "0x1").await;
+
+        // when
+        call(
+            &tracker,
+            &session,
+            method_call(UNINSTALL_FILTER, vec!["0x1".into()]),
+            "true",
+        )
+        .await;
+        drop(session);
+
+        // then
+        assert!(calls.lock().is_empty());
+    }
+}
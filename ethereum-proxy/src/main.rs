@@ -23,11 +23,19 @@
 
 use ethereum_proxy_accounts as accounts;
 
+mod filters;
+
 #[tokio::main]
 async fn main() {
     let yml = clap::load_yaml!("./cli.yml");
     let app = clap::App::from_yaml(yml).set_term_width(80);
 
+    let eth_subscription = upstream::Subscription {
+        subscribe: "eth_subscribe".into(),
+        unsubscribe: "eth_unsubscribe".into(),
+        name: "eth_subscription".into(),
+    };
+
     generic_proxy::run_app(
         app,
         vec![
@@ -40,7 +48,7 @@ async fn main() {
             cache("eth_getBalance"),
             cache("eth_getStorageAt"),
             cache("eth_getBlockByHash"),
-            cache("eth_getBlockByNumber"),
+            cache("eth_getBlockByNumber").with_key_transform(simple_cache::eth::normalize_block_tag),
             cache("eth_getTransactionCount"),
             cache("eth_getBlockTransactionCountByHash"),
             cache("eth_getBlockTransactionCountByNumber"),
@@ -48,7 +56,7 @@ async fn main() {
             cache("eth_getUncleCountByBlockNumber"),
             cache("eth_getCode"),
             cache("eth_call"),
-            cache("eth_estimateGas"),
+            simple_cache::Method::new("eth_estimateGas", simple_cache::CacheEviction::Block),
             cache("eth_getTransactionByHash"),
             cache("eth_getTransactionByBlockHashAndIndex"),
             cache("eth_getTransactionByBlockNumberAndIndex"),
@@ -89,22 +97,18 @@ async fn main() {
             cache("web3_sha3"),
         ],
         vec![
-            upstream::Subscription {
-                subscribe: "eth_subscribe".into(),
-                unsubscribe: "eth_unsubscribe".into(),
-                name: "eth_subscription".into(),
-            },
+            eth_subscription.clone(),
             upstream::Subscription {
                 subscribe: "parity_subscribe".into(),
                 unsubscribe: "parity_unsubscribe".into(),
                 name: "parity_subscription".into(),
             },
-            upstream::Subscription {
-                subscribe: "signer_subscribePending".into(),
-                unsubscribe: "signer_unsubscribePending".into(),
-                name: "signer_pending".into(),
-            },
         ],
+        Some(generic_proxy::BlockSubscription {
+            subscription: eth_subscription,
+            params: jsonrpc_core::Params::Array(vec!["newHeads".into()]),
+            runtime_version: None,
+        }),
         Extension::default(),
     )
 }
@@ -121,8 +125,31 @@ struct Extension {
     params: Vec<cli_params::Param<accounts::config::Param>>,
 }
 
+impl Extension {
+    fn middleware(
+        params: &[accounts::config::Param],
+        upstream: impl upstream::Transport,
+    ) -> <Self as generic_proxy::Extension>::Middleware {
+        use jsonrpc_core::futures::{FutureExt, TryFutureExt};
+
+        let call = move |call: jsonrpc_core::Call| {
+            Box::new(
+                upstream
+                    .send(call)
+                    .map_err(|e| log::error!("Upstream error: {:?}", e))
+                    .map(|res| res.unwrap_or(None)),
+            ) as _
+        };
+        let upstream: std::sync::Arc<filters::Upstream> = std::sync::Arc::new(Box::new(call));
+        (
+            accounts::Middleware::new(upstream.clone(), params),
+            filters::FilterTracker::new(upstream, tokio::runtime::Handle::current()),
+        )
+    }
+}
+
 impl generic_proxy::Extension for Extension {
-    type Middleware = accounts::Middleware;
+    type Middleware = (accounts::Middleware, filters::FilterTracker);
 
     fn configure_app<'a, 'b>(&'a mut self, app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
         self.params = accounts::config::params();
@@ -130,18 +157,26 @@ impl generic_proxy::Extension for Extension {
     }
 
     fn parse_matches(matches: &clap::ArgMatches, upstream: impl upstream::Transport) -> Self::Middleware {
-        use jsonrpc_core::futures::{FutureExt, TryFutureExt};
         let all_params = accounts::config::params();
 
         let params = cli::parse_matches(matches, &all_params).ok().unwrap_or_else(Vec::new);
-        let call = move |call: jsonrpc_core::Call| {
-            Box::new(
-                upstream
-                    .send(call)
-                    .map_err(|e| log::error!("Upstream error: {:?}", e))
-                    .map(|res| res.unwrap_or(None)),
-            ) as _
-        };
-        accounts::Middleware::new(std::sync::Arc::new(Box::new(call)), &params)
+        Self::middleware(&params, upstream)
+    }
+
+    fn configure_from_yaml(
+        value: &serde_json::Value,
+        upstream: impl upstream::Transport,
+    ) -> Result<Self::Middleware, String> {
+        let params = accounts::config::from_yaml(value)?;
+        Ok(Self::middleware(&params, upstream))
+    }
+
+    fn configure_subscriptions(&self) -> Vec<upstream::Subscription> {
+        // Requests awaiting a signature.
+        vec![upstream::Subscription {
+            subscribe: "signer_subscribePending".into(),
+            unsubscribe: "signer_unsubscribePending".into(),
+            name: "signer_pending".into(),
+        }]
     }
 }
@@ -27,5 +27,5 @@ async fn main() {
     let yml = clap::load_yaml!("./cli.yml");
     let app = clap::App::from_yaml(yml).set_term_width(80);
 
-    generic_proxy::run_app(app, vec![], vec![], ())
+    generic_proxy::run_app(app, vec![], vec![], None, ())
 }
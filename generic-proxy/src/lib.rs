@@ -19,49 +19,383 @@
 //!
 //! - Allows configuration to be passed via CLI options or a yaml file.
 //! - Supports simple time-based cache
+//! - Limits calls per client within a sliding window
+//! - Collects per-method latency percentiles
+//! - Lists configured methods via `proxy_methods` and cached results via `proxy_cacheStats`
+//! - Optionally exposes call counters on a Prometheus metrics page of the HTTP server
 
 #![warn(missing_docs)]
 
 use jsonrpc_core as rpc;
 
 use clap::App;
-use std::sync::Arc;
+use rpc::futures::{FutureExt, StreamExt};
+use std::{collections::HashSet, net::IpAddr, sync::Arc};
+use transports::Client;
+
+pub mod methods;
+pub mod metrics;
 
 /// A generic proxy metadata.
-pub type Metadata = Option<Arc<::jsonrpc_pubsub::Session>>;
+#[derive(Debug, Clone, Default)]
+pub struct ProxyMetadata {
+    /// Pub-Sub session of the client (for transports supporting it).
+    pub session: Option<Arc<::jsonrpc_pubsub::Session>>,
+    /// Transport the request has been received with.
+    pub transport: Option<transports::TransportKind>,
+    /// Client making the request (if known).
+    pub client: Option<Client>,
+    /// Identifier used to correlate the request across logs and middlewares.
+    pub trace_id: Option<String>,
+    /// Token the client has authenticated with.
+    pub auth_token: Option<String>,
+}
+
+impl rpc::Metadata for ProxyMetadata {}
+
+/// Former name of `ProxyMetadata`.
+pub type Metadata = ProxyMetadata;
+
+impl From<Option<Arc<::jsonrpc_pubsub::Session>>> for ProxyMetadata {
+    fn from(session: Option<Arc<::jsonrpc_pubsub::Session>>) -> Self {
+        ProxyMetadata {
+            session,
+            ..Default::default()
+        }
+    }
+}
+
+impl
+    From<(
+        transports::TransportKind,
+        Option<Arc<::jsonrpc_pubsub::Session>>,
+        Option<Client>,
+    )> for ProxyMetadata
+{
+    fn from(
+        (transport, session, client): (
+            transports::TransportKind,
+            Option<Arc<::jsonrpc_pubsub::Session>>,
+            Option<Client>,
+        ),
+    ) -> Self {
+        ProxyMetadata {
+            session,
+            transport: Some(transport),
+            client,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ProxyMetadata> for Option<Arc<::jsonrpc_pubsub::Session>> {
+    fn from(meta: ProxyMetadata) -> Self {
+        meta.session
+    }
+}
+
+impl permissioning::ClientIp for ProxyMetadata {
+    fn client_ip(&self) -> Option<IpAddr> {
+        match self.client {
+            Some(Client::Ip(ip)) => Some(ip),
+            _ => None,
+        }
+    }
+
+    fn client(&self) -> Option<Client> {
+        self.client
+    }
+}
+
+impl permissioning::IncomingTransport for ProxyMetadata {
+    fn transport(&self) -> Option<transports::TransportKind> {
+        self.transport
+    }
+}
+
+/// Middlewares handling the calls before they reach the cache.
+type FrontMiddleware = (
+    (
+        metrics::Middleware,
+        audit::AuditMiddleware,
+        upstream::call_timeout::CallTimeoutMiddleware,
+    ),
+    (permissioning::Middleware, ratelimit_per_ip::Middleware),
+    (stats::Middleware, methods::Middleware),
+    (transform::IdentityMiddleware, transform::TransformMiddleware),
+);
 
 type Middleware<T, E> = (
-    permissioning::Middleware,
+    FrontMiddleware,
     simple_cache::Middleware,
     E,
-    upstream::Middleware<T>,
+    (replay::ReplayRecorder, upstream::Middleware<T>),
 );
 
-fn handler<T: upstream::Transport, E: rpc::Middleware<Metadata>>(
+fn handler<T: upstream::Transport, E: rpc::Middleware<ProxyMetadata>>(
     transport: T,
     extra: E,
-    cache_params: &[simple_cache::config::Param],
-    permissioning_params: &[permissioning::config::Param],
+    front: FrontMiddleware,
+    cache: simple_cache::Middleware,
+    recorder: replay::ReplayRecorder,
     upstream_params: &[upstream::config::Param],
-) -> rpc::MetaIoHandler<Metadata, Middleware<T, E>> {
+    unsupported_methods: HashSet<String>,
+) -> rpc::MetaIoHandler<ProxyMetadata, Middleware<T, E>> {
     rpc::MetaIoHandler::with_middleware((
-        permissioning::Middleware::new(permissioning_params),
-        simple_cache::Middleware::new(cache_params),
+        front,
+        cache,
         extra,
-        upstream::Middleware::new(transport, upstream_params),
+        (
+            recorder,
+            upstream::Middleware::new(transport, upstream_params)
+                .with_unsupported_methods(unsupported_methods)
+                .with_response_transform(inject_proxy_version),
+        ),
     ))
 }
 
+/// Appends the proxy version to the client version reported by the upstream.
+fn inject_proxy_version(method: &str, output: rpc::Output) -> rpc::Output {
+    match output {
+        rpc::Output::Success(mut success) if method == "web3_clientVersion" => {
+            if let rpc::Value::String(ref mut version) = success.result {
+                version.push_str(concat!(" (rpc-proxy/", env!("CARGO_PKG_VERSION"), ")"));
+            }
+            rpc::Output::Success(success)
+        }
+        output => output,
+    }
+}
+
+/// Returns the timeout of calls received over HTTP, the lower of the call and HTTP request timeouts.
+fn http_call_timeout(
+    call_timeout: Option<std::time::Duration>,
+    request_timeout: Option<std::time::Duration>,
+) -> Option<std::time::Duration> {
+    match (call_timeout, request_timeout) {
+        (Some(call_timeout), Some(request_timeout)) => Some(call_timeout.min(request_timeout)),
+        (call_timeout, request_timeout) => call_timeout.or(request_timeout),
+    }
+}
+
+/// Upstream subscription that produces a notification for every new block.
+///
+/// Used to evict cache entries with `simple_cache::CacheEviction::Block` and
+/// `simple_cache::CacheEviction::BlockCount` policies. The latter expects notifications
+/// to contain the block header with a `number` field.
+#[derive(Debug, Clone)]
+pub struct BlockSubscription {
+    /// Pub-Sub methods of the subscription.
+    pub subscription: upstream::Subscription,
+    /// Parameters of the subscribe call.
+    pub params: rpc::Params,
+    /// Optional subscription to runtime version changes.
+    ///
+    /// Notifications are expected to contain `specVersion` field, which is reported
+    /// to the cache for `metadata_version_aware` methods.
+    pub runtime_version: Option<upstream::Subscription>,
+}
+
+/// Subscribes to new blocks and notifies the cache about them.
+fn subscribe_new_blocks<T: upstream::Transport + Clone>(
+    transport: T,
+    block_subscription: BlockSubscription,
+    cache: simple_cache::Middleware,
+) {
+    if let Some(runtime_version) = block_subscription.runtime_version {
+        let cache = cache.clone();
+        subscribe(
+            transport.clone(),
+            "proxy_runtimeVersion",
+            runtime_version,
+            rpc::Params::None,
+            move |notification| match spec_version(&notification) {
+                Some(version) => cache.set_spec_version(version),
+                None => log::warn!("Unable to read spec version from: {}", notification),
+            },
+        );
+    }
+
+    subscribe(
+        transport,
+        "proxy_newBlocks",
+        block_subscription.subscription,
+        block_subscription.params,
+        move |notification| {
+            cache.new_block();
+            match block_number(&notification) {
+                Some(number) => cache.set_block_number(number),
+                None => log::debug!("Unable to read block number from: {}", notification),
+            }
+        },
+    );
+}
+
+/// Extracts the block `number` from a new head notification.
+fn block_number(notification: &str) -> Option<u64> {
+    let notification: rpc::Notification = serde_json::from_str(notification).ok()?;
+    let number = match notification.params {
+        rpc::Params::Map(ref map) => map.get("result")?.get("number")?.clone(),
+        _ => return None,
+    };
+    match number {
+        rpc::Value::Number(number) => number.as_u64(),
+        rpc::Value::String(number) => u64::from_str_radix(number.trim_start_matches("0x"), 16).ok(),
+        _ => None,
+    }
+}
+
+/// Extracts `specVersion` from a runtime version notification.
+fn spec_version(notification: &str) -> Option<u64> {
+    let notification: rpc::Notification = serde_json::from_str(notification).ok()?;
+    match notification.params {
+        rpc::Params::Map(ref map) => map.get("result")?.get("specVersion")?.as_u64(),
+        _ => None,
+    }
+}
+
+/// Subscribes to an upstream subscription and invokes `on_notification` for every notification.
+fn subscribe<T: upstream::Transport>(
+    transport: T,
+    id: &str,
+    subscription: upstream::Subscription,
+    params: rpc::Params,
+    mut on_notification: impl FnMut(String) + Send + 'static,
+) {
+    let (sender, receiver) = rpc::futures::channel::mpsc::unbounded();
+    let session = Arc::new(jsonrpc_pubsub::Session::new(sender));
+    let call = rpc::Call::MethodCall(rpc::MethodCall {
+        jsonrpc: Some(rpc::Version::V2),
+        id: rpc::Id::Str(id.into()),
+        method: subscription.subscribe.clone(),
+        params,
+    });
+    let name = subscription.subscribe.clone();
+    let subscribe = transport.subscribe(call, Some(session.clone()), subscription);
+
+    tokio::spawn(async move {
+        match subscribe.await {
+            Ok(Some(rpc::Output::Success(_))) => {}
+            res => {
+                log::error!("Unable to subscribe to {}: {:?}", name, res);
+                return;
+            }
+        }
+
+        receiver
+            .for_each(|notification| {
+                on_notification(notification);
+                rpc::futures::future::ready(())
+            })
+            .map(|_| drop(session))
+            .await
+    });
+}
+
+/// How long to wait for the upstream to respond during `--dry-run`.
+const DRY_RUN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Unwraps the configuration result or exits the process with an error message.
+fn or_exit<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+/// Connects to the upstream of given type (`ws`, `ipc` or `http`).
+async fn connect_upstream(
+    upstream_type: &str,
+    ws_params: Vec<ws_upstream::config::Param>,
+    ipc_params: Vec<ipc_upstream::config::Param>,
+    http_params: Vec<http_upstream::config::Param>,
+) -> Result<upstream::DynTransport, String> {
+    match upstream_type {
+        "ws" => Ok(Arc::new(
+            ws_upstream::WebSocket::new(ws_params, |fut| std::mem::drop(tokio::spawn(fut))).await?,
+        )),
+        "ipc" => Ok(Arc::new(ipc_upstream::IPC::new(ipc_params).await?)),
+        "http" => Ok(Arc::new(http_upstream::HttpPoolTransport::new(http_params)?)),
+        other => Err(format!("Unsupported upstream type: {}", other)),
+    }
+}
+
+/// Runs the future to completion on the current runtime.
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::task::block_in_place(|| tokio::runtime::Handle::current().block_on(future))
+}
+
+/// Verifies the upstream responds and returns the exit code of the process.
+async fn dry_run<T: upstream::Transport>(transport: &T, timeout: std::time::Duration) -> i32 {
+    match tokio::time::timeout(timeout, transport.probe()).await {
+        Ok(Ok(())) => {
+            println!("OK");
+            0
+        }
+        Ok(Err(e)) => {
+            eprintln!("{}", e);
+            1
+        }
+        Err(_) => {
+            eprintln!("Upstream did not respond within {:?}", timeout);
+            1
+        }
+    }
+}
+
+/// Reads the value of `--instance-name` from raw command line arguments.
+///
+/// The name has to be known before the parameters are registered, since it
+/// changes the environment variables their values are read from.
+fn instance_name(args: &[std::ffi::OsString]) -> Option<String> {
+    let mut args = args.iter().filter_map(|arg| arg.to_str());
+    while let Some(arg) = args.next() {
+        if arg == "--instance-name" {
+            return args.next().map(str::to_owned);
+        }
+        if let Some(name) = arg.strip_prefix("--instance-name=") {
+            return Some(name.to_owned());
+        }
+    }
+    None
+}
+
+/// Namespaces environment variable lookups of all parameters with given instance name.
+fn with_instance_name<X>(params: Vec<cli_params::Param<X>>, instance_name: Option<&str>) -> Vec<cli_params::Param<X>> {
+    match instance_name {
+        Some(name) => {
+            let prefix = name.to_uppercase().replace('-', "_");
+            params.into_iter().map(|p| p.with_env_prefix(&prefix)).collect()
+        }
+        None => params,
+    }
+}
+
 /// TODO [ToDr] The whole thing is really shit.
 pub trait Extension {
     /// Middleware type.
-    type Middleware: rpc::Middleware<Metadata> + Clone;
+    type Middleware: rpc::Middleware<ProxyMetadata> + Clone;
 
     /// Configure clap application with parameters.
     fn configure_app<'a, 'b>(&'a mut self, app: clap::App<'a, 'b>) -> clap::App<'a, 'b>;
 
     /// Parse matches and create the middleware.
     fn parse_matches(matches: &clap::ArgMatches, upstream: impl upstream::Transport) -> Self::Middleware;
+
+    /// Pub-sub methods handled by the extension, merged with subscriptions passed to `run_app`.
+    fn configure_subscriptions(&self) -> Vec<upstream::Subscription> {
+        vec![]
+    }
+
+    /// Create the middleware from the YAML configuration file passed via `--config`.
+    ///
+    /// Used instead of `parse_matches` if the file is given.
+    fn configure_from_yaml(
+        _value: &serde_json::Value,
+        _upstream: impl upstream::Transport,
+    ) -> Result<Self::Middleware, String> {
+        Err("Not supported".into())
+    }
 }
 
 impl Extension for () {
@@ -74,73 +408,548 @@ impl Extension for () {
     fn parse_matches(_matches: &clap::ArgMatches, _upstream: impl upstream::Transport) -> Self::Middleware {
         Default::default()
     }
+
+    fn configure_from_yaml(
+        _value: &serde_json::Value,
+        _upstream: impl upstream::Transport,
+    ) -> Result<Self::Middleware, String> {
+        Ok(Default::default())
+    }
+}
+
+/// Parses YAML configuration of the extension.
+pub fn parse_config<R: std::io::Read>(reader: R) -> Result<serde_json::Value, String> {
+    serde_yaml::from_reader(reader).map_err(|e| format!("Invalid YAML: {:?}", e))
+}
+
+/// Reads YAML configuration of the extension from given file.
+fn read_config(path: &str) -> Result<serde_json::Value, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Unable to open config file {}: {}", path, e))?;
+    parse_config(file)
 }
 
 /// Run app with additional cache methods and upstream subscriptions.
+///
+/// Subscriptions declared by the `extension` are added to `upstream_subscriptions`.
+///
+/// `block_subscription` is required for methods with `CacheEviction::Block` to be ever evicted.
 pub fn run_app<E: Extension>(
     app: App,
     simple_cache_methods: Vec<simple_cache::Method>,
-    upstream_subscriptions: Vec<upstream::Subscription>,
+    mut upstream_subscriptions: Vec<upstream::Subscription>,
+    block_subscription: Option<BlockSubscription>,
     mut extension: E,
 ) where
-    <E::Middleware as rpc::Middleware<Metadata>>::Future: Unpin,
-    <E::Middleware as rpc::Middleware<Metadata>>::CallFuture: Unpin,
+    <E::Middleware as rpc::Middleware<ProxyMetadata>>::Future: Unpin,
+    <E::Middleware as rpc::Middleware<ProxyMetadata>>::CallFuture: Unpin,
 {
     env_logger::init();
-    let args = ::std::env::args_os();
+    let args = ::std::env::args_os().collect::<Vec<_>>();
+    let instance_name = instance_name(&args);
+    let instance_name = instance_name.as_deref();
 
-    let ws_params = transports::ws::params();
+    let ws_params = with_instance_name(transports::ws::params(), instance_name);
     let app = cli::configure_app(app, &ws_params);
-    let http_params = transports::http::params();
+    let http_params = with_instance_name(transports::http::params(), instance_name);
     let app = cli::configure_app(app, &http_params);
-    let tcp_params = transports::tcp::params();
+    let http_request_timeout_params = with_instance_name(transports::http::request_timeout_params(), instance_name);
+    let app = cli::configure_app(app, &http_request_timeout_params);
+    let tcp_params = with_instance_name(transports::tcp::params(), instance_name);
     let app = cli::configure_app(app, &tcp_params);
-    let ipc_params = transports::ipc::params();
+    let ipc_params = with_instance_name(transports::ipc::params(), instance_name);
     let app = cli::configure_app(app, &ipc_params);
+    #[cfg(unix)]
+    let unix_params = with_instance_name(transports::unix::params(), instance_name);
+    #[cfg(unix)]
+    let app = cli::configure_app(app, &unix_params);
 
-    let upstream_params = upstream::config::params();
+    let upstream_params = with_instance_name(upstream::config::params(), instance_name);
     let app = cli::configure_app(app, &upstream_params);
-    let ws_upstream_params = ws_upstream::config::params();
+    let ws_upstream_params = with_instance_name(ws_upstream::config::params(), instance_name);
     let app = cli::configure_app(app, &ws_upstream_params);
+    let ipc_upstream_params = with_instance_name(ipc_upstream::config::params(), instance_name);
+    let app = cli::configure_app(app, &ipc_upstream_params);
+    let http_upstream_params = with_instance_name(http_upstream::config::params(), instance_name);
+    let app = cli::configure_app(app, &http_upstream_params);
 
-    let cache_params = simple_cache::config::params();
+    let cache_params = with_instance_name(simple_cache::config::params(), instance_name);
     let app = cli::configure_app(app, &cache_params);
 
-    let permissioning_params = permissioning::config::params();
+    let permissioning_params = with_instance_name(permissioning::config::params(), instance_name);
     let app = cli::configure_app(app, &permissioning_params);
 
+    let ratelimit_params = with_instance_name(ratelimit_per_ip::config::params(), instance_name);
+    let app = cli::configure_app(app, &ratelimit_params);
+
+    let stats_params = with_instance_name(stats::config::params(), instance_name);
+    let app = cli::configure_app(app, &stats_params);
+
+    let transform_params = with_instance_name(transform::config::params(), instance_name);
+    let app = cli::configure_app(app, &transform_params);
+
+    let audit_params = with_instance_name(audit::config::params(), instance_name);
+    let app = cli::configure_app(app, &audit_params);
+
+    let replay_params = with_instance_name(replay::config::params(), instance_name);
+    let app = cli::configure_app(app, &replay_params);
+
+    let methods_params = with_instance_name(methods::params(), instance_name);
+    let app = cli::configure_app(app, &methods_params);
+
+    upstream_subscriptions.extend(extension.configure_subscriptions());
     let app = extension.configure_app(app);
+    let app = app.arg(
+        clap::Arg::with_name("instance-name")
+            .long("instance-name")
+            .takes_value(true)
+            .help("Name of the proxy instance, used as a prefix of environment variables (e.g. `eth` -> `ETH_HTTP_PORT`)."),
+    );
+    let app = app.arg(
+        clap::Arg::with_name("upstream-type")
+            .long("upstream-type")
+            .takes_value(true)
+            .possible_values(&["ws", "ipc", "http"])
+            .default_value("ws")
+            .help("Type of the upstream transport."),
+    );
+    let app = app.arg(
+        clap::Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .help("Path to a YAML configuration file of the proxy extension, used instead of its CLI options."),
+    );
+    let app = app.arg(
+        clap::Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Validate the configuration and upstream connectivity, then exit without accepting clients."),
+    );
 
     // Parse matches
     let matches = app.get_matches_from(args);
-    let ws_params = cli::parse_matches(&matches, &ws_params).unwrap();
-    let http_params = cli::parse_matches(&matches, &http_params).unwrap();
-    let tcp_params = cli::parse_matches(&matches, &tcp_params).unwrap();
-    let ipc_params = cli::parse_matches(&matches, &ipc_params).unwrap();
-    let mut upstream_params = cli::parse_matches(&matches, &upstream_params).unwrap();
+    let ws_params = or_exit(cli::parse_matches(&matches, &ws_params));
+    let mut http_params = or_exit(cli::parse_matches(&matches, &http_params));
+    let http_request_timeout_params = or_exit(cli::parse_matches(&matches, &http_request_timeout_params));
+    let tcp_params = or_exit(cli::parse_matches(&matches, &tcp_params));
+    let ipc_params = or_exit(cli::parse_matches(&matches, &ipc_params));
+    #[cfg(unix)]
+    let unix_params = or_exit(cli::parse_matches(&matches, &unix_params));
+    let mut upstream_params = or_exit(cli::parse_matches(&matches, &upstream_params));
     upstream::config::add_subscriptions(&mut upstream_params, upstream_subscriptions);
-    let ws_upstream_params = cli::parse_matches(&matches, &ws_upstream_params).unwrap();
-    let mut cache_params = cli::parse_matches(&matches, &cache_params).unwrap();
+    let mut ws_upstream_params = or_exit(cli::parse_matches(&matches, &ws_upstream_params));
+    ws_upstream_params.extend(vec![
+        ws_upstream::config::Param::LogRequests(upstream::config::log_requests(&upstream_params)),
+        ws_upstream::config::Param::LogResponses(upstream::config::log_responses(&upstream_params)),
+        ws_upstream::config::Param::MaxPendingRequests(upstream::config::max_pending(&upstream_params)),
+    ]);
+    let mut ipc_upstream_params = or_exit(cli::parse_matches(&matches, &ipc_upstream_params));
+    ipc_upstream_params.extend(vec![
+        ipc_upstream::config::Param::LogRequests(upstream::config::log_requests(&upstream_params)),
+        ipc_upstream::config::Param::LogResponses(upstream::config::log_responses(&upstream_params)),
+        ipc_upstream::config::Param::MaxPendingRequests(upstream::config::max_pending(&upstream_params)),
+        ipc_upstream::config::Param::MaxResponseBytes(ws_upstream::config::max_response_bytes(&ws_upstream_params)),
+    ]);
+    let http_upstream_params = or_exit(cli::parse_matches(&matches, &http_upstream_params));
+    let mut cache_params = or_exit(cli::parse_matches(&matches, &cache_params));
     simple_cache::config::add_methods(&mut cache_params, simple_cache_methods);
-    let permissioning_params = cli::parse_matches(&matches, &permissioning_params).unwrap();
+    let permissioning_params = or_exit(cli::parse_matches(&matches, &permissioning_params));
+    let ratelimit_params = or_exit(cli::parse_matches(&matches, &ratelimit_params));
+    let stats_params = or_exit(cli::parse_matches(&matches, &stats_params));
+    let transform_params = or_exit(cli::parse_matches(&matches, &transform_params));
+    let audit_params = or_exit(cli::parse_matches(&matches, &audit_params));
+    let replay_params = or_exit(cli::parse_matches(&matches, &replay_params));
+    let methods_params = or_exit(cli::parse_matches(&matches, &methods_params));
 
     // Actually run the damn thing.
-    let transport = ws_upstream::WebSocket::new(ws_upstream_params, |fut| std::mem::drop(tokio::spawn(fut))).unwrap();
+    let upstream_type = matches.value_of("upstream-type").unwrap_or("ws");
+    let transport = match replay::config::playback(&replay_params) {
+        Some(path) => upstream::boxed(or_exit(replay::ReplayUpstream::new(path))),
+        None => or_exit(block_on(connect_upstream(
+            upstream_type,
+            ws_upstream_params,
+            ipc_upstream_params,
+            http_upstream_params,
+        ))),
+    };
+    let transport: upstream::DynTransport = match upstream::config::hmac_secret(&upstream_params) {
+        Some(secret) => {
+            if upstream_type != "http" {
+                log::warn!("Only HTTP upstream supports headers, requests are not going to be signed.");
+            }
+            Arc::new(upstream::signing::HmacSigningTransport::new(
+                transport,
+                secret.as_bytes().to_vec(),
+            ))
+        }
+        None => transport,
+    };
+    let transport: upstream::DynTransport = match upstream::config::id_prefix(&upstream_params) {
+        Some(prefix) => upstream::boxed(upstream::meta_inject::MetaInjectTransport::new(
+            transport,
+            prefix.to_owned(),
+        )),
+        None => transport,
+    };
+
+    if matches.is_present("dry-run") {
+        std::process::exit(block_on(dry_run(&transport, DRY_RUN_TIMEOUT)));
+    }
+
+    let capability_probes = upstream::config::capability_probes(&upstream_params);
+    let unsupported_methods = if capability_probes.is_empty() {
+        HashSet::new()
+    } else {
+        block_on(upstream::probe_capabilities(
+            &transport,
+            &capability_probes,
+            upstream::CAPABILITY_PROBE_TIMEOUT,
+        ))
+    };
+
+    let cache = simple_cache::Middleware::new(&cache_params);
+    if cache.uses_block_eviction() {
+        match block_subscription {
+            Some(block_subscription) => subscribe_new_blocks(transport.clone(), block_subscription, cache.clone()),
+            None => log::warn!("No block subscription configured, block-evicted cache entries will never expire."),
+        }
+    }
 
-    let extra = E::parse_matches(&matches, transport.clone());
-    let h = || {
+    let permissioning = permissioning::Middleware::new(&permissioning_params);
+    let ratelimit = ratelimit_per_ip::Middleware::new(&ratelimit_params);
+    let stats = stats::Middleware::new(&stats_params);
+    let methods = methods::Middleware::new(cache.clone(), permissioning.clone(), &methods_params);
+    let identity = transform::IdentityMiddleware::new(&transform_params);
+    let transform = transform::TransformMiddleware::new(&transform_params);
+    let audit = or_exit(audit::AuditMiddleware::new(&audit_params));
+    let metrics = transports::http::Metrics {
+        cache_hits_total: cache.hits_counter(),
+        ..Default::default()
+    };
+    let metrics_middleware = metrics::Middleware::new(&metrics);
+    let call_timeout = upstream::call_timeout::CallTimeoutMiddleware::new(&upstream_params);
+    let http_call_timeout = upstream::call_timeout::CallTimeoutMiddleware::with_timeout(http_call_timeout(
+        upstream::config::call_timeout(&upstream_params),
+        transports::http::request_timeout(&http_request_timeout_params),
+    ));
+    let recorder = or_exit(replay::ReplayRecorder::new(&replay_params));
+    let extra = match matches.value_of("config") {
+        Some(path) => or_exit(read_config(path).and_then(|config| {
+            E::configure_from_yaml(&config, transport.clone())
+                .map_err(|e| format!("Unable to configure extension from {}: {}", path, e))
+        })),
+        None => E::parse_matches(&matches, transport.clone()),
+    };
+    let h = |call_timeout: &upstream::call_timeout::CallTimeoutMiddleware| {
         handler(
             transport.clone(),
             extra.clone(),
-            &cache_params,
-            &permissioning_params,
+            (
+                (metrics_middleware.clone(), audit.clone(), call_timeout.clone()),
+                (permissioning.clone(), ratelimit.clone()),
+                (stats.clone(), methods.clone()),
+                (identity.clone(), transform.clone()),
+            ),
+            cache.clone(),
+            recorder.clone(),
             &upstream_params,
+            unsupported_methods.clone(),
         )
     };
-    let server1 = transports::ws::start(ws_params, h()).unwrap();
-    let _server2 = transports::http::start(http_params, h()).unwrap();
-    let _server3 = transports::tcp::start(tcp_params, h()).unwrap();
-    let _server4 = transports::ipc::start(ipc_params, h()).unwrap();
+    let server1 = transports::ws::start(ws_params, h(&call_timeout)).unwrap();
+    http_params.push(transports::http::with_metrics(metrics));
+    if permissioning.uses_rate_limits() || ratelimit.is_enabled() {
+        http_params.push(transports::http::require_client_address());
+    }
+    let _server2 = or_exit(
+        transports::http::start(http_params, h(&http_call_timeout))
+            .map_err(|e| format!("Unable to start HTTP server: {}", e)),
+    );
+    let _server3 = transports::tcp::start(tcp_params, h(&call_timeout)).unwrap();
+    let _server4 = transports::ipc::start(ipc_params, h(&call_timeout)).unwrap();
+    #[cfg(unix)]
+    let _server5 = transports::unix::start(unix_params, h(&call_timeout)).unwrap();
 
     server1.wait().unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::futures::future;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Upstream(Option<Result<Option<rpc::Output>, String>>);
+
+    impl upstream::Transport for Upstream {
+        type Error = String;
+        type Future = Box<dyn rpc::futures::Future<Output = Result<Option<rpc::Output>, String>> + Send + Unpin>;
+
+        fn subscribe(
+            &self,
+            call: rpc::Call,
+            _: Option<Arc<jsonrpc_pubsub::Session>>,
+            _: upstream::Subscription,
+        ) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(
+            &self,
+            call: rpc::Call,
+            _: Option<Arc<jsonrpc_pubsub::Session>>,
+            _: upstream::Subscription,
+        ) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, _: rpc::Call) -> Self::Future {
+            match self.0.clone() {
+                Some(result) => Box::new(future::ready(result)),
+                None => Box::new(future::pending()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_connect_to_ipc_upstream() {
+        // given
+        let upstream = mock_upstream::MockIpcUpstream::new(|call| match call {
+            rpc::Call::MethodCall(call) => rpc::Output::from(Ok("1".into()), call.id, call.jsonrpc),
+            _ => unreachable!(),
+        })
+        .await;
+
+        // when
+        let transport = connect_upstream(
+            "ipc",
+            vec![],
+            vec![ipc_upstream::config::Param::Path(upstream.path().into())],
+            vec![],
+        )
+        .await
+        .unwrap();
+
+        // then
+        assert_eq!(dry_run(&transport, Duration::from_secs(1)).await, 0);
+    }
+
+    #[test]
+    fn should_inject_proxy_version_into_client_version() {
+        let output = |value: &str| rpc::Output::from(Ok(value.into()), rpc::Id::Num(1), Some(rpc::Version::V2));
+
+        assert_eq!(
+            inject_proxy_version("web3_clientVersion", output("Geth/v1.9.25")),
+            output(concat!("Geth/v1.9.25 (rpc-proxy/", env!("CARGO_PKG_VERSION"), ")"))
+        );
+        assert_eq!(inject_proxy_version("eth_chainId", output("0x1")), output("0x1"));
+    }
+
+    #[test]
+    fn should_read_block_number_from_new_head() {
+        let notification = |number: &str| {
+            format!(
+                r#"{{"jsonrpc":"2.0","method":"eth_subscription","params":{{"subscription":"0x1","result":{{"number":{}}}}}}}"#,
+                number
+            )
+        };
+
+        assert_eq!(block_number(&notification(r#""0x1b4""#)), Some(436));
+        assert_eq!(block_number(&notification("436")), Some(436));
+        assert_eq!(block_number(&notification("null")), None);
+    }
+
+    #[test]
+    fn should_read_instance_name_from_args() {
+        let args = |args: &[&str]| args.iter().map(Into::into).collect::<Vec<std::ffi::OsString>>();
+
+        assert_eq!(
+            instance_name(&args(&["rpc-proxy", "--instance-name", "eth"])),
+            Some("eth".into())
+        );
+        assert_eq!(
+            instance_name(&args(&["rpc-proxy", "--instance-name=eth"])),
+            Some("eth".into())
+        );
+        assert_eq!(instance_name(&args(&["rpc-proxy", "--http-port", "8545"])), None);
+    }
+
+    #[test]
+    fn should_read_params_from_instance_env_vars() {
+        // given
+        let params = with_instance_name(
+            transports::http::params::<ProxyMetadata, rpc::NoopMiddleware>(),
+            Some("eth"),
+        );
+        let port = params.iter().find(|p| p.name == "http-port").unwrap();
+        let env = |var: &str| match var {
+            "ETH_HTTP_PORT" => Some("not-a-port".to_owned()),
+            _ => None,
+        };
+
+        // when
+        let result = port.parse_with_env(None, env);
+
+        // then
+        assert_eq!(port.env_var(), "ETH_HTTP_PORT");
+        assert!(result.is_err());
+        assert!(port.parse_with_env(None, |_| None).is_ok());
+    }
+
+    /// Middleware taking its time before passing the call further, like accounts waiting for a signer.
+    struct SlowMiddleware(Duration);
+
+    impl rpc::Middleware<ProxyMetadata> for SlowMiddleware {
+        type Future = rpc::middleware::NoopFuture;
+        type CallFuture = rpc::middleware::NoopCallFuture;
+
+        fn on_call<F, X>(&self, call: rpc::Call, meta: ProxyMetadata, next: F) -> future::Either<Self::CallFuture, X>
+        where
+            F: FnOnce(rpc::Call, ProxyMetadata) -> X + Send,
+            X: rpc::futures::Future<Output = Option<rpc::Output>> + Send + 'static,
+        {
+            let response = next(call, meta);
+            future::Either::Left(Box::pin(tokio::time::sleep(self.0).then(move |_| response)))
+        }
+    }
+
+    #[test]
+    fn should_time_out_slow_http_requests() {
+        use std::io::{Read, Write};
+
+        // given
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let request_timeout = transports::http::request_timeout_params()
+            .pop()
+            .unwrap()
+            .parse(Some("100".into()))
+            .unwrap();
+        let mut io = rpc::MetaIoHandler::with_middleware((
+            upstream::call_timeout::CallTimeoutMiddleware::with_timeout(http_call_timeout(None, request_timeout)),
+            SlowMiddleware(Duration::from_millis(200)),
+        ));
+        io.add_method("eth_sendTransaction", |_| {
+            future::ready(Ok(rpc::Value::String("0x1".into())))
+        });
+        let params = transports::http::params()
+            .into_iter()
+            .filter(|p| p.name == "http-port")
+            .map(|p| p.parse(Some("0".into())).unwrap())
+            .collect();
+        let server = transports::http::start(params, io).unwrap();
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"eth_sendTransaction","params":[]}"#;
+
+        // when
+        let start = std::time::Instant::now();
+        let mut stream = std::net::TcpStream::connect(server.address()).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            server.address(),
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let elapsed = start.elapsed();
+
+        // then
+        assert!(
+            response
+                .trim_end()
+                .ends_with(r#"{"jsonrpc":"2.0","error":{"code":-32003,"message":"Timeout"},"id":1}"#),
+            "Unexpected response: {}",
+            response
+        );
+        assert!(elapsed < Duration::from_millis(150), "Took {:?}", elapsed);
+    }
+
+    #[test]
+    fn should_use_lower_timeout_for_http_calls() {
+        let second = Some(Duration::from_secs(1));
+        let minute = Some(Duration::from_secs(60));
+
+        assert_eq!(http_call_timeout(second, minute), second);
+        assert_eq!(http_call_timeout(minute, second), second);
+        assert_eq!(http_call_timeout(None, minute), minute);
+        assert_eq!(http_call_timeout(second, None), second);
+        assert_eq!(http_call_timeout(None, None), None);
+    }
+
+    #[test]
+    fn should_parse_extension_config() {
+        // given
+        let yaml = "account:\n  file: ./key.json\n  password: secret\n";
+
+        // when
+        let config = parse_config(yaml.as_bytes()).unwrap();
+
+        // then
+        assert_eq!(config["account"]["file"], "./key.json");
+        assert_eq!(config["account"]["password"], "secret");
+        assert!(parse_config("account: [".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn should_convert_metadata_from_and_to_session() {
+        use permissioning::ClientIp;
+
+        // given
+        let (sender, _receiver) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(jsonrpc_pubsub::Session::new(sender));
+
+        // when
+        let meta = ProxyMetadata::from(Some(session.clone()));
+        let with_ip = ProxyMetadata {
+            client: Some(Client::Ip("127.0.0.1".parse().unwrap())),
+            ..meta.clone()
+        };
+        let with_connection = ProxyMetadata {
+            client: Some(Client::Connection(1)),
+            ..meta.clone()
+        };
+
+        // then
+        assert!(meta.transport.is_none());
+        assert_eq!(meta.client_ip(), None);
+        assert_eq!(with_ip.client_ip(), Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(with_connection.client_ip(), None);
+        assert_eq!(with_connection.client(), Some(Client::Connection(1)));
+        let session2: Option<Arc<jsonrpc_pubsub::Session>> = with_ip.into();
+        assert!(Arc::ptr_eq(&session2.unwrap(), &session));
+    }
+
+    #[test]
+    fn should_accept_metadata_in_all_middlewares() {
+        fn assert_middleware<X: rpc::Middleware<ProxyMetadata>>() {}
+
+        assert_middleware::<Middleware<Upstream, rpc::NoopMiddleware>>();
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_upstream_type() {
+        assert!(connect_upstream("tcp", vec![], vec![], vec![]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dry_run_should_exit_with_zero_if_upstream_responds() {
+        let upstream = Upstream(Some(Ok(Some(rpc::Output::from(Ok("1".into()), rpc::Id::Num(1), None)))));
+
+        assert_eq!(dry_run(&upstream, Duration::from_secs(1)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn dry_run_should_exit_with_one_if_upstream_fails() {
+        let upstream = Upstream(Some(Err("Connection refused".into())));
+
+        assert_eq!(dry_run(&upstream, Duration::from_secs(1)).await, 1);
+    }
+
+    #[tokio::test]
+    async fn dry_run_should_exit_with_one_if_upstream_does_not_respond() {
+        let upstream = Upstream(None);
+
+        assert_eq!(dry_run(&upstream, Duration::from_millis(10)).await, 1);
+    }
+}
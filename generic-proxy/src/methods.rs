@@ -0,0 +1,285 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Lists configured methods together with their caching and access policies,
+//! and describes the current content of the cache.
+
+use crate::rpc;
+use rpc::futures::{
+    future::{self, Either},
+    Future,
+};
+use std::collections::BTreeSet;
+
+/// Method returning all configured methods and the middlewares handling them.
+pub const METHODS_METHOD: &str = "proxy_methods";
+/// Method returning cacheable methods and the remaining time to live of cached results.
+pub const CACHE_STATS_METHOD: &str = "proxy_cacheStats";
+
+/// A configuration option to apply.
+pub enum Param {
+    /// Serves `proxy_methods` and `proxy_cacheStats`.
+    AdminMethods(bool),
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![cli_params::Param::new(
+        "Admin",
+        "proxy-admin-methods",
+        "Answer `proxy_methods` and `proxy_cacheStats` describing the configured methods, their access policies and the cache content. The methods are served to every client allowed by permissioning, so deny them there on public instances.",
+        "false",
+        |value: String| {
+            value
+                .parse()
+                .map(Param::AdminMethods)
+                .map_err(|e| format!("Invalid value of proxy-admin-methods {}: {:?}", value, e))
+        },
+    )]
+}
+
+/// Answers `proxy_methods` and `proxy_cacheStats` calls, passes all other calls through.
+#[derive(Debug, Clone)]
+pub struct Middleware {
+    cache: simple_cache::Middleware,
+    permissioning: permissioning::Middleware,
+    admin_methods: bool,
+}
+
+impl Middleware {
+    /// Creates new middleware describing methods of given cache and permissioning middlewares.
+    pub fn new(cache: simple_cache::Middleware, permissioning: permissioning::Middleware, params: &[Param]) -> Self {
+        let mut admin_methods = false;
+        for p in params {
+            match p {
+                Param::AdminMethods(enabled) => admin_methods = *enabled,
+            }
+        }
+
+        Middleware {
+            cache,
+            permissioning,
+            admin_methods,
+        }
+    }
+
+    /// Returns all methods configured in the cache or permissioning, ordered by name.
+    pub fn methods(&self) -> rpc::Value {
+        let cached = self.cache.methods();
+        let names = cached
+            .iter()
+            .map(|method| method.name.as_str())
+            .chain(self.permissioning.methods())
+            .collect::<BTreeSet<_>>();
+
+        rpc::Value::Array(
+            names
+                .into_iter()
+                .map(|name| {
+                    let cached = cached.iter().find(|method| method.name == name);
+                    serde_json::json!({
+                        "method": name,
+                        "cached": cached.is_some(),
+                        "eviction": cached.map(|method| method.eviction.to_string()),
+                        "access": self.permissioning.access(name).to_string(),
+                        "cache_hits": cached.map_or(0, |method| method.hits),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns cacheable methods and all cached results that are still valid.
+    pub fn cache_stats(&self) -> rpc::Value {
+        let entries = self
+            .cache
+            .active_cache_entries()
+            .into_iter()
+            .map(|(method, ttl_ms)| serde_json::json!({ "method": method, "ttl_ms": ttl_ms }))
+            .collect::<Vec<_>>();
+
+        serde_json::json!({
+            "methods": self.cache.cached_methods(),
+            "entries": entries,
+        })
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = future::Ready<Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        match call {
+            rpc::Call::MethodCall(ref request) if self.admin_methods && request.method == METHODS_METHOD => {
+                let output = rpc::Output::from(Ok(self.methods()), request.id.clone(), request.jsonrpc);
+                Either::Left(future::ready(Some(output)))
+            }
+            rpc::Call::MethodCall(ref request) if self.admin_methods && request.method == CACHE_STATS_METHOD => {
+                let output = rpc::Output::from(Ok(self.cache_stats()), request.id.clone(), request.jsonrpc);
+                Either::Left(future::ready(Some(output)))
+            }
+            _ => Either::Right(next(call, meta)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn method_call(name: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    fn call<T: rpc::Middleware<()>>(middleware: &T, name: &str) -> Option<rpc::Output> {
+        let next = |_, _| future::ready(Some(rpc::Output::from(Ok(rpc::Value::Null), rpc::Id::Num(1), None)));
+        rpc::futures::executor::block_on(middleware.on_call(method_call(name), (), next))
+    }
+
+    fn middleware() -> Middleware {
+        middleware_with(&[Param::AdminMethods(true)])
+    }
+
+    fn middleware_with(params: &[Param]) -> Middleware {
+        let cache = simple_cache::config::Cache {
+            enabled: true,
+            methods: vec![
+                simple_cache::Method::new("eth_call", simple_cache::CacheEviction::Time(Duration::from_secs(30))),
+                simple_cache::Method::new("eth_chainId", simple_cache::CacheEviction::Block),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        };
+        let permissioning = permissioning::Permissioning {
+            policy: permissioning::Access::Allow,
+            methods: vec![permissioning::Method {
+                name: "eth_sendTransaction".into(),
+                name_pattern: permissioning::MatchMode::Exact,
+                policy: permissioning::Access::Deny,
+                allowed_transports: None,
+            }],
+            deny_notifications: false,
+        };
+
+        Middleware::new(
+            simple_cache::Middleware::new(&[simple_cache::config::Param::Config(cache)]),
+            permissioning::Middleware::new(&[permissioning::config::Param::Config(permissioning)]),
+            params,
+        )
+    }
+
+    #[test]
+    fn should_list_all_configured_methods() {
+        // given
+        let middleware = middleware();
+        call(&middleware.cache, "eth_call");
+        call(&middleware.cache, "eth_call");
+
+        // when
+        let methods = match call(&middleware, METHODS_METHOD) {
+            Some(rpc::Output::Success(success)) => success.result,
+            other => panic!("Unexpected output: {:?}", other),
+        };
+
+        // then
+        assert_eq!(
+            methods,
+            serde_json::json!([{
+                "method": "eth_call",
+                "cached": true,
+                "eviction": "30s",
+                "access": "Allow",
+                "cache_hits": 1,
+            }, {
+                "method": "eth_chainId",
+                "cached": true,
+                "eviction": "block",
+                "access": "Allow",
+                "cache_hits": 0,
+            }, {
+                "method": "eth_sendTransaction",
+                "cached": false,
+                "eviction": null,
+                "access": "Deny",
+                "cache_hits": 0,
+            }])
+        );
+    }
+
+    #[test]
+    fn should_not_answer_admin_methods_by_default() {
+        // given
+        let middleware = middleware_with(&[]);
+
+        // when
+        let methods = call(&middleware, METHODS_METHOD);
+        let stats = call(&middleware, CACHE_STATS_METHOD);
+
+        // then
+        let next = Some(rpc::Output::from(Ok(rpc::Value::Null), rpc::Id::Num(1), None));
+        assert_eq!(methods, next);
+        assert_eq!(stats, next);
+    }
+
+    #[test]
+    fn should_describe_cache_content() {
+        // given
+        let middleware = middleware();
+        call(&middleware.cache, "eth_call");
+        call(&middleware.cache, "eth_chainId");
+
+        // when
+        let stats = match call(&middleware, CACHE_STATS_METHOD) {
+            Some(rpc::Output::Success(success)) => success.result,
+            other => panic!("Unexpected output: {:?}", other),
+        };
+
+        // then
+        assert_eq!(stats["methods"], serde_json::json!(["eth_call", "eth_chainId"]));
+        let entries = stats["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["method"], "eth_call");
+        assert!(entries[0]["ttl_ms"].as_u64().unwrap() <= 30_000);
+    }
+
+    #[test]
+    fn should_pass_other_calls_through() {
+        // given
+        let middleware = middleware();
+
+        // when
+        let output = call(&middleware, "eth_call");
+
+        // then
+        assert_eq!(
+            output,
+            Some(rpc::Output::from(Ok(rpc::Value::Null), rpc::Id::Num(1), None))
+        );
+    }
+}
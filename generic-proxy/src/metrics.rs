@@ -0,0 +1,94 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Counts handled calls and errors exposed on the HTTP metrics page.
+
+use crate::rpc;
+use rpc::futures::{future::Either, Future, FutureExt};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Updates `requests_total` and `errors_total` counters of `transports::http::Metrics`.
+#[derive(Debug, Clone)]
+pub struct Middleware {
+    requests: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+}
+
+impl Middleware {
+    /// Creates new middleware updating given counters.
+    pub fn new(metrics: &transports::http::Metrics) -> Self {
+        Middleware {
+            requests: metrics.requests_total.clone(),
+            errors: metrics.errors_total.clone(),
+        }
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = rpc::middleware::NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        let errors = self.errors.clone();
+        Either::Left(Box::pin(next(call, meta).map(move |output| {
+            if let Some(rpc::Output::Failure(_)) = output {
+                errors.fetch_add(1, Ordering::Relaxed);
+            }
+            output
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call<T: rpc::Middleware<()>>(middleware: &T, output: Result<rpc::Value, rpc::Error>) {
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: "eth_chainId".into(),
+            params: rpc::Params::None,
+        });
+        let next =
+            move |_, _| rpc::futures::future::ready(Some(rpc::Output::from(output.clone(), rpc::Id::Num(1), None)));
+        rpc::futures::executor::block_on(middleware.on_call(call, (), next));
+    }
+
+    #[test]
+    fn should_count_requests_and_errors() {
+        // given
+        let metrics = transports::http::Metrics::default();
+        let middleware = Middleware::new(&metrics);
+
+        // when
+        call(&middleware, Ok(rpc::Value::Null));
+        call(&middleware, Err(rpc::Error::internal_error()));
+
+        // then
+        assert_eq!(metrics.requests_total.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.errors_total.load(Ordering::Relaxed), 1);
+    }
+}
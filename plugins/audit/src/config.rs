@@ -0,0 +1,84 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! CLI configuration for audit logging.
+
+use std::path::PathBuf;
+
+/// Methods whose params are not recorded by default.
+pub const DEFAULT_REDACTED_METHODS: &str = "personal_*,*_sign*";
+
+/// A configuration option to apply.
+pub enum Param {
+    /// Path of the audit log file.
+    Path(Option<PathBuf>),
+    /// Answer `proxy_verifyAuditLog` calls.
+    VerifyMethod(bool),
+    /// Methods (with optional `*` wildcard at either end) whose params are not recorded.
+    RedactedMethods(Vec<String>),
+}
+
+/// Parses a comma-separated list of method patterns.
+pub fn parse_methods(val: &str) -> Vec<String> {
+    if val == "-" {
+        return Default::default();
+    }
+
+    val.split(',')
+        .map(str::trim)
+        .filter(|method| !method.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "Audit",
+            "audit-log",
+            "A path to an append-only audit log file. Every call is recorded as a hash-chained JSON line. Use - to disable.",
+            "-",
+            |path: String| {
+                if path == "-" {
+                    return Ok(Param::Path(None));
+                }
+                Ok(Param::Path(Some(path.into())))
+            },
+        ),
+        cli_params::Param::new(
+            "Audit",
+            "audit-verify-method",
+            "Answer `proxy_verifyAuditLog` calls verifying the hash chain of the audit log. The method is available to all clients (before permissioning is applied), so enable it on private instances only.",
+            "false",
+            |value: String| {
+                value
+                    .parse()
+                    .map(Param::VerifyMethod)
+                    .map_err(|e| format!("Invalid value of audit-verify-method {}: {:?}", value, e))
+            },
+        )
+        .hidden(),
+        cli_params::Param::new(
+            "Audit",
+            "audit-redacted-methods",
+            "Comma-separated list of methods whose params are replaced with \"redacted\" in the audit log (e.g. to keep passwords and signed payloads out of it). A `*` at the beginning or end of a name matches any characters. Use - to record params of all methods.",
+            DEFAULT_REDACTED_METHODS,
+            |value: String| Ok(Param::RedactedMethods(parse_methods(&value))),
+        ),
+    ]
+}
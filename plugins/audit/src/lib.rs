@@ -0,0 +1,523 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Append-only audit log.
+//!
+//! Records every call as a newline-delimited JSON record. Each record contains the hash
+//! of the previous one, forming a BLAKE3 hash chain that can be verified
+//! with `proxy_verifyAuditLog` method (if enabled) to detect tampering.
+//!
+//! The records are written by a dedicated thread, so that file I/O never blocks the calls.
+//! Params of methods carrying secrets (see `config::DEFAULT_REDACTED_METHODS`) are not recorded.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use jsonrpc_core as rpc;
+use rpc::futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Either},
+    Future, FutureExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub mod config;
+
+/// Method verifying the hash chain of an audit log.
+pub const VERIFY_METHOD: &str = "proxy_verifyAuditLog";
+
+/// Params recorded instead of the params of redacted methods.
+pub const REDACTED: &str = "redacted";
+
+/// Previous hash of the first record in the log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A single entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    /// Milliseconds since UNIX epoch.
+    pub timestamp: u64,
+    /// Called method.
+    pub method: String,
+    /// Parameters of the call.
+    pub params: rpc::Value,
+    /// Hash of the previous record.
+    pub prev_hash: String,
+    /// Hash of this record.
+    pub hash: String,
+}
+
+impl Record {
+    fn new(timestamp: u64, method: String, params: rpc::Value, prev_hash: String) -> Self {
+        let hash = hash(&prev_hash, timestamp, &method, &params);
+        Record {
+            timestamp,
+            method,
+            params,
+            prev_hash,
+            hash,
+        }
+    }
+}
+
+/// Computes the hash of a record chained to `prev_hash`.
+fn hash(prev_hash: &str, timestamp: u64, method: &str, params: &rpc::Value) -> String {
+    let content = serde_json::to_vec(&(timestamp, method, params)).expect("Serialization of values is infallible; qed");
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(&content);
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Verifies the hash chain of the audit log at given path.
+///
+/// Returns the number of records or a description of the first invalid record.
+pub fn verify(path: &Path) -> Result<usize, String> {
+    let file = fs::File::open(path).map_err(|e| format!("Can't open audit log at {}: {}", path.display(), e))?;
+    let mut prev_hash = GENESIS_HASH.to_owned();
+    let mut count = 0;
+    for (idx, line) in io::BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Can't read audit log: {}", e))?;
+        let record: Record =
+            serde_json::from_str(&line).map_err(|e| format!("Invalid record at line {}: {}", idx + 1, e))?;
+        if record.prev_hash != prev_hash {
+            return Err(format!("Broken chain at line {}", idx + 1));
+        }
+        if record.hash != hash(&record.prev_hash, record.timestamp, &record.method, &record.params) {
+            return Err(format!("Invalid hash at line {}", idx + 1));
+        }
+        prev_hash = record.hash;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Requests handled by the writer thread.
+#[derive(Debug)]
+enum Command {
+    /// Append a record of given method call.
+    Append(String, rpc::Value),
+    /// Verify the log once all previously requested records are written.
+    Verify(oneshot::Sender<Result<usize, String>>),
+}
+
+/// Appends records to the audit log file.
+#[derive(Debug)]
+struct Writer {
+    file: fs::File,
+    last_hash: String,
+}
+
+impl Writer {
+    /// Opens the log in append-only mode, continuing the chain of existing records.
+    fn open(path: &Path) -> io::Result<Self> {
+        let last_hash = match fs::File::open(path) {
+            Ok(file) => match io::BufReader::new(file).lines().last() {
+                Some(line) => {
+                    let record: Record =
+                        serde_json::from_str(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                    record.hash
+                }
+                None => GENESIS_HASH.to_owned(),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => GENESIS_HASH.to_owned(),
+            Err(e) => return Err(e),
+        };
+        let file = fs::OpenOptions::new().append(true).create(true).open(path)?;
+
+        Ok(Writer { file, last_hash })
+    }
+
+    fn append(&mut self, method: &str, params: rpc::Value) -> io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let record = Record::new(timestamp, method.to_owned(), params, self.last_hash.clone());
+        let mut line = serde_json::to_vec(&record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.last_hash = record.hash;
+        Ok(())
+    }
+
+    /// Handles the commands until all senders are dropped.
+    fn run(mut self, path: PathBuf, commands: mpsc::Receiver<Command>) {
+        for command in commands {
+            match command {
+                Command::Append(method, params) => {
+                    if let Err(e) = self.append(&method, params) {
+                        log::error!("Unable to write audit log record: {:?}", e);
+                    }
+                }
+                Command::Verify(result) => {
+                    let _ = result.send(verify(&path));
+                }
+            }
+        }
+    }
+}
+
+/// Returns `true` if the method matches given pattern with an optional `*` wildcard at either end.
+fn matches(pattern: &str, method: &str) -> bool {
+    let (any_prefix, pattern) = match pattern.strip_prefix('*') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+    let (any_suffix, pattern) = match pattern.strip_suffix('*') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+
+    match (any_prefix, any_suffix) {
+        (true, true) => method.contains(pattern),
+        (true, false) => method.ends_with(pattern),
+        (false, true) => method.starts_with(pattern),
+        (false, false) => method == pattern,
+    }
+}
+
+/// Middleware recording every call in the audit log.
+///
+/// Does nothing if the log is not configured.
+#[derive(Debug, Clone, Default)]
+pub struct AuditMiddleware {
+    verify_method: bool,
+    redacted_methods: Vec<String>,
+    writer: Option<mpsc::Sender<Command>>,
+}
+
+impl AuditMiddleware {
+    /// Creates new audit middleware opening the configured log.
+    pub fn new(params: &[config::Param]) -> Result<Self, String> {
+        let mut path = None;
+        let mut verify_method = false;
+        let mut redacted_methods = config::parse_methods(config::DEFAULT_REDACTED_METHODS);
+        for p in params {
+            match p {
+                config::Param::Path(p) => path = p.clone(),
+                config::Param::VerifyMethod(enabled) => verify_method = *enabled,
+                config::Param::RedactedMethods(methods) => redacted_methods = methods.clone(),
+            }
+        }
+
+        let writer = match path {
+            Some(path) => {
+                let writer =
+                    Writer::open(&path).map_err(|e| format!("Can't open audit log at {}: {}", path.display(), e))?;
+                let (sender, receiver) = mpsc::channel();
+                thread::Builder::new()
+                    .name("audit-log".into())
+                    .spawn(move || writer.run(path, receiver))
+                    .map_err(|e| format!("Can't spawn audit log writer: {}", e))?;
+                Some(sender)
+            }
+            None => None,
+        };
+
+        Ok(AuditMiddleware {
+            verify_method,
+            redacted_methods,
+            writer,
+        })
+    }
+
+    /// Appends a record of given call to the log (the params of redacted methods are not recorded).
+    pub fn record(&self, method: &str, params: rpc::Value) {
+        if let Some(ref writer) = self.writer {
+            let params = if self.redacted_methods.iter().any(|pattern| matches(pattern, method)) {
+                REDACTED.into()
+            } else {
+                params
+            };
+            if writer.send(Command::Append(method.to_owned(), params)).is_err() {
+                log::error!("Unable to write audit log record: the writer is gone.");
+            }
+        }
+    }
+
+    /// Verifies the configured log once all previous records are written.
+    fn verify(&self, params: rpc::Params) -> BoxFuture<'static, rpc::Result<rpc::Value>> {
+        if let Err(e) = params.expect_no_params() {
+            return Box::pin(future::ready(Err(e)));
+        }
+        let (sender, receiver) = oneshot::channel();
+        self.writer
+            .as_ref()
+            .expect("Verification is only handled with the log configured; qed")
+            .send(Command::Verify(sender))
+            .ok();
+
+        Box::pin(receiver.map(|result| match result {
+            Ok(Ok(records)) => Ok(serde_json::json!({ "valid": true, "records": records })),
+            Ok(Err(error)) => Ok(serde_json::json!({ "valid": false, "error": error })),
+            Err(oneshot::Canceled) => Err(rpc::Error::internal_error()),
+        }))
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for AuditMiddleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = BoxFuture<'static, Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        if self.writer.is_none() {
+            return Either::Right(next(call, meta));
+        }
+
+        match call {
+            rpc::Call::MethodCall(ref request) => {
+                self.record(&request.method, params_value(&request.params));
+                if self.verify_method && request.method == VERIFY_METHOD {
+                    let (id, jsonrpc) = (request.id.clone(), request.jsonrpc);
+                    let output = self
+                        .verify(request.params.clone())
+                        .map(move |result| Some(rpc::Output::from(result, id, jsonrpc)));
+                    return Either::Left(Box::pin(output));
+                }
+            }
+            rpc::Call::Notification(ref notification) => {
+                self.record(&notification.method, params_value(&notification.params));
+            }
+            rpc::Call::Invalid { .. } => {}
+        }
+
+        Either::Right(next(call, meta))
+    }
+}
+
+fn params_value(params: &rpc::Params) -> rpc::Value {
+    serde_json::to_value(params).expect("Serialization of params is infallible; qed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware as MiddlewareTrait;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("audit-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn method_call(name: &str, params: rpc::Params) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params,
+        })
+    }
+
+    fn call(middleware: &AuditMiddleware, call: rpc::Call) -> Option<rpc::Output> {
+        let next = |_, _| future::ready(None);
+        rpc::futures::executor::block_on(middleware.on_call(call, (), next))
+    }
+
+    /// Waits until all recorded calls are written.
+    fn flush(middleware: &AuditMiddleware) {
+        let (sender, receiver) = oneshot::channel();
+        middleware
+            .writer
+            .as_ref()
+            .unwrap()
+            .send(Command::Verify(sender))
+            .unwrap();
+        let _ = rpc::futures::executor::block_on(receiver).unwrap();
+    }
+
+    fn records(path: &Path) -> Vec<Record> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn should_detect_tampering_with_the_log() {
+        // given
+        let path = temp_path("tampering");
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+        for i in 0..100 {
+            let params = rpc::Params::Array(vec![i.into()]);
+            call(&middleware, method_call("eth_getBlockByNumber", params));
+        }
+        flush(&middleware);
+        assert_eq!(verify(&path), Ok(100));
+
+        // when
+        let content = fs::read_to_string(&path).unwrap();
+        let tampered = content.replacen("[42]", "[43]", 1);
+        assert_ne!(content, tampered);
+        fs::write(&path, tampered).unwrap();
+
+        // then
+        assert_eq!(verify(&path), Err("Invalid hash at line 43".into()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_detect_removed_records() {
+        // given
+        let path = temp_path("removed");
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+        for _ in 0..3 {
+            call(&middleware, method_call("eth_blockNumber", rpc::Params::None));
+        }
+        flush(&middleware);
+
+        // when
+        let content = fs::read_to_string(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+
+        // then
+        assert_eq!(verify(&path), Err("Broken chain at line 2".into()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_continue_existing_chain() {
+        // given
+        let path = temp_path("continue");
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+        call(&middleware, method_call("eth_blockNumber", rpc::Params::None));
+        flush(&middleware);
+        drop(middleware);
+
+        // when
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+        call(&middleware, method_call("eth_blockNumber", rpc::Params::None));
+        flush(&middleware);
+
+        // then
+        assert_eq!(verify(&path), Ok(2));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_verify_log_via_rpc() {
+        // given
+        let path = temp_path("rpc");
+        let middleware = AuditMiddleware::new(&[
+            config::Param::Path(Some(path.clone())),
+            config::Param::VerifyMethod(true),
+        ])
+        .unwrap();
+        call(&middleware, method_call("eth_blockNumber", rpc::Params::None));
+
+        // when
+        let output = call(&middleware, method_call(VERIFY_METHOD, rpc::Params::None));
+        let with_path = call(
+            &middleware,
+            method_call(VERIFY_METHOD, rpc::Params::Array(vec!["/dev/zero".into()])),
+        );
+
+        // then
+        assert_eq!(
+            output,
+            Some(rpc::Output::from(
+                Ok(serde_json::json!({ "valid": true, "records": 2 })),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2),
+            ))
+        );
+        match with_path {
+            Some(rpc::Output::Failure(failure)) => assert_eq!(failure.error.code, rpc::ErrorCode::InvalidParams),
+            other => panic!("Expected invalid params error, got: {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_redact_params_of_signing_methods() {
+        // given
+        let path = temp_path("redacted");
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+        let secret = || rpc::Params::Array(vec!["0x1".into(), "secret".into()]);
+
+        // when
+        call(&middleware, method_call("personal_unlockAccount", secret()));
+        call(&middleware, method_call("eth_signTransaction", secret()));
+        call(&middleware, method_call("eth_getBalance", secret()));
+        flush(&middleware);
+
+        // then
+        let params = records(&path).into_iter().map(|r| r.params).collect::<Vec<_>>();
+        assert_eq!(
+            params,
+            vec![
+                rpc::Value::from(REDACTED),
+                rpc::Value::from(REDACTED),
+                serde_json::json!(["0x1", "secret"]),
+            ]
+        );
+        assert_eq!(verify(&path), Ok(3));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_record_all_params_if_redaction_is_disabled() {
+        // given
+        let path = temp_path("not-redacted");
+        let middleware = AuditMiddleware::new(&[
+            config::Param::Path(Some(path.clone())),
+            config::Param::RedactedMethods(config::parse_methods("-")),
+        ])
+        .unwrap();
+
+        // when
+        call(
+            &middleware,
+            method_call("personal_sign", rpc::Params::Array(vec!["0x1".into()])),
+        );
+        flush(&middleware);
+
+        // then
+        assert_eq!(records(&path)[0].params, serde_json::json!(["0x1"]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_forward_verify_method_by_default() {
+        // given
+        let path = temp_path("verify-disabled");
+        let middleware = AuditMiddleware::new(&[config::Param::Path(Some(path.clone()))]).unwrap();
+
+        // when
+        let output = call(&middleware, method_call(VERIFY_METHOD, rpc::Params::None));
+
+        // then
+        assert_eq!(output, None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! HTTP upstream configuration parameters.
+
+use std::{collections::HashMap, fs, time::Duration};
+
+/// Configuration options of the HTTP upstream
+pub enum Param {
+    /// Upstream URL
+    Url(hyper::Uri),
+    /// How long idle connections are kept in the pool.
+    PoolIdleTimeout(Duration),
+    /// Maximal number of idle connections kept in the pool.
+    PoolMaxIdle(usize),
+    /// Additional headers sent with every request.
+    Headers(HashMap<String, String>),
+}
+
+/// Parses a single `name:value` header and validates it.
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let mut parts = header.splitn(2, ':');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts
+        .next()
+        .ok_or_else(|| format!("Invalid header {:?}: expected `name:value`", header))?
+        .trim();
+
+    hyper::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|e| format!("Invalid header name {:?}: {:?}", name, e))?;
+    hyper::header::HeaderValue::from_str(value).map_err(|e| format!("Invalid value of header {}: {:?}", name, e))?;
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Parses comma-separated list of `name:value` headers.
+fn parse_headers(val: &str) -> Result<HashMap<String, String>, String> {
+    if val == "-" {
+        return Ok(Default::default());
+    }
+
+    val.split(',').map(parse_header).collect()
+}
+
+/// Reads `name:value` headers from a file, one per line.
+///
+/// Empty lines and lines starting with `#` are ignored.
+fn read_headers(path: &str) -> Result<HashMap<String, String>, String> {
+    if path == "-" {
+        return Ok(Default::default());
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| format!("Can't read headers file at {}: {:?}", path, e))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_header)
+        .collect()
+}
+
+/// Returns all configuration parameters for HTTP upstream.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "HTTP upstream",
+            "upstream-http",
+            "Address of the parent HTTP RPC server that we should connect to.",
+            "http://127.0.0.1:8545",
+            move |val: String| {
+                let url = val.parse().map_err(|e| format!("Invalid upstream address: {:?}", e))?;
+                Ok(Param::Url(url))
+            },
+        ),
+        cli_params::Param::new(
+            "HTTP upstream",
+            "upstream-http-pool-idle-timeout",
+            "Number of seconds idle upstream connections are kept open.",
+            "90",
+            move |val: String| {
+                let secs = val.parse().map_err(|e| format!("Invalid pool idle timeout: {:?}", e))?;
+                Ok(Param::PoolIdleTimeout(Duration::from_secs(secs)))
+            },
+        ),
+        cli_params::Param::new(
+            "HTTP upstream",
+            "upstream-http-pool-max-idle",
+            "Maximal number of idle upstream connections kept open. Use 0 to open a new connection for every request.",
+            "16",
+            move |val: String| {
+                let max = val.parse().map_err(|e| format!("Invalid pool max idle: {:?}", e))?;
+                Ok(Param::PoolMaxIdle(max))
+            },
+        ),
+        cli_params::Param::new(
+            "HTTP upstream",
+            "upstream-http-headers",
+            "Comma-separated list of `name:value` headers sent with every upstream request, e.g. `X-API-Key:abc`. Use - to send no additional headers.",
+            "-",
+            move |val: String| Ok(Param::Headers(parse_headers(&val)?)),
+        ),
+        cli_params::Param::new(
+            "HTTP upstream",
+            "upstream-http-headers-file",
+            "A path to a file containing `name:value` headers (one per line) sent with every upstream request. Prefer it over `--upstream-http-headers` for secrets.",
+            "-",
+            move |val: String| Ok(Param::Headers(read_headers(&val)?)),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_headers() {
+        let headers = parse_headers("X-API-Key:abc, Authorization: Bearer x:y").unwrap();
+
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["X-API-Key"], "abc");
+        assert_eq!(headers["Authorization"], "Bearer x:y");
+        assert_eq!(parse_headers("-"), Ok(Default::default()));
+    }
+
+    #[test]
+    fn should_reject_invalid_headers() {
+        assert!(parse_headers("X-API-Key").is_err());
+        assert!(parse_headers("X API Key:abc").is_err());
+        assert!(parse_headers("X-API-Key:a\nb").is_err());
+    }
+
+    #[test]
+    fn should_read_headers_from_file() {
+        // given
+        let path = std::env::temp_dir().join(format!("http-upstream-headers-{}", std::process::id()));
+        fs::write(&path, "# secrets\nX-API-Key: abc\n\nAuthorization: Bearer xyz\n").unwrap();
+
+        // when
+        let headers = read_headers(&path.display().to_string()).unwrap();
+
+        // then
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers["X-API-Key"], "abc");
+        assert_eq!(headers["Authorization"], "Bearer xyz");
+        fs::remove_file(path).unwrap();
+    }
+}
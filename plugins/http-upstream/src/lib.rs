@@ -0,0 +1,396 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! HTTP Upstream Transport
+//!
+//! Sends every call as a separate HTTP POST request, re-using connections from a keep-alive pool.
+//! Subscriptions are not supported.
+
+#![warn(missing_docs)]
+
+pub mod config;
+
+use jsonrpc_core::futures::FutureExt;
+use std::{sync::Arc, time::Duration};
+use upstream::{helpers, Subscription};
+
+/// Default time idle connections are kept in the pool.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Default maximal number of idle connections kept in the pool.
+pub const DEFAULT_POOL_MAX_IDLE: usize = 16;
+
+/// HTTP transport with connection pooling.
+#[derive(Debug, Clone)]
+pub struct HttpPoolTransport {
+    url: hyper::Uri,
+    headers: hyper::HeaderMap,
+    client: hyper::Client<hyper::client::HttpConnector>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl HttpPoolTransport {
+    /// Create new HTTP transport within existing runtime.
+    pub fn new(params: Vec<config::Param>) -> Result<Self, String> {
+        let mut url = "http://127.0.0.1:8545".parse().expect("Valid address given.");
+        let mut pool_idle_timeout = DEFAULT_POOL_IDLE_TIMEOUT;
+        let mut pool_max_idle = DEFAULT_POOL_MAX_IDLE;
+        let mut headers = hyper::HeaderMap::new();
+
+        for p in params {
+            match p {
+                config::Param::Url(new_url) => url = new_url,
+                config::Param::PoolIdleTimeout(timeout) => pool_idle_timeout = timeout,
+                config::Param::PoolMaxIdle(max) => pool_max_idle = max,
+                config::Param::Headers(new_headers) => {
+                    for (name, value) in new_headers {
+                        let name = hyper::header::HeaderName::from_bytes(name.as_bytes())
+                            .map_err(|e| format!("Invalid header name {:?}: {:?}", name, e))?;
+                        let value = hyper::header::HeaderValue::from_str(&value)
+                            .map_err(|e| format!("Invalid value of header {}: {:?}", name, e))?;
+                        headers.insert(name, value);
+                    }
+                }
+            }
+        }
+
+        println!("[HTTP] Sending requests to: {:?}", url);
+
+        let runtime = tokio::runtime::Handle::try_current().map_err(|e| format!("{:?}", e))?;
+        let client = hyper::Client::builder()
+            .pool_idle_timeout(pool_idle_timeout)
+            .pool_max_idle_per_host(pool_max_idle)
+            .build_http();
+
+        Ok(Self {
+            url,
+            headers,
+            client,
+            runtime,
+        })
+    }
+}
+
+impl upstream::Transport for HttpPoolTransport {
+    type Error = String;
+    type Future = upstream::BoxFuture;
+
+    fn send(&self, call: jsonrpc_core::Call) -> Self::Future {
+        self.send_with_headers(call, vec![])
+    }
+
+    fn send_with_headers(&self, call: jsonrpc_core::Call, headers: Vec<(String, String)>) -> Self::Future {
+        log::trace!("Calling: {}", helpers::format_call(&call));
+
+        let is_notification = helpers::get_id(&call).is_none();
+        let body = jsonrpc_core::types::to_string(&call).expect("jsonrpc-core are infallible");
+        let mut request = hyper::Request::post(&self.url).header(hyper::header::CONTENT_TYPE, "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        for (name, value) in &headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        let request = match request.body(hyper::Body::from(body)) {
+            Ok(request) => request,
+            Err(e) => return Box::new(jsonrpc_core::futures::future::err(format!("Invalid request: {:?}", e))),
+        };
+
+        let client = self.client.clone();
+        let response = self.runtime.spawn(async move {
+            let response = client
+                .request(request)
+                .await
+                .map_err(|e| format!("Error sending request: {:?}", e))?;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body())
+                .await
+                .map_err(|e| format!("Error reading response: {:?}", e))?;
+
+            if is_notification {
+                return Ok(None);
+            }
+
+            serde_json::from_slice(&body)
+                .map(Some)
+                .map_err(|e| format!("Invalid response (status: {}): {:?}", status, e))
+        });
+
+        Box::new(response.map(|res| res.map_err(|e| format!("{:?}", e)).and_then(|res| res)))
+    }
+
+    fn subscribe(
+        &self,
+        call: jsonrpc_core::Call,
+        _session: Option<Arc<jsonrpc_pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        log::warn!("Subscriptions are not supported by HTTP upstream: {}", subscription);
+        Box::new(jsonrpc_core::futures::future::ok(Some(unsupported(&call))))
+    }
+
+    fn unsubscribe(
+        &self,
+        call: jsonrpc_core::Call,
+        _session: Option<Arc<jsonrpc_pubsub::Session>>,
+        _subscription: Subscription,
+    ) -> Self::Future {
+        Box::new(jsonrpc_core::futures::future::ok(Some(unsupported(&call))))
+    }
+}
+
+/// Returns an error response for Pub-Sub calls.
+fn unsupported(call: &jsonrpc_core::Call) -> jsonrpc_core::Output {
+    let error = jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::MethodNotFound,
+        message: "Subscriptions are not supported by HTTP upstream.".into(),
+        data: None,
+    };
+    let id = helpers::get_id(call).cloned().unwrap_or(jsonrpc_core::Id::Null);
+    jsonrpc_core::Output::from(Err(error), id, Some(jsonrpc_core::Version::V2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Instant,
+    };
+    use upstream::Transport;
+
+    /// Starts an upstream responding with the method name and the signature header (if any).
+    ///
+    /// Returns the address and the number of accepted connections.
+    fn upstream() -> (hyper::Uri, Arc<AtomicUsize>) {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let make_service = hyper::service::make_service_fn(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async {
+                Ok::<_, Infallible>(hyper::service::service_fn(
+                    |request: hyper::Request<hyper::Body>| async {
+                        let signature = request
+                            .headers()
+                            .get(upstream::signing::SIGNATURE_HEADER)
+                            .map(|v| v.to_str().unwrap().to_owned());
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let call: jsonrpc_core::MethodCall = serde_json::from_slice(&body).unwrap();
+                        let result = serde_json::json!({ "method": call.method, "signature": signature });
+                        let output = jsonrpc_core::Output::from(Ok(result), call.id, call.jsonrpc);
+                        Ok::<_, Infallible>(hyper::Response::new(hyper::Body::from(
+                            serde_json::to_vec(&output).unwrap(),
+                        )))
+                    },
+                ))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr()).parse().unwrap();
+        tokio::spawn(server);
+
+        (url, connections)
+    }
+
+    /// Starts an upstream that requires given `X-API-Key` header and responds with `401` without it.
+    fn authenticated_upstream(key: &'static str) -> hyper::Uri {
+        let make_service = hyper::service::make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                move |request: hyper::Request<hyper::Body>| async move {
+                    if request.headers().get("X-API-Key").map(|v| v.as_bytes()) != Some(key.as_bytes()) {
+                        let mut response = hyper::Response::new(hyper::Body::from("Unauthorized"));
+                        *response.status_mut() = hyper::StatusCode::UNAUTHORIZED;
+                        return Ok::<_, Infallible>(response);
+                    }
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    let call: jsonrpc_core::MethodCall = serde_json::from_slice(&body).unwrap();
+                    let output = jsonrpc_core::Output::from(Ok(call.method.into()), call.id, call.jsonrpc);
+                    Ok::<_, Infallible>(hyper::Response::new(hyper::Body::from(
+                        serde_json::to_vec(&output).unwrap(),
+                    )))
+                },
+            ))
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}", server.local_addr()).parse().unwrap();
+        tokio::spawn(server);
+
+        url
+    }
+
+    fn call(method: &str) -> jsonrpc_core::Call {
+        jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+            jsonrpc: Some(jsonrpc_core::Version::V2),
+            id: jsonrpc_core::Id::Num(1),
+            method: method.into(),
+            params: jsonrpc_core::Params::None,
+        })
+    }
+
+    fn result(output: Result<Option<jsonrpc_core::Output>, String>) -> jsonrpc_core::Value {
+        match output {
+            Ok(Some(jsonrpc_core::Output::Success(success))) => success.result,
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    async fn send_sequential(transport: &HttpPoolTransport, count: usize) {
+        for _ in 0..count {
+            result(transport.send(call("eth_blockNumber")).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_send_calls_to_http_upstream() {
+        // given
+        let (url, _) = upstream();
+        let transport = HttpPoolTransport::new(vec![config::Param::Url(url)]).unwrap();
+
+        // when
+        let res = transport.send(call("eth_blockNumber")).await;
+
+        // then
+        assert_eq!(
+            result(res),
+            serde_json::json!({ "method": "eth_blockNumber", "signature": null })
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pass_signature_header() {
+        // given
+        let (url, _) = upstream();
+        let transport = upstream::signing::HmacSigningTransport::new(
+            HttpPoolTransport::new(vec![config::Param::Url(url)]).unwrap(),
+            b"secret".to_vec(),
+        );
+        let body = jsonrpc_core::types::to_string(&call("eth_blockNumber")).unwrap();
+
+        // when
+        let res = transport.send(call("eth_blockNumber")).await;
+
+        // then
+        assert_eq!(
+            result(res)["signature"],
+            upstream::signing::sign(b"secret", body.as_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_configured_headers() {
+        // given
+        let url = authenticated_upstream("secret");
+        let headers = vec![("X-API-Key".to_owned(), "secret".to_owned())]
+            .into_iter()
+            .collect();
+        let transport = HttpPoolTransport::new(vec![config::Param::Url(url), config::Param::Headers(headers)]).unwrap();
+
+        // when
+        let res = transport.send(call("eth_blockNumber")).await;
+
+        // then
+        assert_eq!(result(res), serde_json::json!("eth_blockNumber"));
+    }
+
+    #[tokio::test]
+    async fn should_fail_without_required_headers() {
+        // given
+        let url = authenticated_upstream("secret");
+        let transport = HttpPoolTransport::new(vec![config::Param::Url(url)]).unwrap();
+
+        // when
+        let res = transport.send(call("eth_blockNumber")).await;
+
+        // then
+        match res {
+            Err(e) => assert!(e.contains("401"), "Unexpected error: {}", e),
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reuse_pooled_connections() {
+        // given
+        let (url, connections) = upstream();
+        let transport = HttpPoolTransport::new(vec![config::Param::Url(url)]).unwrap();
+
+        // when
+        send_sequential(&transport, 10).await;
+
+        // then
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_not_reuse_connections_if_pool_is_disabled() {
+        // given
+        let (url, connections) = upstream();
+        let transport = HttpPoolTransport::new(vec![config::Param::Url(url), config::Param::PoolMaxIdle(0)]).unwrap();
+
+        // when
+        send_sequential(&transport, 10).await;
+
+        // then
+        assert_eq!(connections.load(Ordering::SeqCst), 10);
+    }
+
+    #[tokio::test]
+    async fn should_reject_subscriptions() {
+        // given
+        let transport = HttpPoolTransport::new(vec![]).unwrap();
+        let subscription = Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        };
+
+        // when
+        let res = transport.subscribe(call("eth_subscribe"), None, subscription).await;
+
+        // then
+        match res {
+            Ok(Some(jsonrpc_core::Output::Failure(failure))) => {
+                assert_eq!(failure.error.code, jsonrpc_core::ErrorCode::MethodNotFound);
+                assert_eq!(failure.id, jsonrpc_core::Id::Num(1));
+            }
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    /// Compares 100 sequential requests over a single-use connection and the pool.
+    ///
+    /// Run with `cargo test -p http-upstream -- --ignored --nocapture`.
+    #[ignore]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bench_single_connection_vs_pool() {
+        let (url, _) = upstream();
+        let single =
+            HttpPoolTransport::new(vec![config::Param::Url(url.clone()), config::Param::PoolMaxIdle(0)]).unwrap();
+        let pooled = HttpPoolTransport::new(vec![config::Param::Url(url)]).unwrap();
+
+        let start = Instant::now();
+        send_sequential(&single, 100).await;
+        let single_elapsed = start.elapsed();
+
+        let start = Instant::now();
+        send_sequential(&pooled, 100).await;
+        let pooled_elapsed = start.elapsed();
+
+        println!("100 sequential requests, single-use connections: {:?}", single_elapsed);
+        println!("100 sequential requests, pooled connections: {:?}", pooled_elapsed);
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! IPC upstream configuration parameters.
+
+use std::{num::NonZeroUsize, path::PathBuf, time::Duration};
+
+/// Configuration options of the IPC upstream
+pub enum Param {
+    /// Path to the upstream socket (see `parse_path`).
+    Path(PathBuf),
+    /// Maximal number of requests awaiting a response (unlimited if `None`).
+    PipelineDepth(Option<NonZeroUsize>),
+    /// Log bodies of requests sent to the upstream (see `upstream::config::log_requests`).
+    LogRequests(bool),
+    /// Log bodies of messages received from the upstream (see `upstream::config::log_responses`).
+    LogResponses(bool),
+    /// Maximal number of requests awaiting a response (see `upstream::config::max_pending`).
+    MaxPendingRequests(usize),
+    /// Maximal size of a message received from the upstream.
+    MaxResponseBytes(usize),
+    /// Delay before the first reconnection attempt.
+    ReconnectInitialDelay(Duration),
+    /// Maximal delay between reconnection attempts.
+    ReconnectMaxDelay(Duration),
+    /// Randomize delays between reconnection attempts.
+    ReconnectJitter(bool),
+}
+
+/// Returns all configuration parameters for IPC upstream.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "IPC upstream",
+            "upstream-ipc",
+            "Path to the IPC socket of the parent RPC server that we should connect to. On Linux paths starting with `@` refer to abstract sockets.",
+            "./jsonrpc.ipc",
+            move |val: String| Ok(Param::Path(parse_path(&val))),
+        ),
+        cli_params::Param::new(
+            "IPC upstream",
+            "upstream-ipc-pipeline-depth",
+            "Maximal number of requests sent to the IPC upstream without receiving a response. Further requests wait until a response arrives.",
+            "unlimited",
+            move |val: String| {
+                if val == "unlimited" {
+                    return Ok(Param::PipelineDepth(None));
+                }
+                val.parse()
+                    .map(|depth| Param::PipelineDepth(Some(depth)))
+                    .map_err(|e| format!("Invalid pipeline depth {}: {:?}", val, e))
+            },
+        ),
+        cli_params::Param::new(
+            "IPC upstream",
+            "upstream-ipc-reconnect-initial-delay",
+            "Delay before the first attempt to reconnect to the IPC upstream (e.g. `100ms`, `1s`). Doubled with every next attempt.",
+            "100ms",
+            move |val: String| upstream::config::parse_duration(&val).map(Param::ReconnectInitialDelay),
+        )
+        .hidden(),
+        cli_params::Param::new(
+            "IPC upstream",
+            "upstream-ipc-reconnect-max-delay",
+            "Maximal delay between attempts to reconnect to the IPC upstream (e.g. `30s`, `1m`).",
+            "30s",
+            move |val: String| upstream::config::parse_duration(&val).map(Param::ReconnectMaxDelay),
+        )
+        .hidden(),
+        cli_params::Param::new(
+            "IPC upstream",
+            "upstream-ipc-reconnect-jitter",
+            "Add a random delay of up to the initial delay to every attempt to reconnect to the IPC upstream.",
+            "true",
+            move |val: String| {
+                val.parse()
+                    .map(Param::ReconnectJitter)
+                    .map_err(|e| format!("Invalid value of upstream-ipc-reconnect-jitter {}: {:?}", val, e))
+            },
+        )
+        .hidden(),
+    ]
+}
+
+/// Parses the upstream socket path.
+///
+/// On Linux a path starting with `@` denotes an abstract socket and is converted
+/// to the null-byte-prefixed form (`@geth.ipc` -> `\0geth.ipc`).
+pub fn parse_path(val: &str) -> PathBuf {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(name) = val.strip_prefix('@') {
+            return format!("\0{}", name).into();
+        }
+    }
+
+    val.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn should_parse_abstract_socket_path() {
+        assert_eq!(parse_path("@geth.ipc"), PathBuf::from("\0geth.ipc"));
+        assert_eq!(parse_path("./geth.ipc"), PathBuf::from("./geth.ipc"));
+    }
+}
@@ -0,0 +1,797 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! IPC (Unix socket) Upstream Transport
+
+#![warn(missing_docs)]
+
+pub mod config;
+pub mod reconnect;
+
+use jsonrpc_core::futures::{
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+    Future, FutureExt, StreamExt, TryFutureExt,
+};
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Semaphore,
+};
+use upstream::{
+    helpers,
+    shared::{Detached, PendingGuard, PendingKind, Shared},
+    Subscription,
+};
+
+/// Default maximal size of a message received from the upstream (10 MB).
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Extracts complete JSON messages from the buffer, leaving incomplete data in place.
+fn split_messages(buffer: &mut Vec<u8>) -> Vec<String> {
+    let mut messages = vec![];
+    let mut consumed = 0;
+    {
+        let mut stream = serde_json::Deserializer::from_slice(buffer).into_iter::<serde::de::IgnoredAny>();
+        loop {
+            let start = consumed;
+            match stream.next() {
+                Some(Ok(_)) => {
+                    consumed = stream.byte_offset();
+                    let message = String::from_utf8_lossy(&buffer[start..consumed]);
+                    messages.push(message.trim().to_owned());
+                }
+                Some(Err(ref e)) if e.is_eof() => break,
+                Some(Err(e)) => {
+                    log::warn!("Invalid message received from IPC upstream: {:?}", e);
+                    consumed = buffer.len();
+                    break;
+                }
+                None => break,
+            }
+        }
+    }
+    buffer.drain(..consumed);
+    messages
+}
+
+/// Connects to the upstream socket.
+///
+/// On Linux null-byte-prefixed paths (see `config::parse_path`) refer to abstract sockets.
+async fn connect(path: &Path) -> std::io::Result<tokio::net::UnixStream> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::{linux::net::SocketAddrExt, unix::ffi::OsStrExt};
+
+        if let Some(name) = path.as_os_str().as_bytes().strip_prefix(b"\0") {
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            let stream = std::os::unix::net::UnixStream::connect_addr(&addr)?;
+            stream.set_nonblocking(true)?;
+            return tokio::net::UnixStream::from_std(stream);
+        }
+    }
+
+    tokio::net::UnixStream::connect(path).await
+}
+
+/// IPC transport
+#[derive(Debug, Clone)]
+pub struct IPC {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    runtime: tokio::runtime::Handle,
+    write_sender: mpsc::UnboundedSender<String>,
+    pipeline: Option<Arc<Semaphore>>,
+    reconnect: reconnect::ReconnectConfig,
+}
+
+impl IPC {
+    /// Connect to the upstream socket and create new IPC transport.
+    pub async fn new(params: Vec<config::Param>) -> Result<Self, String> {
+        let mut path = PathBuf::from("./jsonrpc.ipc");
+        let mut pipeline = None;
+        let mut log_requests = false;
+        let mut log_responses = false;
+        let mut max_pending = usize::MAX;
+        let mut max_response_bytes = DEFAULT_MAX_RESPONSE_BYTES;
+        let mut reconnect = reconnect::ReconnectConfig::default();
+
+        for p in params {
+            match p {
+                config::Param::Path(new_path) => {
+                    path = new_path;
+                }
+                config::Param::PipelineDepth(depth) => {
+                    pipeline = depth.map(|depth| Arc::new(Semaphore::new(depth.get())));
+                }
+                config::Param::LogRequests(log) => {
+                    log_requests = log;
+                }
+                config::Param::LogResponses(log) => {
+                    log_responses = log;
+                }
+                config::Param::MaxPendingRequests(max) => {
+                    max_pending = max;
+                }
+                config::Param::MaxResponseBytes(max) => {
+                    max_response_bytes = max;
+                }
+                config::Param::ReconnectInitialDelay(delay) => {
+                    reconnect.initial_delay = delay;
+                }
+                config::Param::ReconnectMaxDelay(delay) => {
+                    reconnect.max_delay = delay;
+                }
+                config::Param::ReconnectJitter(jitter) => {
+                    reconnect.jitter = jitter;
+                }
+            }
+        }
+
+        println!("[IPC] Connecting to: {:?}", path);
+
+        let stream = connect(&path)
+            .await
+            .map_err(|e| format!("Unable to connect to {:?}: {:?}", path, e))?;
+        let (mut reader, mut writer) = stream.into_split();
+        let (write_sender, mut write_receiver) = mpsc::unbounded::<String>();
+        let shared = Arc::new(Shared::default().with_max_pending(max_pending));
+        let runtime = tokio::runtime::Handle::current();
+
+        runtime.spawn(async move {
+            while let Some(msg) = write_receiver.next().await {
+                log::trace!("Sending request: {:?}", msg);
+                if log_requests {
+                    log::trace!("Upstream request: {}", msg);
+                }
+                if let Err(e) = writer.write_all(msg.as_bytes()).await {
+                    log::error!("IPCError: {:?}", e);
+                    break;
+                }
+            }
+        });
+
+        let handler = shared.clone();
+        let closer = write_sender.clone();
+        runtime.spawn(async move {
+            let mut buffer = vec![];
+            let mut chunk = [0u8; 4096];
+            loop {
+                let read = match reader.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(read) => read,
+                    Err(e) => {
+                        log::error!("IPCError: {:?}", e);
+                        break;
+                    }
+                };
+                buffer.extend_from_slice(&chunk[..read]);
+                for message in split_messages(&mut buffer) {
+                    log::trace!("Message received: {:?}", message);
+                    if message.len() > max_response_bytes {
+                        discard_oversized(&handler, message, max_response_bytes);
+                        continue;
+                    }
+                    if log_responses {
+                        log::trace!("Upstream response: {}", message);
+                    }
+                    if let Err(e) = handler.process_text(message) {
+                        log::warn!("Unable to process message: {:?}", e);
+                    }
+                }
+                // The rest of the oversized message can't be told apart from the following ones.
+                if buffer.len() > max_response_bytes {
+                    log::error!(
+                        "IPC upstream message exceeds {} bytes, closing the connection.",
+                        max_response_bytes
+                    );
+                    break;
+                }
+            }
+            log::warn!("IPC upstream connection closed.");
+            closer.close_channel();
+            handler.fail_all_pending(jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::InternalError,
+                message: "Upstream connection closed.".into(),
+                data: None,
+            });
+        });
+
+        Ok(Self {
+            path,
+            shared,
+            runtime,
+            write_sender,
+            pipeline,
+            reconnect,
+        })
+    }
+
+    /// Returns the backoff parameters of reconnection attempts.
+    pub fn reconnect_delay_config(&self) -> &reconnect::ReconnectConfig {
+        &self.reconnect
+    }
+
+    /// Returns the path of the upstream socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `true` until the upstream connection is closed.
+    pub fn is_connected(&self) -> bool {
+        !self.write_sender.is_closed()
+    }
+
+    /// Writes the request upstream and awaits the response if there is any.
+    ///
+    /// If the pipeline depth is limited, requests expecting a response are written only after
+    /// they get a slot in the pipeline. The slot is released once the response arrives.
+    fn write_and_wait(
+        &self,
+        call: jsonrpc_core::Call,
+        response: Option<oneshot::Receiver<String>>,
+    ) -> upstream::BoxFuture {
+        if response.is_none() && helpers::get_id(&call).is_some() {
+            return Box::new(future::err(upstream::shared::QUEUE_FULL_ERROR.to_owned()));
+        }
+
+        // The request is no longer pending if it can't be written or the caller stops waiting.
+        let guard = helpers::get_id(&call).map(|id| PendingGuard::new(self.shared.clone(), id.clone()));
+        let request = jsonrpc_core::types::to_string(&call).expect("jsonrpc-core are infallible");
+
+        match (response, self.pipeline.clone()) {
+            (Some(response), Some(pipeline)) => {
+                let write_sender = self.write_sender.clone();
+                Box::new(Box::pin(async move {
+                    let _slot = pipeline.acquire_owned().await.map_err(|e| format!("{:?}", e))?;
+                    write(&write_sender, request)?;
+                    let response = response.await;
+                    disarm(guard);
+                    parse(response)
+                }))
+            }
+            (response, _) => {
+                let written = future::ready(write(&self.write_sender, request));
+                Box::new(written.and_then(move |_| {
+                    match response {
+                        None => Either::Left(future::ready(Ok(None))),
+                        Some(response) => response
+                            .map(move |response| {
+                                disarm(guard);
+                                parse(response)
+                            })
+                            .right_future(),
+                    }
+                }))
+            }
+        }
+    }
+}
+
+/// Keeps the request pending, the response has been received.
+fn disarm(guard: Option<PendingGuard>) {
+    if let Some(guard) = guard {
+        guard.disarm();
+    }
+}
+
+/// Responds to the request of an oversized message with a parse error instead.
+fn discard_oversized(shared: &Shared, message: String, max_response_bytes: usize) {
+    let id = match helpers::peek_response_id(message.as_bytes()) {
+        Some(id) => id,
+        None => {
+            log::warn!(
+                "Discarding oversized message without id ({} bytes, limit: {})",
+                message.len(),
+                max_response_bytes
+            );
+            return;
+        }
+    };
+
+    log::warn!(
+        "Discarding oversized response (id: {:?}, {} bytes, limit: {})",
+        id,
+        message.len(),
+        max_response_bytes
+    );
+    shared.fail_pending(
+        &id,
+        jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ParseError,
+            message: "Upstream response is too large.".into(),
+            data: None,
+        },
+    );
+}
+
+/// Queues the request to be written to the upstream socket.
+fn write(write_sender: &mpsc::UnboundedSender<String>, request: String) -> Result<(), String> {
+    write_sender
+        .unbounded_send(request)
+        .map_err(|e| format!("Error sending request: {:?}", e))
+}
+
+/// Awaits the upstream response and parses it.
+fn wait(response: oneshot::Receiver<String>) -> impl Future<Output = Result<Option<jsonrpc_core::Output>, String>> {
+    response.map(parse)
+}
+
+/// Parses the upstream response.
+fn parse(response: Result<String, oneshot::Canceled>) -> Result<Option<jsonrpc_core::Output>, String> {
+    response
+        .map(|out| serde_json::from_str(&out).ok())
+        .map_err(|e| format!("{:?}", e))
+}
+
+impl upstream::Transport for IPC {
+    type Error = String;
+    type Future = upstream::BoxFuture;
+
+    fn send(&self, call: jsonrpc_core::Call) -> Self::Future {
+        log::trace!("Calling: {}", helpers::format_call(&call));
+
+        let rx = {
+            let id = helpers::get_id(&call);
+            self.shared.add_pending(id, PendingKind::Regular)
+        };
+
+        self.write_and_wait(call, rx)
+    }
+
+    fn subscribe(
+        &self,
+        call: jsonrpc_core::Call,
+        session: Option<Arc<jsonrpc_pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        let session = match session {
+            Some(session) => session,
+            None => {
+                return Box::new(future::err("Called subscribe without session.".into()));
+            }
+        };
+
+        log::trace!("Subscribing to {}: {}", subscription, helpers::format_call(&call));
+
+        if let Some(rx) = self.shared.join_subscribe(&call, session.clone()) {
+            log::trace!("Joining identical subscribe call to {}", subscription);
+            return Box::new(wait(rx));
+        }
+
+        let rx = {
+            let ipc = self.clone();
+            let id = helpers::get_id(&call);
+            self.shared.add_pending(
+                id,
+                PendingKind::Subscribe(
+                    session,
+                    Box::new(move |subs_id| {
+                        // Create unsubscribe request.
+                        let call = jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                            jsonrpc: Some(jsonrpc_core::Version::V2),
+                            id: jsonrpc_core::Id::Num(1),
+                            method: subscription.unsubscribe.clone(),
+                            params: jsonrpc_core::Params::Array(vec![subs_id.into()]),
+                        });
+                        let fut = helpers::auto_unsubscribe(
+                            upstream::Transport::unsubscribe(&ipc, call, None, subscription.clone()),
+                            subscription.name.clone(),
+                            helpers::AUTO_UNSUBSCRIBE_TIMEOUT,
+                        );
+
+                        ipc.runtime.spawn(fut);
+                    }),
+                ),
+            )
+        };
+
+        self.write_and_wait(call, rx)
+    }
+
+    fn unsubscribe(
+        &self,
+        call: jsonrpc_core::Call,
+        session: Option<Arc<jsonrpc_pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        log::trace!("Unsubscribing from {}: {}", subscription, helpers::format_call(&call));
+
+        // Detach the session, the subscription is cancelled upstream once the last one leaves.
+        if let Some(subscription_id) = helpers::get_unsubscribe_id(&call) {
+            match self.shared.detach_session(&subscription_id, session.as_ref()) {
+                Detached::Unknown | Detached::Last(_) => {}
+                Detached::Remaining => return Box::new(future::ok(helpers::build_response(&call, true.into()))),
+                Detached::NotSubscribed => return Box::new(future::ok(helpers::build_response(&call, false.into()))),
+            }
+        }
+
+        // It's a regular RPC, so just send it
+        upstream::Transport::send(self, call)
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        // Don't wait for the timeout if the connection is already closed.
+        if !self.is_connected() {
+            return Box::pin(future::ready(false));
+        }
+
+        upstream::health_check(upstream::Transport::probe(self), upstream::HEALTH_CHECK_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use upstream::Transport;
+
+    /// Starts a mock IPC server responding with `result` to every call.
+    ///
+    /// Responses are written in two parts to exercise message framing.
+    fn framing_upstream(name: &str, result: &'static str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.ipc", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = vec![];
+            let mut chunk = [0u8; 1024];
+            loop {
+                let read = socket.read(&mut chunk).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                buffer.extend_from_slice(&chunk[..read]);
+                for request in split_messages(&mut buffer) {
+                    let request: jsonrpc_core::MethodCall = serde_json::from_str(&request).unwrap();
+                    let response = jsonrpc_core::Output::from(Ok(result.into()), request.id, request.jsonrpc);
+                    let response = serde_json::to_vec(&response).unwrap();
+                    let (first, second) = response.split_at(response.len() / 2);
+                    socket.write_all(first).await.unwrap();
+                    socket.flush().await.unwrap();
+                    // Make sure the client reads the halves separately.
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    socket.write_all(second).await.unwrap();
+                }
+            }
+        });
+
+        path
+    }
+
+    #[test]
+    fn should_split_messages() {
+        // given
+        let mut buffer = br#"{"id":1} {"id":2}
+[{"id":3}]{"id":"#
+            .to_vec();
+
+        // when
+        let messages = split_messages(&mut buffer);
+
+        // then
+        assert_eq!(messages, vec![r#"{"id":1}"#, r#"{"id":2}"#, r#"[{"id":3}]"#]);
+        assert_eq!(buffer, br#"{"id":"#.to_vec());
+    }
+
+    #[tokio::test]
+    async fn should_send_calls_to_ipc_upstream() {
+        // given
+        let path = framing_upstream("ipc-upstream-send", "pong");
+        let ipc = IPC::new(vec![config::Param::Path(path)]).await.unwrap();
+
+        // when
+        let res = ipc
+            .send(jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(5),
+                method: "ping".into(),
+                params: jsonrpc_core::Params::None,
+            }))
+            .await;
+
+        // then
+        assert_eq!(
+            res,
+            Ok(Some(jsonrpc_core::Output::from(
+                Ok("pong".into()),
+                jsonrpc_core::Id::Num(5),
+                Some(jsonrpc_core::Version::V2)
+            )))
+        );
+    }
+
+    fn respond_with_one(call: jsonrpc_core::Call) -> jsonrpc_core::Output {
+        match call {
+            jsonrpc_core::Call::MethodCall(call) => jsonrpc_core::Output::from(Ok("1".into()), call.id, call.jsonrpc),
+            _ => unreachable!(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_expose_upstream_path() {
+        // given
+        let upstream = mock_upstream::MockIpcUpstream::new(respond_with_one).await;
+
+        // when
+        let ipc = IPC::new(vec![config::Param::Path(upstream.path().into())])
+            .await
+            .unwrap();
+
+        // then
+        assert_eq!(ipc.path(), upstream.path());
+        assert!(ipc.is_connected());
+    }
+
+    #[tokio::test]
+    async fn should_report_healthy_upstream() {
+        // given
+        let upstream = mock_upstream::MockIpcUpstream::new(respond_with_one).await;
+        let ipc = IPC::new(vec![config::Param::Path(upstream.path().into())])
+            .await
+            .unwrap();
+
+        // then
+        assert!(ipc.health_check().await);
+    }
+
+    #[tokio::test]
+    async fn should_report_closed_upstream_as_unhealthy() {
+        // given
+        let path = std::env::temp_dir().join(format!("ipc-upstream-closed-{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            drop(listener.accept().await.unwrap());
+        });
+        let ipc = IPC::new(vec![config::Param::Path(path)]).await.unwrap();
+
+        // when
+        while ipc.is_connected() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        // then
+        assert!(!ipc.health_check().await);
+    }
+
+    /// Starts a mock IPC server that never responds and counts received requests.
+    fn silent_upstream(name: &str) -> (PathBuf, Arc<std::sync::atomic::AtomicUsize>) {
+        let path = std::env::temp_dir().join(format!("{}-{}.ipc", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let received = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let received2 = received.clone();
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = vec![];
+            let mut chunk = [0u8; 1024];
+            while let Ok(read) = socket.read(&mut chunk).await {
+                if read == 0 {
+                    break;
+                }
+                buffer.extend_from_slice(&chunk[..read]);
+                let requests = split_messages(&mut buffer).len();
+                received2.fetch_add(requests, std::sync::atomic::Ordering::SeqCst);
+            }
+        });
+
+        (path, received)
+    }
+
+    async fn send_calls(depth: Option<usize>, name: &str) -> usize {
+        let (path, received) = silent_upstream(name);
+        let mut params = vec![config::Param::Path(path)];
+        params.extend(depth.map(|depth| config::Param::PipelineDepth(std::num::NonZeroUsize::new(depth))));
+        let ipc = IPC::new(params).await.unwrap();
+
+        for id in 0..5 {
+            tokio::spawn(ipc.send(jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(id),
+                method: "ping".into(),
+                params: jsonrpc_core::Params::None,
+            })));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        received.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn should_limit_requests_awaiting_response() {
+        // when
+        let limited = send_calls(Some(2), "ipc-upstream-pipeline-limited").await;
+        let unlimited = send_calls(None, "ipc-upstream-pipeline-unlimited").await;
+
+        // then
+        assert_eq!(limited, 2);
+        assert_eq!(unlimited, 5);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn should_connect_to_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        // given
+        let name = format!("ipc-upstream-abstract-{}", std::process::id());
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(&name).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind_addr(&addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let listener = tokio::net::UnixListener::from_std(listener).unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut chunk = [0u8; 1024];
+            let read = socket.read(&mut chunk).await.unwrap();
+            let request: jsonrpc_core::MethodCall = serde_json::from_slice(&chunk[..read]).unwrap();
+            let response = jsonrpc_core::Output::from(Ok("pong".into()), request.id, request.jsonrpc);
+            socket.write_all(&serde_json::to_vec(&response).unwrap()).await.unwrap();
+        });
+        let path = config::parse_path(&format!("@{}", name));
+        let ipc = IPC::new(vec![config::Param::Path(path)]).await.unwrap();
+
+        // when
+        let res = ipc
+            .send(jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(1),
+                method: "ping".into(),
+                params: jsonrpc_core::Params::None,
+            }))
+            .await;
+
+        // then
+        assert_eq!(
+            res,
+            Ok(Some(jsonrpc_core::Output::from(
+                Ok("pong".into()),
+                jsonrpc_core::Id::Num(1),
+                Some(jsonrpc_core::Version::V2)
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_calls_exceeding_max_pending() {
+        // given
+        let (path, received) = silent_upstream("ipc-upstream-max-pending");
+        let ipc = IPC::new(vec![config::Param::Path(path), config::Param::MaxPendingRequests(1)])
+            .await
+            .unwrap();
+        let call = |id| {
+            jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(id),
+                method: "ping".into(),
+                params: jsonrpc_core::Params::None,
+            })
+        };
+        let _pending = tokio::spawn(ipc.send(call(1)));
+
+        // when
+        let res = ipc.send(call(2)).await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        // then
+        assert_eq!(res, Err(upstream::shared::QUEUE_FULL_ERROR.to_owned()));
+        assert_eq!(received.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn should_fail_if_socket_does_not_exist() {
+        let res = IPC::new(vec![config::Param::Path("/non-existent/jsonrpc.ipc".into())]).await;
+
+        assert!(res.is_err());
+    }
+
+    fn ping(id: u64) -> jsonrpc_core::Call {
+        jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+            jsonrpc: Some(jsonrpc_core::Version::V2),
+            id: jsonrpc_core::Id::Num(id),
+            method: "ping".into(),
+            params: jsonrpc_core::Params::None,
+        })
+    }
+
+    fn failure(id: u64, code: jsonrpc_core::ErrorCode, message: &str) -> Result<Option<jsonrpc_core::Output>, String> {
+        let error = jsonrpc_core::Error {
+            code,
+            message: message.into(),
+            data: None,
+        };
+        Ok(Some(jsonrpc_core::Output::from(
+            Err(error),
+            jsonrpc_core::Id::Num(id),
+            Some(jsonrpc_core::Version::V2),
+        )))
+    }
+
+    #[tokio::test]
+    async fn should_fail_pending_requests_if_upstream_closes_connection() {
+        // given
+        let path = std::env::temp_dir().join(format!("ipc-upstream-close-pending-{}.ipc", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut chunk = [0u8; 1024];
+            let _ = socket.read(&mut chunk).await.unwrap();
+        });
+        let ipc = IPC::new(vec![config::Param::Path(path)]).await.unwrap();
+
+        // when
+        let res = ipc.send(ping(1)).await;
+
+        // then
+        assert_eq!(
+            res,
+            failure(1, jsonrpc_core::ErrorCode::InternalError, "Upstream connection closed.")
+        );
+        assert_eq!(ipc.shared.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn should_respond_with_parse_error_if_response_is_too_large() {
+        // given
+        let path = framing_upstream("ipc-upstream-oversized", "pong");
+        let ipc = IPC::new(vec![config::Param::Path(path), config::Param::MaxResponseBytes(30)])
+            .await
+            .unwrap();
+
+        // when
+        let res = ipc.send(ping(1)).await;
+
+        // then
+        assert_eq!(
+            res,
+            failure(
+                1,
+                jsonrpc_core::ErrorCode::ParseError,
+                "Upstream response is too large."
+            )
+        );
+        assert!(ipc.is_connected());
+    }
+
+    #[tokio::test]
+    async fn should_close_connection_if_incomplete_message_is_too_large() {
+        // given
+        let path = framing_upstream("ipc-upstream-oversized-incomplete", "pong");
+        let ipc = IPC::new(vec![config::Param::Path(path), config::Param::MaxResponseBytes(10)])
+            .await
+            .unwrap();
+
+        // when
+        let res = ipc.send(ping(1)).await;
+
+        // then
+        assert_eq!(
+            res,
+            failure(1, jsonrpc_core::ErrorCode::InternalError, "Upstream connection closed.")
+        );
+        assert!(!ipc.is_connected());
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Backoff of reconnection attempts to the IPC upstream.
+
+use rand::Rng;
+use std::time::Duration;
+
+/// Default delay before the first reconnection attempt.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+/// Default maximal delay between reconnection attempts (excluding jitter).
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Jittered exponential backoff parameters of reconnection attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectConfig {
+    /// Delay before the first attempt, doubled with every next one.
+    pub initial_delay: Duration,
+    /// Maximal delay between attempts (excluding jitter).
+    pub max_delay: Duration,
+    /// Add a random delay of up to `initial_delay` to every attempt.
+    pub jitter: bool,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: true,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Returns the delay before given (zero-based) reconnection attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, &mut rand::thread_rng())
+    }
+
+    /// Returns the delay before given (zero-based) reconnection attempt using given source of jitter.
+    ///
+    /// The delay is `min(max_delay, initial_delay * 2^attempt) + random(0..initial_delay)`.
+    pub fn delay_with<R: Rng>(&self, attempt: u32, rng: &mut R) -> Duration {
+        let backoff = 2u32
+            .checked_pow(attempt)
+            .and_then(|factor| self.initial_delay.checked_mul(factor))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        if !self.jitter {
+            return backoff;
+        }
+
+        backoff + self.initial_delay.mul_f64(rng.gen::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+
+    #[test]
+    fn should_double_the_delay_up_to_the_maximum() {
+        // given
+        let config = ReconnectConfig {
+            jitter: false,
+            ..Default::default()
+        };
+
+        // when
+        let delays = (0..12).map(|attempt| config.delay(attempt)).collect::<Vec<_>>();
+
+        // then
+        assert_eq!(delays[0], Duration::from_millis(100));
+        assert_eq!(delays[1], Duration::from_millis(200));
+        assert_eq!(delays[8], Duration::from_millis(25_600));
+        assert_eq!(delays[9], Duration::from_secs(30));
+        assert_eq!(delays[11], Duration::from_secs(30));
+        assert_eq!(config.delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn should_add_jitter_below_initial_delay() {
+        // given
+        let config = ReconnectConfig::default();
+        let mut zero = StepRng::new(0, 0);
+        let mut max = StepRng::new(u64::MAX, 0);
+
+        // when
+        let min_delay = config.delay_with(3, &mut zero);
+        let max_delay = config.delay_with(3, &mut max);
+        let capped = config.delay_with(20, &mut max);
+
+        // then
+        assert_eq!(min_delay, Duration::from_millis(800));
+        assert!(max_delay > Duration::from_millis(800) && max_delay <= Duration::from_millis(900));
+        assert!(capped > Duration::from_secs(30) && capped <= Duration::from_millis(30_100));
+    }
+}
@@ -0,0 +1,339 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Mock upstream nodes for tests.
+//!
+//! The mocks answer every call using given handler and run on the current tokio runtime
+//! until dropped. Notifications are passed to the handler, but never responded to.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use jsonrpc_core as rpc;
+use rpc::futures::{SinkExt, StreamExt};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+type Handler = Arc<dyn Fn(rpc::Call) -> rpc::Output + Send + Sync>;
+
+/// Mock WebSocket upstream.
+#[derive(Debug)]
+pub struct MockWsUpstream {
+    url: url::Url,
+    server: JoinHandle<()>,
+}
+
+impl MockWsUpstream {
+    /// Starts a WebSocket server on a random local port answering calls with `handler`.
+    pub async fn new<F>(handler: F) -> Self
+    where
+        F: Fn(rpc::Call) -> rpc::Output + Send + Sync + 'static,
+    {
+        let handler: Handler = Arc::new(handler);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Binding to a random local port never fails; qed");
+        let url = format!("ws://{}", listener.local_addr().expect("The listener is bound; qed"))
+            .parse()
+            .expect("Valid URL given; qed");
+
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let mut socket = match tokio_tungstenite::accept_async(stream).await {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            log::warn!("Mock upstream handshake failed: {:?}", e);
+                            return;
+                        }
+                    };
+                    while let Some(Ok(message)) = socket.next().await {
+                        let request = match message {
+                            Message::Text(text) => text,
+                            Message::Close(_) => break,
+                            _ => continue,
+                        };
+                        if let Some(response) = respond(&handler, request.as_bytes()) {
+                            if socket.send(Message::Text(response)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        MockWsUpstream { url, server }
+    }
+
+    /// Starts a WebSocket server answering calls with `handler` that can modify given state.
+    pub async fn with_state_handler<S, F>(state: S, handler: F) -> Self
+    where
+        S: Send + 'static,
+        F: Fn(&mut S, rpc::Call) -> rpc::Output + Send + Sync + 'static,
+    {
+        let state = Mutex::new(state);
+        Self::new(move |call| {
+            let mut state = state
+                .lock()
+                .expect("The handler never panics while holding the lock; qed");
+            handler(&mut state, call)
+        })
+        .await
+    }
+
+    /// Returns the URL of the server.
+    pub fn url(&self) -> url::Url {
+        self.url.clone()
+    }
+}
+
+impl Drop for MockWsUpstream {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Mock IPC upstream.
+#[derive(Debug)]
+pub struct MockIpcUpstream {
+    path: PathBuf,
+    server: JoinHandle<()>,
+}
+
+impl MockIpcUpstream {
+    /// Starts an IPC server on a temporary socket answering calls with `handler`.
+    pub async fn new<F>(handler: F) -> Self
+    where
+        F: Fn(rpc::Call) -> rpc::Output + Send + Sync + 'static,
+    {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let handler: Handler = Arc::new(handler);
+        let path = std::env::temp_dir().join(format!(
+            "mock-upstream-{}-{}.ipc",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path).expect("Binding to a temporary socket never fails; qed");
+
+        let server = tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let mut buffer = vec![];
+                    let mut chunk = [0u8; 4096];
+                    loop {
+                        let read = match socket.read(&mut chunk).await {
+                            Ok(0) | Err(_) => break,
+                            Ok(read) => read,
+                        };
+                        buffer.extend_from_slice(&chunk[..read]);
+                        for request in split_requests(&mut buffer) {
+                            if let Some(response) = respond(&handler, &request) {
+                                if socket.write_all(response.as_bytes()).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        MockIpcUpstream { path, server }
+    }
+
+    /// Returns the path of the server socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for MockIpcUpstream {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Takes all complete requests from the beginning of the buffer.
+///
+/// Invalid data is discarded.
+fn split_requests(buffer: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    let mut requests = vec![];
+    let mut stream = serde_json::Deserializer::from_slice(buffer).into_iter::<serde_json::Value>();
+    let mut consumed = 0;
+    loop {
+        match stream.next() {
+            Some(Ok(request)) => {
+                requests.push(serde_json::to_vec(&request).expect("Serializing a JSON value never fails; qed"));
+                consumed = stream.byte_offset();
+            }
+            Some(Err(e)) if e.is_eof() => break,
+            Some(Err(_)) => {
+                consumed = buffer.len();
+                break;
+            }
+            None => break,
+        }
+    }
+    buffer.drain(..consumed);
+    requests
+}
+
+/// Answers a single or batch request, returns `None` if there is nothing to respond with.
+fn respond(handler: &Handler, request: &[u8]) -> Option<String> {
+    let handle = |call: rpc::Call| {
+        let is_notification = matches!(call, rpc::Call::Notification(_));
+        let output = handler(call);
+        if is_notification {
+            None
+        } else {
+            Some(output)
+        }
+    };
+
+    let response = match serde_json::from_slice(request) {
+        Ok(rpc::Request::Single(call)) => rpc::Response::Single(handle(call)?),
+        Ok(rpc::Request::Batch(calls)) => {
+            let outputs = calls.into_iter().filter_map(handle).collect::<Vec<_>>();
+            if outputs.is_empty() {
+                return None;
+            }
+            rpc::Response::Batch(outputs)
+        }
+        Err(e) => {
+            log::warn!("Mock upstream received an invalid request: {:?}", e);
+            return None;
+        }
+    };
+
+    Some(serde_json::to_string(&response).expect("Serializing a response never fails; qed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(call: rpc::Call) -> rpc::Output {
+        match call {
+            rpc::Call::MethodCall(call) => rpc::Output::from(Ok(call.method.into()), call.id, call.jsonrpc),
+            _ => rpc::Output::from(Err(rpc::Error::invalid_request()), rpc::Id::Null, None),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_ws_calls() {
+        // given
+        let upstream = MockWsUpstream::new(echo).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(upstream.url().as_str()).await.unwrap();
+
+        // when
+        socket
+            .send(Message::Text(
+                r#"{"jsonrpc":"2.0","id":1,"method":"eth_chainId"}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let response = socket.next().await.unwrap().unwrap();
+
+        // then
+        assert_eq!(
+            response,
+            Message::Text(r#"{"jsonrpc":"2.0","result":"eth_chainId","id":1}"#.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn should_keep_ws_handler_state() {
+        // given
+        let upstream = MockWsUpstream::with_state_handler(0u64, |count, call| {
+            *count += 1;
+            match call {
+                rpc::Call::MethodCall(call) => rpc::Output::from(Ok((*count).into()), call.id, call.jsonrpc),
+                _ => unreachable!(),
+            }
+        })
+        .await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(upstream.url().as_str()).await.unwrap();
+
+        // when
+        for _ in 0..2 {
+            socket
+                .send(Message::Text(
+                    r#"{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber"}"#.into(),
+                ))
+                .await
+                .unwrap();
+        }
+        let first = socket.next().await.unwrap().unwrap();
+        let second = socket.next().await.unwrap().unwrap();
+
+        // then
+        assert_eq!(first, Message::Text(r#"{"jsonrpc":"2.0","result":1,"id":1}"#.into()));
+        assert_eq!(second, Message::Text(r#"{"jsonrpc":"2.0","result":2,"id":1}"#.into()));
+    }
+
+    #[tokio::test]
+    async fn should_answer_ipc_calls() {
+        // given
+        let upstream = MockIpcUpstream::new(echo).await;
+        let mut socket = tokio::net::UnixStream::connect(upstream.path()).await.unwrap();
+
+        // when
+        socket
+            .write_all(
+                br#"{"jsonrpc":"2.0","method":"eth_submitWork"}[{"jsonrpc":"2.0","id":1,"method":"eth_chainId"}]"#,
+            )
+            .await
+            .unwrap();
+        let mut response = vec![0u8; 1024];
+        let read = socket.read(&mut response).await.unwrap();
+
+        // then
+        assert_eq!(
+            std::str::from_utf8(&response[..read]).unwrap(),
+            r#"[{"jsonrpc":"2.0","result":"eth_chainId","id":1}]"#
+        );
+    }
+
+    #[test]
+    fn should_split_requests() {
+        // given
+        let mut buffer = br#"{"id":1} [{"id":2}]{"id":"#.to_vec();
+
+        // when
+        let requests = split_requests(&mut buffer);
+
+        // then
+        assert_eq!(requests, vec![br#"{"id":1}"#.to_vec(), br#"[{"id":2}]"#.to_vec()]);
+        assert_eq!(buffer, br#"{"id":"#.to_vec());
+    }
+}
@@ -44,6 +44,7 @@ pub fn params() -> Vec<cli_params::Param<Param>> {
                 let file = fs::File::open(&path).map_err(|e| format!("Can't open permissioning file at {}: {:?}", path, e))?;
                 let buf_file = io::BufReader::new(file);
                 let config: Permissioning = serde_json::from_reader(buf_file).map_err(|e| format!("Invalid JSON at {}: {:?}", path, e))?;
+                config.validate().map_err(|e| format!("Invalid permissioning config at {}: {}", path, e))?;
                 Ok(Param::Config(config))
             }
         )
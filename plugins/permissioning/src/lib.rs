@@ -17,32 +17,60 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 //! A simple permissioning system.
 //!
-//! Allows you to turn off particular methods.
+//! Allows you to turn off or throttle particular methods.
 
 #![warn(missing_docs)]
 #![warn(unused_extern_crates)]
 
 extern crate cli_params;
+extern crate dashmap;
 extern crate fnv;
 extern crate jsonrpc_core as rpc;
+extern crate regex;
+extern crate request_meta;
 extern crate serde_json;
 
 #[macro_use]
 extern crate serde_derive;
 
+#[macro_use]
+extern crate log;
+
+use dashmap::DashMap;
 use fnv::FnvHashMap;
+use regex::Regex;
+pub use request_meta::{Client, TransportKind};
 use rpc::futures::{future::Either, Future};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
 pub mod config;
 
 /// Describes method access.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum Access {
     /// Allow all access to that method
     Allow,
     /// Deny any access to that method
     Deny,
+    /// Allow access to that method, but throttle the number of requests per client.
+    ///
+    /// All methods matching the same rule (or falling back to the base policy) share the limit.
+    /// Requests which client is not known (see `ClientIp`) share a single bucket.
+    #[serde(rename_all = "camelCase")]
+    RateLimit {
+        /// Number of requests per second the bucket is refilled with.
+        requests_per_second: f64,
+        /// Maximal number of requests that can be made at once.
+        burst: u32,
+    },
     // TODO [ToDr] Add other policies like:
     // 1. Require authorization header (fixed)
     // 2. Require HTTP basic credentials
@@ -50,24 +78,96 @@ pub enum Access {
     // (All will require extending the metadata to contain this info)
 }
 
+impl ::std::fmt::Display for Access {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Access::Allow => write!(fmt, "Allow"),
+            Access::Deny => write!(fmt, "Deny"),
+            Access::RateLimit {
+                requests_per_second,
+                burst,
+            } => write!(fmt, "RateLimit({}/s, burst {})", requests_per_second, burst),
+        }
+    }
+}
+
+/// Describes how method names are matched.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum MatchMode {
+    /// Method name has to be exactly the same as `Method::name`.
+    #[default]
+    Exact,
+    /// Method name has to fully match given regular expression.
+    Regex(String),
+}
+
+impl MatchMode {
+    /// Compiles the regular expression (if any).
+    ///
+    /// The pattern is anchored, so it has to match the whole method name.
+    pub fn compile(&self) -> Result<Option<Regex>, String> {
+        match *self {
+            MatchMode::Exact => Ok(None),
+            MatchMode::Regex(ref pattern) => Regex::new(&format!("^(?:{})$", pattern))
+                .map(Some)
+                .map_err(|e| format!("Invalid method pattern {}: {}", pattern, e)),
+        }
+    }
+}
+
 /// Represents a managed method.
 ///
 /// Should know how to compute a hash that is used to compare requests.
 #[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Method {
     /// Method name
     pub name: String,
+    /// How the method name is matched
+    #[serde(default)]
+    pub name_pattern: MatchMode,
     /// Method access details
     pub policy: Access,
+    /// Transports the method can be called over (all if not set)
+    #[serde(default)]
+    pub allowed_transports: Option<Vec<TransportKind>>,
+}
+
+impl Method {
+    /// Returns `true` if the method can be called over given transport.
+    ///
+    /// Requests over unknown transports are rejected if the method is restricted.
+    pub fn is_allowed_over(&self, transport: Option<TransportKind>) -> bool {
+        match (&self.allowed_transports, transport) {
+            (None, _) => true,
+            (Some(allowed), Some(transport)) => allowed.contains(&transport),
+            (Some(_), None) => false,
+        }
+    }
 }
 
 /// Represents permissioning configuration
 #[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Permissioning {
     /// Default (base) policy
     pub policy: Access,
     /// Method overrides
     pub methods: Vec<Method>,
+    /// Reject all notifications (they are subject to the same policies as method calls otherwise)
+    #[serde(default)]
+    pub deny_notifications: bool,
+}
+
+impl Permissioning {
+    /// Makes sure that all method patterns are valid regular expressions.
+    pub fn validate(&self) -> Result<(), String> {
+        for method in &self.methods {
+            method.name_pattern.compile()?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for Permissioning {
@@ -75,19 +175,122 @@ impl Default for Permissioning {
         Permissioning {
             policy: Access::Allow,
             methods: Default::default(),
+            deny_notifications: false,
         }
     }
 }
 
-/// Simple static permissioning scheme
+/// Exposes the client making the request.
+///
+/// Used to keep separate rate limits for every client.
+pub trait ClientIp {
+    /// Returns the IP address of the client if known.
+    fn client_ip(&self) -> Option<IpAddr> {
+        None
+    }
+
+    /// Returns the client, identified by the address or the connection if the address is not known.
+    fn client(&self) -> Option<Client> {
+        self.client_ip().map(Client::Ip)
+    }
+}
+
+impl ClientIp for () {}
+
+impl<T> ClientIp for Option<T> {}
+
+/// Exposes the transport the request has been received with.
+///
+/// Used to restrict methods to particular transports.
+pub trait IncomingTransport {
+    /// Returns the kind of the transport if known.
+    fn transport(&self) -> Option<TransportKind> {
+        None
+    }
+}
+
+impl IncomingTransport for () {}
+
+impl<T> IncomingTransport for Option<T> {}
+
+/// Number of buckets above which refilled ones are forgotten.
+///
+/// The threshold grows with the number of buckets still in use, so that sweeping stays amortized O(1).
+const MIN_SWEEP_SIZE: usize = 1024;
+
+/// A rule the policy of a call comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Rule {
+    /// The base policy.
+    Base,
+    /// A method configured by exact name.
+    Method(String),
+    /// A method pattern (index in `Middleware::patterns`).
+    Pattern(usize),
+}
+
+/// A token bucket used to throttle requests.
 #[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    requests_per_second: f64,
+    burst: u32,
+}
+
+impl TokenBucket {
+    fn new(requests_per_second: f64, burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst as f64,
+            last_refill: now,
+            requests_per_second,
+            burst,
+        }
+    }
+
+    /// Returns the number of tokens in the bucket at given time.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * self.requests_per_second).min(self.burst as f64)
+    }
+
+    /// Returns `true` if the bucket is refilled, it's no different from a new one then.
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(now) >= self.burst as f64
+    }
+
+    /// Refills the bucket and attempts to take a single token out of it.
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        self.tokens = self.tokens_at(now);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Simple static permissioning scheme
+///
+/// Clones share the same rate limits.
+#[derive(Debug, Clone)]
 pub struct Middleware {
     base: Access,
+    deny_notifications: bool,
     permissioned: FnvHashMap<String, Method>,
+    patterns: Vec<(Regex, Method)>,
+    buckets: Arc<DashMap<(Rule, Option<Client>), TokenBucket>>,
+    next_sweep: Arc<AtomicUsize>,
 }
 
 impl Middleware {
     /// Creates new permissioning middleware
+    ///
+    /// Panics if any of the method patterns is not a valid regular expression,
+    /// use `Permissioning::validate` to check the configuration beforehand.
     pub fn new(params: &[config::Param]) -> Self {
         let mut config = Permissioning::default();
         for p in params {
@@ -96,14 +299,95 @@ impl Middleware {
             }
         }
 
+        let mut permissioned = FnvHashMap::default();
+        let mut patterns = vec![];
+        for method in config.methods {
+            match method.name_pattern.compile().expect("Invalid method pattern.") {
+                Some(regex) => patterns.push((regex, method)),
+                None => {
+                    permissioned.insert(method.name.clone(), method);
+                }
+            }
+        }
+
         Middleware {
             base: config.policy,
-            permissioned: config.methods.into_iter().map(|x| (x.name.clone(), x)).collect(),
+            deny_notifications: config.deny_notifications,
+            permissioned,
+            patterns,
+            buckets: Default::default(),
+            next_sweep: Arc::new(AtomicUsize::new(MIN_SWEEP_SIZE)),
+        }
+    }
+
+    /// Returns `true` if any of the policies throttles requests per client.
+    pub fn uses_rate_limits(&self) -> bool {
+        let is_rate_limit = |access: &Access| matches!(access, Access::RateLimit { .. });
+        is_rate_limit(&self.base)
+            || self.permissioned.values().any(|m| is_rate_limit(&m.policy))
+            || self.patterns.iter().any(|(_, m)| is_rate_limit(&m.policy))
+    }
+
+    /// Returns names of methods configured explicitly (not by a pattern), ordered by name.
+    pub fn methods(&self) -> Vec<&str> {
+        let mut methods = self.permissioned.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        methods.sort();
+        methods
+    }
+
+    /// Returns the policy applied to given method.
+    pub fn access(&self, method: &str) -> &Access {
+        self.method(method).map_or(&self.base, |m| &m.policy)
+    }
+
+    /// Returns a copy of the policy applied to given method.
+    ///
+    /// Exact matches take precedence over patterns, the base policy is used for unlisted methods.
+    pub fn policy_for(&self, method: &str) -> Access {
+        self.access(method).clone()
+    }
+
+    /// Returns the managed method matching given name.
+    ///
+    /// Exact matches take precedence over patterns, the first matching pattern wins.
+    fn method(&self, method: &str) -> Option<&Method> {
+        self.rule(method).map(|(_, m)| m)
+    }
+
+    /// Returns the rule matching given method name together with the managed method.
+    fn rule(&self, method: &str) -> Option<(Rule, &Method)> {
+        if let Some(m) = self.permissioned.get(method) {
+            return Some((Rule::Method(method.to_owned()), m));
         }
+
+        self.patterns
+            .iter()
+            .enumerate()
+            .find(|(_, (regex, _))| regex.is_match(method))
+            .map(|(index, (_, m))| (Rule::Pattern(index), m))
+    }
+
+    /// Takes a token from the bucket of given rule and client.
+    ///
+    /// Returns `false` if the bucket is exhausted.
+    fn acquire(&self, rule: Rule, client: Option<Client>, requests_per_second: f64, burst: u32) -> bool {
+        let now = Instant::now();
+        let key = (rule, client);
+        if !self.buckets.contains_key(&key) && self.buckets.len() >= self.next_sweep.load(Ordering::Relaxed) {
+            // Forget refilled buckets to keep the map bounded.
+            self.buckets.retain(|_, bucket| !bucket.is_full(now));
+            self.next_sweep
+                .store(MIN_SWEEP_SIZE.max(self.buckets.len() * 2), Ordering::Relaxed);
+        }
+
+        self.buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(requests_per_second, burst, now))
+            .try_acquire(now)
     }
 }
 
-impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
+impl<M: rpc::Metadata + ClientIp + IncomingTransport> rpc::Middleware<M> for Middleware {
     type Future = rpc::middleware::NoopFuture;
     type CallFuture = rpc::futures::future::Ready<Option<rpc::Output>>;
 
@@ -115,60 +399,79 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
         enum Action {
             Next,
             Reject,
+            Throttle,
         }
 
-        let to_action = |access: &Access| match *access {
+        let to_action = |rule: Rule, access: &Access| match *access {
             Access::Allow => Action::Next,
             Access::Deny => Action::Reject,
+            Access::RateLimit {
+                requests_per_second,
+                burst,
+            } => {
+                if self.acquire(rule, meta.client(), requests_per_second, burst) {
+                    Action::Next
+                } else {
+                    Action::Throttle
+                }
+            }
+        };
+
+        let method_action = |method: &str| match self.rule(method) {
+            Some((_, m)) if !m.is_allowed_over(meta.transport()) => Action::Reject,
+            Some((rule, m)) => to_action(rule, &m.policy),
+            None => to_action(Rule::Base, &self.base),
         };
 
         let action = {
             match call {
-                rpc::Call::MethodCall(rpc::MethodCall { ref method, .. }) => {
-                    if let Some(m) = self.permissioned.get(method) {
-                        to_action(&m.policy)
-                    } else {
-                        to_action(&self.base)
-                    }
-                }
-                _ => to_action(&self.base),
+                rpc::Call::MethodCall(rpc::MethodCall { ref method, .. }) => method_action(method),
+                rpc::Call::Notification(_) if self.deny_notifications => Action::Reject,
+                rpc::Call::Notification(rpc::Notification { ref method, .. }) => method_action(method),
+                _ => to_action(Rule::Base, &self.base),
             }
         };
 
-        match action {
-            Action::Next => Either::Right(next(call, meta)),
-            Action::Reject => {
-                let (version, id) = get_call_details(call);
-
-                Either::Left(rpc::futures::future::ready(id.map(|id| {
-                    rpc::Output::Failure(rpc::Failure {
-                        jsonrpc: version,
-                        error: rpc::Error {
-                            code: rpc::ErrorCode::ServerError(-1),
-                            message: "You are not allowed to call that method.".into(),
-                            data: None,
-                        },
-                        id,
-                    })
-                })))
+        if let rpc::Call::Notification(ref notification) = call {
+            if !matches!(action, Action::Next) {
+                warn!("Denied notification: {}", notification.method);
             }
         }
+
+        let response = match action {
+            Action::Next => return Either::Right(next(call, meta)),
+            Action::Reject => build_error_response(&call, -1, "You are not allowed to call that method."),
+            Action::Throttle => build_error_response(&call, -32005, "Request rate limit exceeded."),
+        };
+
+        Either::Left(rpc::futures::future::ready(response))
     }
 }
 
-fn get_call_details(call: rpc::Call) -> (Option<rpc::Version>, Option<rpc::Id>) {
-    match call {
-        rpc::Call::MethodCall(rpc::MethodCall { jsonrpc, id, .. }) => (jsonrpc, Some(id)),
-        rpc::Call::Notification(rpc::Notification { jsonrpc, .. }) => (jsonrpc, None),
-        rpc::Call::Invalid { id, .. } => (None, Some(id)),
-    }
+/// Builds an error response to given call, notifications are not responded to.
+fn build_error_response(call: &rpc::Call, code: i64, message: &str) -> Option<rpc::Output> {
+    let (jsonrpc, id) = match *call {
+        rpc::Call::MethodCall(rpc::MethodCall { jsonrpc, ref id, .. }) => (jsonrpc, id.clone()),
+        rpc::Call::Notification(_) => return None,
+        rpc::Call::Invalid { ref id, .. } => (None, id.clone()),
+    };
+
+    Some(rpc::Output::Failure(rpc::Failure {
+        jsonrpc,
+        error: rpc::Error {
+            code: code.into(),
+            message: message.into(),
+            data: None,
+        },
+        id,
+    }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use rpc::Middleware as MiddlewareTrait;
-    use std::sync::{atomic, Arc};
+    use std::sync::atomic;
 
     trait FutExt: std::future::Future {
         fn wait(self) -> Self::Output;
@@ -206,10 +509,57 @@ mod tests {
         })
     }
 
+    fn notification(name: &str) -> rpc::Call {
+        rpc::Call::Notification(rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params: rpc::Params::Array(vec![]),
+        })
+    }
+
+    #[derive(Clone, Default)]
+    struct Meta {
+        ip: Option<IpAddr>,
+        transport: Option<TransportKind>,
+    }
+
+    impl rpc::Metadata for Meta {}
+
+    impl ClientIp for Meta {
+        fn client_ip(&self) -> Option<IpAddr> {
+            self.ip
+        }
+    }
+
+    impl IncomingTransport for Meta {
+        fn transport(&self) -> Option<TransportKind> {
+            self.transport
+        }
+    }
+
+    fn over(transport: TransportKind) -> Meta {
+        Meta {
+            ip: None,
+            transport: Some(transport),
+        }
+    }
+
     fn middleware(config: Permissioning) -> Middleware {
         Middleware::new(&[config::Param::Config(config)])
     }
 
+    fn rate_limited() -> Option<rpc::Output> {
+        Some(rpc::Output::Failure(rpc::Failure {
+            id: rpc::Id::Num(1),
+            error: rpc::Error {
+                code: rpc::ErrorCode::ServerError(-32005),
+                message: "Request rate limit exceeded.".into(),
+                data: None,
+            },
+            jsonrpc: Some(rpc::Version::V2),
+        }))
+    }
+
     fn not_allowed() -> Option<rpc::Output> {
         Some(rpc::Output::Failure(rpc::Failure {
             id: rpc::Id::Num(1),
@@ -243,8 +593,11 @@ mod tests {
             policy: Access::Allow,
             methods: vec![Method {
                 name: "eth_getBlock".into(),
+                name_pattern: MatchMode::Exact,
                 policy: Access::Deny,
+                allowed_transports: None,
             }],
+            deny_notifications: false,
         });
         let (next, called) = callback();
 
@@ -262,6 +615,7 @@ mod tests {
         let middleware = middleware(Permissioning {
             policy: Access::Deny,
             methods: vec![],
+            deny_notifications: false,
         });
         let (next, called) = callback();
 
@@ -273,6 +627,39 @@ mod tests {
         assert_eq!(result.wait(), not_allowed());
     }
 
+    #[test]
+    fn should_return_policy_for_method() {
+        // given
+        let method = |name: &str, policy| Method {
+            name: name.into(),
+            name_pattern: MatchMode::Exact,
+            policy,
+            allowed_transports: None,
+        };
+        let middleware = middleware(Permissioning {
+            policy: Access::RateLimit {
+                requests_per_second: 1.0,
+                burst: 5,
+            },
+            methods: vec![
+                method("eth_getBlock", Access::Allow),
+                method("personal_unlockAccount", Access::Deny),
+            ],
+            deny_notifications: false,
+        });
+
+        // then
+        assert_eq!(middleware.policy_for("eth_getBlock"), Access::Allow);
+        assert_eq!(middleware.policy_for("personal_unlockAccount"), Access::Deny);
+        assert_eq!(
+            middleware.policy_for("eth_call"),
+            Access::RateLimit {
+                requests_per_second: 1.0,
+                burst: 5,
+            }
+        );
+    }
+
     #[test]
     fn should_allow_whitelisted_method() {
         // given
@@ -280,8 +667,11 @@ mod tests {
             policy: Access::Deny,
             methods: vec![Method {
                 name: "eth_getBlock".into(),
+                name_pattern: MatchMode::Exact,
                 policy: Access::Allow,
+                allowed_transports: None,
             }],
+            deny_notifications: false,
         });
         let (next, called) = callback();
 
@@ -292,4 +682,446 @@ mod tests {
         assert_eq!(called.load(atomic::Ordering::SeqCst), true);
         assert_eq!(result.wait(), None);
     }
+
+    #[test]
+    fn should_apply_policies_to_notifications() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Deny,
+            methods: vec![Method {
+                name: "eth_submitWork".into(),
+                name_pattern: MatchMode::Exact,
+                policy: Access::Allow,
+                allowed_transports: None,
+            }],
+            deny_notifications: false,
+        });
+        let (next1, called1) = callback();
+        let (next2, called2) = callback();
+
+        // when
+        let result1 = middleware.on_call(notification("eth_submitHashrate"), (), next1);
+        let result2 = middleware.on_call(notification("eth_submitWork"), (), next2);
+
+        // then
+        assert_eq!(called1.load(atomic::Ordering::SeqCst), false);
+        assert_eq!(result1.wait(), None);
+        assert_eq!(called2.load(atomic::Ordering::SeqCst), true);
+        assert_eq!(result2.wait(), None);
+    }
+
+    #[test]
+    fn should_deny_notifications_if_configured() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Allow,
+            methods: vec![Method {
+                name: "eth_submitHashrate".into(),
+                name_pattern: MatchMode::Exact,
+                policy: Access::Deny,
+                allowed_transports: None,
+            }],
+            deny_notifications: true,
+        });
+        let (next1, called1) = callback();
+        let (next2, called2) = callback();
+
+        // when
+        let result1 = middleware.on_call(notification("eth_submitHashrate"), (), next1);
+        let result2 = middleware.on_call(notification("eth_submitWork"), (), next2);
+
+        // then
+        assert_eq!(called1.load(atomic::Ordering::SeqCst), false);
+        assert_eq!(result1.wait(), None);
+        assert_eq!(called2.load(atomic::Ordering::SeqCst), false);
+        assert_eq!(result2.wait(), None);
+    }
+
+    #[test]
+    fn should_throttle_rate_limited_method() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Allow,
+            methods: vec![Method {
+                name: "eth_getBlock".into(),
+                name_pattern: MatchMode::Exact,
+                policy: Access::RateLimit {
+                    requests_per_second: 0.001,
+                    burst: 2,
+                },
+                allowed_transports: None,
+            }],
+            deny_notifications: false,
+        });
+
+        // when
+        let (next1, called1) = callback();
+        let result1 = middleware.on_call(method_call("eth_getBlock"), (), next1);
+        let (next2, called2) = callback();
+        let result2 = middleware.on_call(method_call("eth_getBlock"), (), next2);
+        let (next3, called3) = callback();
+        let result3 = middleware.on_call(method_call("eth_getBlock"), (), next3);
+        let (next4, called4) = callback();
+        let result4 = middleware.on_call(method_call("eth_getBalance"), (), next4);
+
+        // then
+        assert_eq!(called1.load(atomic::Ordering::SeqCst), true);
+        assert_eq!(result1.wait(), None);
+        assert_eq!(called2.load(atomic::Ordering::SeqCst), true);
+        assert_eq!(result2.wait(), None);
+        assert_eq!(called3.load(atomic::Ordering::SeqCst), false);
+        assert_eq!(result3.wait(), rate_limited());
+        assert_eq!(called4.load(atomic::Ordering::SeqCst), true);
+        assert_eq!(result4.wait(), None);
+    }
+
+    #[test]
+    fn should_keep_separate_buckets_per_client() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::RateLimit {
+                requests_per_second: 0.001,
+                burst: 1,
+            },
+            methods: vec![],
+            deny_notifications: false,
+        });
+        let client1 = Meta {
+            ip: Some([127, 0, 0, 1].into()),
+            transport: None,
+        };
+        let client2 = Meta {
+            ip: Some([127, 0, 0, 2].into()),
+            transport: None,
+        };
+        let next = |_, _| rpc::futures::future::ready(None);
+
+        // when
+        let result1 = middleware.on_call(method_call("eth_getBlock"), client1.clone(), next);
+        let result2 = middleware.on_call(method_call("eth_getBlock"), client1, next);
+        let result3 = middleware.on_call(method_call("eth_getBlock"), client2, next);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), rate_limited());
+        assert_eq!(result3.wait(), None);
+    }
+
+    #[test]
+    fn should_tell_if_rate_limits_are_used() {
+        // given
+        let rate_limit = Access::RateLimit {
+            requests_per_second: 1.0,
+            burst: 1,
+        };
+        let with_method = |policy: Access| {
+            middleware(Permissioning {
+                policy: Access::Allow,
+                methods: vec![Method {
+                    name: "eth_getBlock".into(),
+                    name_pattern: MatchMode::Exact,
+                    policy,
+                    allowed_transports: None,
+                }],
+                deny_notifications: false,
+            })
+        };
+
+        // when
+        let allowed = with_method(Access::Deny);
+        let limited = with_method(rate_limit.clone());
+        let limited_by_base = middleware(Permissioning {
+            policy: rate_limit,
+            methods: vec![],
+            deny_notifications: false,
+        });
+
+        // then
+        assert!(!allowed.uses_rate_limits());
+        assert!(limited.uses_rate_limits());
+        assert!(limited_by_base.uses_rate_limits());
+    }
+
+    #[test]
+    fn should_share_buckets_of_methods_matching_the_same_rule() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::RateLimit {
+                requests_per_second: 0.001,
+                burst: 1,
+            },
+            methods: vec![Method {
+                name: "debug".into(),
+                name_pattern: MatchMode::Regex("debug_.*".into()),
+                policy: Access::RateLimit {
+                    requests_per_second: 0.001,
+                    burst: 1,
+                },
+                allowed_transports: None,
+            }],
+            deny_notifications: false,
+        });
+        let next = |_, _| rpc::futures::future::ready(None);
+
+        // when
+        let result1 = middleware.on_call(method_call("eth_getBlock"), (), next);
+        let result2 = middleware.on_call(method_call("eth_getBalance"), (), next);
+        let result3 = middleware.on_call(method_call("debug_traceCall"), (), next);
+        let result4 = middleware.on_call(method_call("debug_traceBlock"), (), next);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), rate_limited());
+        assert_eq!(result3.wait(), None);
+        assert_eq!(result4.wait(), rate_limited());
+        assert_eq!(middleware.buckets.len(), 2);
+    }
+
+    #[test]
+    fn should_forget_refilled_buckets() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::RateLimit {
+                requests_per_second: 1_000_000.0,
+                burst: 1,
+            },
+            methods: vec![],
+            deny_notifications: false,
+        });
+        let client = |i: usize| Meta {
+            ip: Some([10, 0, (i / 256) as u8, (i % 256) as u8].into()),
+            transport: None,
+        };
+        let next = |_, _| rpc::futures::future::ready(None);
+        for i in 0..MIN_SWEEP_SIZE {
+            middleware.on_call(method_call("eth_getBlock"), client(i), next).wait();
+        }
+        assert_eq!(middleware.buckets.len(), MIN_SWEEP_SIZE);
+        std::thread::sleep(std::time::Duration::from_millis(1));
+
+        // when
+        middleware
+            .on_call(method_call("eth_getBlock"), client(MIN_SWEEP_SIZE), next)
+            .wait();
+
+        // then
+        let clients = middleware
+            .buckets
+            .iter()
+            .map(|bucket| bucket.key().1)
+            .collect::<Vec<_>>();
+        assert_eq!(clients, vec![client(MIN_SWEEP_SIZE).ip.map(Client::Ip)]);
+    }
+
+    #[test]
+    fn should_share_rate_limits_between_clones() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::RateLimit {
+                requests_per_second: 0.001,
+                burst: 1,
+            },
+            methods: vec![],
+            deny_notifications: false,
+        });
+        let clone = middleware.clone();
+        let next = |_, _| rpc::futures::future::ready(None);
+
+        // when
+        let result1 = middleware.on_call(method_call("eth_getBlock"), (), next);
+        let result2 = clone.on_call(method_call("eth_getBlock"), (), next);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), rate_limited());
+    }
+
+    #[test]
+    fn should_refill_token_bucket() {
+        // given
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(10.0, 1, now);
+
+        // when
+        let first = bucket.try_acquire(now);
+        let exhausted = bucket.try_acquire(now);
+        let refilled = bucket.try_acquire(now + std::time::Duration::from_millis(100));
+
+        // then
+        assert!(first);
+        assert!(!exhausted);
+        assert!(refilled);
+    }
+
+    #[test]
+    fn should_deserialize_rate_limit_policy() {
+        // given
+        let json = r#"{
+            "policy": "allow",
+            "methods": [{ "name": "eth_call", "policy": { "rateLimit": { "requestsPerSecond": 5.0, "burst": 10 } } }]
+        }"#;
+
+        // when
+        let config: Permissioning = serde_json::from_str(json).unwrap();
+
+        // then
+        match config.methods[0].policy {
+            Access::RateLimit {
+                requests_per_second,
+                burst,
+            } => {
+                assert_eq!(requests_per_second, 5.0);
+                assert_eq!(burst, 10);
+            }
+            ref other => panic!("Unexpected policy: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_deny_methods_matching_pattern() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Allow,
+            methods: vec![Method {
+                name: "debug".into(),
+                name_pattern: MatchMode::Regex("debug_.*".into()),
+                policy: Access::Deny,
+                allowed_transports: None,
+            }],
+            deny_notifications: false,
+        });
+
+        // when
+        let result1 = middleware.on_call(method_call("debug_traceTransaction"), (), callback().0);
+        let result2 = middleware.on_call(method_call("debug_getBadBlocks"), (), callback().0);
+        let result3 = middleware.on_call(method_call("eth_debug_call"), (), callback().0);
+
+        // then
+        assert_eq!(result1.wait(), not_allowed());
+        assert_eq!(result2.wait(), not_allowed());
+        assert_eq!(result3.wait(), None);
+    }
+
+    #[test]
+    fn should_prefer_exact_match_over_pattern() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Allow,
+            methods: vec![
+                Method {
+                    name: "debug".into(),
+                    name_pattern: MatchMode::Regex("debug_.*".into()),
+                    policy: Access::Deny,
+                    allowed_transports: None,
+                },
+                Method {
+                    name: "debug_getBadBlocks".into(),
+                    name_pattern: MatchMode::Exact,
+                    policy: Access::Allow,
+                    allowed_transports: None,
+                },
+            ],
+            deny_notifications: false,
+        });
+
+        // when
+        let result1 = middleware.on_call(method_call("debug_getBadBlocks"), (), callback().0);
+        let result2 = middleware.on_call(method_call("debug_traceTransaction"), (), callback().0);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), not_allowed());
+    }
+
+    #[test]
+    fn should_use_first_matching_pattern() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Deny,
+            methods: vec![
+                Method {
+                    name: "debug trace".into(),
+                    name_pattern: MatchMode::Regex("debug_trace.*".into()),
+                    policy: Access::Allow,
+                    allowed_transports: None,
+                },
+                Method {
+                    name: "debug".into(),
+                    name_pattern: MatchMode::Regex("debug_.*".into()),
+                    policy: Access::Deny,
+                    allowed_transports: None,
+                },
+            ],
+            deny_notifications: false,
+        });
+
+        // when
+        let result1 = middleware.on_call(method_call("debug_traceTransaction"), (), callback().0);
+        let result2 = middleware.on_call(method_call("debug_getBadBlocks"), (), callback().0);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), not_allowed());
+    }
+
+    #[test]
+    fn should_reject_invalid_pattern() {
+        // given
+        let json = r#"{
+            "policy": "allow",
+            "methods": [{ "name": "debug", "namePattern": { "regex": "debug_(" }, "policy": "deny" }]
+        }"#;
+
+        // when
+        let config: Permissioning = serde_json::from_str(json).unwrap();
+
+        // then
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn should_restrict_method_to_transports() {
+        // given
+        let middleware = middleware(Permissioning {
+            policy: Access::Allow,
+            methods: vec![Method {
+                name: "personal_unlockAccount".into(),
+                name_pattern: MatchMode::Exact,
+                policy: Access::Allow,
+                allowed_transports: Some(vec![TransportKind::Ipc]),
+            }],
+            deny_notifications: false,
+        });
+        let next = |_, _| rpc::futures::future::ready(None);
+
+        // when
+        let result1 = middleware.on_call(method_call("personal_unlockAccount"), over(TransportKind::Ipc), next);
+        let result2 = middleware.on_call(method_call("personal_unlockAccount"), over(TransportKind::Http), next);
+        let result3 = middleware.on_call(method_call("personal_unlockAccount"), Meta::default(), next);
+        let result4 = middleware.on_call(method_call("eth_getBlock"), over(TransportKind::Http), next);
+
+        // then
+        assert_eq!(result1.wait(), None);
+        assert_eq!(result2.wait(), not_allowed());
+        assert_eq!(result3.wait(), not_allowed());
+        assert_eq!(result4.wait(), None);
+    }
+
+    #[test]
+    fn should_deserialize_allowed_transports() {
+        // given
+        let json = r#"{
+            "policy": "allow",
+            "methods": [{ "name": "personal_unlockAccount", "policy": "allow", "allowedTransports": ["ipc", "webSocket"] }]
+        }"#;
+
+        // when
+        let config: Permissioning = serde_json::from_str(json).unwrap();
+
+        // then
+        assert_eq!(
+            config.methods[0].allowed_transports,
+            Some(vec![TransportKind::Ipc, TransportKind::WebSocket])
+        );
+    }
 }
@@ -0,0 +1,61 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! CLI configuration for per-client rate limiting.
+
+/// A configuration option to apply.
+pub enum Param {
+    /// Maximal number of calls to a single method per client within a window (unlimited if `None`).
+    Limit(Option<usize>),
+    /// Methods limited separately, calls to all other methods share a single limit.
+    Methods(Vec<String>),
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "Rate Limiting",
+            "ratelimit-per-ip-limit",
+            "Maximal number of calls to a single method a client can make within any 1-second window. Clients are told apart by their IP, or by the connection if the IP is not known. HTTP requires `--http-trust-forwarded-for` to be used with the limit. Use `-` to disable the limit.",
+            "-",
+            |value: String| {
+                if value == "-" {
+                    return Ok(Param::Limit(None));
+                }
+
+                value
+                    .parse()
+                    .map(|limit| Param::Limit(Some(limit)))
+                    .map_err(|e| format!("Invalid value of ratelimit-per-ip-limit {}: {:?}", value, e))
+            },
+        ),
+        cli_params::Param::new(
+            "Rate Limiting",
+            "ratelimit-per-ip-methods",
+            "Comma-separated list of methods limited separately. Calls to all other methods count towards a single limit per client. Use `-` to limit all methods together.",
+            "-",
+            |value: String| {
+                if value == "-" {
+                    return Ok(Param::Methods(vec![]));
+                }
+
+                Ok(Param::Methods(value.split(',').map(|method| method.trim().to_owned()).collect()))
+            },
+        ),
+    ]
+}
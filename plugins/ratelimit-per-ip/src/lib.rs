@@ -0,0 +1,397 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Per-client rate limiting with a sliding window.
+//!
+//! Unlike the token bucket used by `permissioning` it never allows a burst above the limit:
+//! a client can make at most `limit` calls to a single method within any 1-second window.
+//! Only configured methods are limited separately, calls to other methods share a single window.
+//!
+//! Clients are told apart by their address, or by the connection if the address is not known.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use dashmap::DashMap;
+use jsonrpc_core as rpc;
+use parking_lot::Mutex;
+use permissioning::{Client, ClientIp};
+use rpc::futures::{future::Either, Future};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use upstream::helpers::build_error_response;
+
+pub mod config;
+
+/// Length of the sliding window.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Window key of methods which are not limited separately.
+const OTHER_METHODS: &str = "*";
+
+/// Limits the number of calls per method and client within a sliding window.
+///
+/// Calls of unknown clients share the same limit.
+#[derive(Debug)]
+pub struct SlidingWindowRateLimiter {
+    limit: usize,
+    requests: DashMap<(String, Option<Client>), Mutex<VecDeque<Instant>>>,
+}
+
+impl SlidingWindowRateLimiter {
+    /// Creates new limiter allowing at most `limit` calls per method and client within 1 second.
+    pub fn new(limit: usize) -> Self {
+        SlidingWindowRateLimiter {
+            limit,
+            requests: Default::default(),
+        }
+    }
+
+    /// Records a call to given method made by given client.
+    ///
+    /// Returns `false` if the limit within the current window is already reached.
+    pub fn try_acquire(&self, method: &str, client: Option<Client>) -> bool {
+        self.try_acquire_at(method, client, Instant::now())
+    }
+
+    fn try_acquire_at(&self, method: &str, client: Option<Client>, now: Instant) -> bool {
+        let key = (method.to_owned(), client);
+        if let Some(requests) = self.requests.get(&key) {
+            return admit(&mut requests.lock(), self.limit, now);
+        }
+
+        // Forget windows without calls within the last second to keep the map bounded.
+        self.requests.retain(|_, requests| {
+            requests
+                .get_mut()
+                .back()
+                .is_some_and(|last| now.saturating_duration_since(*last) < WINDOW)
+        });
+        admit(&mut self.requests.entry(key).or_default().lock(), self.limit, now)
+    }
+}
+
+/// Drops requests which left the window and records a new one if the limit allows.
+fn admit(requests: &mut VecDeque<Instant>, limit: usize, now: Instant) -> bool {
+    while let Some(oldest) = requests.front() {
+        if now.saturating_duration_since(*oldest) < WINDOW {
+            break;
+        }
+        requests.pop_front();
+    }
+
+    if requests.len() >= limit {
+        return false;
+    }
+
+    requests.push_back(now);
+    true
+}
+
+/// Per-client rate limiting middleware.
+///
+/// Clones share the same limits.
+#[derive(Debug, Clone)]
+pub struct Middleware {
+    limiter: Option<Arc<SlidingWindowRateLimiter>>,
+    methods: Arc<HashSet<String>>,
+}
+
+impl Middleware {
+    /// Creates new rate limiting middleware.
+    pub fn new(params: &[config::Param]) -> Self {
+        let mut limit = None;
+        let mut methods = HashSet::new();
+        for p in params {
+            match p {
+                config::Param::Limit(l) => limit = *l,
+                config::Param::Methods(m) => methods.extend(m.iter().cloned()),
+            }
+        }
+
+        Middleware {
+            limiter: limit.map(|limit| Arc::new(SlidingWindowRateLimiter::new(limit))),
+            methods: Arc::new(methods),
+        }
+    }
+
+    /// Returns `true` if calls are limited.
+    pub fn is_enabled(&self) -> bool {
+        self.limiter.is_some()
+    }
+}
+
+impl<M: rpc::Metadata + ClientIp> rpc::Middleware<M> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = rpc::futures::future::Ready<Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        let limiter = match self.limiter {
+            Some(ref limiter) => limiter,
+            None => return Either::Right(next(call, meta)),
+        };
+
+        let method = match call {
+            rpc::Call::MethodCall(rpc::MethodCall { ref method, .. }) => method,
+            rpc::Call::Notification(rpc::Notification { ref method, .. }) => method,
+            rpc::Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+
+        let client = meta.client();
+        let window = if self.methods.contains(method) {
+            method.as_str()
+        } else {
+            OTHER_METHODS
+        };
+        if limiter.try_acquire(window, client) {
+            return Either::Right(next(call, meta));
+        }
+
+        log::debug!("Rate limit exceeded for {} by {:?}", method, client);
+        Either::Left(rpc::futures::future::ready(build_error_response(
+            &call,
+            -32005,
+            "Request rate limit exceeded.",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware as MiddlewareTrait;
+
+    fn method_call(name: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params: rpc::Params::Array(vec![]),
+        })
+    }
+
+    #[derive(Clone, Default)]
+    struct Meta {
+        client: Option<Client>,
+    }
+
+    impl rpc::Metadata for Meta {}
+
+    impl ClientIp for Meta {
+        fn client(&self) -> Option<Client> {
+            self.client
+        }
+    }
+
+    fn ip(ip: [u8; 4]) -> Option<Client> {
+        Some(Client::Ip(ip.into()))
+    }
+
+    impl permissioning::IncomingTransport for Meta {}
+
+    fn rate_limited() -> Option<rpc::Output> {
+        Some(rpc::Output::Failure(rpc::Failure {
+            id: rpc::Id::Num(1),
+            error: rpc::Error {
+                code: rpc::ErrorCode::ServerError(-32005),
+                message: "Request rate limit exceeded.".into(),
+                data: None,
+            },
+            jsonrpc: Some(rpc::Version::V2),
+        }))
+    }
+
+    fn call<T: MiddlewareTrait<Meta>>(middleware: &T, method: &str, meta: Meta) -> Option<rpc::Output> {
+        rpc::futures::executor::block_on(
+            middleware.on_call(method_call(method), meta, |_, _| rpc::futures::future::ready(None)),
+        )
+    }
+
+    #[test]
+    fn should_slide_the_window() {
+        // given
+        let limiter = SlidingWindowRateLimiter::new(2);
+        let client = ip([127, 0, 0, 1]);
+        let now = Instant::now();
+
+        // when
+        let first = limiter.try_acquire_at("eth_getBlock", client, now);
+        let second = limiter.try_acquire_at("eth_getBlock", client, now + Duration::from_millis(500));
+        let exhausted = limiter.try_acquire_at("eth_getBlock", client, now + Duration::from_millis(999));
+        let other_method = limiter.try_acquire_at("eth_getBalance", client, now + Duration::from_millis(999));
+        let slid = limiter.try_acquire_at("eth_getBlock", client, now + Duration::from_millis(1000));
+        let exhausted_again = limiter.try_acquire_at("eth_getBlock", client, now + Duration::from_millis(1499));
+
+        // then
+        assert!(first);
+        assert!(second);
+        assert!(!exhausted);
+        assert!(other_method);
+        assert!(slid);
+        assert!(!exhausted_again);
+    }
+
+    #[test]
+    fn should_forget_expired_windows() {
+        // given
+        let limiter = SlidingWindowRateLimiter::new(2);
+        let now = Instant::now();
+        limiter.try_acquire_at("eth_getBlock", ip([127, 0, 0, 1]), now);
+        limiter.try_acquire_at("eth_getBalance", ip([127, 0, 0, 2]), now + Duration::from_millis(500));
+
+        // when
+        limiter.try_acquire_at("eth_getBlock", ip([127, 0, 0, 3]), now + Duration::from_millis(1000));
+
+        // then
+        let mut windows = limiter
+            .requests
+            .iter()
+            .map(|entry| entry.key().0.clone())
+            .collect::<Vec<_>>();
+        windows.sort();
+        assert_eq!(windows, vec!["eth_getBalance".to_owned(), "eth_getBlock".to_owned()]);
+        assert!(limiter
+            .requests
+            .contains_key(&("eth_getBlock".to_owned(), ip([127, 0, 0, 3]))));
+    }
+
+    #[test]
+    fn should_limit_configured_methods_separately() {
+        // given
+        let middleware = Middleware::new(&[
+            config::Param::Limit(Some(1)),
+            config::Param::Methods(vec!["eth_getBlock".into()]),
+        ]);
+        let client = Meta {
+            client: ip([127, 0, 0, 1]),
+        };
+
+        // when
+        let result1 = call(&middleware, "eth_getBlock", client.clone());
+        let result2 = call(&middleware, "eth_getBalance", client.clone());
+        let result3 = call(&middleware, "eth_getCode", client.clone());
+        let result4 = call(&middleware, "eth_getBlock", client);
+
+        // then
+        assert_eq!(result1, None);
+        assert_eq!(result2, None);
+        assert_eq!(result3, rate_limited());
+        assert_eq!(result4, rate_limited());
+    }
+
+    #[test]
+    fn should_keep_separate_windows_per_client() {
+        // given
+        let middleware = Middleware::new(&[config::Param::Limit(Some(1))]);
+        let client1 = Meta {
+            client: ip([127, 0, 0, 1]),
+        };
+        let client2 = Meta {
+            client: ip([127, 0, 0, 2]),
+        };
+
+        // when
+        let result1 = call(&middleware, "eth_getBlock", client1.clone());
+        let result2 = call(&middleware, "eth_getBlock", client1);
+        let result3 = call(&middleware, "eth_getBlock", client2);
+        let result4 = call(&middleware, "eth_getBlock", Meta::default());
+
+        // then
+        assert_eq!(result1, None);
+        assert_eq!(result2, rate_limited());
+        assert_eq!(result3, None);
+        assert_eq!(result4, None);
+    }
+
+    #[test]
+    fn should_keep_separate_windows_per_connection() {
+        // given
+        let middleware = Middleware::new(&[config::Param::Limit(Some(1))]);
+        let connection1 = Meta {
+            client: Some(Client::Connection(1)),
+        };
+        let connection2 = Meta {
+            client: Some(Client::Connection(2)),
+        };
+
+        // when
+        let result1 = call(&middleware, "eth_getBlock", connection1.clone());
+        let result2 = call(&middleware, "eth_getBlock", connection1);
+        let result3 = call(&middleware, "eth_getBlock", connection2);
+
+        // then
+        assert_eq!(result1, None);
+        assert_eq!(result2, rate_limited());
+        assert_eq!(result3, None);
+    }
+
+    #[test]
+    fn should_not_limit_by_default() {
+        // given
+        let middleware = Middleware::new(&[]);
+
+        // when
+        let results = (0..100)
+            .map(|_| call(&middleware, "eth_getBlock", Meta::default()))
+            .collect::<Vec<_>>();
+
+        // then
+        assert!(results.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn should_not_burst_above_the_limit_unlike_token_bucket() {
+        // given
+        let sliding_window = Middleware::new(&[config::Param::Limit(Some(10))]);
+        let token_bucket =
+            permissioning::Middleware::new(&[permissioning::config::Param::Config(permissioning::Permissioning {
+                policy: permissioning::Access::RateLimit {
+                    requests_per_second: 10.0,
+                    burst: 10,
+                },
+                methods: vec![],
+                deny_notifications: false,
+            })]);
+        let count_accepted = || {
+            let by_window = (0..10)
+                .filter(|_| call(&sliding_window, "eth_getBlock", Meta::default()).is_none())
+                .count();
+            let by_bucket = (0..10)
+                .filter(|_| call(&token_bucket, "eth_getBlock", Meta::default()).is_none())
+                .count();
+            (by_window, by_bucket)
+        };
+
+        // when
+        let first = count_accepted();
+        std::thread::sleep(Duration::from_millis(200));
+        let second = count_accepted();
+
+        // then
+        assert_eq!(first, (10, 10));
+        // the bucket got refilled, but all the previous calls are still within the window.
+        assert_eq!(second.0, 0);
+        assert!(second.1 >= 2);
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! CLI configuration for recording and replaying sessions.
+
+use std::path::{Path, PathBuf};
+
+/// A configuration option to apply.
+pub enum Param {
+    /// File to record calls and responses to.
+    Record(Option<PathBuf>),
+    /// File to replay responses from instead of connecting to the upstream.
+    Playback(Option<PathBuf>),
+}
+
+fn optional_path(path: String) -> Option<PathBuf> {
+    if path == "-" {
+        None
+    } else {
+        Some(path.into())
+    }
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "Replay",
+            "replay-record",
+            "A path to a file where all upstream calls and their responses are recorded (NDJSON). Use - to disable.",
+            "-",
+            |path: String| Ok(Param::Record(optional_path(path))),
+        ),
+        cli_params::Param::new(
+            "Replay",
+            "replay-playback",
+            "A path to a previously recorded file. Responses are served from the file instead of the upstream. Use - to disable.",
+            "-",
+            |path: String| Ok(Param::Playback(optional_path(path))),
+        ),
+    ]
+}
+
+/// Returns the playback file if configured.
+pub fn playback(params: &[Param]) -> Option<&Path> {
+    params.iter().find_map(|p| match p {
+        Param::Playback(ref path) => path.as_deref(),
+        _ => None,
+    })
+}
@@ -0,0 +1,323 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Recording and replaying RPC sessions.
+//!
+//! `ReplayRecorder` writes every call together with its response to a NDJSON file,
+//! `ReplayUpstream` serves the recorded responses instead of a real upstream.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use jsonrpc_core as rpc;
+use parking_lot::Mutex;
+use rpc::futures::{
+    future::{self, Either},
+    Future, FutureExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+pub mod config;
+
+/// A single recorded call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The call sent upstream.
+    pub call: rpc::Call,
+    /// The response (if any).
+    pub output: Option<rpc::Output>,
+}
+
+/// Middleware recording calls and their responses.
+///
+/// Should be placed right before the upstream, so that the responses are the ones returned by the node.
+/// Does nothing if the recording is not configured.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayRecorder {
+    file: Option<Arc<Mutex<fs::File>>>,
+}
+
+impl ReplayRecorder {
+    /// Creates new recorder appending to the configured file.
+    pub fn new(params: &[config::Param]) -> Result<Self, String> {
+        let mut path = None;
+        for p in params {
+            match p {
+                config::Param::Record(p) => path = p.clone(),
+                config::Param::Playback(_) => {}
+            }
+        }
+
+        let file = match path {
+            Some(path) => Some(Arc::new(Mutex::new(
+                fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&path)
+                    .map_err(|e| format!("Can't open replay file at {}: {}", path.display(), e))?,
+            ))),
+            None => None,
+        };
+
+        Ok(ReplayRecorder { file })
+    }
+}
+
+fn write(file: &Mutex<fs::File>, entry: &Entry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    line.push(b'\n');
+    file.lock().write_all(&line)
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for ReplayRecorder {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = rpc::middleware::NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        let file = match self.file {
+            Some(ref file) => file.clone(),
+            None => return Either::Right(next(call, meta)),
+        };
+
+        let recorded = call.clone();
+        Either::Left(Box::pin(next(call, meta).map(move |output| {
+            let entry = Entry { call: recorded, output };
+            if let Err(e) = write(&file, &entry) {
+                log::error!("Unable to record call: {:?}", e);
+            }
+            entry.output
+        })))
+    }
+}
+
+/// Key identifying recorded calls regardless of their id.
+fn key(call: &rpc::Call) -> Option<String> {
+    match call {
+        rpc::Call::MethodCall(rpc::MethodCall { method, params, .. })
+        | rpc::Call::Notification(rpc::Notification { method, params, .. }) => {
+            serde_json::to_string(&(method, params)).ok()
+        }
+        rpc::Call::Invalid { .. } => None,
+    }
+}
+
+/// Upstream serving recorded responses.
+///
+/// Calls are matched by method and parameters. If the same call was recorded multiple times,
+/// responses are returned in the recorded order and the last one is repeated afterwards.
+#[derive(Debug)]
+pub struct ReplayUpstream {
+    path: PathBuf,
+    responses: Mutex<HashMap<String, VecDeque<Option<rpc::Output>>>>,
+}
+
+impl ReplayUpstream {
+    /// Reads recorded responses from given file.
+    pub fn new(path: &Path) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("Can't open replay file at {}: {}", path.display(), e))?;
+        let mut responses = HashMap::<_, VecDeque<_>>::new();
+        for (idx, line) in io::BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("Can't read replay file: {}", e))?;
+            let entry: Entry =
+                serde_json::from_str(&line).map_err(|e| format!("Invalid entry at line {}: {}", idx + 1, e))?;
+            if let Some(key) = key(&entry.call) {
+                responses.entry(key).or_default().push_back(entry.output);
+            }
+        }
+
+        println!("[Replay] Serving responses from: {}", path.display());
+
+        Ok(ReplayUpstream {
+            path: path.to_owned(),
+            responses: Mutex::new(responses),
+        })
+    }
+
+    fn respond(&self, call: rpc::Call) -> Option<rpc::Output> {
+        let (id, jsonrpc) = match call {
+            rpc::Call::MethodCall(ref request) => (request.id.clone(), request.jsonrpc),
+            _ => return None,
+        };
+
+        let mut responses = self.responses.lock();
+        let recorded = key(&call).and_then(|key| responses.get_mut(&key)).and_then(|queue| {
+            if queue.len() > 1 {
+                queue.pop_front()
+            } else {
+                queue.front().cloned()
+            }
+        });
+
+        match recorded {
+            Some(Some(rpc::Output::Success(success))) => Some(rpc::Output::from(Ok(success.result), id, jsonrpc)),
+            Some(Some(rpc::Output::Failure(failure))) => Some(rpc::Output::from(Err(failure.error), id, jsonrpc)),
+            Some(None) | None => {
+                log::warn!("No recorded response in {} for {:?}", self.path.display(), call);
+                let error = rpc::Error {
+                    code: rpc::ErrorCode::InternalError,
+                    message: "No recorded response for that call.".into(),
+                    data: None,
+                };
+                Some(rpc::Output::from(Err(error), id, jsonrpc))
+            }
+        }
+    }
+}
+
+impl upstream::Transport for ReplayUpstream {
+    type Error = String;
+    type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        _session: Option<Arc<jsonrpc_pubsub::Session>>,
+        _subscription: upstream::Subscription,
+    ) -> Self::Future {
+        self.send(call)
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        _session: Option<Arc<jsonrpc_pubsub::Session>>,
+        _subscription: upstream::Subscription,
+    ) -> Self::Future {
+        self.send(call)
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        future::ready(Ok(self.respond(call)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware;
+    use upstream::Transport;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("replay-{}-{}.ndjson", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn method_call(id: u64, method: &str, params: Vec<rpc::Value>) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(id),
+            method: method.into(),
+            params: rpc::Params::Array(params),
+        })
+    }
+
+    /// Upstream returning the block number and failing other methods.
+    fn upstream(call: rpc::Call, _: ()) -> future::Ready<Option<rpc::Output>> {
+        future::ready(match call {
+            rpc::Call::MethodCall(request) => {
+                let result = match request.method.as_str() {
+                    "eth_blockNumber" => Ok("0x10".into()),
+                    "eth_getBalance" => Ok(request.params.parse::<(String,)>().unwrap().0.into()),
+                    _ => Err(rpc::Error::method_not_found()),
+                };
+                Some(rpc::Output::from(result, request.id, request.jsonrpc))
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn should_replay_recorded_session() {
+        // given
+        let path = temp_path("session");
+        let recorder = ReplayRecorder::new(&[config::Param::Record(Some(path.clone()))]).unwrap();
+        let calls = vec![
+            method_call(1, "eth_blockNumber", vec![]),
+            method_call(2, "eth_getBalance", vec!["0x1".into()]),
+            method_call(3, "eth_getBalance", vec!["0x2".into()]),
+            method_call(4, "eth_unknown", vec![]),
+        ];
+        let recorded = calls
+            .iter()
+            .map(|call| rpc::futures::executor::block_on(recorder.on_call(call.clone(), (), upstream)))
+            .collect::<Vec<_>>();
+
+        // when
+        let replay = ReplayUpstream::new(&path).unwrap();
+        let replayed = calls
+            .into_iter()
+            .map(|call| rpc::futures::executor::block_on(replay.send(call)).unwrap())
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(replayed, recorded);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_respond_with_id_of_the_replayed_call() {
+        // given
+        let path = temp_path("ids");
+        let recorder = ReplayRecorder::new(&[config::Param::Record(Some(path.clone()))]).unwrap();
+        rpc::futures::executor::block_on(recorder.on_call(method_call(1, "eth_blockNumber", vec![]), (), upstream));
+
+        // when
+        let replay = ReplayUpstream::new(&path).unwrap();
+        let res = rpc::futures::executor::block_on(replay.send(method_call(5, "eth_blockNumber", vec![]))).unwrap();
+
+        // then
+        assert_eq!(
+            res,
+            Some(rpc::Output::from(
+                Ok("0x10".into()),
+                rpc::Id::Num(5),
+                Some(rpc::Version::V2)
+            ))
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_fail_calls_that_were_not_recorded() {
+        // given
+        let path = temp_path("missing");
+        fs::write(&path, "").unwrap();
+        let replay = ReplayUpstream::new(&path).unwrap();
+
+        // when
+        let res = rpc::futures::executor::block_on(replay.send(method_call(1, "eth_blockNumber", vec![]))).unwrap();
+
+        // then
+        match res {
+            Some(rpc::Output::Failure(failure)) => assert_eq!(failure.error.code, rpc::ErrorCode::InternalError),
+            other => panic!("Unexpected output: {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
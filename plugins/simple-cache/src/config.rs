@@ -19,60 +19,160 @@
 
 use cli_params;
 use serde_json;
-use std::{fs, io};
-use Method;
+use serde_path_to_error;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use {CacheEviction, Method};
 
 /// A configuration option to apply.
 pub enum Param {
     /// Methods that should be cached.
     Config(Cache),
+    /// Methods that should be cached, loaded from given file.
+    ConfigFile(PathBuf, Cache),
+    /// Log cache key hashes of all calls to cached methods.
+    LogRequests(bool),
+    /// Reload methods whenever the configuration file changes.
+    Watch(bool),
 }
 
 /// Returns a list of supported configuration parameters.
 pub fn params() -> Vec<cli_params::Param<Param>> {
-    vec![cli_params::Param::new(
-        "Simple Cache",
-        "simple-cache-config",
-        "A path to a JSON file containing a list of methods that should be cached. See examples for the file schema.",
-        "-",
-        |path: String| {
-            if &path == "-" {
-                return Ok(Param::Config(Default::default()));
-            }
+    vec![
+        cli_params::Param::new(
+            "Simple Cache",
+            "simple-cache-config",
+            "A path to a JSON file containing a list of methods that should be cached. See examples for the file schema.",
+            "-",
+            |path: String| {
+                if &path == "-" {
+                    return Ok(Param::Config(Default::default()));
+                }
+
+                let cache = load(Path::new(&path))?;
+                Ok(Param::ConfigFile(path.into(), cache))
+            },
+        ),
+        cli_params::Param::new(
+            "Simple Cache",
+            "simple-cache-log-requests",
+            "Log the method, cache key hash and hit/miss of every call to a cached method (requires debug logging).",
+            "false",
+            |value: String| {
+                value
+                    .parse()
+                    .map(Param::LogRequests)
+                    .map_err(|e| format!("Invalid value of simple-cache-log-requests {}: {:?}", value, e))
+            },
+        )
+        .hidden(),
+        cli_params::Param::new(
+            "Simple Cache",
+            "simple-cache-config-watch",
+            "Reload the list of cached methods whenever the file given in `simple-cache-config` changes. Results of removed methods are evicted when the method is called.",
+            "false",
+            |value: String| {
+                value
+                    .parse()
+                    .map(Param::Watch)
+                    .map_err(|e| format!("Invalid value of simple-cache-config-watch {}: {:?}", value, e))
+            },
+        ),
+    ]
+}
+
+/// Loads the cache configuration from given JSON file.
+pub fn load(path: &Path) -> Result<Cache, String> {
+    let file = fs::File::open(path).map_err(|e| format!("Can't open cache file at {}: {:?}", path.display(), e))?;
+    let buf_file = io::BufReader::new(file);
+    parse(buf_file).map_err(|e| format!("Invalid JSON at {}: {}", path.display(), e))
+}
+
+/// Parses the cache configuration, reporting the path of the invalid field on error.
+pub fn parse<R: io::Read>(reader: R) -> Result<Cache, ConfigError> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let cache = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| ConfigError {
+        path: e.path().to_string(),
+        message: e.into_inner().to_string(),
+    })?;
+    // make sure there is no trailing data
+    deserializer.end().map_err(|e| ConfigError {
+        path: ".".into(),
+        message: e.to_string(),
+    })?;
+    Ok(cache)
+}
 
-            let file = fs::File::open(&path).map_err(|e| format!("Can't open cache file at {}: {:?}", path, e))?;
-            let buf_file = io::BufReader::new(file);
-            let methods: Cache =
-                serde_json::from_reader(buf_file).map_err(|e| format!("Invalid JSON at {}: {:?}", path, e))?;
-            Ok(Param::Config(methods))
-        },
-    )]
+/// Invalid cache configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Path to the invalid field (e.g. `methods[2].eviction`).
+    pub path: String,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}: {}", self.path, self.message)
+    }
 }
 
 /// Add methods given as the first parameter to the config in one of the params.
 pub fn add_methods(params: &mut [Param], methods: Vec<Method>) {
     for p in params {
         match p {
-            Param::Config(ref mut config) => {
+            Param::Config(ref mut config) | Param::ConfigFile(_, ref mut config) => {
                 config.methods.extend(methods.clone());
             }
+            Param::LogRequests(_) | Param::Watch(_) => {}
         }
     }
 }
 
 /// Cache configuration
 #[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Cache {
     /// If not enabled method definitions are ignored.
     pub enabled: bool,
     /// Per-method definitions
     pub methods: Vec<Method>,
+    /// Eviction policy of methods that are not listed in `methods`.
+    ///
+    /// If not set, unlisted methods are not cached. Subscriptions, filters and methods
+    /// changing the state (e.g. sending transactions) are never cached this way,
+    /// and only a limited number of results of every method is kept.
+    #[serde(default)]
+    pub default_eviction: Option<CacheEviction>,
+    /// Compress large cached results to reduce memory usage (at the cost of CPU time on every hit).
+    #[serde(default)]
+    pub compress: bool,
+    /// Minimal size (in bytes) of a serialized response to be cached.
+    ///
+    /// Smaller responses (e.g. `null` or `false` results) are forwarded on every call. All responses are cached if not set.
+    #[serde(default)]
+    pub min_response_size: Option<usize>,
 }
+
+impl Cache {
+    /// Cache results of all unlisted methods using given eviction policy.
+    pub fn with_default_eviction(mut self, eviction: CacheEviction) -> Self {
+        self.default_eviction = Some(eviction);
+        self
+    }
+}
+
 impl Default for Cache {
     fn default() -> Self {
         Self {
             enabled: true,
             methods: Default::default(),
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         }
     }
 }
@@ -85,4 +185,50 @@ mod tests {
     fn should_deserialize_example() {
         let _m: Cache = serde_json::from_slice(include_bytes!("../../../examples/cache.json")).unwrap();
     }
+
+    #[test]
+    fn should_report_path_of_invalid_field() {
+        // given
+        let json = r#"{"enabled":true,"methods":[
+            {"name":"eth_chainId","eviction":"block"},
+            {"name":"net_version","eviction":"block"},
+            {"name":"eth_getBlockByNumber","eviction":"forever"}
+        ]}"#;
+
+        // when
+        let err = parse(json.as_bytes()).err().unwrap();
+
+        // then
+        assert_eq!(err.path, "methods[2].eviction");
+        assert!(err.message.starts_with("unknown variant `forever`"), "{}", err.message);
+    }
+
+    #[test]
+    fn should_include_path_in_load_error() {
+        // given
+        let path = std::env::temp_dir().join(format!("simple-cache-invalid-{}.json", std::process::id()));
+        fs::write(&path, r#"{"enabled":"yes","methods":[]}"#).unwrap();
+
+        // when
+        let err = load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // then
+        assert!(err.contains("enabled: invalid type"), "{}", err);
+    }
+
+    #[test]
+    fn should_deserialize_default_eviction() {
+        // given
+        let json = r#"{"enabled":true,"methods":[],"defaultEviction":{"time":{"secs":5,"nanos":0}}}"#;
+
+        // when
+        let cache: Cache = serde_json::from_str(json).unwrap();
+
+        // then
+        match cache.default_eviction {
+            Some(CacheEviction::Time(duration)) => assert_eq!(duration.as_secs(), 5),
+            other => panic!("Unexpected default eviction: {:?}", other),
+        }
+    }
 }
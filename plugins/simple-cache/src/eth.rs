@@ -0,0 +1,75 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Ethereum-specific normalization of cache keys.
+
+use rpc;
+
+/// Converts decimal block numbers to hex, so that equivalent calls share a cache entry.
+///
+/// Meant to be used with `Method::with_key_transform`, e.g. `["1", true]` and `["0x1", true]`
+/// passed to `eth_getBlockByNumber` become the same key. Other parameters are left intact.
+pub fn normalize_block_tag(params: &rpc::Params) -> rpc::Params {
+    match *params {
+        rpc::Params::Array(ref params) => rpc::Params::Array(params.iter().map(normalize_number).collect()),
+        ref params => params.clone(),
+    }
+}
+
+fn normalize_number(param: &rpc::Value) -> rpc::Value {
+    match *param {
+        rpc::Value::String(ref value) if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+            match value.parse::<u64>() {
+                Ok(number) => rpc::Value::String(format!("0x{:x}", number)),
+                Err(_) => param.clone(),
+            }
+        }
+        _ => param.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {CacheEviction, Method};
+
+    fn params(block: &str) -> rpc::Params {
+        rpc::Params::Array(vec![block.into(), true.into()])
+    }
+
+    #[test]
+    fn should_use_same_key_for_decimal_and_hex_block_numbers() {
+        // given
+        let method = Method::new("eth_getBlockByNumber", CacheEviction::Block).with_key_transform(normalize_block_tag);
+
+        // then
+        assert_eq!(method.hash(&params("1"), None), method.hash(&params("0x1"), None));
+        assert_eq!(method.hash(&params("255"), None), method.hash(&params("0xff"), None));
+        assert_ne!(method.hash(&params("1"), None), method.hash(&params("0x2"), None));
+    }
+
+    #[test]
+    fn should_leave_other_params_intact() {
+        assert_eq!(normalize_block_tag(&params("latest")), params("latest"));
+        assert_eq!(normalize_block_tag(&params("0x10")), params("0x10"));
+        assert_eq!(
+            normalize_block_tag(&params("99999999999999999999")),
+            params("99999999999999999999")
+        );
+        assert_eq!(normalize_block_tag(&rpc::Params::None), rpc::Params::None);
+    }
+}
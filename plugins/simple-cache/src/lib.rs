@@ -23,32 +23,53 @@
 #![warn(missing_docs)]
 #![warn(unused_extern_crates)]
 
+extern crate arc_swap;
 extern crate cli_params;
 extern crate fnv;
 extern crate jsonrpc_core as rpc;
+extern crate notify;
 extern crate parking_lot;
 extern crate serde_json;
+extern crate serde_path_to_error;
+#[cfg(feature = "tower")]
+extern crate tower;
 extern crate twox_hash;
 
+#[macro_use]
+extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
-use fnv::FnvHashMap;
+use arc_swap::ArcSwap;
+use fnv::{FnvHashMap, FnvHashSet};
 use parking_lot::RwLock;
 use rpc::futures::{
     future::{self, Either},
     Future,
 };
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    convert::TryFrom,
     hash::{Hash as HashTrait, Hasher},
     io,
-    sync::Arc,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
     time,
 };
 
 type Hash = u64;
 
 pub mod config;
+pub mod eth;
+#[cfg(feature = "tower")]
+mod tower_middleware;
+
+#[cfg(feature = "tower")]
+pub use tower_middleware::{TowerFuture, TowerMiddleware};
 
 /// Cache eviction policy
 #[derive(Clone, Debug, Deserialize)]
@@ -56,13 +77,230 @@ pub mod config;
 pub enum CacheEviction {
     /// Time-based caching. The cache entry is discarded after given amount of time.
     Time(time::Duration),
-    // TODO [ToDr] notification (via subscription)
+    /// Block-based caching. The cache entry is discarded when a new block is imported.
+    ///
+    /// Requires new blocks to be reported via `Middleware::new_block`.
+    Block,
+    /// Block-count-based caching. The cache entry is discarded after given number of blocks.
+    ///
+    /// Requires the current block number to be reported via `Middleware::set_block_number`.
+    BlockCount(u64),
+}
+
+impl ::std::fmt::Display for CacheEviction {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            CacheEviction::Time(duration) if duration.subsec_nanos() == 0 => write!(fmt, "{}s", duration.as_secs()),
+            CacheEviction::Time(duration) => write!(fmt, "{}ms", duration.as_millis()),
+            CacheEviction::Block => write!(fmt, "block"),
+            CacheEviction::BlockCount(blocks) => write!(fmt, "{} blocks", blocks),
+        }
+    }
+}
+
+/// Parameters of a call included in the cache key.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ParamHashMode {
+    /// All parameters (`"all"`).
+    #[default]
+    All,
+    /// Only the first N parameters (`"first:N"`).
+    First(usize),
+    /// Parameters at given indices (`"indices:0,2"`).
+    Indices(Vec<usize>),
+}
+
+impl TryFrom<String> for ParamHashMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = |e: std::num::ParseIntError| format!("Invalid param hash mode {}: {:?}", value, e);
+        if value == "all" {
+            return Ok(ParamHashMode::All);
+        }
+        if let Some(count) = value.strip_prefix("first:") {
+            return count.trim().parse().map(ParamHashMode::First).map_err(invalid);
+        }
+        if let Some(indices) = value.strip_prefix("indices:") {
+            return indices
+                .split(',')
+                .map(|index| index.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(ParamHashMode::Indices)
+                .map_err(invalid);
+        }
+        Err(format!(
+            "Invalid param hash mode {}: expected \"all\", \"first:N\" or \"indices:I,J\"",
+            value
+        ))
+    }
+}
+
+impl ParamHashMode {
+    /// Selects parameters that should be hashed.
+    ///
+    /// Only positional parameters are selected, named ones are always hashed in full.
+    fn select<'a>(&self, parameters: &'a rpc::Params) -> Cow<'a, rpc::Params> {
+        match (self, parameters) {
+            (ParamHashMode::First(count), rpc::Params::Array(params)) => {
+                Cow::Owned(rpc::Params::Array(params.iter().take(*count).cloned().collect()))
+            }
+            (ParamHashMode::Indices(indices), rpc::Params::Array(params)) => Cow::Owned(rpc::Params::Array(
+                indices
+                    .iter()
+                    .map(|index| params.get(*index).cloned().unwrap_or(rpc::Value::Null))
+                    .collect(),
+            )),
+            _ => Cow::Borrowed(parameters),
+        }
+    }
+}
+
+/// Describes a cached method together with its usage.
+#[derive(Clone, Debug)]
+pub struct CachedMethod {
+    /// Method name
+    pub name: String,
+    /// Eviction policy of the method
+    pub eviction: CacheEviction,
+    /// Number of calls answered from the cache
+    pub hits: u64,
 }
 
 /// Method metadata
 #[derive(Debug)]
 enum MethodMeta {
     Deadline(time::Instant),
+    Generation(usize),
+    SpecVersion(u64),
+    BlockExpiry { created_block: u64, max_blocks: u64 },
+}
+
+/// Minimal size of a serialized result to be compressed.
+const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Maximal number of cached results of every method cached with the default eviction policy.
+const DEFAULT_MAX_ENTRIES: usize = 1024;
+
+/// Methods which results are never cached with the default eviction policy.
+///
+/// Filters return changes since the last call and the rest have side effects.
+/// Subscriptions are recognized by name (see `is_subscription`).
+const NEVER_CACHED: &[&str] = &[
+    "eth_newFilter",
+    "eth_newBlockFilter",
+    "eth_newPendingTransactionFilter",
+    "eth_getFilterChanges",
+    "eth_getFilterLogs",
+    "eth_uninstallFilter",
+    "eth_sendRawTransaction",
+    "eth_sendTransaction",
+    "eth_sign",
+    "eth_signTransaction",
+    "eth_submitWork",
+    "eth_submitHashrate",
+    "author_submitExtrinsic",
+    "author_submitAndWatchExtrinsic",
+];
+
+/// Returns `true` if the method (un)subscribes, e.g. `eth_subscribe` or `chain_unsubscribeNewHeads`.
+fn is_subscription(method: &str) -> bool {
+    method.contains("subscribe") || method.contains("Subscribe")
+}
+
+/// Returns `true` if results of given unlisted method can be cached with the default eviction policy.
+fn is_cacheable_by_default(method: &str) -> bool {
+    !NEVER_CACHED.contains(&method) && !method.starts_with("personal_") && !is_subscription(method)
+}
+
+/// A cached result, compressed if large enough and compression is enabled.
+#[derive(Debug, Clone)]
+enum CachedOutput {
+    Plain(Option<rpc::Output>),
+    /// LZ4-compressed JSON of the output.
+    Compressed(Vec<u8>),
+}
+
+impl CachedOutput {
+    fn new(output: &Option<rpc::Output>, compress: bool) -> Self {
+        if let (true, Some(ref out)) = (compress, output) {
+            let json = serde_json::to_vec(out).expect("Output serialization is infallible.");
+            if json.len() >= COMPRESSION_THRESHOLD {
+                return CachedOutput::Compressed(lz4_flex::compress_prepend_size(&json));
+            }
+        }
+
+        CachedOutput::Plain(output.clone())
+    }
+
+    /// Returns the (decompressed) result.
+    fn output(&self) -> Result<Option<rpc::Output>, String> {
+        match *self {
+            CachedOutput::Plain(ref output) => Ok(output.clone()),
+            CachedOutput::Compressed(ref compressed) => {
+                let json = lz4_flex::decompress_size_prepended(compressed).map_err(|e| e.to_string())?;
+                serde_json::from_slice(&json).map(Some).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+type CacheEntry = (CachedOutput, MethodMeta);
+
+/// Cached results together with the entries of every method
+/// and the insertion order of methods with limited number of entries.
+#[derive(Debug, Default)]
+struct Cached {
+    entries: FnvHashMap<Hash, CacheEntry>,
+    methods: FnvHashMap<String, FnvHashSet<Hash>>,
+    limited: FnvHashMap<String, VecDeque<Hash>>,
+}
+
+impl Cached {
+    /// Inserts a new entry, evicting the oldest ones if the method exceeds `max_entries`.
+    fn insert(&mut self, method: String, max_entries: Option<usize>, hash: Hash, entry: CacheEntry) {
+        self.entries.insert(hash, entry);
+        let hashes = self.methods.entry(method.clone()).or_default();
+        hashes.insert(hash);
+        if let Some(max_entries) = max_entries {
+            let order = self.limited.entry(method).or_default();
+            if !order.contains(&hash) {
+                order.push_back(hash);
+            }
+            while order.len() > max_entries {
+                if let Some(evicted) = order.pop_front() {
+                    self.entries.remove(&evicted);
+                    hashes.remove(&evicted);
+                }
+            }
+        }
+    }
+
+    /// Removes all entries of given method.
+    fn remove_method(&mut self, method: &str) {
+        for hash in self.methods.remove(method).unwrap_or_default() {
+            self.entries.remove(&hash);
+        }
+        self.limited.remove(method);
+    }
+
+    /// Removes all entries.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.methods.clear();
+        self.limited.clear();
+    }
+}
+
+/// Transformation of parameters applied before computing the cache key.
+#[derive(Clone)]
+pub struct KeyTransform(Arc<dyn Fn(&rpc::Params) -> rpc::Params + Send + Sync>);
+
+impl ::std::fmt::Debug for KeyTransform {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(fmt, "KeyTransform")
+    }
 }
 
 /// Represents a cacheable method.
@@ -70,9 +308,35 @@ enum MethodMeta {
 /// Should know how to compute a hash that is used to compare requests.
 /// TODO [ToDr] Support different eviction policies.
 #[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Method {
     name: String,
     eviction: CacheEviction,
+    /// Maximal number of cached results (for different parameters) of this method.
+    #[serde(default)]
+    max_entries: Option<usize>,
+    /// Include the runtime `spec_version` in the cache key.
+    ///
+    /// Block-evicted entries of such methods are kept across new blocks
+    /// for as long as the `spec_version` stays the same.
+    #[serde(default)]
+    metadata_version_aware: bool,
+    /// Normalizes parameters before computing the cache key.
+    #[serde(skip)]
+    key_transform: Option<KeyTransform>,
+    /// Cache successful responses with `null` result (e.g. a receipt of a pending transaction).
+    #[serde(default = "cache_null_results_default")]
+    cache_null_results: bool,
+    /// Parameters included in the cache key.
+    #[serde(default)]
+    hash_params: ParamHashMode,
+    /// Sort keys of JSON objects in parameters before computing the cache key.
+    #[serde(default)]
+    canonical_params: bool,
+}
+
+fn cache_null_results_default() -> bool {
+    true
 }
 
 impl Method {
@@ -81,28 +345,106 @@ impl Method {
         Method {
             name: name.into(),
             eviction,
+            max_entries: None,
+            metadata_version_aware: false,
+            key_transform: None,
+            cache_null_results: true,
+            hash_params: ParamHashMode::All,
+            canonical_params: false,
         }
     }
 
+    /// Limit the number of cached results of this method.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Include the runtime `spec_version` in the cache key.
+    pub fn metadata_version_aware(mut self, metadata_version_aware: bool) -> Self {
+        self.metadata_version_aware = metadata_version_aware;
+        self
+    }
+
+    /// Cache successful responses with `null` result.
+    ///
+    /// Enabled by default, if disabled such calls are forwarded every time.
+    pub fn cache_null_results(mut self, cache_null_results: bool) -> Self {
+        self.cache_null_results = cache_null_results;
+        self
+    }
+
+    /// Include only some of the parameters in the cache key.
+    ///
+    /// Useful to let calls differing only in irrelevant parameters share a cache entry.
+    pub fn with_hash_params(mut self, hash_params: ParamHashMode) -> Self {
+        self.hash_params = hash_params;
+        self
+    }
+
+    /// Sort keys of JSON objects in parameters before computing the cache key.
+    ///
+    /// Lets calls with the same objects share a cache entry regardless of the order of their fields.
+    pub fn canonical_params(mut self, canonical_params: bool) -> Self {
+        self.canonical_params = canonical_params;
+        self
+    }
+
+    /// Normalize parameters before computing the cache key.
+    ///
+    /// Useful to let equivalent calls share a cache entry, e.g. by resolving `"latest"`
+    /// block tag to the current block number.
+    pub fn with_key_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&rpc::Params) -> rpc::Params + Send + Sync + 'static,
+    {
+        self.key_transform = Some(KeyTransform(Arc::new(transform)));
+        self
+    }
+
     /// Returns a hash of parameters of this method.
-    fn hash(&self, parameters: &rpc::Params) -> Hash {
+    fn hash(&self, parameters: &rpc::Params, spec_version: Option<u64>) -> Hash {
         let mut hasher = twox_hash::XxHash::default();
         self.name.hash(&mut hasher);
-        serde_json::to_writer(HashWriter(&mut hasher), parameters).expect("HashWriter never fails.");
+        if self.metadata_version_aware {
+            spec_version.hash(&mut hasher);
+        }
+        let parameters = match self.key_transform {
+            Some(KeyTransform(ref transform)) => Cow::Owned(transform(parameters)),
+            None => Cow::Borrowed(parameters),
+        };
+        let parameters = self.hash_params.select(&parameters);
+        if self.canonical_params {
+            canonical_hash(&parameters).hash(&mut hasher);
+        } else {
+            serde_json::to_writer(HashWriter(&mut hasher), &parameters).expect("HashWriter never fails.");
+        }
         hasher.finish()
     }
 
     /// Generates metadata that should be stored in the cache together with the value.
-    fn meta(&self) -> MethodMeta {
-        match self.eviction {
-            CacheEviction::Time(duration) => MethodMeta::Deadline(time::Instant::now() + duration),
+    fn meta(&self, generation: usize, block: u64, spec_version: Option<u64>) -> MethodMeta {
+        match (&self.eviction, spec_version) {
+            (CacheEviction::BlockCount(max_blocks), _) => MethodMeta::BlockExpiry {
+                created_block: block,
+                max_blocks: *max_blocks,
+            },
+            (CacheEviction::Time(duration), _) => MethodMeta::Deadline(time::Instant::now() + *duration),
+            (CacheEviction::Block, Some(version)) if self.metadata_version_aware => MethodMeta::SpecVersion(version),
+            (CacheEviction::Block, _) => MethodMeta::Generation(generation),
         }
     }
 
     /// Determines if the cached result is still ok to use.
-    fn is_fresh(&self, meta: &MethodMeta) -> bool {
+    fn is_fresh(&self, meta: &MethodMeta, generation: usize, block: u64, spec_version: Option<u64>) -> bool {
         match *meta {
             MethodMeta::Deadline(deadline) => time::Instant::now() < deadline,
+            MethodMeta::Generation(cached) => cached == generation,
+            MethodMeta::SpecVersion(cached) => Some(cached) == spec_version,
+            MethodMeta::BlockExpiry {
+                created_block,
+                max_blocks,
+            } => block.saturating_sub(created_block) < max_blocks,
         }
     }
 }
@@ -111,84 +453,384 @@ impl Method {
 ///
 /// Takes a list of cacheable methods as a parameter. Can construct multiple caches
 /// for single method, based on the parameters.
-#[derive(Debug)]
+///
+/// Clones share the same cache.
+#[derive(Debug, Clone)]
 pub struct Middleware {
     enabled: bool,
-    cacheable: FnvHashMap<String, Method>,
-    cached: Arc<RwLock<FnvHashMap<Hash, (Option<rpc::Output>, MethodMeta)>>>,
+    log_requests: bool,
+    cacheable: Arc<ArcSwap<FnvHashMap<String, Method>>>,
+    default_eviction: Option<CacheEviction>,
+    compress: bool,
+    min_response_size: Option<usize>,
+    cached: Arc<RwLock<Cached>>,
+    generation: Arc<AtomicUsize>,
+    block_number: Arc<AtomicU64>,
+    spec_version: Arc<RwLock<Option<u64>>>,
+    hits: Arc<RwLock<FnvHashMap<String, AtomicU64>>>,
+    total_hits: Arc<AtomicU64>,
+    watcher: Option<Arc<notify::RecommendedWatcher>>,
 }
 
 impl Middleware {
     /// Creates new caching middleware given cacheable methods definitions.
     ///
+    /// If watching is enabled, methods are reloaded whenever the configuration file changes.
+    ///
     /// TODO [ToDr] Cache limits
     pub fn new(params: &[config::Param]) -> Self {
         let mut cache = config::Cache::default();
+        let mut path = None;
+        let mut log_requests = false;
+        let mut watch = false;
         for p in params {
             match p {
                 config::Param::Config(ref m) => cache = m.clone(),
+                config::Param::ConfigFile(ref file, ref m) => {
+                    cache = m.clone();
+                    path = Some(file.clone());
+                }
+                config::Param::LogRequests(enabled) => log_requests = *enabled,
+                config::Param::Watch(enabled) => watch = *enabled,
             }
         }
 
-        Middleware {
+        let mut middleware = Middleware {
             enabled: cache.enabled,
-            cacheable: cache.methods.into_iter().map(|x| (x.name.clone(), x)).collect(),
+            log_requests,
+            cacheable: Default::default(),
+            default_eviction: cache.default_eviction,
+            compress: cache.compress,
+            min_response_size: cache.min_response_size,
             cached: Default::default(),
+            generation: Default::default(),
+            block_number: Default::default(),
+            spec_version: Default::default(),
+            hits: Default::default(),
+            total_hits: Default::default(),
+            watcher: None,
+        };
+        middleware.reload(&[], cache.methods);
+
+        match path {
+            Some(path) if watch => match middleware.clone().watch(path) {
+                Ok(watcher) => middleware.watcher = Some(Arc::new(watcher)),
+                Err(e) => warn!("Unable to watch cache configuration: {}", e),
+            },
+            None if watch => warn!("Cache configuration file not given, there is nothing to watch."),
+            _ => {}
+        }
+
+        middleware
+    }
+
+    /// Replaces `previous` methods with the new definitions.
+    ///
+    /// Results of removed methods stay in the cache until the method is called.
+    fn reload(&self, previous: &[String], methods: Vec<Method>) {
+        {
+            let mut hits = self.hits.write();
+            for method in &methods {
+                hits.entry(method.name.clone()).or_default();
+            }
+        }
+
+        let mut cacheable = FnvHashMap::clone(&self.cacheable.load());
+        for name in previous {
+            cacheable.remove(name);
         }
+        cacheable.extend(methods.into_iter().map(|x| (x.name.clone(), x)));
+        self.cacheable.store(Arc::new(cacheable));
     }
-}
 
-impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
-    type Future = rpc::middleware::NoopFuture;
-    type CallFuture = Either<rpc::middleware::NoopCallFuture, rpc::futures::future::Ready<Option<rpc::Output>>>;
+    /// Reloads methods defined in given file whenever it changes.
+    ///
+    /// The parent directory is watched, since editors often replace the file instead of modifying it.
+    fn watch(self, path: PathBuf) -> Result<notify::RecommendedWatcher, String> {
+        use notify::Watcher;
 
-    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
-    where
-        F: FnOnce(rpc::Call, M) -> X + Send,
-        X: Future<Output = Option<rpc::Output>> + Send + 'static,
-    {
-        use rpc::futures::FutureExt;
+        let directory = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => PathBuf::from("."),
+            Some(parent) => parent.to_path_buf(),
+            None => return Err(format!("Invalid cache configuration path: {}", path.display())),
+        };
+        let mut previous = self.cacheable.load().keys().cloned().collect::<Vec<_>>();
+        let file_name = path.file_name().map(|name| name.to_owned());
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Error watching cache configuration: {:?}", e);
+                    return;
+                }
+            };
+            let changed = (event.kind.is_create() || event.kind.is_modify())
+                && event.paths.iter().any(|p| p.file_name() == file_name.as_deref());
+            if !changed {
+                return;
+            }
+
+            match config::load(&path) {
+                Ok(cache) => {
+                    info!("Reloading cached methods from {}", path.display());
+                    let names = cache.methods.iter().map(|x| x.name.clone()).collect();
+                    self.reload(&previous, cache.methods);
+                    previous = names;
+                }
+                Err(e) => warn!("Unable to reload cache configuration: {}", e),
+            }
+        })
+        .map_err(|e| format!("{:?}", e))?;
+        watcher
+            .watch(&directory, notify::RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Unable to watch {}: {:?}", directory.display(), e))?;
+        Ok(watcher)
+    }
+
+    /// Returns `true` if any of the cached methods is evicted on new blocks.
+    pub fn uses_block_eviction(&self) -> bool {
+        let is_block =
+            |eviction: &CacheEviction| matches!(eviction, CacheEviction::Block | CacheEviction::BlockCount(_));
+        self.enabled
+            && (self.default_eviction.as_ref().is_some_and(is_block)
+                || self.cacheable.load().values().any(|method| is_block(&method.eviction)))
+    }
+
+    /// Returns the counter of calls answered from the cache (shared with all clones).
+    pub fn hits_counter(&self) -> Arc<AtomicU64> {
+        self.total_hits.clone()
+    }
+
+    /// Returns all cacheable methods ordered by name (empty if the cache is disabled).
+    pub fn methods(&self) -> Vec<CachedMethod> {
+        if !self.enabled {
+            return vec![];
+        }
+
+        let hits = self.hits.read();
+        let mut methods = self
+            .cacheable
+            .load()
+            .values()
+            .map(|method| CachedMethod {
+                name: method.name.clone(),
+                eviction: method.eviction.clone(),
+                hits: hits.get(&method.name).map_or(0, |hits| hits.load(Ordering::Relaxed)),
+            })
+            .collect::<Vec<_>>();
+        methods.sort_by(|a, b| a.name.cmp(&b.name));
+        methods
+    }
 
+    /// Returns names of all cacheable methods ordered by name (empty if the cache is disabled).
+    pub fn cached_methods(&self) -> Vec<String> {
         if !self.enabled {
-            return Either::Right(next(call, meta));
+            return vec![];
+        }
+
+        let mut methods = self.cacheable.load().keys().cloned().collect::<Vec<_>>();
+        methods.sort();
+        methods
+    }
+
+    /// Returns the method name and the remaining time to live (in milliseconds) of every cached result.
+    ///
+    /// Only time-evicted results that haven't expired yet are listed, ordered by method name.
+    pub fn active_cache_entries(&self) -> Vec<(String, u64)> {
+        let now = time::Instant::now();
+        let cached = self.cached.read();
+        let entries = &cached.entries;
+        let mut active = cached
+            .methods
+            .iter()
+            .flat_map(|(method, hashes)| {
+                hashes.iter().filter_map(move |hash| match entries.get(hash) {
+                    Some((_, MethodMeta::Deadline(deadline))) if *deadline > now => {
+                        Some((method.clone(), (*deadline - now).as_millis() as u64))
+                    }
+                    _ => None,
+                })
+            })
+            .collect::<Vec<_>>();
+        active.sort();
+        active
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        self.cached.write().clear();
+    }
+
+    /// Removes all cached results of given method.
+    pub fn evict_method(&self, method: &str) {
+        self.cached.write().remove_method(method);
+    }
+
+    /// Notifies the cache about a new block.
+    ///
+    /// All entries using `CacheEviction::Block` become stale. Stale entries are not removed,
+    /// but rather overwritten on the next call.
+    pub fn new_block(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Notifies the cache about the current block number.
+    ///
+    /// Entries using `CacheEviction::BlockCount(n)` become stale once the number
+    /// is at least `n` blocks past the one they were cached at.
+    pub fn set_block_number(&self, number: u64) {
+        self.block_number.store(number, Ordering::SeqCst);
+    }
+
+    /// Notifies the cache about the current runtime `spec_version`.
+    ///
+    /// Entries of `metadata_version_aware` methods cached for a different version become stale.
+    pub fn set_spec_version(&self, spec_version: u64) {
+        *self.spec_version.write() = Some(spec_version);
+    }
+}
+
+/// What to do with a call.
+enum Action {
+    /// Pass the call further.
+    Next,
+    /// Pass the call further and cache the result.
+    NextAndCache(PendingEntry),
+    /// Respond with a cached result.
+    Return(Option<rpc::Output>),
+}
+
+/// A cache entry awaiting the result of the call.
+struct PendingEntry {
+    name: String,
+    max_entries: Option<usize>,
+    cache_null_results: bool,
+    compress: bool,
+    min_response_size: Option<usize>,
+    hash: Hash,
+    meta: MethodMeta,
+}
+
+impl PendingEntry {
+    /// Caches the result of the call.
+    fn store(self, cached: &RwLock<Cached>, result: &Option<rpc::Output>) {
+        if !self.cache_null_results && is_null_result(result) {
+            return;
+        }
+        if let Some(min_response_size) = self.min_response_size {
+            if response_size(result) < min_response_size {
+                return;
+            }
         }
 
-        enum Action {
-            Next,
-            NextAndCache(Hash, MethodMeta),
-            Return(Option<rpc::Output>),
+        // Compress before taking the lock.
+        let output = CachedOutput::new(result, self.compress);
+        cached
+            .write()
+            .insert(self.name, self.max_entries, self.hash, (output, self.meta));
+    }
+}
+
+impl Middleware {
+    /// Looks up the result of given call in the cache.
+    fn action(&self, call: &rpc::Call) -> Action {
+        if !self.enabled {
+            return Action::Next;
         }
 
-        let action = match call {
+        match *call {
             rpc::Call::MethodCall(rpc::MethodCall {
                 ref method, ref params, ..
             }) => {
-                if let Some(method) = self.cacheable.get(method) {
-                    let hash = method.hash(params);
-                    if let Some((result, meta)) = self.cached.read().get(&hash) {
-                        if method.is_fresh(meta) {
-                            Action::Return(result.clone())
-                        } else {
-                            Action::NextAndCache(hash, method.meta())
+                let cacheable = self.cacheable.load();
+                // Unlisted methods are cached with the default eviction policy (if any).
+                let cached_method = match cacheable.get(method) {
+                    Some(method) => Some(Cow::Borrowed(method)),
+                    None if is_cacheable_by_default(method) => self.default_eviction.clone().map(|eviction| {
+                        Cow::Owned(Method::new(method.clone(), eviction).with_max_entries(DEFAULT_MAX_ENTRIES))
+                    }),
+                    None => None,
+                };
+                if let Some(ref method) = cached_method {
+                    let method: &Method = method;
+                    let spec_version = *self.spec_version.read();
+                    let hash = method.hash(params, spec_version);
+                    let generation = self.generation.load(Ordering::SeqCst);
+                    let block = self.block_number.load(Ordering::SeqCst);
+                    let fresh = match self.cached.read().entries.get(&hash) {
+                        Some((result, meta)) if method.is_fresh(meta, generation, block, spec_version) => {
+                            Some(result.clone())
+                        }
+                        _ => None,
+                    };
+                    // Decompress outside of the lock, corrupted results are treated as a miss.
+                    let result = fresh.and_then(|result| {
+                        result
+                            .output()
+                            .map_err(|e| warn!("Unable to decompress cached result of {}: {}", method.name, e))
+                            .ok()
+                    });
+                    let action = match result {
+                        Some(result) => {
+                            if let Some(hits) = self.hits.read().get(&method.name) {
+                                hits.fetch_add(1, Ordering::Relaxed);
+                            }
+                            self.total_hits.fetch_add(1, Ordering::Relaxed);
+                            Action::Return(result)
                         }
-                    } else {
-                        Action::NextAndCache(hash, method.meta())
+                        None => Action::NextAndCache(PendingEntry {
+                            name: method.name.clone(),
+                            max_entries: method.max_entries,
+                            cache_null_results: method.cache_null_results,
+                            compress: self.compress,
+                            min_response_size: self.min_response_size,
+                            hash,
+                            meta: method.meta(generation, block, spec_version),
+                        }),
+                    };
+                    if self.log_requests && log_enabled!(log::Level::Debug) {
+                        let status = if let Action::Return(_) = action { "hit" } else { "miss" };
+                        debug!("{} {:016x} {}", method.name, hash, status);
                     }
+                    action
                 } else {
+                    // Lazily evict results of methods removed from the configuration.
+                    if self.cached.read().methods.contains_key(method) {
+                        self.evict_method(method);
+                    }
                     Action::Next
                 }
             }
-            _ => Action::Next,
-        };
+            // Notifications don't get any response, so there is nothing to cache
+            // and they have to reach the upstream every time.
+            rpc::Call::Notification(ref notification) => {
+                debug!("Not caching notification: {}", notification.method);
+                Action::Next
+            }
+            rpc::Call::Invalid { .. } => Action::Next,
+        }
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = Either<rpc::middleware::NoopCallFuture, rpc::futures::future::Ready<Option<rpc::Output>>>;
 
-        match action {
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        use rpc::futures::FutureExt;
+
+        match self.action(&call) {
             // Fallback
             Action::Next => Either::Right(next(call, meta)),
             // TODO [ToDr] Prevent multiple requests being made.
-            Action::NextAndCache(hash, method_meta) => {
+            Action::NextAndCache(entry) => {
                 let cached = self.cached.clone();
                 Either::Left(Either::Left(Box::pin(next(call, meta).map(move |result| {
-                    cached.write().insert(hash, (result.clone(), method_meta));
+                    entry.store(&cached, &result);
                     result
                 }))))
             }
@@ -197,6 +839,46 @@ impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
     }
 }
 
+/// Returns the size of the serialized output (`0` if there is no output).
+fn response_size(output: &Option<rpc::Output>) -> usize {
+    output.as_ref().map_or(0, |output| {
+        serde_json::to_vec(output)
+            .expect("Output serialization is infallible.")
+            .len()
+    })
+}
+
+/// Returns `true` if the output is a successful response with `null` result.
+fn is_null_result(output: &Option<rpc::Output>) -> bool {
+    match *output {
+        Some(rpc::Output::Success(ref success)) => success.result.is_null(),
+        _ => false,
+    }
+}
+
+/// Returns a hash of parameters that doesn't depend on the order of keys in JSON objects.
+pub fn canonical_hash(params: &rpc::Params) -> Hash {
+    let mut hasher = twox_hash::XxHash::default();
+    let params = serde_json::to_value(params).expect("Params are serializable to JSON.");
+    serde_json::to_writer(HashWriter(&mut hasher), &canonicalize(params)).expect("HashWriter never fails.");
+    hasher.finish()
+}
+
+/// Recursively sorts keys of JSON objects.
+fn canonicalize(value: rpc::Value) -> rpc::Value {
+    match value {
+        rpc::Value::Object(map) => rpc::Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .collect(),
+        ),
+        rpc::Value::Array(values) => rpc::Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
 struct HashWriter<'a, W: 'a>(&'a mut W);
 
 impl<'a, W: 'a + Hasher> io::Write for HashWriter<'a, W> {
@@ -243,6 +925,28 @@ mod tests {
         (next, called)
     }
 
+    fn null_callback() -> (
+        impl Fn(rpc::Call, ()) -> rpc::futures::future::Ready<Option<rpc::Output>>,
+        Arc<atomic::AtomicUsize>,
+    ) {
+        let called = Arc::new(atomic::AtomicUsize::new(0));
+        let called2 = called.clone();
+        let next = move |_, _| {
+            called2.fetch_add(1, atomic::Ordering::SeqCst);
+            rpc::futures::future::ready(null_output())
+        };
+
+        (next, called)
+    }
+
+    fn null_output() -> Option<rpc::Output> {
+        Some(rpc::Output::from(
+            Ok(rpc::Value::Null),
+            rpc::Id::Num(1),
+            Some(rpc::Version::V2),
+        ))
+    }
+
     fn method_call(name: &str, param: &str) -> rpc::Call {
         rpc::Call::MethodCall(rpc::MethodCall {
             id: rpc::Id::Num(1),
@@ -265,6 +969,9 @@ mod tests {
                 "eth_getBlock",
                 CacheEviction::Time(time::Duration::from_secs(1)),
             )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         });
         let (next, called) = callback();
 
@@ -287,6 +994,9 @@ mod tests {
                 "eth_getBlock",
                 CacheEviction::Time(time::Duration::from_secs(1)),
             )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         });
         let (next, called) = callback();
 
@@ -301,54 +1011,817 @@ mod tests {
     }
 
     #[test]
-    fn should_not_cache_when_params_different() {
+    fn should_forward_after_cache_is_cleared() {
         // given
         let middleware = middleware(config::Cache {
             enabled: true,
             methods: vec![Method::new(
                 "eth_getBlock",
-                CacheEviction::Time(time::Duration::from_secs(1)),
+                CacheEviction::Time(time::Duration::from_secs(30)),
             )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         });
         let (next, called) = callback();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
 
         // when
-        let res1 = middleware
-            .on_call(method_call("eth_getBlock", "xyz1"), (), &next)
-            .wait();
-        let res2 = middleware
-            .on_call(method_call("eth_getBlock", "xyz2"), (), &next)
-            .wait();
+        middleware.clear();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
 
         // then
         assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
-        assert_eq!(res1, None);
-        assert_eq!(res2, None);
     }
 
     #[test]
-    fn should_invalidate_cache_after_specified_time() {
+    fn should_evict_only_given_method() {
         // given
         let middleware = middleware(config::Cache {
             enabled: true,
-            methods: vec![Method::new(
-                "eth_getBlock",
-                CacheEviction::Time(time::Duration::from_millis(1)),
-            )],
+            methods: vec![
+                Method::new("eth_getBlock", CacheEviction::Time(time::Duration::from_secs(30))),
+                Method::new("eth_chainId", CacheEviction::Time(time::Duration::from_secs(30))),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         });
         let (next, called) = callback();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "abc"), (), &next).wait();
+        middleware.on_call(method_call("eth_chainId", "xyz"), (), &next).wait();
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 3);
 
         // when
-        let res1 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
-        let res2 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
-        ::std::thread::sleep(time::Duration::from_millis(2));
-        let res3 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.evict_method("eth_getBlock");
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "abc"), (), &next).wait();
+        middleware.on_call(method_call("eth_chainId", "xyz"), (), &next).wait();
 
         // then
-        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
-        assert_eq!(res1, None);
-        assert_eq!(res2, None);
-        assert_eq!(res3, None);
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 5);
+    }
+
+    fn cache_config(method: &str) -> String {
+        format!(
+            r#"{{"enabled":true,"methods":[{{"name":"{}","eviction":{{"time":{{"secs":60,"nanos":0}}}}}}]}}"#,
+            method
+        )
+    }
+
+    #[test]
+    fn should_reload_methods_when_config_file_changes() {
+        // given
+        let dir = std::env::temp_dir().join(format!("simple-cache-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("cache.json");
+        std::fs::write(&path, cache_config("eth_getBlock")).unwrap();
+        let middleware = Middleware::new(&[
+            config::Param::ConfigFile(path.clone(), config::load(&path).unwrap()),
+            config::Param::Watch(true),
+        ]);
+        let (next, called) = callback();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // when
+        std::fs::write(&path, cache_config("eth_chainId")).unwrap();
+        let deadline = time::Instant::now() + time::Duration::from_secs(10);
+        while !middleware.cacheable.load().contains_key("eth_chainId") {
+            assert!(time::Instant::now() < deadline, "Configuration was not reloaded.");
+            std::thread::sleep(time::Duration::from_millis(10));
+        }
+        called.store(0, atomic::Ordering::SeqCst);
+        for _ in 0..2 {
+            middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+            middleware.on_call(method_call("eth_chainId", "xyz"), (), &next).wait();
+        }
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 3);
+        assert!(!middleware.cacheable.load().contains_key("eth_getBlock"));
+        assert!(!middleware.cached.read().methods.contains_key("eth_getBlock"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn should_describe_cached_methods() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![
+                Method::new("eth_getBlock", CacheEviction::Time(time::Duration::from_secs(30))),
+                Method::new("eth_chainId", CacheEviction::BlockCount(10)),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, _called) = callback();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // when
+        let methods = middleware
+            .methods()
+            .into_iter()
+            .map(|m| (m.name, m.eviction.to_string(), m.hits))
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(
+            methods,
+            vec![
+                ("eth_chainId".into(), "10 blocks".into(), 0),
+                ("eth_getBlock".into(), "30s".into(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_list_cached_methods_and_active_entries() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![
+                Method::new("eth_getBlock", CacheEviction::Time(time::Duration::from_secs(30))),
+                Method::new("eth_chainId", CacheEviction::Block),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, _) = callback();
+
+        // when
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "abc"), (), &next).wait();
+        middleware.on_call(method_call("eth_chainId", ""), (), &next).wait();
+        middleware
+            .on_call(method_call("eth_getBalance", "xyz"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(middleware.cached_methods(), vec!["eth_chainId", "eth_getBlock"]);
+        let entries = middleware.active_cache_entries();
+        assert_eq!(entries.len(), 2);
+        for (method, ttl) in entries {
+            assert_eq!(method, "eth_getBlock");
+            assert!(ttl > 0 && ttl <= 30_000, "Unexpected TTL: {}", ttl);
+        }
+    }
+
+    #[test]
+    fn should_cache_unlisted_methods_with_default_eviction() {
+        // given
+        let cache = config::Cache {
+            enabled: true,
+            methods: vec![Method::new("eth_chainId", CacheEviction::Block)],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        };
+        let middleware = middleware(cache.with_default_eviction(CacheEviction::Time(time::Duration::from_secs(5))));
+        let (next, called) = callback();
+
+        // when
+        middleware
+            .on_call(method_call("eth_getBalance", "xyz"), (), &next)
+            .wait();
+        middleware
+            .on_call(method_call("eth_getBalance", "xyz"), (), &next)
+            .wait();
+        middleware.on_call(method_call("eth_chainId", ""), (), &next).wait();
+        middleware.on_call(method_call("eth_chainId", ""), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert!(middleware.uses_block_eviction());
+        let entries = middleware.active_cache_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, "eth_getBalance");
+        assert!(
+            entries[0].1 > 0 && entries[0].1 <= 5_000,
+            "Unexpected TTL: {}",
+            entries[0].1
+        );
+    }
+
+    #[test]
+    fn should_never_cache_subscriptions_and_state_changes_with_default_eviction() {
+        // given
+        let middleware = middleware(
+            config::Cache::default().with_default_eviction(CacheEviction::Time(time::Duration::from_secs(5))),
+        );
+        let (next, called) = callback();
+
+        // when
+        for method in &[
+            "eth_subscribe",
+            "eth_unsubscribe",
+            "eth_getFilterChanges",
+            "eth_sendRawTransaction",
+            "personal_sign",
+        ] {
+            middleware.on_call(method_call(method, "xyz"), (), &next).wait();
+            middleware.on_call(method_call(method, "xyz"), (), &next).wait();
+        }
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 10);
+        assert!(middleware.active_cache_entries().is_empty());
+    }
+
+    #[test]
+    fn should_limit_number_of_entries_with_default_eviction() {
+        // given
+        let middleware = middleware(
+            config::Cache::default().with_default_eviction(CacheEviction::Time(time::Duration::from_secs(5))),
+        );
+        let (next, _called) = callback();
+
+        // when
+        for i in 0..DEFAULT_MAX_ENTRIES + 1 {
+            middleware
+                .on_call(method_call("eth_getBalance", &format!("0x{:x}", i)), (), &next)
+                .wait();
+        }
+
+        // then
+        assert_eq!(middleware.cached.read().entries.len(), DEFAULT_MAX_ENTRIES);
+    }
+
+    #[test]
+    fn should_always_forward_notifications() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getBlock",
+                CacheEviction::Time(time::Duration::from_secs(1)),
+            )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+        let notification = || {
+            rpc::Call::Notification(rpc::Notification {
+                jsonrpc: Some(rpc::Version::V2),
+                method: "eth_getBlock".into(),
+                params: rpc::Params::Array(vec!["xyz".into()]),
+            })
+        };
+
+        // when
+        let res1 = middleware.on_call(notification(), (), &next).wait();
+        let res2 = middleware.on_call(notification(), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(res1, None);
+        assert_eq!(res2, None);
+        assert!(middleware.active_cache_entries().is_empty());
+    }
+
+    #[test]
+    fn should_cache_null_results() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getTransactionReceipt",
+                CacheEviction::Time(time::Duration::from_secs(1)),
+            )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = null_callback();
+
+        // when
+        let res1 = middleware
+            .on_call(method_call("eth_getTransactionReceipt", "0x1"), (), &next)
+            .wait();
+        let res2 = middleware
+            .on_call(method_call("eth_getTransactionReceipt", "0x1"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+        assert_eq!(res1, null_output());
+        assert_eq!(res2, null_output());
+    }
+
+    #[test]
+    fn should_forward_null_results_if_not_cached() {
+        // given
+        let method: Method =
+            serde_json::from_str(r#"{"name":"eth_getTransactionReceipt","eviction":"block","cacheNullResults":false}"#)
+                .unwrap();
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![method],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = null_callback();
+
+        // when
+        let res1 = middleware
+            .on_call(method_call("eth_getTransactionReceipt", "0x1"), (), &next)
+            .wait();
+        let res2 = middleware
+            .on_call(method_call("eth_getTransactionReceipt", "0x1"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(res1, null_output());
+        assert_eq!(res2, null_output());
+    }
+
+    #[test]
+    fn should_not_cache_when_params_different() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getBlock",
+                CacheEviction::Time(time::Duration::from_secs(1)),
+            )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        let res1 = middleware
+            .on_call(method_call("eth_getBlock", "xyz1"), (), &next)
+            .wait();
+        let res2 = middleware
+            .on_call(method_call("eth_getBlock", "xyz2"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(res1, None);
+        assert_eq!(res2, None);
+    }
+
+    #[test]
+    fn should_hash_transformed_params() {
+        // given
+        let method =
+            Method::new("eth_call", CacheEviction::Block).with_key_transform(|params: &rpc::Params| match params {
+                rpc::Params::Array(params) => rpc::Params::Array(
+                    params
+                        .iter()
+                        .map(|p| if p == "latest" { "0x1234".into() } else { p.clone() })
+                        .collect(),
+                ),
+                params => params.clone(),
+            });
+        let latest = rpc::Params::Array(vec!["latest".into()]);
+        let number = rpc::Params::Array(vec!["0x1234".into()]);
+        let other = rpc::Params::Array(vec!["0x1235".into()]);
+
+        // then
+        assert_eq!(method.hash(&latest, None), method.hash(&number, None));
+        assert_ne!(method.hash(&latest, None), method.hash(&other, None));
+    }
+
+    #[test]
+    fn should_share_cache_entry_if_only_unhashed_params_differ() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getBlockByNumber",
+                CacheEviction::Time(time::Duration::from_secs(1)),
+            )
+            .with_hash_params(ParamHashMode::First(1))],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+        let call = |include_transactions: bool| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                id: rpc::Id::Num(1),
+                jsonrpc: Some(rpc::Version::V2),
+                method: "eth_getBlockByNumber".into(),
+                params: rpc::Params::Array(vec!["0x1".into(), include_transactions.into()]),
+            })
+        };
+
+        // when
+        middleware.on_call(call(false), (), &next).wait();
+        middleware.on_call(call(true), (), &next).wait();
+        middleware
+            .on_call(method_call("eth_getBlockByNumber", "0x2"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_hash_selected_params() {
+        // given
+        let method = Method::new("eth_call", CacheEviction::Block).with_hash_params(ParamHashMode::Indices(vec![0, 2]));
+        let params = |second: &str, third: &str| rpc::Params::Array(vec!["0x1".into(), second.into(), third.into()]);
+
+        // then
+        assert_eq!(
+            method.hash(&params("a", "c"), None),
+            method.hash(&params("b", "c"), None)
+        );
+        assert_ne!(
+            method.hash(&params("a", "c"), None),
+            method.hash(&params("a", "d"), None)
+        );
+    }
+
+    #[test]
+    fn should_hash_objects_independently_of_key_order() {
+        // given
+        let params = |json: &str| serde_json::from_str::<rpc::Params>(json).unwrap();
+        let method = Method::new("eth_call", CacheEviction::Block).canonical_params(true);
+
+        // then
+        assert_eq!(
+            canonical_hash(&params(r#"{"a":1,"b":2}"#)),
+            canonical_hash(&params(r#"{"b":2,"a":1}"#))
+        );
+        assert_eq!(
+            canonical_hash(&params(r#"[{"to":"0x1","data":{"x":1,"y":[{"b":2,"a":1}]}}]"#)),
+            canonical_hash(&params(r#"[{"data":{"y":[{"a":1,"b":2}],"x":1},"to":"0x1"}]"#))
+        );
+        assert_ne!(
+            canonical_hash(&params(r#"{"a":1,"b":2}"#)),
+            canonical_hash(&params(r#"{"a":2,"b":1}"#))
+        );
+        assert_eq!(
+            method.hash(&params(r#"[{"a":1,"b":2}]"#), None),
+            method.hash(&params(r#"[{"b":2,"a":1}]"#), None)
+        );
+    }
+
+    #[test]
+    fn should_deserialize_param_hash_mode() {
+        let mode = |json: &str| serde_json::from_str::<ParamHashMode>(json);
+
+        assert_eq!(mode(r#""all""#).unwrap(), ParamHashMode::All);
+        assert_eq!(mode(r#""first:1""#).unwrap(), ParamHashMode::First(1));
+        assert_eq!(mode(r#""indices:0, 2""#).unwrap(), ParamHashMode::Indices(vec![0, 2]));
+        assert!(mode(r#""first:x""#).is_err());
+        assert!(mode(r#""last:1""#).is_err());
+
+        let method: Method =
+            serde_json::from_str(r#"{"name":"eth_getBlockByNumber","eviction":"block","hashParams":"first:1"}"#)
+                .unwrap();
+        assert_eq!(method.hash_params, ParamHashMode::First(1));
+    }
+
+    #[test]
+    fn should_return_cached_result_for_equivalent_params() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("eth_call", CacheEviction::Block)
+                .with_key_transform(|_: &rpc::Params| rpc::Params::Array(vec!["0x1234".into()]))],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        middleware.on_call(method_call("eth_call", "latest"), (), &next).wait();
+        middleware.on_call(method_call("eth_call", "0x1234"), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn should_invalidate_cache_after_specified_time() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getBlock",
+                CacheEviction::Time(time::Duration::from_millis(1)),
+            )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        let res1 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        let res2 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        ::std::thread::sleep(time::Duration::from_millis(2));
+        let res3 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(res1, None);
+        assert_eq!(res2, None);
+        assert_eq!(res3, None);
+    }
+
+    #[test]
+    fn should_cache_within_a_block() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("eth_estimateGas", CacheEviction::Block)],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        let res1 = middleware
+            .on_call(method_call("eth_estimateGas", "xyz"), (), &next)
+            .wait();
+        let res2 = middleware
+            .on_call(method_call("eth_estimateGas", "xyz"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+        assert_eq!(res1, None);
+        assert_eq!(res2, None);
+    }
+
+    #[test]
+    fn should_invalidate_cache_after_new_block() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![
+                Method::new("eth_estimateGas", CacheEviction::Block),
+                Method::new("eth_getBlock", CacheEviction::Time(time::Duration::from_secs(1))),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+        middleware
+            .on_call(method_call("eth_estimateGas", "xyz"), (), &next)
+            .wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // when
+        middleware.clone().new_block();
+        middleware
+            .on_call(method_call("eth_estimateGas", "xyz"), (), &next)
+            .wait();
+        middleware
+            .on_call(method_call("eth_estimateGas", "xyz"), (), &next)
+            .wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 3);
+        assert!(middleware.uses_block_eviction());
+    }
+
+    #[test]
+    fn should_invalidate_cache_after_block_count() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("eth_getBlock", CacheEviction::BlockCount(3))],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+        middleware.set_block_number(10);
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // when
+        for block in 11..13 {
+            middleware.set_block_number(block);
+            middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        }
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+        middleware.set_block_number(13);
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert!(middleware.uses_block_eviction());
+    }
+
+    #[test]
+    fn should_limit_number_of_entries() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("state_getMetadata", CacheEviction::Block).with_max_entries(1)],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        middleware
+            .on_call(method_call("state_getMetadata", "0x1"), (), &next)
+            .wait();
+        middleware
+            .on_call(method_call("state_getMetadata", "0x2"), (), &next)
+            .wait();
+        middleware
+            .on_call(method_call("state_getMetadata", "0x1"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 3);
+        assert_eq!(middleware.cached.read().entries.len(), 1);
+    }
+
+    #[test]
+    fn should_cache_metadata_until_spec_version_changes() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("state_getMetadata", CacheEviction::Block)
+                .with_max_entries(1)
+                .metadata_version_aware(true)],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        middleware.set_spec_version(1);
+        let (next, called) = callback();
+
+        // when
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+
+        middleware.new_block();
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+
+        middleware.set_spec_version(2);
+        middleware.new_block();
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(middleware.cached.read().entries.len(), 1);
+    }
+
+    #[test]
+    fn should_evict_version_aware_entries_on_new_block_if_version_unknown() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new("state_getMetadata", CacheEviction::Block).metadata_version_aware(true)],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        });
+        let (next, called) = callback();
+
+        // when
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+        middleware.new_block();
+        middleware
+            .on_call(method_call("state_getMetadata", "xyz"), (), &next)
+            .wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_not_cache_responses_below_min_size() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![
+                Method::new("eth_getCode", CacheEviction::Block),
+                Method::new("eth_getBlockByHash", CacheEviction::Block),
+            ],
+            default_eviction: None,
+            compress: false,
+            min_response_size: Some(100),
+        });
+        let called = Arc::new(atomic::AtomicUsize::new(0));
+        let called2 = called.clone();
+        let next = move |call: rpc::Call, _| {
+            called2.fetch_add(1, atomic::Ordering::SeqCst);
+            let result = match call {
+                rpc::Call::MethodCall(ref call) if call.method == "eth_getCode" => "0x".to_owned(),
+                _ => format!("0x{}", "ab".repeat(100)),
+            };
+            rpc::futures::future::ready(Some(rpc::Output::from(
+                Ok(result.into()),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2),
+            )))
+        };
+
+        // when
+        for _ in 0..2 {
+            middleware.on_call(method_call("eth_getCode", "0x1"), (), &next).wait();
+            middleware
+                .on_call(method_call("eth_getBlockByHash", "0x1"), (), &next)
+                .wait();
+        }
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 3);
+        let cached = middleware.cached.read();
+        assert_eq!(cached.entries.len(), 1);
+        assert!(cached.methods.contains_key("eth_getBlockByHash"));
+    }
+
+    #[test]
+    fn should_compress_large_results() {
+        // given
+        let output = Some(rpc::Output::from(
+            Ok(rpc::Value::String("0x00".repeat(COMPRESSION_THRESHOLD))),
+            rpc::Id::Num(1),
+            Some(rpc::Version::V2),
+        ));
+        let json_len = serde_json::to_vec(output.as_ref().unwrap()).unwrap().len();
+
+        // when
+        let small = CachedOutput::new(&null_output(), true);
+        let large = CachedOutput::new(&output, true);
+        let uncompressed = CachedOutput::new(&output, false);
+
+        // then
+        assert!(matches!(small, CachedOutput::Plain(_)));
+        assert!(matches!(uncompressed, CachedOutput::Plain(_)));
+        match large {
+            CachedOutput::Compressed(ref bytes) => assert!(bytes.len() < json_len / 4),
+            CachedOutput::Plain(_) => panic!("Expected the output to be compressed."),
+        }
+        assert_eq!(large.output(), Ok(output));
+    }
+
+    #[test]
+    fn should_return_decompressed_cached_result() {
+        // given
+        let middleware = middleware(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_getBlock",
+                CacheEviction::Time(time::Duration::from_secs(30)),
+            )],
+            default_eviction: None,
+            compress: true,
+            min_response_size: None,
+        });
+        let called = Arc::new(atomic::AtomicUsize::new(0));
+        let called2 = called.clone();
+        let next = move |_, _| {
+            called2.fetch_add(1, atomic::Ordering::SeqCst);
+            rpc::futures::future::ready(Some(rpc::Output::from(
+                Ok(rpc::Value::String("0x00".repeat(COMPRESSION_THRESHOLD))),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2),
+            )))
+        };
+
+        // when
+        let res1 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+        let res2 = middleware.on_call(method_call("eth_getBlock", "xyz"), (), &next).wait();
+
+        // then
+        assert_eq!(called.load(atomic::Ordering::SeqCst), 1);
+        assert!(res1.is_some());
+        assert_eq!(res1, res2);
     }
 
     // TODO [ToDr] Implement me
@@ -362,6 +1835,9 @@ mod tests {
                 "eth_getBlock",
                 CacheEviction::Time(time::Duration::from_secs(1)),
             )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
         });
         let (next, called) = callback();
 
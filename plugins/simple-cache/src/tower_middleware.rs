@@ -0,0 +1,153 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! `tower` integration of the cache.
+
+use rpc::{
+    self,
+    futures::{
+        future::{self, Either},
+        Future, TryFutureExt,
+    },
+};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+use {Action, Middleware};
+
+/// Future returned by `TowerMiddleware` service.
+pub type TowerFuture<E> = Either<
+    future::Ready<Result<Option<rpc::Output>, E>>,
+    Pin<Box<dyn Future<Output = Result<Option<rpc::Output>, E>> + Send>>,
+>;
+
+/// Caching middleware for `tower` services handling JSON-RPC calls.
+///
+/// Used as a `tower::Layer` it responds with cached results and passes other calls to the wrapped service.
+/// Clones share the same cache.
+#[derive(Debug, Clone)]
+pub struct TowerMiddleware<S = ()> {
+    cache: Middleware,
+    inner: S,
+}
+
+impl TowerMiddleware {
+    /// Creates a layer caching results of the wrapped service in given cache.
+    pub fn new(cache: Middleware) -> Self {
+        TowerMiddleware { cache, inner: () }
+    }
+}
+
+impl<S> Layer<S> for TowerMiddleware {
+    type Service = TowerMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TowerMiddleware {
+            cache: self.cache.clone(),
+            inner,
+        }
+    }
+}
+
+impl<S> Service<rpc::Call> for TowerMiddleware<S>
+where
+    S: Service<rpc::Call, Response = Option<rpc::Output>>,
+    S::Future: Send + 'static,
+{
+    type Response = Option<rpc::Output>;
+    type Error = S::Error;
+    type Future = TowerFuture<S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, call: rpc::Call) -> Self::Future {
+        match self.cache.action(&call) {
+            Action::Next => Either::Right(Box::pin(self.inner.call(call))),
+            Action::NextAndCache(entry) => {
+                let cached = self.cache.cached.clone();
+                Either::Right(Box::pin(self.inner.call(call).map_ok(move |result| {
+                    entry.store(&cached, &result);
+                    result
+                })))
+            }
+            Action::Return(result) => Either::Left(future::ready(Ok(result))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tower::ServiceExt;
+    use {CacheEviction, Method};
+
+    fn call(method: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: method.into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    #[test]
+    fn should_cache_results_of_tower_service() {
+        // given
+        let cache = Middleware::new(&[config::Param::Config(config::Cache {
+            enabled: true,
+            methods: vec![Method::new(
+                "eth_chainId",
+                CacheEviction::Time(::std::time::Duration::from_secs(60)),
+            )],
+            default_eviction: None,
+            compress: false,
+            min_response_size: None,
+        })]);
+        let called = Arc::new(AtomicUsize::new(0));
+        let called2 = called.clone();
+        let inner = tower::service_fn(move |call: rpc::Call| {
+            called2.fetch_add(1, Ordering::SeqCst);
+            let id = match call {
+                rpc::Call::MethodCall(ref call) => call.id.clone(),
+                _ => rpc::Id::Null,
+            };
+            future::ready(Ok::<_, String>(Some(rpc::Output::from(Ok("0x1".into()), id, None))))
+        });
+        let service = TowerMiddleware::new(cache).layer(inner);
+
+        // when
+        let first = rpc::futures::executor::block_on(service.clone().oneshot(call("eth_chainId")));
+        let second = rpc::futures::executor::block_on(service.clone().oneshot(call("eth_chainId")));
+        let uncached = rpc::futures::executor::block_on(service.oneshot(call("eth_blockNumber")));
+
+        // then
+        assert_eq!(first, second);
+        assert!(first.unwrap().is_some());
+        assert!(uncached.unwrap().is_some());
+        assert_eq!(called.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! CLI configuration for stats.
+
+/// A configuration option to apply.
+pub enum Param {
+    /// Enables collecting the stats.
+    Enabled(bool),
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![cli_params::Param::new(
+        "Stats",
+        "stats-enabled",
+        "Collect per-method latency percentiles, available via `proxy_stats` method. The `proxy_stats` and `proxy_stats_reset` methods are served to every client allowed by permissioning, so deny them there on public instances.",
+        "false",
+        |value: String| {
+            value
+                .parse()
+                .map(Param::Enabled)
+                .map_err(|e| format!("Invalid value of stats-enabled {}: {:?}", value, e))
+        },
+    )]
+}
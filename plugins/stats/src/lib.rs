@@ -0,0 +1,279 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Per-method latency statistics.
+//!
+//! Records the duration of every call in a per-method HDR histogram
+//! and exposes the percentiles via `proxy_stats` method.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use fnv::FnvHashMap;
+use hdrhistogram::Histogram;
+use jsonrpc_core as rpc;
+use parking_lot::Mutex;
+use rpc::futures::{
+    future::{self, Either},
+    Future, FutureExt,
+};
+use std::{sync::Arc, time};
+
+pub mod config;
+
+/// Method returning latency percentiles of all tracked methods.
+pub const STATS_METHOD: &str = "proxy_stats";
+/// Method clearing all recorded latencies.
+pub const STATS_RESET_METHOD: &str = "proxy_stats_reset";
+
+/// Highest trackable latency in microseconds (one minute), higher values are saturated.
+const MAX_LATENCY_US: u64 = 60_000_000;
+/// Number of significant digits of the histograms.
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+type Histograms = Arc<Mutex<FnvHashMap<String, Histogram<u64>>>>;
+
+/// Stats collecting middleware.
+///
+/// Clones share the same histograms.
+#[derive(Debug, Clone)]
+pub struct Middleware {
+    enabled: bool,
+    histograms: Histograms,
+}
+
+impl Middleware {
+    /// Creates new stats middleware.
+    pub fn new(params: &[config::Param]) -> Self {
+        let mut enabled = false;
+        for p in params {
+            match p {
+                config::Param::Enabled(e) => enabled = *e,
+            }
+        }
+
+        Middleware {
+            enabled,
+            histograms: Default::default(),
+        }
+    }
+
+    /// Records a duration of a single call to given method.
+    pub fn record(&self, method: &str, duration: time::Duration) {
+        record(&self.histograms, method, duration)
+    }
+
+    /// Returns latency percentiles of all tracked methods ordered by method name.
+    pub fn stats(&self) -> rpc::Value {
+        let histograms = self.histograms.lock();
+        let mut methods = histograms.keys().collect::<Vec<_>>();
+        methods.sort();
+        rpc::Value::Array(
+            methods
+                .into_iter()
+                .map(|method| {
+                    let histogram = &histograms[method];
+                    serde_json::json!({
+                        "method": method,
+                        "p50_us": histogram.value_at_quantile(0.5),
+                        "p95_us": histogram.value_at_quantile(0.95),
+                        "p99_us": histogram.value_at_quantile(0.99),
+                        "count": histogram.len(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Clears all recorded latencies.
+    pub fn reset(&self) {
+        self.histograms.lock().clear();
+    }
+}
+
+fn record(histograms: &Histograms, method: &str, duration: time::Duration) {
+    let micros = duration.as_micros().min(MAX_LATENCY_US as u128) as u64;
+    let mut histograms = histograms.lock();
+    if !histograms.contains_key(method) {
+        let histogram =
+            Histogram::new_with_bounds(1, MAX_LATENCY_US, SIGNIFICANT_DIGITS).expect("Bounds are valid; qed");
+        histograms.insert(method.to_owned(), histogram);
+    }
+    histograms
+        .get_mut(method)
+        .expect("Inserted above; qed")
+        .saturating_record(micros);
+}
+
+/// Returns `true` if the output indicates the method is not supported.
+///
+/// Such calls are not recorded to avoid tracking arbitrary method names.
+fn is_unknown_method(output: &Option<rpc::Output>) -> bool {
+    match output {
+        Some(rpc::Output::Failure(failure)) => failure.error.code == rpc::ErrorCode::MethodNotFound,
+        _ => false,
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = Either<rpc::middleware::NoopCallFuture, future::Ready<Option<rpc::Output>>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        if !self.enabled {
+            return Either::Right(next(call, meta));
+        }
+
+        let method = match call {
+            rpc::Call::MethodCall(ref request) => {
+                let result = match request.method.as_str() {
+                    STATS_METHOD => Some(self.stats()),
+                    STATS_RESET_METHOD => {
+                        self.reset();
+                        Some(rpc::Value::Bool(true))
+                    }
+                    _ => None,
+                };
+                if let Some(result) = result {
+                    let output = rpc::Output::from(Ok(result), request.id.clone(), request.jsonrpc);
+                    return Either::Left(Either::Right(future::ready(Some(output))));
+                }
+                request.method.clone()
+            }
+            _ => return Either::Right(next(call, meta)),
+        };
+
+        let histograms = self.histograms.clone();
+        let start = time::Instant::now();
+        Either::Left(Either::Left(Box::pin(next(call, meta).map(move |output| {
+            if !is_unknown_method(&output) {
+                record(&histograms, &method, start.elapsed());
+            }
+            output
+        }))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware as MiddlewareTrait;
+
+    fn method_call(name: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    fn call(middleware: &Middleware, name: &str) -> Option<rpc::Output> {
+        let next = |_, _| future::ready(Some(rpc::Output::from(Ok(rpc::Value::Null), rpc::Id::Num(1), None)));
+        rpc::futures::executor::block_on(middleware.on_call(method_call(name), (), next))
+    }
+
+    fn result(output: Option<rpc::Output>) -> rpc::Value {
+        match output {
+            Some(rpc::Output::Success(success)) => success.result,
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_return_percentiles() {
+        // given
+        let middleware = Middleware::new(&[config::Param::Enabled(true)]);
+        for i in 1..=1000 {
+            middleware.record("eth_call", time::Duration::from_micros(i));
+        }
+
+        // when
+        let stats = result(call(&middleware, STATS_METHOD));
+
+        // then
+        assert_eq!(
+            stats,
+            serde_json::json!([{
+                "method": "eth_call",
+                "p50_us": 500,
+                "p95_us": 950,
+                "p99_us": 990,
+                "count": 1000,
+            }])
+        );
+    }
+
+    #[test]
+    fn should_record_calls_and_reset() {
+        // given
+        let middleware = Middleware::new(&[config::Param::Enabled(true)]);
+        call(&middleware, "eth_blockNumber");
+        call(&middleware, "eth_blockNumber");
+        call(&middleware, "eth_call");
+
+        // when
+        let stats = result(call(&middleware, STATS_METHOD));
+        let reset = result(call(&middleware, STATS_RESET_METHOD));
+
+        // then
+        let counts = stats
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["method"].as_str().unwrap().to_owned(), s["count"].as_u64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, vec![("eth_blockNumber".into(), 2), ("eth_call".into(), 1)]);
+        assert_eq!(reset, rpc::Value::Bool(true));
+        assert_eq!(result(call(&middleware, STATS_METHOD)), serde_json::json!([]));
+    }
+
+    #[test]
+    fn should_not_record_unknown_methods() {
+        // given
+        let middleware = Middleware::new(&[config::Param::Enabled(true)]);
+        let next = |_, _| {
+            future::ready(Some(rpc::Output::from(
+                Err(rpc::Error::method_not_found()),
+                rpc::Id::Num(1),
+                None,
+            )))
+        };
+
+        // when
+        rpc::futures::executor::block_on(middleware.on_call(method_call("eth_unknown"), (), next));
+
+        // then
+        assert_eq!(middleware.stats(), serde_json::json!([]));
+    }
+
+    #[test]
+    fn should_forward_by_default() {
+        // given
+        let middleware = Middleware::new(&[]);
+
+        // when
+        let output = call(&middleware, STATS_METHOD);
+
+        // then
+        assert_eq!(result(output), rpc::Value::Null);
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! CLI configuration for transforms.
+
+use crate::rules::TransformRule;
+use std::{fs, io};
+
+/// A configuration option to apply.
+pub enum Param {
+    /// Value returned for `net_version` calls.
+    NetworkId(Option<String>),
+    /// Value returned for `web3_clientVersion` calls.
+    ClientVersion(Option<String>),
+    /// Request & response transformation rules.
+    Rules(Vec<TransformRule>),
+}
+
+fn optional(value: String) -> Option<String> {
+    if value == "-" {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Returns a list of supported configuration parameters.
+pub fn params() -> Vec<cli_params::Param<Param>> {
+    vec![
+        cli_params::Param::new(
+            "Identity",
+            "identity-network-id",
+            "Network id returned for `net_version` calls instead of asking the upstream. Use - to forward the calls.",
+            "-",
+            |value: String| Ok(Param::NetworkId(optional(value))),
+        ),
+        cli_params::Param::new(
+            "Identity",
+            "identity-client-version",
+            "Client version returned for `web3_clientVersion` calls instead of asking the upstream. Use - to forward the calls.",
+            "-",
+            |value: String| Ok(Param::ClientVersion(optional(value))),
+        ),
+        cli_params::Param::new(
+            "Transform",
+            "transform-rules",
+            "A path to a JSON file containing a list of request & response transformation rules. See examples for the file schema.",
+            "-",
+            |path: String| {
+                if path == "-" {
+                    return Ok(Param::Rules(vec![]));
+                }
+
+                let file =
+                    fs::File::open(&path).map_err(|e| format!("Can't open transform rules file at {}: {:?}", path, e))?;
+                let buf_file = io::BufReader::new(file);
+                let rules = serde_json::from_reader(buf_file).map_err(|e| format!("Invalid JSON at {}: {:?}", path, e))?;
+                Ok(Param::Rules(rules))
+            },
+        ),
+    ]
+}
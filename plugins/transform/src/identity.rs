@@ -0,0 +1,160 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Static network and client identity.
+
+use crate::config;
+use jsonrpc_core as rpc;
+use rpc::futures::{
+    future::{self, Either},
+    Future,
+};
+
+/// Method returning the network id.
+const NET_VERSION: &str = "net_version";
+/// Method returning the client version.
+const CLIENT_VERSION: &str = "web3_clientVersion";
+
+/// Answers `net_version` and `web3_clientVersion` with configured values
+/// without forwarding them upstream.
+///
+/// Calls for values that are not configured are passed through.
+#[derive(Debug, Clone, Default)]
+pub struct IdentityMiddleware {
+    network_id: Option<String>,
+    client_version: Option<String>,
+}
+
+impl IdentityMiddleware {
+    /// Creates new identity middleware.
+    pub fn new(params: &[config::Param]) -> Self {
+        let mut middleware = Self::default();
+        for p in params {
+            match p {
+                config::Param::NetworkId(id) => middleware.network_id = id.clone(),
+                config::Param::ClientVersion(version) => middleware.client_version = version.clone(),
+                config::Param::Rules(_) => {}
+            }
+        }
+        middleware
+    }
+
+    fn value(&self, method: &str) -> Option<&String> {
+        match method {
+            NET_VERSION => self.network_id.as_ref(),
+            CLIENT_VERSION => self.client_version.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for IdentityMiddleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = future::Ready<Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        if let rpc::Call::MethodCall(ref request) = call {
+            if let Some(value) = self.value(&request.method) {
+                let output = rpc::Output::from(
+                    Ok(rpc::Value::String(value.clone())),
+                    request.id.clone(),
+                    request.jsonrpc,
+                );
+                return Either::Left(future::ready(Some(output)));
+            }
+        }
+
+        Either::Right(next(call, meta))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::Middleware as MiddlewareTrait;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn method_call(name: &str) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    fn call(middleware: &IdentityMiddleware, name: &str, upstream_calls: &Arc<AtomicUsize>) -> rpc::Value {
+        let upstream_calls = upstream_calls.clone();
+        let next = move |_, _| {
+            upstream_calls.fetch_add(1, Ordering::SeqCst);
+            future::ready(Some(rpc::Output::from(
+                Ok("upstream".into()),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2),
+            )))
+        };
+        match rpc::futures::executor::block_on(middleware.on_call(method_call(name), (), next)) {
+            Some(rpc::Output::Success(success)) => success.result,
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_return_configured_identity() {
+        // given
+        let middleware = IdentityMiddleware::new(&[
+            config::Param::NetworkId(Some("1".into())),
+            config::Param::ClientVersion(Some("my-proxy/1.0".into())),
+        ]);
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+
+        // when
+        let network_id = call(&middleware, "net_version", &upstream_calls);
+        let client_version = call(&middleware, "web3_clientVersion", &upstream_calls);
+
+        // then
+        assert_eq!(network_id, rpc::Value::String("1".into()));
+        assert_eq!(client_version, rpc::Value::String("my-proxy/1.0".into()));
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn should_forward_calls_if_not_configured() {
+        // given
+        let middleware = IdentityMiddleware::new(&[
+            config::Param::NetworkId(Some("1".into())),
+            config::Param::ClientVersion(None),
+        ]);
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+
+        // when
+        let client_version = call(&middleware, "web3_clientVersion", &upstream_calls);
+        let block_number = call(&middleware, "eth_blockNumber", &upstream_calls);
+
+        // then
+        assert_eq!(client_version, rpc::Value::String("upstream".into()));
+        assert_eq!(block_number, rpc::Value::String("upstream".into()));
+        assert_eq!(upstream_calls.load(Ordering::SeqCst), 2);
+    }
+}
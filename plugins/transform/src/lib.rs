@@ -0,0 +1,31 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Middlewares transforming or answering calls on the proxy side.
+//!
+//! - `IdentityMiddleware` overrides the network and client identity reported to the clients.
+//! - `TransformMiddleware` modifies requests and responses according to configured rules.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+pub mod config;
+mod identity;
+pub mod rules;
+
+pub use identity::IdentityMiddleware;
+pub use rules::{TransformMiddleware, TransformRule};
@@ -0,0 +1,385 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Configurable request & response transformations.
+//!
+//! Rules are matched by method name. Request operations are applied to the call parameters,
+//! response operations to the `result` of successful responses. Since clients always have to
+//! receive valid JSON-RPC messages, the envelope itself (`id`, `jsonrpc`, etc.) is never modified.
+
+use jsonrpc_core as rpc;
+use rpc::futures::{
+    future::{BoxFuture, Either},
+    Future, FutureExt,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::Arc;
+
+use crate::config;
+
+/// A single transformation of a JSON value.
+///
+/// Params are addressed by their position in an array, fields by a dot-separated
+/// path within an object (e.g. `block.number`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Op {
+    /// Sets param at given index, padding the params with `null` if needed.
+    SetParam {
+        /// Param position.
+        index: usize,
+        /// New value.
+        value: Value,
+    },
+    /// Removes param at given index.
+    RemoveParam {
+        /// Param position.
+        index: usize,
+    },
+    /// Moves a field to a different path.
+    RenameField {
+        /// Current path of the field.
+        from: String,
+        /// New path of the field.
+        to: String,
+    },
+    /// Removes a field.
+    RemoveField {
+        /// Path of the field.
+        field: String,
+    },
+    /// Sets a field, creating intermediate objects if needed.
+    SetField {
+        /// Path of the field.
+        field: String,
+        /// New value.
+        value: Value,
+    },
+}
+
+impl Op {
+    /// Applies the operation to given value.
+    ///
+    /// Operations that don't fit the shape of the value (e.g. params ops on objects) are ignored.
+    pub fn apply(&self, value: &mut Value) {
+        match *self {
+            Op::SetParam { index, value: ref new } => {
+                if value.is_null() {
+                    *value = Value::Array(vec![]);
+                }
+                if let Value::Array(ref mut params) = *value {
+                    if params.len() <= index {
+                        params.resize(index + 1, Value::Null);
+                    }
+                    params[index] = new.clone();
+                }
+            }
+            Op::RemoveParam { index } => {
+                if let Value::Array(ref mut params) = *value {
+                    if index < params.len() {
+                        params.remove(index);
+                    }
+                }
+            }
+            Op::RenameField { ref from, ref to } => {
+                if let Some(field) = remove_field(value, from) {
+                    set_field(value, to, field);
+                }
+            }
+            Op::RemoveField { ref field } => {
+                remove_field(value, field);
+            }
+            Op::SetField {
+                ref field,
+                value: ref new,
+            } => set_field(value, field, new.clone()),
+        }
+    }
+}
+
+fn remove_field(value: &mut Value, path: &str) -> Option<Value> {
+    let mut parts = path.split('.').collect::<Vec<_>>();
+    let last = parts.pop()?;
+    let mut current = value;
+    for part in parts {
+        current = current.as_object_mut()?.get_mut(part)?;
+    }
+    current.as_object_mut()?.remove(last)
+}
+
+fn set_field(value: &mut Value, path: &str, new: Value) {
+    let mut current = value;
+    for part in path.split('.') {
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+        current = match *current {
+            Value::Object(ref mut map) => map.entry(part).or_insert(Value::Null),
+            _ => return,
+        };
+    }
+    *current = new;
+}
+
+/// Transformations applied to calls of a particular method.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformRule {
+    /// Name of the method the rule applies to.
+    pub match_method: String,
+    /// Operations applied to the request params.
+    #[serde(default)]
+    pub on_request: Vec<Op>,
+    /// Operations applied to the result of a successful response.
+    #[serde(default)]
+    pub on_response: Vec<Op>,
+}
+
+fn apply_all<'a>(ops: impl IntoIterator<Item = &'a Op>, value: &mut Value) {
+    for op in ops {
+        op.apply(value);
+    }
+}
+
+/// Applies configured transformation rules to requests and responses.
+#[derive(Debug, Clone, Default)]
+pub struct TransformMiddleware {
+    rules: Arc<Vec<TransformRule>>,
+}
+
+impl TransformMiddleware {
+    /// Creates new transform middleware.
+    pub fn new(params: &[config::Param]) -> Self {
+        let mut rules = vec![];
+        for p in params {
+            if let config::Param::Rules(r) = p {
+                rules.extend(r.iter().cloned());
+            }
+        }
+        TransformMiddleware { rules: Arc::new(rules) }
+    }
+
+    fn matching(&self, method: &str) -> Vec<&TransformRule> {
+        self.rules.iter().filter(|rule| rule.match_method == method).collect()
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for TransformMiddleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = BoxFuture<'static, Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        let mut request = match call {
+            rpc::Call::MethodCall(request) => request,
+            call => return Either::Right(next(call, meta)),
+        };
+
+        let on_response = {
+            let rules = self.matching(&request.method);
+            if rules.is_empty() {
+                return Either::Right(next(rpc::Call::MethodCall(request), meta));
+            }
+
+            let mut on_response = vec![];
+            let mut params = match request.params {
+                rpc::Params::None => Value::Null,
+                rpc::Params::Array(params) => Value::Array(params),
+                rpc::Params::Map(params) => Value::Object(params),
+            };
+            for rule in rules {
+                apply_all(&rule.on_request, &mut params);
+                on_response.extend(rule.on_response.iter().cloned());
+            }
+            request.params = match params {
+                Value::Array(params) => rpc::Params::Array(params),
+                Value::Object(params) => rpc::Params::Map(params),
+                _ => rpc::Params::None,
+            };
+            on_response
+        };
+
+        let response = next(rpc::Call::MethodCall(request), meta);
+        if on_response.is_empty() {
+            return Either::Right(response);
+        }
+
+        Either::Left(
+            response
+                .map(move |output| match output {
+                    Some(rpc::Output::Success(mut success)) => {
+                        apply_all(&on_response, &mut success.result);
+                        Some(rpc::Output::Success(success))
+                    }
+                    other => other,
+                })
+                .boxed(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::futures::future;
+    use rpc::Middleware as MiddlewareTrait;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn method_call(name: &str, params: rpc::Params) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            id: rpc::Id::Num(1),
+            jsonrpc: Some(rpc::Version::V2),
+            method: name.into(),
+            params,
+        })
+    }
+
+    fn middleware(rules: Value) -> TransformMiddleware {
+        TransformMiddleware::new(&[config::Param::Rules(serde_json::from_value(rules).unwrap())])
+    }
+
+    fn call(middleware: &TransformMiddleware, call: rpc::Call, result: Value) -> (rpc::Params, Value) {
+        let sent = Arc::new(Mutex::new(None));
+        let sent2 = sent.clone();
+        let next = move |call, _| {
+            if let rpc::Call::MethodCall(request) = call {
+                *sent2.lock().unwrap() = Some(request.params);
+            }
+            future::ready(Some(rpc::Output::from(
+                Ok(result.clone()),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2),
+            )))
+        };
+        let output = rpc::futures::executor::block_on(middleware.on_call(call, (), next));
+        let params = sent.lock().unwrap().take().unwrap();
+        match output {
+            Some(rpc::Output::Success(success)) => (params, success.result),
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_apply_param_ops() {
+        let mut value = json!(["0x1"]);
+
+        Op::SetParam {
+            index: 2,
+            value: "latest".into(),
+        }
+        .apply(&mut value);
+        assert_eq!(value, json!(["0x1", null, "latest"]));
+
+        Op::RemoveParam { index: 0 }.apply(&mut value);
+        assert_eq!(value, json!([null, "latest"]));
+
+        Op::RemoveParam { index: 5 }.apply(&mut value);
+        assert_eq!(value, json!([null, "latest"]));
+    }
+
+    #[test]
+    fn should_apply_field_ops() {
+        let mut value = json!({ "a": { "b": 1 }, "c": 2 });
+
+        Op::RenameField {
+            from: "a.b".into(),
+            to: "d.e".into(),
+        }
+        .apply(&mut value);
+        assert_eq!(value, json!({ "a": {}, "c": 2, "d": { "e": 1 } }));
+
+        Op::RemoveField { field: "c".into() }.apply(&mut value);
+        assert_eq!(value, json!({ "a": {}, "d": { "e": 1 } }));
+
+        Op::SetField {
+            field: "a.f".into(),
+            value: true.into(),
+        }
+        .apply(&mut value);
+        assert_eq!(value, json!({ "a": { "f": true }, "d": { "e": 1 } }));
+
+        // ignore ops not matching the shape
+        Op::SetField {
+            field: "d.e.g".into(),
+            value: true.into(),
+        }
+        .apply(&mut value);
+        Op::RemoveField { field: "x.y".into() }.apply(&mut value);
+        Op::RemoveParam { index: 0 }.apply(&mut value);
+        assert_eq!(value, json!({ "a": { "f": true }, "d": { "e": 1 } }));
+    }
+
+    #[test]
+    fn should_transform_matching_requests_and_responses() {
+        // given
+        let middleware = middleware(json!([{
+            "match_method": "eth_call",
+            "on_request": [{ "op": "set_param", "index": 1, "value": "latest" }],
+            "on_response": [{ "op": "rename_field", "from": "output", "to": "data" }],
+        }]));
+
+        // when
+        let (params, result) = call(
+            &middleware,
+            method_call("eth_call", rpc::Params::Array(vec![json!({ "to": "0x0" })])),
+            json!({ "output": "0x01" }),
+        );
+
+        // then
+        assert_eq!(
+            params,
+            rpc::Params::Array(vec![json!({ "to": "0x0" }), "latest".into()])
+        );
+        assert_eq!(result, json!({ "data": "0x01" }));
+    }
+
+    #[test]
+    fn should_pass_through_other_methods() {
+        // given
+        let middleware = middleware(json!([{
+            "match_method": "eth_call",
+            "on_request": [{ "op": "remove_param", "index": 0 }],
+            "on_response": [{ "op": "set_field", "field": "x", "value": 1 }],
+        }]));
+
+        // when
+        let (params, result) = call(
+            &middleware,
+            method_call("eth_getBalance", rpc::Params::Array(vec!["0x0".into()])),
+            json!({ "output": "0x01" }),
+        );
+
+        // then
+        assert_eq!(params, rpc::Params::Array(vec!["0x0".into()]));
+        assert_eq!(result, json!({ "output": "0x01" }));
+    }
+
+    #[test]
+    fn should_reject_unknown_ops() {
+        let rules = serde_json::from_value::<Vec<TransformRule>>(json!([{
+            "match_method": "eth_call",
+            "on_request": [{ "op": "drop_table" }],
+        }]));
+
+        assert!(rules.is_err());
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Timeout of handling the whole call.
+
+use config;
+use helpers;
+use rpc::{
+    self,
+    futures::{future::Either, Future, FutureExt},
+};
+use std::time::Duration;
+use tokio;
+use TIMEOUT_ERROR_CODE;
+
+/// Limits the time spent handling a call by all following middlewares, including the upstream.
+///
+/// Unlike the upstream `Middleware` timeout it also covers middlewares doing some work
+/// before the call reaches the transport (e.g. signing a transaction).
+/// Handling of the call is cancelled once the timeout fires.
+#[derive(Debug, Clone)]
+pub struct CallTimeoutMiddleware {
+    timeout: Option<(Duration, tokio::runtime::Handle)>,
+}
+
+impl CallTimeoutMiddleware {
+    /// Creates new middleware using the timeout configured in given params.
+    pub fn new(params: &[config::Param]) -> Self {
+        Self::with_timeout(config::call_timeout(params))
+    }
+
+    /// Creates new middleware with given timeout (disabled if `None`).
+    pub fn with_timeout(timeout: Option<Duration>) -> Self {
+        // The timer has to be registered within the runtime, while calls may come from other threads.
+        let timeout = timeout.and_then(|timeout| match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => Some((timeout, runtime)),
+            Err(_) => {
+                warn!("Call timeout requires a tokio runtime and is disabled.");
+                None
+            }
+        });
+
+        CallTimeoutMiddleware { timeout }
+    }
+}
+
+impl<M: rpc::Metadata> rpc::Middleware<M> for CallTimeoutMiddleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = rpc::middleware::NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, M) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        let (timeout, runtime) = match self.timeout {
+            Some((timeout, ref runtime)) => (timeout, runtime),
+            None => return Either::Right(next(call, meta)),
+        };
+
+        let method = helpers::get_method_name(&call).unwrap_or_default().to_owned();
+        let timeout_response = helpers::build_error_response(&call, TIMEOUT_ERROR_CODE, "Timeout");
+        let _guard = runtime.enter();
+        let response = tokio::time::timeout(timeout, next(call, meta)).map(move |result| {
+            result.unwrap_or_else(|_| {
+                warn!("Call to {} timed out after {:?}", method, timeout);
+                timeout_response
+            })
+        });
+        Either::Left(Box::pin(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Middleware taking its time before passing the call further, like accounts waiting for a signer.
+    struct SlowAccounts(Duration);
+
+    impl rpc::Middleware<()> for SlowAccounts {
+        type Future = rpc::middleware::NoopFuture;
+        type CallFuture = rpc::middleware::NoopCallFuture;
+
+        fn on_call<F, X>(&self, call: rpc::Call, meta: (), next: F) -> Either<Self::CallFuture, X>
+        where
+            F: FnOnce(rpc::Call, ()) -> X + Send,
+            X: Future<Output = Option<rpc::Output>> + Send + 'static,
+        {
+            let response = next(call, meta);
+            Either::Left(Box::pin(tokio::time::sleep(self.0).then(move |_| response)))
+        }
+    }
+
+    fn handle(timeout: Duration, delay: Duration) -> (Option<String>, Duration) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let mut io = rpc::MetaIoHandler::with_middleware((
+            CallTimeoutMiddleware::new(&[config::Param::CallTimeout(Some(timeout))]),
+            SlowAccounts(delay),
+        ));
+        io.add_method("eth_sendTransaction", |_| {
+            rpc::futures::future::ready(Ok(rpc::Value::String("0x1".into())))
+        });
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"eth_sendTransaction","params":[]}"#;
+
+        let start = Instant::now();
+        let response = runtime.block_on(io.handle_request(request, ()));
+        (response, start.elapsed())
+    }
+
+    #[test]
+    fn should_time_out_slow_middlewares() {
+        // when
+        let (response, elapsed) = handle(Duration::from_millis(100), Duration::from_millis(200));
+
+        // then
+        assert_eq!(
+            response,
+            Some(r#"{"jsonrpc":"2.0","error":{"code":-32003,"message":"Timeout"},"id":1}"#.into())
+        );
+        assert!(elapsed < Duration::from_millis(200), "Took {:?}", elapsed);
+    }
+
+    #[test]
+    fn should_pass_response_handled_within_timeout() {
+        // when
+        let (response, _) = handle(Duration::from_millis(500), Duration::from_millis(10));
+
+        // then
+        assert_eq!(response, Some(r#"{"jsonrpc":"2.0","result":"0x1","id":1}"#.into()));
+    }
+}
@@ -19,7 +19,8 @@
 
 use cli_params;
 use serde_json;
-use std::{fs, io};
+use serde_yaml;
+use std::{collections::HashMap, fs, io, time::Duration};
 use Subscription;
 
 /// Configuration options of an upstream
@@ -27,28 +28,265 @@ use Subscription;
 pub enum Param {
     /// PublishSubscribe methods
     PubSubMethods(Vec<Subscription>),
+    /// PublishSubscribe methods loaded from a YAML file.
+    ExternalSubscriptions(Vec<Subscription>),
+    /// Secret used to sign requests with HMAC.
+    HmacSecret(Option<String>),
+    /// Prefix added to ids of calls sent upstream.
+    IdPrefix(Option<String>),
+    /// Maximal time to wait for the upstream to respond to a call.
+    Timeout(Option<Duration>),
+    /// Maximal time to handle a call by all middlewares, including the upstream.
+    CallTimeout(Option<Duration>),
+    /// Log bodies of requests sent to the upstream.
+    LogRequests(bool),
+    /// Log bodies of messages received from the upstream.
+    LogResponses(bool),
+    /// Maximal number of requests awaiting the upstream response.
+    MaxPendingRequests(usize),
+    /// Methods (keys) that are forwarded only if the upstream answers the probe call (values).
+    CapabilityProbe(HashMap<String, String>),
 }
 
 /// Returns all configuration parameters for WS upstream.
 pub fn params() -> Vec<cli_params::Param<Param>> {
-    vec![cli_params::Param::new(
-        "Upstream configuration",
-        "upstream-config",
-        "Configuration of the upstream. Should contain a list of supported pub-sub methods.",
-        "-",
-        move |path: String| {
-            if &path == "-" {
-                return Ok(Param::PubSubMethods(Default::default()));
-            }
+    vec![
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-config",
+            "Configuration of the upstream. Should contain a list of supported pub-sub methods.",
+            "-",
+            move |path: String| {
+                if &path == "-" {
+                    return Ok(Param::PubSubMethods(Default::default()));
+                }
+
+                let file = fs::File::open(&path)
+                    .map_err(|e| format!("Can't open upstream config file at {}: {:?}", path, e))?;
+                let buf_file = io::BufReader::new(file);
+                let config: Upstream =
+                    serde_json::from_reader(buf_file).map_err(|e| format!("Invalid JSON at {}: {:?}", path, e))?;
+                Ok(Param::PubSubMethods(config.pubsub_methods))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "subscriptions-config",
+            "A path to a YAML file containing a list of pub-sub methods (`subscribe`, `unsubscribe` and `name` of every subscription). Extends the built-in subscriptions.",
+            "-",
+            move |path: String| {
+                if &path == "-" {
+                    return Ok(Param::ExternalSubscriptions(Default::default()));
+                }
+
+                let file = fs::File::open(&path)
+                    .map_err(|e| format!("Can't open subscriptions file at {}: {:?}", path, e))?;
+                let subscriptions =
+                    parse_subscriptions(io::BufReader::new(file)).map_err(|e| format!("{} at {}", e, path))?;
+                Ok(Param::ExternalSubscriptions(subscriptions))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-hmac-secret",
+            "Secret used to sign upstream requests (HMAC-SHA256 in `X-Proxy-Signature` header). Requires an upstream transport supporting headers.",
+            "-",
+            move |secret: String| {
+                if &secret == "-" {
+                    return Ok(Param::HmacSecret(None));
+                }
+                Ok(Param::HmacSecret(Some(secret)))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-id-prefix",
+            "Prefix added to ids of calls sent upstream (e.g. `proxy-`), so that the upstream can distinguish proxy traffic. Responses keep the original ids.",
+            "-",
+            move |prefix: String| {
+                if &prefix == "-" {
+                    return Ok(Param::IdPrefix(None));
+                }
+                Ok(Param::IdPrefix(Some(prefix)))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-timeout",
+            "Maximal time to wait for the upstream to respond to a call (e.g. `30s`, `500ms`, `1m`). Timed out calls fail with `Upstream timeout` error.",
+            "-",
+            move |timeout: String| {
+                if &timeout == "-" {
+                    return Ok(Param::Timeout(None));
+                }
+                Ok(Param::Timeout(Some(parse_duration(&timeout)?)))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "call-timeout",
+            "Maximal time to handle a call, including all middlewares before the upstream (e.g. `30s`, `500ms`, `1m`). Timed out calls are cancelled and fail with `Timeout` error.",
+            "-",
+            move |timeout: String| {
+                if &timeout == "-" {
+                    return Ok(Param::CallTimeout(None));
+                }
+                Ok(Param::CallTimeout(Some(parse_duration(&timeout)?)))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-log-requests",
+            "Log raw bodies of requests sent to the WS or IPC upstream (requires trace logging).",
+            "false",
+            |value: String| {
+                value
+                    .parse()
+                    .map(Param::LogRequests)
+                    .map_err(|e| format!("Invalid value of upstream-log-requests {}: {:?}", value, e))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-log-responses",
+            "Log raw bodies of messages received from the WS or IPC upstream (requires trace logging).",
+            "false",
+            |value: String| {
+                value
+                    .parse()
+                    .map(Param::LogResponses)
+                    .map_err(|e| format!("Invalid value of upstream-log-responses {}: {:?}", value, e))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-max-pending",
+            "Maximal number of requests awaiting the WS or IPC upstream response. Further calls fail with `QueueFull` error.",
+            "unlimited",
+            |value: String| {
+                if value == "unlimited" {
+                    return Ok(Param::MaxPendingRequests(usize::MAX));
+                }
+                value
+                    .parse()
+                    .map(Param::MaxPendingRequests)
+                    .map_err(|e| format!("Invalid maximal number of pending requests {}: {:?}", value, e))
+            },
+        ),
+        cli_params::Param::new(
+            "Upstream configuration",
+            "upstream-capability-probe",
+            "Comma-separated `method:probe` pairs (e.g. `parity_chainId:net_version`). The probe methods are called on startup and if a probe fails, calls to the corresponding method are rejected with `Method not found` without reaching the upstream.",
+            "-",
+            |value: String| {
+                if &value == "-" {
+                    return Ok(Param::CapabilityProbe(Default::default()));
+                }
+                value
+                    .split(',')
+                    .map(|pair| {
+                        let mut parts = pair.splitn(2, ':').map(str::trim);
+                        match (parts.next(), parts.next()) {
+                            (Some(method), Some(probe)) if !method.is_empty() && !probe.is_empty() => {
+                                Ok((method.to_owned(), probe.to_owned()))
+                            }
+                            _ => Err(format!("Invalid capability probe {:?}: expected `method:probe`", pair)),
+                        }
+                    })
+                    .collect::<Result<_, _>>()
+                    .map(Param::CapabilityProbe)
+            },
+        ),
+    ]
+}
+
+/// Parses a YAML list of pub-sub methods.
+pub fn parse_subscriptions<R: io::Read>(reader: R) -> Result<Vec<Subscription>, String> {
+    serde_yaml::from_reader(reader).map_err(|e| format!("Invalid YAML: {:?}", e))
+}
+
+/// Parses a duration with `ms`, `s` or `m` suffix (e.g. `30s`).
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit): (&str, fn(u64) -> Duration) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, Duration::from_millis)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, Duration::from_secs)
+    } else if let Some(m) = value.strip_suffix('m') {
+        (m, |m| Duration::from_secs(m * 60))
+    } else {
+        return Err(format!("Missing unit (ms, s or m) in duration: {}", value));
+    };
+
+    number
+        .trim()
+        .parse()
+        .map(unit)
+        .map_err(|e| format!("Invalid duration {}: {:?}", value, e))
+}
 
-            let file =
-                fs::File::open(&path).map_err(|e| format!("Can't open upstream config file at {}: {:?}", path, e))?;
-            let buf_file = io::BufReader::new(file);
-            let config: Upstream =
-                serde_json::from_reader(buf_file).map_err(|e| format!("Invalid JSON at {}: {:?}", path, e))?;
-            Ok(Param::PubSubMethods(config.pubsub_methods))
-        },
-    )]
+/// Returns the HMAC secret if configured.
+pub fn hmac_secret(params: &[Param]) -> Option<&str> {
+    params.iter().find_map(|p| match p {
+        Param::HmacSecret(ref secret) => secret.as_ref().map(|s| s.as_str()),
+        _ => None,
+    })
+}
+
+/// Returns the call timeout if configured.
+pub fn timeout(params: &[Param]) -> Option<Duration> {
+    params.iter().find_map(|p| match p {
+        Param::Timeout(timeout) => *timeout,
+        _ => None,
+    })
+}
+
+/// Returns the timeout of handling the whole call if configured.
+pub fn call_timeout(params: &[Param]) -> Option<Duration> {
+    params.iter().find_map(|p| match p {
+        Param::CallTimeout(timeout) => *timeout,
+        _ => None,
+    })
+}
+
+/// Returns `true` if bodies of upstream requests should be logged.
+pub fn log_requests(params: &[Param]) -> bool {
+    params.iter().any(|p| matches!(p, Param::LogRequests(true)))
+}
+
+/// Returns `true` if bodies of upstream responses should be logged.
+pub fn log_responses(params: &[Param]) -> bool {
+    params.iter().any(|p| matches!(p, Param::LogResponses(true)))
+}
+
+/// Returns configured capability probes (method -> probe method).
+pub fn capability_probes(params: &[Param]) -> HashMap<String, String> {
+    params
+        .iter()
+        .filter_map(|p| match p {
+            Param::CapabilityProbe(probes) => Some(probes.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Returns the maximal number of requests awaiting the upstream response (`usize::MAX` if unlimited).
+pub fn max_pending(params: &[Param]) -> usize {
+    params
+        .iter()
+        .find_map(|p| match p {
+            Param::MaxPendingRequests(max) => Some(*max),
+            _ => None,
+        })
+        .unwrap_or(usize::MAX)
+}
+
+/// Returns the call id prefix if configured.
+pub fn id_prefix(params: &[Param]) -> Option<&str> {
+    params.iter().find_map(|p| match p {
+        Param::IdPrefix(ref prefix) => prefix.as_ref().map(|s| s.as_str()),
+        _ => None,
+    })
 }
 
 /// Adds pubsub methods definitions to the existing parameter.
@@ -58,6 +296,15 @@ pub fn add_subscriptions(params: &mut [Param], methods: Vec<Subscription>) {
             Param::PubSubMethods(ref mut m) => {
                 m.extend(methods.clone());
             }
+            Param::ExternalSubscriptions(_)
+            | Param::HmacSecret(_)
+            | Param::IdPrefix(_)
+            | Param::Timeout(_)
+            | Param::CallTimeout(_)
+            | Param::LogRequests(_)
+            | Param::LogResponses(_)
+            | Param::MaxPendingRequests(_)
+            | Param::CapabilityProbe(_) => {}
         }
     }
 }
@@ -76,4 +323,53 @@ mod tests {
     fn should_deserialize_example_configuration() {
         let _m: Upstream = serde_json::from_slice(include_bytes!("../../../examples/upstream.json")).unwrap();
     }
+
+    #[test]
+    fn should_load_substrate_subscriptions() {
+        // when
+        let subscriptions = parse_subscriptions(&include_bytes!("../../../substrate-proxy/substrate.yml")[..]).unwrap();
+
+        // then
+        let names = subscriptions.iter().map(|s| s.subscribe.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "author_submitAndWatchExtrinsic",
+                "chain_subscribeNewHead",
+                "state_subscribeStorage",
+                "grandpa_subscribeJustifications",
+            ]
+        );
+        assert_eq!(subscriptions[3].unsubscribe, "grandpa_unsubscribeJustifications");
+        assert_eq!(subscriptions[3].name, "grandpa_justifications");
+    }
+
+    #[test]
+    fn should_parse_capability_probes() {
+        // given
+        let param = params()
+            .into_iter()
+            .find(|p| p.name == "upstream-capability-probe")
+            .unwrap();
+
+        // when
+        let probes = param.parse(Some("parity_chainId:net_version, eth_chainId:eth_chainId".into()));
+
+        // then
+        let probes = capability_probes(&[probes.unwrap()]);
+        assert_eq!(probes.len(), 2);
+        assert_eq!(probes["parity_chainId"], "net_version");
+        assert_eq!(probes["eth_chainId"], "eth_chainId");
+        assert!(param.parse(Some("parity_chainId".into())).is_err());
+        assert!(capability_probes(&[param.parse(None).unwrap()]).is_empty());
+    }
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("xs").is_err());
+    }
 }
@@ -18,23 +18,46 @@
 //! Request parsing helper methods.
 
 use pubsub;
-use rpc;
+use rpc::{
+    self,
+    futures::{future, Future, FutureExt},
+};
 use serde_json;
+use std::{fmt, pin::Pin, time::Duration};
+use tokio;
+
+/// Time to wait for the upstream to confirm an automatic unsubscribe.
+pub const AUTO_UNSUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Custom parser of subscription ids from notification params.
+///
+/// Used for chains that don't put the id in the `subscription` field of notifications.
+pub type SubscriptionParser = dyn Fn(&rpc::Params) -> Option<pubsub::SubscriptionId> + Send + Sync;
 
 /// Attempt to peek subscription id from the request given as bytes.
 ///
 /// TODO [ToDr] The implementation should deserialize only subscriptionId part,
 /// not the entire `Notification`
 pub fn peek_subscription_id(bytes: &[u8]) -> Option<pubsub::SubscriptionId> {
+    peek_subscription_id_with(bytes, None)
+}
+
+/// Attempt to peek subscription id from the request given as bytes,
+/// falling back to the custom parser if the params don't contain a standard `subscription` field.
+pub fn peek_subscription_id_with(
+    bytes: &[u8],
+    custom_parser: Option<&SubscriptionParser>,
+) -> Option<pubsub::SubscriptionId> {
     serde_json::from_slice::<rpc::Notification>(bytes)
         .ok()
         .and_then(|notification| {
-            if let rpc::Params::Map(ref map) = notification.params {
+            let id = if let rpc::Params::Map(ref map) = notification.params {
                 map.get("subscription")
                     .and_then(|v| pubsub::SubscriptionId::parse_value(v))
             } else {
                 None
-            }
+            };
+            id.or_else(|| custom_parser.and_then(|parser| parser(&notification.params)))
         })
 }
 
@@ -56,6 +79,47 @@ pub fn peek_id(bytes: &[u8]) -> Option<rpc::Id> {
         .and_then(|call| get_id(&call).cloned())
 }
 
+/// Attempt to peek the id of a response without deserializing the rest of it.
+///
+/// Other fields are skipped, so it's suitable for messages that are too large to be fully processed.
+pub fn peek_response_id(bytes: &[u8]) -> Option<rpc::Id> {
+    #[derive(Deserialize)]
+    struct Response {
+        id: rpc::Id,
+    }
+
+    serde_json::from_slice::<Response>(bytes)
+        .ok()
+        .map(|response| response.id)
+}
+
+/// Attempt to parse a batch of responses, returning every response along with its id.
+///
+/// Returns `None` if the top-level JSON value is not an array.
+/// Elements without an `id` field are reported as `rpc::Id::Null`.
+pub(crate) fn parse_batch(bytes: &[u8]) -> Option<Vec<(rpc::Id, rpc::Value)>> {
+    serde_json::from_slice::<Vec<rpc::Value>>(bytes).ok().map(|batch| {
+        batch
+            .into_iter()
+            .map(|response| {
+                let id = response
+                    .get("id")
+                    .and_then(|id| serde_json::from_value(id.clone()).ok())
+                    .unwrap_or(rpc::Id::Null);
+                (id, response)
+            })
+            .collect()
+    })
+}
+
+/// Attempt to peek the ids of a batch of responses.
+///
+/// Returns `None` if the top-level JSON value is not an array.
+/// Elements without an `id` field are reported as `rpc::Id::Null`.
+pub fn peek_batch_ids(bytes: &[u8]) -> Option<Vec<rpc::Id>> {
+    parse_batch(bytes).map(|batch| batch.into_iter().map(|(id, _)| id).collect())
+}
+
 /// Extract method name of given call.
 pub fn get_method_name(call: &rpc::Call) -> Option<&str> {
     match *call {
@@ -74,6 +138,69 @@ pub fn get_id(call: &rpc::Call) -> Option<&rpc::Id> {
     }
 }
 
+/// Format given call compactly for logging, e.g. `eth_getBlockByNumber("latest", false)`.
+///
+/// Notifications are prefixed with `[notify]`, invalid calls are reported as `[invalid id=...]`.
+pub fn format_call(call: &rpc::Call) -> String {
+    fn format_params(params: &rpc::Params) -> String {
+        match *params {
+            rpc::Params::None => String::new(),
+            rpc::Params::Array(ref params) => params
+                .iter()
+                .map(|param| param.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            rpc::Params::Map(ref params) => rpc::Value::Object(params.clone()).to_string(),
+        }
+    }
+
+    match *call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            ref method, ref params, ..
+        }) => format!("{}({})", method, format_params(params)),
+        rpc::Call::Notification(rpc::Notification {
+            ref method, ref params, ..
+        }) => format!("[notify] {}({})", method, format_params(params)),
+        rpc::Call::Invalid { ref id, .. } => format!(
+            "[invalid id={}]",
+            serde_json::to_string(id).expect("Id serialization is infallible.")
+        ),
+    }
+}
+
+/// Build a failure response to given call.
+///
+/// Returns `None` for notifications, since they don't expect any response.
+pub fn build_error_response(call: &rpc::Call, code: i64, message: &str) -> Option<rpc::Output> {
+    let (jsonrpc, id) = match *call {
+        rpc::Call::MethodCall(rpc::MethodCall { jsonrpc, ref id, .. }) => (jsonrpc, id.clone()),
+        rpc::Call::Notification(_) => return None,
+        rpc::Call::Invalid { ref id, .. } => (None, id.clone()),
+    };
+
+    Some(rpc::Output::Failure(rpc::Failure {
+        jsonrpc,
+        error: rpc::Error {
+            code: code.into(),
+            message: message.into(),
+            data: None,
+        },
+        id,
+    }))
+}
+
+/// Build a successful response to given call.
+///
+/// Returns `None` for notifications and invalid calls.
+pub fn build_response(call: &rpc::Call, result: rpc::Value) -> Option<rpc::Output> {
+    match *call {
+        rpc::Call::MethodCall(rpc::MethodCall { jsonrpc, ref id, .. }) => {
+            Some(rpc::Output::from(Ok(result), id.clone(), jsonrpc))
+        }
+        _ => None,
+    }
+}
+
 /// Extract the first parameter of a call and parse it as subscription id.
 pub fn get_unsubscribe_id(call: &rpc::Call) -> Option<pubsub::SubscriptionId> {
     match *call {
@@ -97,3 +224,229 @@ pub fn get_unsubscribe_id(call: &rpc::Call) -> Option<pubsub::SubscriptionId> {
         }
     }
 }
+
+/// Wraps an automatic unsubscribe request (sent when the subscriber is gone), so that it's abandoned after `timeout`.
+///
+/// Failures are only logged, since there is no one to report them to.
+/// The returned future has to be polled within tokio runtime.
+pub fn auto_unsubscribe<F, E>(request: F, name: String, timeout: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>
+where
+    F: Future<Output = Result<Option<rpc::Output>, E>> + Send + 'static,
+    E: fmt::Debug,
+{
+    // The timer is created lazily, since the request may be constructed outside of the runtime.
+    Box::pin(
+        future::lazy(move |_| tokio::time::timeout(timeout, request))
+            .flatten()
+            .map(move |result| match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => warn!("Unable to auto-unsubscribe from '{}': {:?}", name, e),
+                Err(_) => warn!("Auto-unsubscribe from '{}' timed out after {:?}", name, timeout),
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_batch() {
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"result":"0x1"},{"jsonrpc":"2.0","id":"a","error":{"code":-32601,"message":"Method not found"}},{"jsonrpc":"2.0","result":null}]"#;
+        let responses = parse_batch(batch).unwrap();
+
+        assert_eq!(
+            responses.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>(),
+            vec![rpc::Id::Num(1), rpc::Id::Str("a".into()), rpc::Id::Null]
+        );
+        assert_eq!(
+            responses[0].1,
+            serde_json::json!({"jsonrpc":"2.0","id":1,"result":"0x1"})
+        );
+    }
+
+    #[test]
+    fn should_not_parse_single_response_as_batch() {
+        assert_eq!(parse_batch(br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#), None);
+    }
+
+    #[test]
+    fn should_peek_batch_ids() {
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"result":"0x1"},{"jsonrpc":"2.0","id":"a","result":"0x2"}]"#;
+
+        assert_eq!(
+            peek_batch_ids(batch),
+            Some(vec![rpc::Id::Num(1), rpc::Id::Str("a".into())])
+        );
+        assert_eq!(peek_batch_ids(br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#), None);
+    }
+
+    #[test]
+    fn should_format_calls() {
+        // given
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_getBlockByNumber".into(),
+            params: rpc::Params::Array(vec!["latest".into(), false.into()]),
+        });
+        let no_params = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(2),
+            method: "eth_chainId".into(),
+            params: rpc::Params::None,
+        });
+        let notification = rpc::Call::Notification(rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: "eth_log".into(),
+            params: rpc::Params::Map(serde_json::from_str(r#"{"level":"info"}"#).unwrap()),
+        });
+        let invalid = rpc::Call::Invalid {
+            id: rpc::Id::Str("a".into()),
+        };
+
+        // when
+        let formatted = [&call, &no_params, &notification, &invalid]
+            .iter()
+            .map(|call| format_call(call))
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(
+            formatted,
+            vec![
+                r#"eth_getBlockByNumber("latest", false)"#,
+                "eth_chainId()",
+                r#"[notify] eth_log({"level":"info"})"#,
+                r#"[invalid id="a"]"#,
+            ]
+        );
+    }
+
+    fn unsubscribe(id: rpc::Value) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_unsubscribe".into(),
+            params: rpc::Params::Array(vec![id]),
+        })
+    }
+
+    #[test]
+    fn should_get_numeric_unsubscribe_id() {
+        assert_eq!(
+            get_unsubscribe_id(&unsubscribe(5.into())),
+            Some(pubsub::SubscriptionId::Number(5))
+        );
+    }
+
+    #[test]
+    fn should_get_string_unsubscribe_id() {
+        assert_eq!(
+            get_unsubscribe_id(&unsubscribe("0xdeadbeef".into())),
+            Some(pubsub::SubscriptionId::String("0xdeadbeef".into()))
+        );
+    }
+
+    #[test]
+    fn should_peek_string_subscription_id() {
+        let notification =
+            br#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0xdeadbeef","result":"0x1"}}"#;
+
+        assert_eq!(
+            peek_subscription_id(notification),
+            Some(pubsub::SubscriptionId::String("0xdeadbeef".into()))
+        );
+    }
+
+    #[test]
+    fn should_peek_nested_subscription_id_with_custom_parser() {
+        // given
+        let notification =
+            br#"{"jsonrpc":"2.0","method":"chain_notify","params":{"subscription":{"id":"0xdeadbeef"},"result":"0x1"}}"#;
+        let parser = |params: &rpc::Params| match *params {
+            rpc::Params::Map(ref map) => map
+                .get("subscription")
+                .and_then(|subscription| subscription.get("id"))
+                .and_then(pubsub::SubscriptionId::parse_value),
+            _ => None,
+        };
+
+        // when
+        let standard = peek_subscription_id(notification);
+        let custom = peek_subscription_id_with(notification, Some(&parser));
+
+        // then
+        assert_eq!(standard, None);
+        assert_eq!(custom, Some(pubsub::SubscriptionId::String("0xdeadbeef".into())));
+    }
+
+    fn failure(jsonrpc: Option<rpc::Version>, id: rpc::Id) -> Option<rpc::Output> {
+        Some(rpc::Output::Failure(rpc::Failure {
+            jsonrpc,
+            error: rpc::Error {
+                code: rpc::ErrorCode::ServerError(-1),
+                message: "Not allowed.".into(),
+                data: None,
+            },
+            id,
+        }))
+    }
+
+    #[test]
+    fn should_build_error_response_to_method_call() {
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(5),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        });
+
+        assert_eq!(
+            build_error_response(&call, -1, "Not allowed."),
+            failure(Some(rpc::Version::V2), rpc::Id::Num(5))
+        );
+    }
+
+    #[test]
+    fn should_not_build_error_response_to_notification() {
+        let call = rpc::Call::Notification(rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        });
+
+        assert_eq!(build_error_response(&call, -1, "Not allowed."), None);
+    }
+
+    #[test]
+    fn should_build_error_response_to_invalid_call() {
+        let call = rpc::Call::Invalid {
+            id: rpc::Id::Str("a".into()),
+        };
+
+        assert_eq!(
+            build_error_response(&call, -1, "Not allowed."),
+            failure(None, rpc::Id::Str("a".into()))
+        );
+    }
+
+    #[test]
+    fn should_abandon_slow_auto_unsubscribe() {
+        // given
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let request = future::pending::<Result<Option<rpc::Output>, String>>();
+        let unsubscribe = auto_unsubscribe(request, "eth_subscription".into(), Duration::from_millis(10));
+        let start = ::std::time::Instant::now();
+
+        // when
+        runtime.block_on(unsubscribe);
+
+        // then
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -23,10 +23,15 @@
 #![warn(unused_extern_crates)]
 
 extern crate cli_params;
+extern crate hex;
+extern crate hmac;
 extern crate jsonrpc_core as rpc;
 extern crate jsonrpc_pubsub as pubsub;
 extern crate parking_lot;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate sha2;
+extern crate tokio;
 
 #[macro_use]
 extern crate serde_derive;
@@ -34,13 +39,37 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
 use rpc::futures::{future::Either, Future};
 
+/// Methods used to probe the upstream, at least one of them is expected to succeed.
+const PROBE_METHODS: &[&str] = &["net_version", "system_name"];
+
+/// How long to wait for the upstream to respond to a health check.
+pub const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the first notification when probing subscriptions.
+pub const SUBSCRIPTION_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for the upstream to respond to a capability probe.
+pub const CAPABILITY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Error code returned to the client if the call times out (see also `call_timeout`).
+pub const TIMEOUT_ERROR_CODE: i64 = -32003;
+
+pub mod call_timeout;
 pub mod config;
 pub mod helpers;
+pub mod meta_inject;
 pub mod shared;
+pub mod signing;
+pub mod transport;
 
 /// Represents a Pub-Sub method description.
 #[derive(Debug, Clone, Deserialize)]
@@ -53,6 +82,16 @@ pub struct Subscription {
     pub name: String,
 }
 
+impl std::fmt::Display for Subscription {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{}/{} (notifications: {})",
+            self.subscribe, self.unsubscribe, self.name
+        )
+    }
+}
+
 /// Passthrough transport.
 ///
 /// This is an upstream transport (can do load balancing, failover or parallel requests)
@@ -71,10 +110,374 @@ pub trait Transport: Send + Sync + 'static {
     ) -> Self::Future;
 
     /// Send unsubscribe call upstream.
-    fn unsubscribe(&self, call: rpc::Call, subscription: Subscription) -> Self::Future;
+    ///
+    /// The `sink` is the session of the caller, the call may be answered without reaching the upstream
+    /// if the subscription is shared with other sessions.
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future;
 
     /// Send a regular call upstream.
+    ///
+    /// Notifications are sent without awaiting the returned future,
+    /// so the call should be written upstream eagerly.
     fn send(&self, call: rpc::Call) -> Self::Future;
+
+    /// Send a regular call upstream together with additional headers.
+    ///
+    /// Transports that don't support headers ignore them.
+    fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+        let _ = headers;
+        self.send(call)
+    }
+
+    /// Verify that the upstream is reachable and responds to calls.
+    ///
+    /// Sends `net_version` and `system_name` and succeeds if any of them returns a result.
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        use rpc::futures::FutureExt;
+
+        let calls = PROBE_METHODS.iter().map(|method| {
+            self.send(rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Str(format!("proxy_probe_{}", method)),
+                method: (*method).into(),
+                params: rpc::Params::None,
+            }))
+            .map(|result| result.map_err(|e| format!("{:?}", e)))
+        });
+
+        Box::pin(rpc::futures::future::join_all(calls).map(|results| {
+            let mut errors = vec![];
+            for result in results {
+                match result {
+                    Ok(Some(rpc::Output::Success(_))) => return Ok(()),
+                    Ok(Some(rpc::Output::Failure(failure))) => errors.push(failure.error.message),
+                    Ok(None) => errors.push("No response".into()),
+                    Err(e) => errors.push(e),
+                }
+            }
+            Err(format!("Upstream did not respond to {:?}: {:?}", PROBE_METHODS, errors))
+        }))
+    }
+
+    /// Check if the upstream is healthy.
+    ///
+    /// Returns `true` if the `probe` succeeds within `HEALTH_CHECK_TIMEOUT`.
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        health_check(self.probe(), HEALTH_CHECK_TIMEOUT)
+    }
+
+    /// Verify that the upstream delivers notifications of given subscription.
+    ///
+    /// Subscribes with given params, waits for the first notification and unsubscribes.
+    /// Returns `false` if no notification arrives within `SUBSCRIPTION_PROBE_TIMEOUT`.
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        probe_subscriptions(self, subscription, params, SUBSCRIPTION_PROBE_TIMEOUT)
+    }
+}
+
+/// Resolves to `true` if a notification of given subscription arrives within the timeout.
+///
+/// See `Transport::probe_subscriptions`.
+pub fn probe_subscriptions<'a, T: Transport + ?Sized>(
+    transport: &'a T,
+    subscription: &'a Subscription,
+    params: rpc::Params,
+    timeout: Duration,
+) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+    use rpc::futures::{FutureExt, StreamExt, TryFutureExt};
+
+    let (sender, notifications) = rpc::futures::channel::mpsc::unbounded();
+    // The session is kept until the probe is finished, dropping it cancels the subscription.
+    let session = Arc::new(pubsub::Session::new(sender));
+    let subscribe = transport.subscribe(
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Str("proxy_probe_subscribe".into()),
+            method: subscription.subscribe.clone(),
+            params,
+        }),
+        Some(session.clone()),
+        subscription.clone(),
+    );
+    let first_notification = subscribe
+        .map(|result| match result {
+            Ok(Some(rpc::Output::Success(success))) => Ok(success.result),
+            Ok(Some(rpc::Output::Failure(failure))) => Err(failure.error.message),
+            Ok(None) => Err("No response".into()),
+            Err(e) => Err(format!("{:?}", e)),
+        })
+        .and_then(|id| {
+            notifications.into_future().map(|(notification, _)| match notification {
+                Some(_) => Ok(id),
+                None => Err("Session closed".into()),
+            })
+        });
+
+    // The timer is created lazily, since it requires to be within the runtime context.
+    Box::pin(
+        rpc::futures::future::lazy(move |_| tokio::time::timeout(timeout, first_notification))
+            .flatten()
+            .then(move |result| {
+                let id = match result {
+                    Ok(Ok(id)) => id,
+                    Ok(Err(e)) => {
+                        warn!("Subscription probe of {} failed: {}", subscription, e);
+                        return Either::Left(rpc::futures::future::ready(false));
+                    }
+                    Err(_) => {
+                        warn!("No notification of {} received within {:?}", subscription, timeout);
+                        return Either::Left(rpc::futures::future::ready(false));
+                    }
+                };
+
+                let unsubscribe = transport.unsubscribe(
+                    rpc::Call::MethodCall(rpc::MethodCall {
+                        jsonrpc: Some(rpc::Version::V2),
+                        id: rpc::Id::Str("proxy_probe_unsubscribe".into()),
+                        method: subscription.unsubscribe.clone(),
+                        params: rpc::Params::Array(vec![id]),
+                    }),
+                    Some(session.clone()),
+                    subscription.clone(),
+                );
+                Either::Right(unsubscribe.map(move |result| {
+                    if let Err(e) = result {
+                        warn!("Unable to unsubscribe from {} after probing: {:?}", subscription, e);
+                    }
+                    drop(session);
+                    true
+                }))
+            }),
+    )
+}
+
+/// Calls probe methods of given capability probes (method -> probe method).
+///
+/// Resolves to the methods whose probe failed or didn't respond within the timeout,
+/// see `Middleware::with_unsupported_methods`.
+pub fn probe_capabilities<'a, T: Transport + ?Sized>(
+    transport: &'a T,
+    probes: &HashMap<String, String>,
+    timeout: Duration,
+) -> Pin<Box<dyn Future<Output = HashSet<String>> + Send + 'a>> {
+    use rpc::futures::FutureExt;
+
+    let probe_methods = probes.values().cloned().collect::<HashSet<_>>();
+    let calls = probe_methods.into_iter().map(move |probe| {
+        let call = transport.send(rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Str(format!("proxy_capability_{}", probe)),
+            method: probe.clone(),
+            params: rpc::Params::None,
+        }));
+        // The timer is created lazily, since it requires to be within the runtime context.
+        rpc::futures::future::lazy(move |_| tokio::time::timeout(timeout, call))
+            .flatten()
+            .map(move |result| {
+                let supported = match result {
+                    Ok(Ok(Some(rpc::Output::Success(_)))) => true,
+                    Ok(Ok(Some(rpc::Output::Failure(failure)))) => {
+                        warn!("Capability probe {} failed: {}", probe, failure.error.message);
+                        false
+                    }
+                    Ok(Ok(None)) => {
+                        warn!("Capability probe {} failed: No response", probe);
+                        false
+                    }
+                    Ok(Err(e)) => {
+                        warn!("Capability probe {} failed: {:?}", probe, e);
+                        false
+                    }
+                    Err(_) => {
+                        warn!(
+                            "Upstream did not respond to capability probe {} within {:?}",
+                            probe, timeout
+                        );
+                        false
+                    }
+                };
+                (probe, supported)
+            })
+    });
+
+    let probes = probes.clone();
+    Box::pin(rpc::futures::future::join_all(calls).map(move |results| {
+        let failed = results
+            .into_iter()
+            .filter(|(_, supported)| !supported)
+            .map(|(probe, _)| probe)
+            .collect::<HashSet<_>>();
+        probes
+            .into_iter()
+            .filter(|(_, probe)| failed.contains(probe))
+            .map(|(method, _)| method)
+            .collect()
+    }))
+}
+
+/// Resolves to `true` if given probe succeeds within the timeout.
+pub fn health_check(
+    probe: Pin<Box<dyn Future<Output = Result<(), String>> + Send>>,
+    timeout: Duration,
+) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+    use rpc::futures::FutureExt;
+
+    // The timer is created lazily, since it requires to be within the runtime context.
+    Box::pin(
+        rpc::futures::future::lazy(move |_| tokio::time::timeout(timeout, probe))
+            .flatten()
+            .map(move |result| match result {
+                Ok(Ok(())) => true,
+                Ok(Err(e)) => {
+                    warn!("Upstream health check failed: {}", e);
+                    false
+                }
+                Err(_) => {
+                    warn!("Upstream did not respond to health check within {:?}", timeout);
+                    false
+                }
+            }),
+    )
+}
+
+/// Future returned by dynamically dispatched transports.
+pub type BoxFuture = Box<dyn Future<Output = Result<Option<rpc::Output>, String>> + Send + Unpin>;
+
+/// A dynamically dispatched transport.
+pub type DynTransport = Arc<dyn Transport<Error = String, Future = BoxFuture>>;
+
+/// Boxes futures of given transport, so that it can be used as `DynTransport`.
+pub fn boxed<T: Transport<Error = String>>(transport: T) -> DynTransport {
+    Arc::new(Boxed(transport))
+}
+
+#[derive(Debug)]
+struct Boxed<T>(T);
+
+impl<T: Transport<Error = String>> Transport for Boxed<T> {
+    type Error = String;
+    type Future = BoxFuture;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        Box::new(self.0.subscribe(call, sink, subscription))
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        Box::new(self.0.unsubscribe(call, sink, subscription))
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        Box::new(self.0.send(call))
+    }
+
+    fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+        Box::new(self.0.send_with_headers(call, headers))
+    }
+
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        self.0.probe()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        self.0.health_check()
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.0.probe_subscriptions(subscription, params)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        (**self).subscribe(call, sink, subscription)
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        (**self).unsubscribe(call, sink, subscription)
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        (**self).send(call)
+    }
+
+    fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+        (**self).send_with_headers(call, headers)
+    }
+
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        (**self).probe()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        (**self).health_check()
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        (**self).probe_subscriptions(subscription, params)
+    }
+}
+
+/// Transformation applied to calls before they are sent upstream.
+#[derive(Clone)]
+pub struct RequestTransform(Arc<dyn Fn(rpc::Call) -> rpc::Call + Send + Sync>);
+
+impl std::fmt::Debug for RequestTransform {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "RequestTransform")
+    }
+}
+
+/// Function transforming the response of a method call, given the name of the method.
+type TransformResponse = dyn Fn(&str, rpc::Output) -> rpc::Output + Send + Sync;
+
+/// Transformation applied to responses of method calls, given the name of the called method.
+#[derive(Clone)]
+pub struct ResponseTransform(Arc<TransformResponse>);
+
+impl std::fmt::Debug for ResponseTransform {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "ResponseTransform")
+    }
 }
 
 /// Pass-through middleware
@@ -86,6 +489,10 @@ pub struct Middleware<T> {
     transport: T,
     subscribe_methods: HashMap<String, Subscription>,
     unsubscribe_methods: HashMap<String, Subscription>,
+    timeout: Option<(Duration, tokio::runtime::Handle)>,
+    request_transform: Option<RequestTransform>,
+    response_transform: Option<ResponseTransform>,
+    unsupported_methods: Arc<HashSet<String>>,
 }
 
 impl<T> Middleware<T> {
@@ -94,10 +501,29 @@ impl<T> Middleware<T> {
         let mut pubsub_methods = vec![];
         for p in params {
             match p {
-                config::Param::PubSubMethods(ref m) => pubsub_methods.extend(m.clone()),
+                config::Param::PubSubMethods(ref m) | config::Param::ExternalSubscriptions(ref m) => {
+                    pubsub_methods.extend(m.clone())
+                }
+                config::Param::HmacSecret(_)
+                | config::Param::IdPrefix(_)
+                | config::Param::Timeout(_)
+                | config::Param::CallTimeout(_)
+                | config::Param::LogRequests(_)
+                | config::Param::LogResponses(_)
+                | config::Param::MaxPendingRequests(_)
+                | config::Param::CapabilityProbe(_) => {}
             }
         }
 
+        // The timer has to be registered within the runtime, while calls may come from other threads.
+        let timeout = config::timeout(params).and_then(|timeout| match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => Some((timeout, runtime)),
+            Err(_) => {
+                warn!("Upstream timeout requires a tokio runtime and is disabled.");
+                None
+            }
+        });
+
         Self {
             transport,
             subscribe_methods: pubsub_methods
@@ -105,6 +531,75 @@ impl<T> Middleware<T> {
                 .map(|s| (s.subscribe.clone(), s.clone()))
                 .collect(),
             unsubscribe_methods: pubsub_methods.into_iter().map(|s| (s.unsubscribe.clone(), s)).collect(),
+            timeout,
+            request_transform: None,
+            response_transform: None,
+            unsupported_methods: Default::default(),
+        }
+    }
+
+    /// Reject calls to given methods with `Method not found` error instead of forwarding them.
+    ///
+    /// Usually the result of `probe_capabilities` done on startup.
+    pub fn with_unsupported_methods(mut self, methods: HashSet<String>) -> Self {
+        self.unsupported_methods = Arc::new(methods);
+        self
+    }
+
+    /// Modify every call before it's forwarded to the transport.
+    ///
+    /// Useful for one-off changes that don't deserve a separate middleware.
+    pub fn with_request_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(rpc::Call) -> rpc::Call + Send + Sync + 'static,
+    {
+        self.request_transform = Some(RequestTransform(Arc::new(transform)));
+        self
+    }
+
+    /// Modify every upstream response to a method call before it's returned.
+    ///
+    /// The transform receives the name of the called method, since responses don't carry it.
+    /// Subscription responses and notifications are not transformed.
+    pub fn with_response_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&str, rpc::Output) -> rpc::Output + Send + Sync + 'static,
+    {
+        self.response_transform = Some(ResponseTransform(Arc::new(transform)));
+        self
+    }
+}
+
+impl<T: Transport> Middleware<T> {
+    /// Awaits the upstream response.
+    ///
+    /// Responds with `timeout_response` if the upstream doesn't respond within configured timeout.
+    fn respond<F>(
+        &self,
+        future: F,
+        timeout_response: Option<rpc::Output>,
+        action: &'static str,
+    ) -> rpc::middleware::NoopCallFuture
+    where
+        F: Future<Output = Result<Option<rpc::Output>, T::Error>> + Send + 'static,
+    {
+        use rpc::futures::{FutureExt, TryFutureExt};
+
+        let future = future
+            .map_err(move |e| warn!("Failed to {}: {:?}", action, e))
+            .map(|v| v.unwrap_or(None));
+
+        match self.timeout {
+            None => Box::pin(future),
+            Some((timeout, ref runtime)) => {
+                let _guard = runtime.enter();
+                Box::pin(tokio::time::timeout(timeout, future).map(move |result| {
+                    result.unwrap_or_else(|_| {
+                        warn!("Failed to {}: Upstream timeout", action);
+                        timeout_response
+                    })
+                }))
+            }
         }
     }
 }
@@ -122,7 +617,29 @@ where
         F: FnOnce(rpc::Call, M) -> X + Send,
         X: Future<Output = Option<rpc::Output>> + Send + 'static,
     {
-        use rpc::futures::{FutureExt, TryFutureExt};
+        // Invalid calls can't be answered by the upstream, so they are rejected right away.
+        if let rpc::Call::Invalid { .. } = request {
+            let response = helpers::build_error_response(
+                &request,
+                rpc::ErrorCode::InvalidRequest.code(),
+                &rpc::ErrorCode::InvalidRequest.description(),
+            );
+            return Either::Left(Box::pin(rpc::futures::future::ready(response)));
+        }
+
+        if helpers::get_method_name(&request).is_some_and(|method| self.unsupported_methods.contains(method)) {
+            let response = helpers::build_error_response(
+                &request,
+                rpc::ErrorCode::MethodNotFound.code(),
+                &rpc::ErrorCode::MethodNotFound.description(),
+            );
+            return Either::Left(Box::pin(rpc::futures::future::ready(response)));
+        }
+
+        let request = match self.request_transform {
+            Some(RequestTransform(ref transform)) => transform(request),
+            None => request,
+        };
 
         let (subscribe, unsubscribe) = {
             let method = helpers::get_method_name(&request);
@@ -136,29 +653,518 @@ where
             }
         };
 
+        let timeout_response = self
+            .timeout
+            .as_ref()
+            .and_then(|_| helpers::build_error_response(&request, TIMEOUT_ERROR_CODE, "Upstream timeout"));
+
         if let Some(subscription) = subscribe {
-            return Either::Left(Box::pin(
-                self.transport
-                    .subscribe(request, meta.into(), subscription)
-                    .map_err(|e| warn!("Failed to subscribe: {:?}", e))
-                    .map(|v| v.unwrap_or(None)),
-            ));
+            let future = self.transport.subscribe(request, meta.into(), subscription);
+            return Either::Left(self.respond(future, timeout_response, "subscribe"));
         }
 
         if let Some(subscription) = unsubscribe {
-            return Either::Left(Box::pin(
-                self.transport
-                    .unsubscribe(request, subscription)
-                    .map_err(|e| warn!("Failed to unsubscribe: {:?}", e))
-                    .map(|v| v.unwrap_or(None)),
-            ));
-        }
-
-        Either::Left(Box::pin(
-            self.transport
-                .send(request)
-                .map_err(|e| warn!("Failed to send: {:?}", e))
-                .map(|v| v.unwrap_or(None)),
-        ))
+            let future = self.transport.unsubscribe(request, meta.into(), subscription);
+            return Either::Left(self.respond(future, timeout_response, "unsubscribe"));
+        }
+
+        // Notifications are fire-and-forget, there is no response to wait for.
+        if let rpc::Call::Notification(_) = request {
+            drop(self.transport.send(request));
+            return Either::Left(Box::pin(rpc::futures::future::ready(None)));
+        }
+
+        let transform = self.response_transform.clone().and_then(|transform| {
+            let method = helpers::get_method_name(&request)?.to_owned();
+            Some((transform, method))
+        });
+        let future = self.respond(self.transport.send(request), timeout_response, "send");
+        match transform {
+            Some((ResponseTransform(transform), method)) => {
+                use rpc::futures::FutureExt;
+
+                Either::Left(Box::pin(
+                    future.map(move |output| output.map(|output| transform(&method, output))),
+                ))
+            }
+            None => Either::Left(future),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpc::futures::{executor::block_on, future};
+
+    struct Upstream(fn(&str) -> Result<Option<rpc::Output>, String>);
+
+    impl Transport for Upstream {
+        type Error = String;
+        type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+        fn subscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, call: rpc::Call) -> Self::Future {
+            future::ready((self.0)(helpers::get_method_name(&call).unwrap()))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Meta;
+
+    impl rpc::Metadata for Meta {}
+
+    impl From<Meta> for Option<Arc<pubsub::Session>> {
+        fn from(_: Meta) -> Self {
+            None
+        }
+    }
+
+    /// Registers pending requests, but never receives any response.
+    struct Unresponsive(Arc<shared::Shared>);
+
+    impl Transport for Unresponsive {
+        type Error = String;
+        type Future = BoxFuture;
+
+        fn subscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, call: rpc::Call) -> Self::Future {
+            use rpc::futures::FutureExt;
+
+            let id = match helpers::get_id(&call) {
+                Some(id) => id.clone(),
+                None => return Box::new(future::ok(None)),
+            };
+            if self.0.add_pending(Some(&id), shared::PendingKind::Regular).is_none() {
+                return Box::new(future::err(shared::QUEUE_FULL_ERROR.to_owned()));
+            }
+            // Like the real transports, the request is abandoned once the caller stops waiting.
+            let guard = shared::PendingGuard::new(self.0.clone(), id);
+            Box::new(future::pending().map(move |result| {
+                guard.disarm();
+                result
+            }))
+        }
+    }
+
+    fn output(result: rpc::Result<rpc::Value>) -> Result<Option<rpc::Output>, String> {
+        Ok(Some(rpc::Output::from(result, rpc::Id::Num(1), None)))
+    }
+
+    #[test]
+    fn should_display_subscription() {
+        let subscription = Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        };
+
+        assert_eq!(
+            subscription.to_string(),
+            "eth_subscribe/eth_unsubscribe (notifications: eth_subscription)"
+        );
+    }
+
+    #[test]
+    fn should_reject_methods_with_failed_capability_probe() {
+        // given
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let upstream = Upstream(|method| match method {
+            "net_version" => Err("Method not supported".into()),
+            "eth_chainId" => output(Ok("0x1".into())),
+            method => output(Ok(method.into())),
+        });
+        let probes = vec![
+            ("parity_chainId".to_owned(), "net_version".to_owned()),
+            ("eth_chainId".to_owned(), "eth_chainId".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        let unsupported = block_on(probe_capabilities(&upstream, &probes, CAPABILITY_PROBE_TIMEOUT));
+        let middleware = Middleware::new(upstream, &[]).with_unsupported_methods(unsupported.clone());
+        let call = |method: &str| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Num(1),
+                method: method.into(),
+                params: rpc::Params::None,
+            })
+        };
+
+        // when
+        let rejected = rpc::Middleware::on_call(&middleware, call("parity_chainId"), Meta, |_, _| {
+            future::pending::<Option<rpc::Output>>()
+        });
+        let forwarded = rpc::Middleware::on_call(&middleware, call("eth_chainId"), Meta, |_, _| {
+            future::pending::<Option<rpc::Output>>()
+        });
+
+        // then
+        assert_eq!(unsupported, vec!["parity_chainId".to_owned()].into_iter().collect());
+        assert_eq!(
+            block_on(rejected),
+            Some(rpc::Output::from(
+                Err(rpc::Error::method_not_found()),
+                rpc::Id::Num(1),
+                Some(rpc::Version::V2)
+            ))
+        );
+        assert_eq!(
+            block_on(forwarded),
+            Some(rpc::Output::from(Ok("0x1".into()), rpc::Id::Num(1), None))
+        );
+    }
+
+    #[test]
+    fn should_transform_calls_before_sending_upstream() {
+        // given
+        let middleware = Middleware::new(Upstream(|method| output(Ok(method.into()))), &[]).with_request_transform(
+            |call| match call {
+                rpc::Call::MethodCall(mut request) => {
+                    request.method = format!("{}_v2", request.method);
+                    rpc::Call::MethodCall(request)
+                }
+                other => other,
+            },
+        );
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_call".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        let response =
+            rpc::Middleware::on_call(&middleware, call, Meta, |_, _| future::pending::<Option<rpc::Output>>());
+
+        // then
+        assert_eq!(block_on(response), output(Ok("eth_call_v2".into())).unwrap());
+    }
+
+    #[test]
+    fn should_transform_responses_of_method_calls() {
+        // given
+        let middleware =
+            Middleware::new(Upstream(|_| output(Ok("Geth".into()))), &[]).with_response_transform(|method, output| {
+                match output {
+                    rpc::Output::Success(mut success) => {
+                        success.result = format!("{} ({})", success.result.as_str().unwrap(), method).into();
+                        rpc::Output::Success(success)
+                    }
+                    other => other,
+                }
+            });
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "web3_clientVersion".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        let response =
+            rpc::Middleware::on_call(&middleware, call, Meta, |_, _| future::pending::<Option<rpc::Output>>());
+
+        // then
+        assert_eq!(
+            block_on(response),
+            output(Ok("Geth (web3_clientVersion)".into())).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_not_wait_for_response_to_notification() {
+        // given
+        let shared = Arc::new(shared::Shared::default());
+        let middleware = Middleware::new(Unresponsive(shared.clone()), &[]);
+        let notification = rpc::Call::Notification(rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: "eth_submitHashrate".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        let response = rpc::Middleware::on_call(&middleware, notification, Meta, |_, _| {
+            future::pending::<Option<rpc::Output>>()
+        });
+
+        // then
+        assert_eq!(block_on(response), None);
+        assert_eq!(shared.pending_count(), 0);
+    }
+
+    #[test]
+    fn should_reject_invalid_calls_without_sending_them_upstream() {
+        // given
+        let shared = Arc::new(shared::Shared::default());
+        let middleware = Middleware::new(Unresponsive(shared.clone()), &[]);
+        let call = rpc::Call::Invalid { id: rpc::Id::Num(5) };
+
+        // when
+        let response =
+            rpc::Middleware::on_call(&middleware, call, Meta, |_, _| future::pending::<Option<rpc::Output>>());
+
+        // then
+        assert_eq!(
+            block_on(response),
+            Some(rpc::Output::Failure(rpc::Failure {
+                jsonrpc: None,
+                error: rpc::Error::invalid_request(),
+                id: rpc::Id::Num(5),
+            }))
+        );
+        assert_eq!(shared.pending_count(), 0);
+    }
+
+    #[test]
+    fn should_respond_with_error_if_upstream_times_out() {
+        // given
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let shared = Arc::new(shared::Shared::default());
+        let middleware = Middleware::new(
+            Unresponsive(shared.clone()),
+            &[config::Param::Timeout(Some(Duration::from_millis(10)))],
+        );
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        let response = runtime.block_on(rpc::Middleware::on_call(&middleware, call, Meta, |_, _| {
+            future::pending::<Option<rpc::Output>>()
+        }));
+
+        // then
+        assert_eq!(
+            response,
+            Some(rpc::Output::Failure(rpc::Failure {
+                jsonrpc: Some(rpc::Version::V2),
+                error: rpc::Error {
+                    code: rpc::ErrorCode::ServerError(TIMEOUT_ERROR_CODE),
+                    message: "Upstream timeout".into(),
+                    data: None,
+                },
+                id: rpc::Id::Num(1),
+            }))
+        );
+        assert_eq!(shared.pending_count(), 0);
+    }
+
+    #[test]
+    fn should_accept_requests_after_pending_ones_time_out() {
+        // given
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let shared = Arc::new(shared::Shared::default().with_max_pending(1));
+        let middleware = Middleware::new(
+            Unresponsive(shared.clone()),
+            &[config::Param::Timeout(Some(Duration::from_millis(10)))],
+        );
+        let call = |id| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Num(id),
+                method: "eth_blockNumber".into(),
+                params: rpc::Params::None,
+            })
+        };
+        let timeout = |id| {
+            Some(rpc::Output::Failure(rpc::Failure {
+                jsonrpc: Some(rpc::Version::V2),
+                error: rpc::Error {
+                    code: rpc::ErrorCode::ServerError(TIMEOUT_ERROR_CODE),
+                    message: "Upstream timeout".into(),
+                    data: None,
+                },
+                id: rpc::Id::Num(id),
+            }))
+        };
+
+        // when
+        let responses = (1..=3)
+            .map(|id| {
+                runtime.block_on(rpc::Middleware::on_call(&middleware, call(id), Meta, |_, _| {
+                    future::pending::<Option<rpc::Output>>()
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        // then
+        assert_eq!(responses, vec![timeout(1), timeout(2), timeout(3)]);
+        assert_eq!(shared.pending_count(), 0);
+    }
+
+    #[test]
+    fn should_pass_response_received_within_timeout() {
+        // given
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let _guard = runtime.enter();
+        let middleware = Middleware::new(
+            Upstream(|_| output(Ok("0x1".into()))),
+            &[config::Param::Timeout(Some(Duration::from_secs(5)))],
+        );
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        let response = runtime.block_on(rpc::Middleware::on_call(&middleware, call, Meta, |_, _| {
+            future::pending::<Option<rpc::Output>>()
+        }));
+
+        // then
+        assert_eq!(response, output(Ok("0x1".into())).unwrap());
+    }
+
+    #[test]
+    fn should_probe_substrate_upstream() {
+        let upstream = Upstream(|method| match method {
+            "system_name" => output(Ok("substrate".into())),
+            _ => output(Err(rpc::Error::method_not_found())),
+        });
+
+        assert_eq!(block_on(upstream.probe()), Ok(()));
+    }
+
+    #[test]
+    fn should_fail_probe_if_upstream_is_unavailable() {
+        let upstream = Upstream(|_| Err("Connection refused".into()));
+
+        assert!(block_on(upstream.probe()).is_err());
+    }
+
+    fn run<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn should_report_healthy_upstream() {
+        let upstream = Upstream(|method| match method {
+            "net_version" => output(Ok("1".into())),
+            _ => output(Err(rpc::Error::method_not_found())),
+        });
+
+        assert!(run(upstream.health_check()));
+    }
+
+    #[test]
+    fn should_report_unhealthy_upstream() {
+        let upstream = Upstream(|_| output(Err(rpc::Error::internal_error())));
+
+        assert!(!run(upstream.health_check()));
+    }
+
+    #[test]
+    fn should_report_unresponsive_upstream_as_unhealthy() {
+        let upstream = Unresponsive(Default::default());
+
+        assert!(!run(health_check(upstream.probe(), Duration::from_millis(10))));
+    }
+
+    /// Sends a notification to every subscriber and records unsubscribe calls.
+    #[derive(Default)]
+    struct Notifying(parking_lot::Mutex<Vec<rpc::Call>>);
+
+    impl Transport for Notifying {
+        type Error = String;
+        type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+        fn subscribe(&self, _: rpc::Call, session: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            session.unwrap().sender().unbounded_send("notification".into()).unwrap();
+            future::ready(output(Ok("0x1".into())))
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.0.lock().push(call);
+            future::ready(output(Ok(true.into())))
+        }
+
+        fn send(&self, _: rpc::Call) -> Self::Future {
+            unreachable!()
+        }
+    }
+
+    fn subscription() -> Subscription {
+        Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        }
+    }
+
+    #[test]
+    fn should_probe_subscriptions() {
+        // given
+        let upstream = Notifying::default();
+        let subscription = subscription();
+
+        // when
+        let res = run(upstream.probe_subscriptions(&subscription, rpc::Params::Array(vec!["newHeads".into()])));
+
+        // then
+        assert!(res);
+        let unsubscribed = upstream.0.lock();
+        assert_eq!(unsubscribed.len(), 1);
+        assert_eq!(
+            helpers::get_unsubscribe_id(&unsubscribed[0]),
+            Some(pubsub::SubscriptionId::String("0x1".into()))
+        );
+    }
+
+    #[test]
+    fn should_fail_probe_if_no_notification_arrives() {
+        // given
+        let upstream = Upstream(|_| output(Ok("0x1".into())));
+        let subscription = subscription();
+
+        // when
+        let res = run(probe_subscriptions(
+            &upstream,
+            &subscription,
+            rpc::Params::None,
+            Duration::from_millis(10),
+        ));
+
+        // then
+        assert!(!res);
     }
 }
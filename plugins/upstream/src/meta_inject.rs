@@ -0,0 +1,257 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Injecting proxy-specific metadata into upstream calls.
+
+use pubsub;
+use rpc;
+use rpc::futures::{task, Future};
+use std::{pin::Pin, sync::Arc};
+use {Subscription, Transport};
+
+/// Transport decorator prefixing ids of every method call.
+///
+/// Allows the upstream to distinguish proxy traffic, e.g. `Id::Num(5)` is sent as `Id::Str("proxy-5")`.
+/// Responses are returned to the caller with the original id.
+#[derive(Debug, Clone)]
+pub struct MetaInjectTransport<T> {
+    inner: T,
+    id_prefix: Arc<String>,
+}
+
+impl<T> MetaInjectTransport<T> {
+    /// Wraps given transport.
+    pub fn new(inner: T, id_prefix: String) -> Self {
+        MetaInjectTransport {
+            inner,
+            id_prefix: Arc::new(id_prefix),
+        }
+    }
+
+    /// Prefixes the id of a method call and returns the original one.
+    fn mangle(&self, call: rpc::Call) -> (rpc::Call, Option<rpc::Id>) {
+        match call {
+            rpc::Call::MethodCall(mut request) => {
+                let id = match request.id {
+                    rpc::Id::Num(ref num) => num.to_string(),
+                    rpc::Id::Str(ref s) => s.clone(),
+                    rpc::Id::Null => "null".into(),
+                };
+                let original = ::std::mem::replace(&mut request.id, rpc::Id::Str(format!("{}{}", self.id_prefix, id)));
+                (rpc::Call::MethodCall(request), Some(original))
+            }
+            other => (other, None),
+        }
+    }
+}
+
+/// Future restoring the original id of the response.
+#[derive(Debug)]
+pub struct Demangle<F> {
+    inner: F,
+    original: Option<rpc::Id>,
+}
+
+impl<F, E> Future for Demangle<F>
+where
+    F: Future<Output = Result<Option<rpc::Output>, E>> + Unpin,
+{
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        let output = match Pin::new(&mut self.inner).poll(cx) {
+            task::Poll::Ready(output) => output,
+            task::Poll::Pending => return task::Poll::Pending,
+        };
+        let original = self.original.take();
+        task::Poll::Ready(output.map(|output| match (output, original) {
+            (Some(rpc::Output::Success(mut success)), Some(id)) => {
+                success.id = id;
+                Some(rpc::Output::Success(success))
+            }
+            (Some(rpc::Output::Failure(mut failure)), Some(id)) => {
+                failure.id = id;
+                Some(rpc::Output::Failure(failure))
+            }
+            (output, _) => output,
+        }))
+    }
+}
+
+impl<T: Transport> Transport for MetaInjectTransport<T> {
+    type Error = T::Error;
+    type Future = Demangle<T::Future>;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        let (call, original) = self.mangle(call);
+        Demangle {
+            inner: self.inner.subscribe(call, sink, subscription),
+            original,
+        }
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        let (call, original) = self.mangle(call);
+        Demangle {
+            inner: self.inner.unsubscribe(call, sink, subscription),
+            original,
+        }
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        let (call, original) = self.mangle(call);
+        Demangle {
+            inner: self.inner.send(call),
+            original,
+        }
+    }
+
+    fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+        let (call, original) = self.mangle(call);
+        Demangle {
+            inner: self.inner.send_with_headers(call, headers),
+            original,
+        }
+    }
+
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        self.inner.probe()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        self.inner.health_check()
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.inner.probe_subscriptions(subscription, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use rpc::futures::{executor::block_on, future};
+
+    /// Upstream echoing the id of every call.
+    #[derive(Default)]
+    struct Upstream {
+        ids: Mutex<Vec<rpc::Id>>,
+    }
+
+    impl Transport for Upstream {
+        type Error = String;
+        type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+        fn subscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, call: rpc::Call) -> Self::Future {
+            match call {
+                rpc::Call::MethodCall(request) => {
+                    self.ids.lock().push(request.id.clone());
+                    future::ready(Ok(Some(rpc::Output::from(
+                        Ok(rpc::Value::Null),
+                        request.id,
+                        request.jsonrpc,
+                    ))))
+                }
+                _ => future::ready(Ok(None)),
+            }
+        }
+
+        fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+            Box::pin(future::ready(Err("Upstream is syncing".into())))
+        }
+    }
+
+    fn call(id: rpc::Id) -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id,
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    fn id(output: Option<rpc::Output>) -> rpc::Id {
+        match output {
+            Some(rpc::Output::Success(success)) => success.id,
+            other => panic!("Unexpected output: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_prefix_ids_sent_upstream() {
+        // given
+        let transport = MetaInjectTransport::new(Upstream::default(), "proxy-".into());
+
+        // when
+        block_on(transport.send(call(rpc::Id::Num(5)))).unwrap();
+        block_on(transport.send(call(rpc::Id::Str("abc".into())))).unwrap();
+
+        // then
+        assert_eq!(
+            *transport.inner.ids.lock(),
+            vec![rpc::Id::Str("proxy-5".into()), rpc::Id::Str("proxy-abc".into())]
+        );
+    }
+
+    #[test]
+    fn should_restore_original_ids_of_responses() {
+        // given
+        let transport = MetaInjectTransport::new(Upstream::default(), "proxy-".into());
+
+        // when
+        let res1 = block_on(transport.send(call(rpc::Id::Num(5)))).unwrap();
+        let res2 = block_on(transport.send_with_headers(call(rpc::Id::Str("5".into())), vec![])).unwrap();
+
+        // then
+        assert_eq!(id(res1), rpc::Id::Num(5));
+        assert_eq!(id(res2), rpc::Id::Str("5".into()));
+    }
+    #[test]
+    fn should_forward_probes() {
+        // given
+        let transport = MetaInjectTransport::new(Upstream::default(), "proxy-".into());
+
+        // when
+        let result = block_on(transport.probe());
+
+        // then
+        assert_eq!(result, Err("Upstream is syncing".into()));
+    }
+}
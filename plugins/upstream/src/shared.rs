@@ -17,15 +17,23 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 //! Shared pieces for building upstream transport.
 
-use parking_lot::{Mutex, RwLock};
+use helpers;
+use parking_lot::Mutex;
 use pubsub;
 use rpc::{self, futures::channel::oneshot};
+use serde_json;
 use std::{
     collections::HashMap,
     fmt,
     sync::{Arc, Weak},
 };
 
+/// Error returned by transports if the call is rejected because of too many pending requests.
+pub const QUEUE_FULL_ERROR: &str = "QueueFull: too many requests awaiting upstream response.";
+
+/// Method of the notification sent to sessions once their subscription is re-issued to the upstream.
+pub const RESUBSCRIBED_NOTIFICATION: &str = "proxy_resubscribed";
+
 /// Pending request details
 pub type Pending = (oneshot::Sender<String>, PendingKind);
 /// A type of unsubscribe function
@@ -37,6 +45,8 @@ pub enum PendingKind {
     Regular,
     /// Subscribe request (after it's successful we should create a subscription)
     Subscribe(Arc<pubsub::Session>, Unsubscribe),
+    /// Subscribe call re-issued after reconnecting (the new subscription replaces the one with given id)
+    Resubscribe(pubsub::SubscriptionId),
 }
 
 impl fmt::Debug for PendingKind {
@@ -44,77 +54,1031 @@ impl fmt::Debug for PendingKind {
         match *self {
             PendingKind::Regular => write!(fmt, "Regular"),
             PendingKind::Subscribe(ref session, _) => write!(fmt, "Subscribe({:?})", session),
+            PendingKind::Resubscribe(ref id) => write!(fmt, "Resubscribe({:?})", id),
+        }
+    }
+}
+
+impl fmt::Display for PendingKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PendingKind::Regular => write!(fmt, "regular"),
+            PendingKind::Subscribe(..) => write!(fmt, "subscribe"),
+            PendingKind::Resubscribe(..) => write!(fmt, "resubscribe"),
         }
     }
 }
 
+/// Method name and serialized params of a subscribe call.
+type SubscribeKey = (String, String);
+
+/// A client waiting for the response to an identical subscribe call.
+struct Waiter {
+    id: rpc::Id,
+    sender: oneshot::Sender<String>,
+    session: Arc<pubsub::Session>,
+}
+
+/// Subscribe calls awaiting the upstream response.
+#[derive(Default)]
+struct InFlight {
+    waiters: HashMap<SubscribeKey, Vec<Waiter>>,
+    keys: HashMap<rpc::Id, (SubscribeKey, rpc::MethodCall)>,
+}
+
+impl InFlight {
+    /// Removes the call with given id, returning the call and clients waiting for its response.
+    fn finish(&mut self, id: &rpc::Id) -> (Option<rpc::MethodCall>, Vec<Waiter>) {
+        match self.keys.remove(id) {
+            Some((key, call)) => (Some(call), self.waiters.remove(&key).unwrap_or_default()),
+            None => (None, vec![]),
+        }
+    }
+}
+
+/// Sessions receiving notifications of a single upstream subscription.
+struct Subscribers {
+    sessions: Vec<Weak<pubsub::Session>>,
+    unsubscribe: Unsubscribe,
+    /// The subscribe call, used to re-issue the subscription after the upstream reconnects.
+    call: Option<rpc::MethodCall>,
+    /// Id of the re-issued subscription, if it differs from the one known to the sessions.
+    upstream_id: Option<pubsub::SubscriptionId>,
+}
+
+type Subscriptions = HashMap<pubsub::SubscriptionId, Subscribers>;
+
+/// Outcome of detaching a session from a subscription (see `Shared::detach_session`).
+#[derive(Debug, PartialEq)]
+pub enum Detached {
+    /// The subscription is not known, the unsubscribe call should be forwarded as is.
+    Unknown,
+    /// The session is not subscribed, the subscription is left intact.
+    NotSubscribed,
+    /// Other sessions still receive the notifications, the upstream subscription is kept.
+    Remaining,
+    /// The session was the last one, the subscription has to be cancelled at the upstream with given id.
+    Last(pubsub::SubscriptionId),
+}
+
 /// Shared subscription and pending requests manager.
-#[derive(Debug, Default)]
 pub struct Shared {
     // TODO [ToDr] Get rid of Mutex, rather use `Select` and have another channel that sets up pending requests.
     pending: Mutex<HashMap<rpc::Id, Pending>>,
+    max_pending: usize,
+    in_flight: Mutex<InFlight>,
     // TODO [ToDr] Use (SubscriptionName, SubscriptionId) as key.
-    subscriptions: RwLock<HashMap<pubsub::SubscriptionId, Weak<pubsub::Session>>>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    /// Ids of re-issued subscriptions mapped to the ids known to the sessions.
+    aliases: Arc<Mutex<HashMap<pubsub::SubscriptionId, pubsub::SubscriptionId>>>,
+    custom_subscription_parser: Option<Box<helpers::SubscriptionParser>>,
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("Shared")
+            .field("pending", &self.pending)
+            .field("max_pending", &self.max_pending)
+            .field("subscriptions", &self.subscriptions.lock().keys().collect::<Vec<_>>())
+            .field("custom_subscription_parser", &self.custom_subscription_parser.is_some())
+            .finish()
+    }
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Shared {
+            pending: Default::default(),
+            max_pending: usize::MAX,
+            in_flight: Default::default(),
+            subscriptions: Default::default(),
+            aliases: Default::default(),
+            custom_subscription_parser: None,
+        }
+    }
 }
 
 impl Shared {
+    /// Creates a manager that uses given parser for notifications without a standard `subscription` field.
+    ///
+    /// The parser has to return the id the subscription was registered with (i.e. the result of subscribe call).
+    pub fn with_subscription_parser<F>(parser: F) -> Self
+    where
+        F: Fn(&rpc::Params) -> Option<pubsub::SubscriptionId> + Send + Sync + 'static,
+    {
+        Shared {
+            custom_subscription_parser: Some(Box::new(parser)),
+            ..Default::default()
+        }
+    }
+
+    /// Limits the number of requests awaiting a response (see `add_pending`).
+    pub fn with_max_pending(mut self, max_pending: usize) -> Self {
+        self.max_pending = max_pending;
+        self
+    }
+
     /// Adds a new request to the list of pending requests
     ///
     /// We are awaiting the response for those requests.
+    /// Returns `None` for notifications and if there are already `max_pending` requests,
+    /// in the latter case the call should be rejected with `QUEUE_FULL_ERROR`.
     pub fn add_pending(&self, id: Option<&rpc::Id>, kind: PendingKind) -> Option<oneshot::Receiver<String>> {
-        if let Some(id) = id {
-            let (tx, rx) = oneshot::channel();
-            self.pending.lock().insert(id.clone(), (tx, kind));
-            Some(rx)
-        } else {
-            None
+        let id = id?;
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.pending.lock();
+        if pending.len() >= self.max_pending {
+            warn!(
+                "Too many requests awaiting upstream response ({}), rejecting {:?}.",
+                pending.len(),
+                id
+            );
+            drop(pending);
+            // Clients that joined the subscribe call are not going to get a response either.
+            self.in_flight.lock().finish(id);
+            return None;
         }
+        pending.insert(id.clone(), (tx, kind));
+        Some(rx)
+    }
+
+    /// Joins an identical subscribe call (same method and params) that is still awaiting the upstream response.
+    ///
+    /// The returned receiver gets the same response (and the session the same subscription) as the first caller.
+    /// Returns `None` if there is no such call, the caller is tracked then and should send the call upstream.
+    pub fn join_subscribe(&self, call: &rpc::Call, session: Arc<pubsub::Session>) -> Option<oneshot::Receiver<String>> {
+        let call = match *call {
+            rpc::Call::MethodCall(ref call) => call,
+            _ => return None,
+        };
+        let params = serde_json::to_string(&call.params).expect("Params serialization is infallible.");
+        let key = (call.method.clone(), params);
+
+        let mut in_flight = self.in_flight.lock();
+        if let Some(waiters) = in_flight.waiters.get_mut(&key) {
+            trace!("Joining in-flight subscribe call to {}", call.method);
+            let (sender, rx) = oneshot::channel();
+            waiters.push(Waiter {
+                id: call.id.clone(),
+                sender,
+                session,
+            });
+            return Some(rx);
+        }
+
+        in_flight.waiters.insert(key.clone(), vec![]);
+        in_flight.keys.insert(call.id.clone(), (key, call.clone()));
+        None
     }
 
     /// Removes a requests from the list of pending requests.
     ///
     /// Most likely the response has been received so we can respond or add a subscription instead.
+    /// Clients that joined the request (see `join_subscribe`) are not going to receive the response.
     pub fn remove_pending(&self, id: &rpc::Id) -> Option<Pending> {
+        self.in_flight.lock().finish(id);
         self.pending.lock().remove(id)
     }
 
+    /// Removes all requests from the list of pending requests.
+    ///
+    /// Should be used when the upstream disconnects, so that the requests can be failed
+    /// right away instead of waiting for a response that will never arrive.
+    pub fn remove_all_pending(&self) -> Vec<(rpc::Id, Pending)> {
+        *self.in_flight.lock() = Default::default();
+        self.pending.lock().drain().collect()
+    }
+
+    /// Responds to the pending request with given error instead of the upstream response.
+    ///
+    /// Returns `false` if there is no such request.
+    pub fn fail_pending(&self, id: &rpc::Id, error: rpc::Error) -> bool {
+        match self.remove_pending(id) {
+            Some((sink, _kind)) => {
+                send_error(sink, id.clone(), error);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Responds to all pending requests with given error.
+    ///
+    /// Should be used when the upstream disconnects, since the responses are never going to arrive.
+    pub fn fail_all_pending(&self, error: rpc::Error) {
+        for (id, (sink, _kind)) in self.remove_all_pending() {
+            send_error(sink, id, error.clone());
+        }
+    }
+
+    /// Returns the number of requests awaiting a response.
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    /// Returns ids of all requests awaiting a response.
+    #[doc(hidden)]
+    pub fn pending_ids(&self) -> Vec<rpc::Id> {
+        self.pending.lock().keys().cloned().collect()
+    }
+
+    /// Returns the number of active subscriptions.
+    pub fn subscription_count(&self) -> usize {
+        self.subscriptions.lock().len()
+    }
+
     /// Add a new subscription id and it's correlation with the session.
+    ///
+    /// A subscription can be shared by multiple sessions, `unsubscribe` of the first one is called
+    /// once all of them are dropped.
     pub fn add_subscription(
         &self,
         id: pubsub::SubscriptionId,
         session: Arc<pubsub::Session>,
         unsubscribe: Unsubscribe,
+    ) {
+        self.register_subscription(id, session, unsubscribe, None)
+    }
+
+    fn register_subscription(
+        &self,
+        id: pubsub::SubscriptionId,
+        session: Arc<pubsub::Session>,
+        unsubscribe: Unsubscribe,
+        call: Option<rpc::MethodCall>,
     ) {
         // make sure to send unsubscribe request and remove the subscription.
+        let subscriptions = self.subscriptions.clone();
+        let aliases = self.aliases.clone();
         let id2 = id.clone();
-        session.on_drop(move || unsubscribe(id2));
+        session.on_drop(move || {
+            let unsubscribe = {
+                let mut subscriptions = subscriptions.lock();
+                let is_last = subscriptions.get_mut(&id2).is_some_and(|subscribers| {
+                    // the session being dropped can't be upgraded anymore
+                    subscribers.sessions.retain(|session| session.strong_count() > 0);
+                    subscribers.sessions.is_empty()
+                });
+                if is_last {
+                    subscriptions.remove(&id2)
+                } else {
+                    None
+                }
+            };
+            if let Some(subscribers) = unsubscribe {
+                // re-issued subscriptions have to be cancelled with the new id.
+                let upstream_id = subscribers.upstream_id.unwrap_or(id2);
+                aliases.lock().remove(&upstream_id);
+                (subscribers.unsubscribe)(upstream_id);
+            }
+        });
 
         trace!("Registered subscription id {:?}", id);
-        self.subscriptions.write().insert(id, Arc::downgrade(&session));
+        let mut subscriptions = self.subscriptions.lock();
+        let subscribers = subscriptions.entry(id).or_insert_with(|| Subscribers {
+            sessions: vec![],
+            unsubscribe,
+            call: None,
+            upstream_id: None,
+        });
+        subscribers.sessions.push(Arc::downgrade(&session));
+        if subscribers.call.is_none() {
+            subscribers.call = call;
+        }
     }
 
     /// Removes a subscription.
+    ///
+    /// The `id` is the one known to the sessions, see `upstream_subscription_id`.
     pub fn remove_subscription(&self, id: &pubsub::SubscriptionId) {
         trace!("Removing subscription id {:?}", id);
-        self.subscriptions.write().remove(id);
+        let removed = self.subscriptions.lock().remove(id);
+        if let Some(upstream_id) = removed.and_then(|subscribers| subscribers.upstream_id) {
+            self.aliases.lock().remove(&upstream_id);
+        }
+    }
+
+    /// Detaches given session from a subscription, so that it no longer receives the notifications.
+    ///
+    /// The `id` is the one known to the sessions. Without a session the subscription is removed
+    /// for all of them.
+    pub fn detach_session(&self, id: &pubsub::SubscriptionId, session: Option<&Arc<pubsub::Session>>) -> Detached {
+        let mut subscriptions = self.subscriptions.lock();
+        if let Some(session) = session {
+            let subscribers = match subscriptions.get_mut(id) {
+                Some(subscribers) => subscribers,
+                None => return Detached::Unknown,
+            };
+            let is_session = |subscriber: &Weak<pubsub::Session>| subscriber.as_ptr() == Arc::as_ptr(session);
+            if !subscribers.sessions.iter().any(is_session) {
+                return Detached::NotSubscribed;
+            }
+            subscribers
+                .sessions
+                .retain(|subscriber| !is_session(subscriber) && subscriber.strong_count() > 0);
+            if !subscribers.sessions.is_empty() {
+                trace!("Session detached from subscription id {:?}", id);
+                return Detached::Remaining;
+            }
+        }
+
+        trace!("Removing subscription id {:?}", id);
+        let subscribers = match subscriptions.remove(id) {
+            Some(subscribers) => subscribers,
+            None => return Detached::Unknown,
+        };
+        match subscribers.upstream_id {
+            Some(upstream_id) => {
+                self.aliases.lock().remove(&upstream_id);
+                Detached::Last(upstream_id)
+            }
+            None => Detached::Last(id.clone()),
+        }
+    }
+
+    /// Returns the id of given subscription at the upstream.
+    ///
+    /// Differs from the id known to the sessions if the subscription has been re-issued (see `resubscribed`).
+    pub fn upstream_subscription_id(&self, id: &pubsub::SubscriptionId) -> pubsub::SubscriptionId {
+        self.subscriptions
+            .lock()
+            .get(id)
+            .and_then(|subscribers| subscribers.upstream_id.clone())
+            .unwrap_or_else(|| id.clone())
+    }
+
+    /// Returns subscribe calls of active subscriptions together with the ids known to the sessions.
+    ///
+    /// Used to re-issue the subscriptions after the upstream reconnects.
+    pub fn subscribe_calls(&self) -> Vec<(pubsub::SubscriptionId, rpc::MethodCall)> {
+        self.subscriptions
+            .lock()
+            .iter()
+            .filter_map(|(id, subscribers)| subscribers.call.clone().map(|call| (id.clone(), call)))
+            .collect()
+    }
+
+    /// Records that the subscription `id` has been re-issued and the upstream now uses `upstream_id`.
+    ///
+    /// Notifications of `upstream_id` are forwarded to the sessions with the original id
+    /// and the sessions are notified with `RESUBSCRIBED_NOTIFICATION`.
+    pub fn resubscribed(&self, id: &pubsub::SubscriptionId, upstream_id: pubsub::SubscriptionId) {
+        let sessions = {
+            let mut subscriptions = self.subscriptions.lock();
+            let subscribers = match subscriptions.get_mut(id) {
+                Some(subscribers) => subscribers,
+                None => return,
+            };
+            let mut aliases = self.aliases.lock();
+            if let Some(previous) = subscribers.upstream_id.take() {
+                aliases.remove(&previous);
+            }
+            if upstream_id != *id {
+                aliases.insert(upstream_id.clone(), id.clone());
+                subscribers.upstream_id = Some(upstream_id);
+            }
+            subscribers
+                .sessions
+                .iter()
+                .filter_map(Weak::upgrade)
+                .collect::<Vec<_>>()
+        };
+
+        trace!("Subscription {:?} re-issued to the upstream", id);
+        let notification = rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: RESUBSCRIBED_NOTIFICATION.into(),
+            params: rpc::Params::Map(
+                vec![("subscription".to_owned(), id.clone().into())]
+                    .into_iter()
+                    .collect(),
+            ),
+        };
+        let notification = serde_json::to_string(&notification).expect("Notification serialization is infallible.");
+        for session in sessions {
+            if let Err(e) = session.sender().unbounded_send(notification.clone()) {
+                warn!("Unable to notify about re-issued subscription {:?}: {:?}", id, e);
+            }
+        }
+    }
+
+    /// Returns the id known to the sessions and the sessions subscribed to given upstream subscription.
+    ///
+    /// `None` if the subscription is unknown.
+    fn subscribers(&self, id: &pubsub::SubscriptionId) -> Option<(pubsub::SubscriptionId, Vec<Arc<pubsub::Session>>)> {
+        let id = self.aliases.lock().get(id).cloned().unwrap_or_else(|| id.clone());
+        let sessions = self
+            .subscriptions
+            .lock()
+            .get(&id)
+            .map(|subscribers| subscribers.sessions.iter().filter_map(Weak::upgrade).collect())?;
+        Some((id, sessions))
     }
 
     /// Forwards a notification to given subscription.
     pub fn notify_subscription(&self, id: &pubsub::SubscriptionId, msg: String) -> Option<Result<(), String>> {
-        if let Some(session) = self.subscriptions.read().get(&id) {
-            if let Some(session) = session.upgrade() {
-                return Some(
-                    session
-                        .sender()
-                        .unbounded_send(msg)
-                        .map_err(|e| format!("Error sending notification: {:?}", e))
-                        .map(|_| ()),
-                );
-            } else {
-                error!("Session is not available and subscription was not removed.");
+        let (client_id, sessions) = self.subscribers(id)?;
+        let msg = if client_id != *id {
+            with_subscription_id(&msg, &client_id)
+        } else {
+            msg
+        };
+        if sessions.is_empty() {
+            error!("Session is not available and subscription was not removed.");
+            return None;
+        }
+
+        let mut result = Ok(());
+        for session in &sessions {
+            let sent = session
+                .sender()
+                .unbounded_send(msg.clone())
+                .map_err(|e| format!("Error sending notification: {:?}", e));
+            result = result.and(sent);
+        }
+        Some(result)
+    }
+
+    /// Forwards a notification to given subscription and removes the subscription if it can't be delivered.
+    ///
+    /// `pubsub::Session` only exposes an unbounded sender, so delivery fails once the client is gone.
+    /// Returns `false` if the subscription is unknown.
+    pub fn notify_subscription_checked(&self, id: &pubsub::SubscriptionId, msg: String) -> bool {
+        let (client_id, sessions) = match self.subscribers(id) {
+            Some(subscribers) => subscribers,
+            None => return false,
+        };
+        let msg = if client_id != *id {
+            with_subscription_id(&msg, &client_id)
+        } else {
+            msg
+        };
+        let delivered = sessions
+            .iter()
+            .filter(|session| match session.sender().unbounded_send(msg.clone()) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Unable to deliver notification of subscription {:?}: {:?}", id, e);
+                    false
+                }
+            })
+            .count();
+
+        if delivered == 0 {
+            warn!(
+                "Notification not delivered to any session, removing subscription {:?}",
+                id
+            );
+            // the sessions might outlive the subscription, so unsubscribe right away.
+            let subscribers = self.subscriptions.lock().remove(&client_id);
+            if let Some(subscribers) = subscribers {
+                self.aliases.lock().remove(id);
+                (subscribers.unsubscribe)(id.clone());
             }
         }
+        true
+    }
 
-        None
+    /// Passes the response to the pending request with given id.
+    ///
+    /// Clients that joined a subscribe call get the same response (with their request id).
+    pub fn respond(&self, id: rpc::Id, response: String) {
+        let (call, waiters) = self.in_flight.lock().finish(&id);
+        if let Some((sink, kind)) = self.pending.lock().remove(&id) {
+            match kind {
+                // Just a regular call, don't do anything else.
+                PendingKind::Regular => {}
+                // We have a subscription ID, register subscription.
+                PendingKind::Subscribe(session, unsubscribe) => {
+                    let subscription_id = helpers::peek_result(response.as_bytes())
+                        .as_ref()
+                        .and_then(pubsub::SubscriptionId::parse_value);
+                    if let Some(subscription_id) = subscription_id {
+                        self.register_subscription(subscription_id.clone(), session, unsubscribe, call);
+                        for waiter in &waiters {
+                            // the subscription is already registered, so the closure is never called
+                            self.add_subscription(subscription_id.clone(), waiter.session.clone(), Box::new(|_| {}));
+                        }
+                    }
+                }
+                // Notifications might follow right away, so start forwarding them before responding.
+                PendingKind::Resubscribe(subscription_id) => {
+                    let upstream_id = helpers::peek_result(response.as_bytes())
+                        .as_ref()
+                        .and_then(pubsub::SubscriptionId::parse_value);
+                    match upstream_id {
+                        Some(upstream_id) => self.resubscribed(&subscription_id, upstream_id),
+                        None => warn!("Unable to re-issue subscription {:?}: {}", subscription_id, response),
+                    }
+                }
+            }
+
+            for waiter in waiters {
+                trace!("Responding to joined subscribe call (id: {:?})", waiter.id);
+                if let Err(err) = waiter.sender.send(with_id(&response, &waiter.id)) {
+                    warn!("Sending a response to deallocated channel: {:?}", err);
+                }
+            }
+
+            trace!("Responding to (id: {:?}) with {:?}", id, response);
+            if let Err(err) = sink.send(response) {
+                warn!("Sending a response to deallocated channel: {:?}", err);
+            }
+        } else {
+            warn!("Got response for unknown request (id: {:?})", id);
+        }
+    }
+
+    /// Processes a text message received from the upstream.
+    ///
+    /// Forwards subscription notifications and routes (batch) responses to pending requests.
+    pub fn process_text(&self, t: String) -> Result<(), String> {
+        // First check if it's a notification for a subscription
+        let custom_parser = self.custom_subscription_parser.as_deref();
+        if let Some(id) = helpers::peek_subscription_id_with(t.as_bytes(), custom_parser) {
+            if !self.notify_subscription_checked(&id, t) {
+                warn!("Got notification for unknown subscription (id: {:?})", id);
+            }
+            return Ok(());
+        }
+
+        // then check if it's a batch of responses
+        if let Some(responses) = helpers::parse_batch(t.as_bytes()) {
+            for (id, response) in responses {
+                self.respond(id, response.to_string());
+            }
+            return Ok(());
+        }
+
+        // then check if it's one of the pending calls
+        if let Some(id) = helpers::peek_id(t.as_bytes()) {
+            self.respond(id, t);
+        } else {
+            warn!("Got unexpected notification: {:?}", t);
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes a pending request (see `Shared::remove_pending`) when dropped, unless disarmed.
+///
+/// Should be owned by the future awaiting the response, so that requests which failed to be written
+/// or have been abandoned (e.g. timed out) don't stay pending and identical subscribe calls don't join them.
+pub struct PendingGuard {
+    shared: Arc<Shared>,
+    id: Option<rpc::Id>,
+}
+
+impl PendingGuard {
+    /// Creates a guard of the pending request with given id.
+    pub fn new(shared: Arc<Shared>, id: rpc::Id) -> Self {
+        PendingGuard { shared, id: Some(id) }
+    }
+
+    /// Leaves the request alone, should be called once the response is received.
+    pub fn disarm(mut self) {
+        self.id = None;
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            trace!("Abandoning pending request (id: {:?})", id);
+            self.shared.remove_pending(&id);
+        }
+    }
+}
+
+/// Sends an error response to the pending request.
+fn send_error(sink: oneshot::Sender<String>, id: rpc::Id, error: rpc::Error) {
+    let output = rpc::Output::from(Err(error), id, Some(rpc::Version::V2));
+    let response = serde_json::to_string(&output).expect("Output serialization is infallible.");
+    if let Err(err) = sink.send(response) {
+        warn!("Sending a response to deallocated channel: {:?}", err);
+    }
+}
+
+/// Replaces the subscription id of given notification.
+fn with_subscription_id(notification: &str, id: &pubsub::SubscriptionId) -> String {
+    match serde_json::from_str::<serde_json::Value>(notification) {
+        Ok(mut notification) => {
+            if let Some(subscription) = notification.pointer_mut("/params/subscription") {
+                *subscription = id.clone().into();
+            }
+            notification.to_string()
+        }
+        Err(_) => notification.to_owned(),
+    }
+}
+
+/// Replaces the id of given response.
+fn with_id(response: &str, id: &rpc::Id) -> String {
+    match serde_json::from_str::<serde_json::Value>(response) {
+        Ok(serde_json::Value::Object(mut response)) => {
+            response.insert(
+                "id".into(),
+                serde_json::to_value(id).expect("Id serialization is infallible."),
+            );
+            serde_json::Value::Object(response).to_string()
+        }
+        _ => response.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_display_pending_kind() {
+        let (sender, _receiver) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(sender));
+
+        assert_eq!(PendingKind::Regular.to_string(), "regular");
+        assert_eq!(
+            PendingKind::Subscribe(session, Box::new(|_| {})).to_string(),
+            "subscribe"
+        );
+    }
+
+    #[test]
+    fn should_count_pending_requests() {
+        // given
+        let shared = Shared::default();
+
+        // when
+        let _rx1 = shared.add_pending(Some(&rpc::Id::Num(1)), PendingKind::Regular);
+        let _rx2 = shared.add_pending(Some(&rpc::Id::Num(2)), PendingKind::Regular);
+        let _rx3 = shared.add_pending(None, PendingKind::Regular);
+        assert_eq!(shared.pending_count(), 2);
+        shared.remove_pending(&rpc::Id::Num(1));
+
+        // then
+        assert_eq!(shared.pending_count(), 1);
+        assert_eq!(shared.pending_ids(), vec![rpc::Id::Num(2)]);
+    }
+
+    #[test]
+    fn should_reject_requests_exceeding_max_pending() {
+        // given
+        let shared = Shared::default().with_max_pending(2);
+        let _rx1 = shared.add_pending(Some(&rpc::Id::Num(1)), PendingKind::Regular);
+        let _rx2 = shared.add_pending(Some(&rpc::Id::Num(2)), PendingKind::Regular);
+
+        // when
+        let rejected = shared.add_pending(Some(&rpc::Id::Num(3)), PendingKind::Regular);
+        shared.remove_pending(&rpc::Id::Num(1));
+        let accepted = shared.add_pending(Some(&rpc::Id::Num(4)), PendingKind::Regular);
+
+        // then
+        assert!(rejected.is_none());
+        assert!(accepted.is_some());
+        assert_eq!(shared.pending_ids().len(), 2);
+    }
+
+    #[test]
+    fn should_remove_all_pending_requests() {
+        // given
+        let shared = Shared::default();
+        let receivers = (1..=5)
+            .map(|id| {
+                shared
+                    .add_pending(Some(&rpc::Id::Num(id)), PendingKind::Regular)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // when
+        let pending = shared.remove_all_pending();
+        assert_eq!(pending.len(), 5);
+        for (id, (sender, _kind)) in pending {
+            sender.send(format!("error {:?}", id)).unwrap();
+        }
+
+        // then
+        assert_eq!(shared.pending_count(), 0);
+        let responses = receivers
+            .into_iter()
+            .map(|rx| rpc::futures::executor::block_on(rx).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            responses,
+            (1..=5)
+                .map(|id| format!("error {:?}", rpc::Id::Num(id)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_count_subscriptions() {
+        // given
+        let shared = Shared::default();
+        let (tx, _rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+
+        // when
+        shared.add_subscription(pubsub::SubscriptionId::Number(1), session.clone(), Box::new(|_| {}));
+        shared.add_subscription(pubsub::SubscriptionId::Number(2), session.clone(), Box::new(|_| {}));
+        assert_eq!(shared.subscription_count(), 2);
+        shared.remove_subscription(&pubsub::SubscriptionId::Number(1));
+
+        // then
+        assert_eq!(shared.subscription_count(), 1);
+    }
+
+    #[test]
+    fn should_remove_subscription_if_notification_cannot_be_delivered() {
+        // given
+        let shared = Shared::default();
+        let (tx, rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        let id = pubsub::SubscriptionId::String("0xdeadbeef".into());
+        shared.add_subscription(id, session.clone(), Box::new(|_| {}));
+        drop(rx);
+
+        // when
+        let result = shared.process_text(
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0xdeadbeef","result":"0x1"}}"#
+                .into(),
+        );
+
+        // then
+        assert_eq!(result, Ok(()));
+        assert_eq!(shared.subscription_count(), 0);
+    }
+
+    #[test]
+    fn should_forward_notifications_with_custom_subscription_ids() {
+        // given
+        let shared = Shared::with_subscription_parser(|params| match *params {
+            rpc::Params::Array(ref params) => params.first().and_then(pubsub::SubscriptionId::parse_value),
+            _ => None,
+        });
+        let (tx, mut rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        shared.add_subscription(pubsub::SubscriptionId::Number(5), session.clone(), Box::new(|_| {}));
+        let notification = r#"{"jsonrpc":"2.0","method":"chain_notify","params":[5,"0x1"]}"#;
+
+        // when
+        let result = shared.process_text(notification.into());
+
+        // then
+        assert_eq!(result, Ok(()));
+        assert_eq!(rx.try_next().unwrap(), Some(notification.into()));
+    }
+
+    #[test]
+    fn should_handle_string_subscription_ids() {
+        // given
+        let shared = Shared::default();
+        let (tx, mut rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        let _response = shared.add_pending(
+            Some(&rpc::Id::Num(1)),
+            PendingKind::Subscribe(session.clone(), Box::new(|_| {})),
+        );
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0xdeadbeef","result":"0x1"}}"#;
+
+        // when
+        shared
+            .process_text(r#"{"jsonrpc":"2.0","id":1,"result":"0xdeadbeef"}"#.into())
+            .unwrap();
+        shared.process_text(notification.into()).unwrap();
+        let unsubscribe = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(2),
+            method: "eth_unsubscribe".into(),
+            params: rpc::Params::Array(vec!["0xdeadbeef".into()]),
+        });
+        let id = helpers::get_unsubscribe_id(&unsubscribe).unwrap();
+        assert_eq!(shared.subscription_count(), 1);
+        shared.remove_subscription(&id);
+
+        // then
+        assert_eq!(rx.try_next().unwrap(), Some(notification.to_owned()));
+        assert_eq!(shared.subscription_count(), 0);
+    }
+
+    #[test]
+    fn should_share_identical_in_flight_subscriptions() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // given
+        let shared = Shared::default();
+        let unsubscribed = Arc::new(AtomicUsize::new(0));
+        let subscribe = |id| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Num(id),
+                method: "eth_subscribe".into(),
+                params: rpc::Params::Array(vec!["newHeads".into()]),
+            })
+        };
+        let (tx1, mut rx1) = rpc::futures::channel::mpsc::unbounded();
+        let session1 = Arc::new(pubsub::Session::new(tx1));
+        let (tx2, mut rx2) = rpc::futures::channel::mpsc::unbounded();
+        let session2 = Arc::new(pubsub::Session::new(tx2));
+
+        // when
+        assert!(shared.join_subscribe(&subscribe(1), session1.clone()).is_none());
+        let unsubscribed2 = unsubscribed.clone();
+        let response1 = shared
+            .add_pending(
+                Some(&rpc::Id::Num(1)),
+                PendingKind::Subscribe(
+                    session1.clone(),
+                    Box::new(move |_| {
+                        unsubscribed2.fetch_add(1, Ordering::SeqCst);
+                    }),
+                ),
+            )
+            .unwrap();
+        let response2 = shared.join_subscribe(&subscribe(2), session2.clone()).unwrap();
+        assert_eq!(shared.pending_count(), 1);
+        shared
+            .process_text(r#"{"jsonrpc":"2.0","id":1,"result":"0xdeadbeef"}"#.into())
+            .unwrap();
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0xdeadbeef","result":"0x1"}}"#;
+        shared.process_text(notification.into()).unwrap();
+
+        // then
+        assert_eq!(
+            rpc::futures::executor::block_on(response1).unwrap(),
+            r#"{"jsonrpc":"2.0","id":1,"result":"0xdeadbeef"}"#
+        );
+        let response2: serde_json::Value =
+            serde_json::from_str(&rpc::futures::executor::block_on(response2).unwrap()).unwrap();
+        assert_eq!(response2["id"], 2);
+        assert_eq!(response2["result"], "0xdeadbeef");
+        assert_eq!(rx1.try_next().unwrap(), Some(notification.to_owned()));
+        assert_eq!(rx2.try_next().unwrap(), Some(notification.to_owned()));
+        assert_eq!(shared.subscription_count(), 1);
+
+        drop(session1);
+        assert_eq!(shared.subscription_count(), 1);
+        assert_eq!(unsubscribed.load(Ordering::SeqCst), 0);
+        drop(session2);
+        assert_eq!(shared.subscription_count(), 0);
+        assert_eq!(unsubscribed.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn should_forward_notifications_of_resubscribed_subscriptions() {
+        use std::sync::Mutex as StdMutex;
+
+        // given
+        let shared = Shared::default();
+        let unsubscribed = Arc::new(StdMutex::new(vec![]));
+        let subscribe = rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_subscribe".into(),
+            params: rpc::Params::Array(vec!["newHeads".into()]),
+        };
+        let (tx, mut rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        assert!(shared
+            .join_subscribe(&rpc::Call::MethodCall(subscribe.clone()), session.clone())
+            .is_none());
+        let unsubscribed2 = unsubscribed.clone();
+        let _response = shared.add_pending(
+            Some(&rpc::Id::Num(1)),
+            PendingKind::Subscribe(
+                session.clone(),
+                Box::new(move |id| unsubscribed2.lock().unwrap().push(id)),
+            ),
+        );
+        shared
+            .process_text(r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#.into())
+            .unwrap();
+        let id = pubsub::SubscriptionId::String("0x1".into());
+
+        // when
+        let calls = shared.subscribe_calls();
+        assert_eq!(calls, vec![(id.clone(), subscribe)]);
+        let resubscribe_id = rpc::Id::Str("proxy_resubscribe_1".into());
+        let _response = shared.add_pending(Some(&resubscribe_id), PendingKind::Resubscribe(id.clone()));
+        shared
+            .process_text(r#"{"jsonrpc":"2.0","id":"proxy_resubscribe_1","result":"0x2"}"#.into())
+            .unwrap();
+        shared
+            .process_text(
+                r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0x2","result":"0x5"}}"#
+                    .into(),
+            )
+            .unwrap();
+
+        // then
+        assert_eq!(
+            rx.try_next().unwrap(),
+            Some(r#"{"jsonrpc":"2.0","method":"proxy_resubscribed","params":{"subscription":"0x1"}}"#.into())
+        );
+        let notification: serde_json::Value = serde_json::from_str(&rx.try_next().unwrap().unwrap()).unwrap();
+        assert_eq!(notification["params"]["subscription"], "0x1");
+        assert_eq!(notification["params"]["result"], "0x5");
+        assert_eq!(
+            shared.upstream_subscription_id(&id),
+            pubsub::SubscriptionId::String("0x2".into())
+        );
+
+        drop(session);
+        assert_eq!(shared.subscription_count(), 0);
+        assert_eq!(
+            *unsubscribed.lock().unwrap(),
+            vec![pubsub::SubscriptionId::String("0x2".into())]
+        );
+    }
+
+    #[test]
+    fn should_not_share_subscriptions_with_different_params() {
+        // given
+        let shared = Shared::default();
+        let (tx, _rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        let subscribe = |id, kind: &str| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Num(id),
+                method: "eth_subscribe".into(),
+                params: rpc::Params::Array(vec![kind.into()]),
+            })
+        };
+
+        // when
+        let first = shared.join_subscribe(&subscribe(1, "newHeads"), session.clone());
+        let second = shared.join_subscribe(&subscribe(2, "logs"), session.clone());
+        shared.remove_pending(&rpc::Id::Num(1));
+        let third = shared.join_subscribe(&subscribe(3, "newHeads"), session.clone());
+
+        // then
+        assert!(first.is_none());
+        assert!(second.is_none());
+        assert!(third.is_none());
+    }
+
+    #[test]
+    fn should_detach_sessions_from_shared_subscription() {
+        // given
+        let shared = Shared::default();
+        let id = pubsub::SubscriptionId::Number(1);
+        let (tx1, mut rx1) = rpc::futures::channel::mpsc::unbounded();
+        let session1 = Arc::new(pubsub::Session::new(tx1));
+        let (tx2, mut rx2) = rpc::futures::channel::mpsc::unbounded();
+        let session2 = Arc::new(pubsub::Session::new(tx2));
+        let (tx3, _rx3) = rpc::futures::channel::mpsc::unbounded();
+        let session3 = Arc::new(pubsub::Session::new(tx3));
+        shared.add_subscription(id.clone(), session1.clone(), Box::new(|_| {}));
+        shared.add_subscription(id.clone(), session2.clone(), Box::new(|_| {}));
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":1,"result":"0x1"}}"#;
+
+        // when
+        let detached1 = shared.detach_session(&id, Some(&session1));
+        shared.process_text(notification.into()).unwrap();
+        let detached3 = shared.detach_session(&id, Some(&session3));
+        let detached2 = shared.detach_session(&id, Some(&session2));
+
+        // then
+        assert_eq!(detached1, Detached::Remaining);
+        assert_eq!(detached3, Detached::NotSubscribed);
+        assert_eq!(detached2, Detached::Last(id.clone()));
+        assert!(rx1.try_next().is_err());
+        assert_eq!(rx2.try_next().unwrap(), Some(notification.to_owned()));
+        assert_eq!(shared.subscription_count(), 0);
+        assert_eq!(shared.detach_session(&id, Some(&session1)), Detached::Unknown);
+    }
+
+    #[test]
+    fn should_remove_abandoned_pending_requests() {
+        // given
+        let shared = Arc::new(Shared::default());
+        let subscribe = |id| {
+            rpc::Call::MethodCall(rpc::MethodCall {
+                jsonrpc: Some(rpc::Version::V2),
+                id: rpc::Id::Num(id),
+                method: "eth_subscribe".into(),
+                params: rpc::Params::Array(vec!["newHeads".into()]),
+            })
+        };
+        let (tx, _rx) = rpc::futures::channel::mpsc::unbounded();
+        let session = Arc::new(pubsub::Session::new(tx));
+        assert!(shared.join_subscribe(&subscribe(1), session.clone()).is_none());
+        let _response1 = shared.add_pending(
+            Some(&rpc::Id::Num(1)),
+            PendingKind::Subscribe(session.clone(), Box::new(|_| {})),
+        );
+        let _response2 = shared.add_pending(Some(&rpc::Id::Num(2)), PendingKind::Regular);
+
+        // when
+        drop(PendingGuard::new(shared.clone(), rpc::Id::Num(1)));
+        PendingGuard::new(shared.clone(), rpc::Id::Num(2)).disarm();
+
+        // then
+        assert_eq!(shared.pending_ids(), vec![rpc::Id::Num(2)]);
+        assert!(shared.join_subscribe(&subscribe(3), session).is_none());
     }
 }
@@ -0,0 +1,193 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! HMAC request signing.
+
+use hex;
+use hmac::{Hmac, Mac, NewMac};
+use pubsub;
+use rpc;
+use rpc::futures::Future;
+use sha2::Sha256;
+use std::{pin::Pin, sync::Arc};
+use {Subscription, Transport};
+
+/// Name of the header carrying the request signature.
+pub const SIGNATURE_HEADER: &str = "X-Proxy-Signature";
+
+/// Computes hex-encoded `HMAC-SHA256(secret, body)`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC accepts keys of any size; qed");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Transport decorator signing every call with a shared secret.
+///
+/// The signature of the serialized call is passed to the inner transport
+/// as `X-Proxy-Signature` header, so it's only effective for transports supporting headers.
+/// Probes and health checks are delegated to the inner transport and are not signed.
+#[derive(Debug, Clone)]
+pub struct HmacSigningTransport<T> {
+    inner: T,
+    secret: Arc<Vec<u8>>,
+}
+
+impl<T> HmacSigningTransport<T> {
+    /// Wraps given transport.
+    pub fn new(inner: T, secret: Vec<u8>) -> Self {
+        HmacSigningTransport {
+            inner,
+            secret: Arc::new(secret),
+        }
+    }
+}
+
+impl<T: Transport> Transport for HmacSigningTransport<T> {
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        self.inner.subscribe(call, sink, subscription)
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        self.inner.unsubscribe(call, sink, subscription)
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        self.send_with_headers(call, vec![])
+    }
+
+    fn send_with_headers(&self, call: rpc::Call, mut headers: Vec<(String, String)>) -> Self::Future {
+        let body = rpc::types::to_string(&call).expect("jsonrpc-core are infallible");
+        headers.push((SIGNATURE_HEADER.into(), sign(&self.secret, body.as_bytes())));
+        self.inner.send_with_headers(call, headers)
+    }
+
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        self.inner.probe()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        self.inner.health_check()
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.inner.probe_subscriptions(subscription, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use rpc::futures::{executor::block_on, future};
+
+    #[derive(Default)]
+    struct Upstream {
+        headers: Mutex<Vec<(String, String)>>,
+    }
+
+    impl Transport for Upstream {
+        type Error = String;
+        type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+        fn subscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, _: rpc::Call) -> Self::Future {
+            future::ready(Ok(None))
+        }
+
+        fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+            self.headers.lock().extend(headers);
+            self.send(call)
+        }
+
+        fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+            Box::pin(future::ready(true))
+        }
+    }
+
+    #[test]
+    fn should_compute_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn should_add_signature_header() {
+        // given
+        let transport = HmacSigningTransport::new(Upstream::default(), b"secret".to_vec());
+        let call = rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        rpc::futures::executor::block_on(transport.send(call)).unwrap();
+
+        // then
+        assert_eq!(
+            *transport.inner.headers.lock(),
+            vec![(
+                SIGNATURE_HEADER.to_owned(),
+                sign(
+                    b"secret",
+                    br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":null,"id":1}"#
+                )
+            )]
+        );
+    }
+
+    #[test]
+    fn should_forward_health_checks() {
+        // given
+        let transport = HmacSigningTransport::new(Upstream::default(), b"secret".to_vec());
+
+        // when
+        let healthy = block_on(transport.health_check());
+
+        // then
+        assert!(healthy);
+    }
+}
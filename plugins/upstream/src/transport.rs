@@ -0,0 +1,311 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Composing upstream transports into pipelines.
+//!
+//! Every stage of the pipeline is a regular `Transport`. A stage that resolves a call with
+//! `Ok(None)` passes it on to the next one, any response or error ends the pipeline early,
+//! e.g. `logging.and_then(circuit_breaker.and_then(websocket))`.
+
+use pubsub;
+use rpc;
+use rpc::futures::{task, Future};
+use std::{fmt, pin::Pin, sync::Arc};
+use {Subscription, Transport};
+
+/// Extension methods for transports.
+pub trait TransportExt: Transport + Sized {
+    /// Composes this transport with the `next` one.
+    ///
+    /// Calls are processed by this transport first and delegated to `next`
+    /// unless this transport resolves them with a response or an error.
+    fn and_then<B: Transport<Error = Self::Error>>(self, next: B) -> AndThen<Self, B> {
+        AndThen {
+            first: self,
+            second: Arc::new(next),
+        }
+    }
+}
+
+impl<T: Transport> TransportExt for T {}
+
+/// Two transports composed with `TransportExt::and_then`.
+///
+/// Probes and health checks are delegated to the second transport,
+/// since it's the one eventually passing the calls upstream.
+#[derive(Debug)]
+pub struct AndThen<A, B> {
+    first: A,
+    second: Arc<B>,
+}
+
+impl<A, B> AndThen<A, B>
+where
+    A: Transport,
+    B: Transport<Error = A::Error>,
+{
+    /// Chains `first` future with a call to the second transport.
+    ///
+    /// Notifications are never awaited, hence they are sent to the second transport right away.
+    fn chain<F>(&self, call: rpc::Call, first: A::Future, send: F) -> AndThenFuture<A::Future, B::Future>
+    where
+        F: FnOnce(&B, rpc::Call) -> B::Future + Send + 'static,
+    {
+        let second = self.second.clone();
+        let next: Box<dyn FnOnce() -> B::Future + Send> = match call {
+            rpc::Call::Notification(_) => {
+                let started = send(&second, call);
+                Box::new(move || started)
+            }
+            _ => Box::new(move || send(&second, call)),
+        };
+
+        AndThenFuture {
+            state: State::First(first, Some(next)),
+        }
+    }
+}
+
+impl<A, B> Transport for AndThen<A, B>
+where
+    A: Transport,
+    B: Transport<Error = A::Error>,
+{
+    type Error = A::Error;
+    type Future = AndThenFuture<A::Future, B::Future>;
+
+    fn subscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        let first = self.first.subscribe(call.clone(), sink.clone(), subscription.clone());
+        self.chain(call, first, move |second, call| {
+            second.subscribe(call, sink, subscription)
+        })
+    }
+
+    fn unsubscribe(
+        &self,
+        call: rpc::Call,
+        sink: Option<Arc<pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        let first = self.first.unsubscribe(call.clone(), sink.clone(), subscription.clone());
+        self.chain(call, first, move |second, call| {
+            second.unsubscribe(call, sink, subscription)
+        })
+    }
+
+    fn send(&self, call: rpc::Call) -> Self::Future {
+        let first = self.first.send(call.clone());
+        self.chain(call, first, |second, call| second.send(call))
+    }
+
+    fn send_with_headers(&self, call: rpc::Call, headers: Vec<(String, String)>) -> Self::Future {
+        let first = self.first.send_with_headers(call.clone(), headers.clone());
+        self.chain(call, first, move |second, call| second.send_with_headers(call, headers))
+    }
+
+    fn probe(&self) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> {
+        self.second.probe()
+    }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        self.second.health_check()
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: rpc::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        self.second.probe_subscriptions(subscription, params)
+    }
+}
+
+enum State<A, B> {
+    First(A, Option<Box<dyn FnOnce() -> B + Send>>),
+    Second(B),
+}
+
+/// Future resolving the call with the first transport and then, if needed, with the second one.
+pub struct AndThenFuture<A, B> {
+    state: State<A, B>,
+}
+
+impl<A, B> fmt::Debug for AndThenFuture<A, B> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self.state {
+            State::First(..) => "First",
+            State::Second(..) => "Second",
+        };
+        fmt.debug_struct("AndThenFuture").field("stage", &stage).finish()
+    }
+}
+
+impl<A, B, E> Future for AndThenFuture<A, B>
+where
+    A: Future<Output = Result<Option<rpc::Output>, E>> + Unpin,
+    B: Future<Output = Result<Option<rpc::Output>, E>> + Unpin,
+{
+    type Output = A::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Self::Output> {
+        loop {
+            let next = match self.state {
+                State::First(ref mut first, ref mut next) => match Pin::new(first).poll(cx) {
+                    task::Poll::Ready(Ok(None)) => next.take().expect("AndThenFuture polled after completion."),
+                    task::Poll::Ready(result) => return task::Poll::Ready(result),
+                    task::Poll::Pending => return task::Poll::Pending,
+                },
+                State::Second(ref mut second) => return Pin::new(second).poll(cx),
+            };
+            self.state = State::Second(next());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use rpc::futures::{executor::block_on, future};
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    /// Transport recording its name and resolving every call with given output.
+    struct Stage {
+        name: &'static str,
+        log: Log,
+        output: Option<rpc::Output>,
+    }
+
+    impl Stage {
+        fn new(name: &'static str, log: &Log, output: Option<rpc::Output>) -> Self {
+            Stage {
+                name,
+                log: log.clone(),
+                output,
+            }
+        }
+    }
+
+    impl Transport for Stage {
+        type Error = String;
+        type Future = future::Ready<Result<Option<rpc::Output>, String>>;
+
+        fn subscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn unsubscribe(&self, call: rpc::Call, _: Option<Arc<pubsub::Session>>, _: Subscription) -> Self::Future {
+            self.send(call)
+        }
+
+        fn send(&self, _: rpc::Call) -> Self::Future {
+            self.log.lock().push(self.name);
+            future::ready(Ok(self.output.clone()))
+        }
+
+        fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+            self.log.lock().push(self.name);
+            Box::pin(future::ready(true))
+        }
+    }
+
+    fn call() -> rpc::Call {
+        rpc::Call::MethodCall(rpc::MethodCall {
+            jsonrpc: Some(rpc::Version::V2),
+            id: rpc::Id::Num(1),
+            method: "eth_blockNumber".into(),
+            params: rpc::Params::None,
+        })
+    }
+
+    fn output(value: &str) -> Option<rpc::Output> {
+        Some(rpc::Output::from(
+            Ok(rpc::Value::String(value.into())),
+            rpc::Id::Num(1),
+            Some(rpc::Version::V2),
+        ))
+    }
+
+    #[test]
+    fn should_invoke_all_layers_in_order() {
+        // given
+        let log = Log::default();
+        let transport = Stage::new("logging", &log, None)
+            .and_then(Stage::new("circuit-breaker", &log, None).and_then(Stage::new("upstream", &log, output("0x1"))));
+
+        // when
+        let result = block_on(transport.send(call()));
+
+        // then
+        assert_eq!(result, Ok(output("0x1")));
+        assert_eq!(*log.lock(), vec!["logging", "circuit-breaker", "upstream"]);
+    }
+
+    #[test]
+    fn should_stop_at_layer_returning_response() {
+        // given
+        let log = Log::default();
+        let transport = Stage::new("logging", &log, None).and_then(
+            Stage::new("circuit-breaker", &log, output("open")).and_then(Stage::new("upstream", &log, output("0x1"))),
+        );
+
+        // when
+        let result = block_on(transport.send(call()));
+
+        // then
+        assert_eq!(result, Ok(output("open")));
+        assert_eq!(*log.lock(), vec!["logging", "circuit-breaker"]);
+    }
+
+    #[test]
+    fn should_send_notifications_eagerly() {
+        // given
+        let log = Log::default();
+        let transport = Stage::new("logging", &log, None).and_then(Stage::new("upstream", &log, None));
+        let notification = rpc::Call::Notification(rpc::Notification {
+            jsonrpc: Some(rpc::Version::V2),
+            method: "eth_subscription".into(),
+            params: rpc::Params::None,
+        });
+
+        // when
+        drop(transport.send(notification));
+
+        // then
+        assert_eq!(*log.lock(), vec!["logging", "upstream"]);
+    }
+    #[test]
+    fn should_check_health_of_the_last_layer() {
+        // given
+        let log = Log::default();
+        let transport = Stage::new("logging", &log, None)
+            .and_then(Stage::new("circuit-breaker", &log, None).and_then(Stage::new("upstream", &log, output("0x1"))));
+
+        // when
+        let healthy = block_on(transport.health_check());
+
+        // then
+        assert!(healthy);
+        assert_eq!(*log.lock(), vec!["upstream"]);
+    }
+}
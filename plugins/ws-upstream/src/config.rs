@@ -17,24 +17,304 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 //! WebSocket upstream configuration parameters.
 
+use crate::reconnect::ResubscribePolicy;
 use cli_params;
+use std::time::Duration;
+
+/// Default timeout of the initial connection to the upstream.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Configuration options of the WS upstream
 pub enum Param {
     /// Upstream URL
     Url(url::Url),
+    /// Maximal size of a message received from the upstream.
+    MaxResponseBytes(usize),
+    /// Timeout of the initial connection to the upstream.
+    ConnectTimeout(Duration),
+    /// WebSocket subprotocol to negotiate during the handshake.
+    Subprotocol(Option<String>),
+    /// Additional headers sent with the handshake request.
+    Headers(Vec<(String, String)>),
+    /// Log bodies of requests sent to the upstream (see `upstream::config::log_requests`).
+    LogRequests(bool),
+    /// Log bodies of messages received from the upstream (see `upstream::config::log_responses`).
+    LogResponses(bool),
+    /// Maximal number of requests awaiting a response (see `upstream::config::max_pending`).
+    MaxPendingRequests(usize),
+    /// Delay before the first reconnection attempt.
+    ReconnectInitialDelay(Duration),
+    /// Multiplier of the delay applied with every next reconnection attempt.
+    ReconnectBackoffFactor(f64),
+    /// Maximal delay between reconnection attempts.
+    ReconnectMaxDelay(Duration),
+    /// Number of failed reconnection attempts after which the upstream is considered failed (unlimited if `None`).
+    ReconnectMaxAttempts(Option<u32>),
+    /// Randomize delays between reconnection attempts.
+    ReconnectJitter(bool),
+    /// Subscription (and its params) used to verify the upstream delivers notifications after reconnecting.
+    ReconnectProbe(Option<(upstream::Subscription, jsonrpc_core::Params)>),
+    /// How long to wait for the first notification of the reconnect probe.
+    ReconnectProbeTimeout(Duration),
+    /// Handling of active subscriptions after reconnecting.
+    ResubscribePolicy(ResubscribePolicy),
+}
+
+/// Parses a single `name:value` header and validates it.
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let mut parts = header.splitn(2, ':');
+    let name = parts.next().unwrap_or_default().trim();
+    let value = parts
+        .next()
+        .ok_or_else(|| format!("Invalid header {:?}: expected `name:value`", header))?
+        .trim();
+
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("Invalid header name {:?}", name));
+    }
+    if value.bytes().any(|b| b == b'\r' || b == b'\n') {
+        return Err(format!("Invalid value of header {}", name));
+    }
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Parses `subscribe,unsubscribe,notification[,param...]` description of the reconnect probe.
+fn parse_probe(val: &str) -> Result<Option<(upstream::Subscription, jsonrpc_core::Params)>, String> {
+    if val == "-" {
+        return Ok(None);
+    }
+
+    let mut parts = val.split(',').map(str::trim);
+    let mut method = || {
+        parts
+            .next()
+            .filter(|method| !method.is_empty())
+            .map(str::to_owned)
+            .ok_or_else(|| {
+                format!(
+                    "Invalid reconnect probe {:?}: expected `subscribe,unsubscribe,notification[,param...]`",
+                    val
+                )
+            })
+    };
+    let subscription = upstream::Subscription {
+        subscribe: method()?,
+        unsubscribe: method()?,
+        name: method()?,
+    };
+    let params = parts.map(|param| param.to_owned().into()).collect::<Vec<_>>();
+    let params = if params.is_empty() {
+        jsonrpc_core::Params::None
+    } else {
+        jsonrpc_core::Params::Array(params)
+    };
+
+    Ok(Some((subscription, params)))
+}
+
+/// Returns the maximal size of a message received from the upstream.
+pub fn max_response_bytes(params: &[Param]) -> usize {
+    params
+        .iter()
+        .find_map(|p| match p {
+            Param::MaxResponseBytes(max) => Some(*max),
+            _ => None,
+        })
+        .unwrap_or(crate::DEFAULT_MAX_RESPONSE_BYTES)
 }
 
 /// Returns all configuration parameters for WS upstream.
 pub fn params() -> Vec<cli_params::Param<Param>> {
-    vec![cli_params::Param::new(
-        "WebSockets upstream",
-        "upstream-ws",
-        "Address of the parent WebSockets RPC server that we should connect to.",
-        "ws://127.0.0.1:9944",
-        move |val: String| {
-            let url = val.parse().map_err(|e| format!("Invalid upstream address: {:?}", e))?;
-            Ok(Param::Url(url))
-        },
-    )]
+    vec![
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws",
+            "Address of the parent WebSockets RPC server that we should connect to.",
+            "ws://127.0.0.1:9944",
+            move |val: String| {
+                let url = val.parse().map_err(|e| format!("Invalid upstream address: {:?}", e))?;
+                Ok(Param::Url(url))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-max-response-bytes",
+            "Maximal size of a response from the WebSockets or IPC upstream. Larger responses are discarded and a parse error is returned instead.",
+            "10485760",
+            move |val: String| {
+                let max = val
+                    .parse()
+                    .map_err(|e| format!("Invalid maximal response size: {:?}", e))?;
+                Ok(Param::MaxResponseBytes(max))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-connect-timeout",
+            "Number of seconds to wait for the initial connection to the upstream.",
+            "10",
+            move |val: String| {
+                let secs = val.parse().map_err(|e| format!("Invalid connect timeout: {:?}", e))?;
+                Ok(Param::ConnectTimeout(Duration::from_secs(secs)))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-subprotocol",
+            "WebSocket subprotocol to negotiate with the upstream (e.g. json-rpc/2.0). If \"none\" no subprotocol is requested.",
+            "none",
+            move |val: String| match val.as_str() {
+                "none" => Ok(Param::Subprotocol(None)),
+                _ => Ok(Param::Subprotocol(Some(val))),
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-header",
+            "A `name:value` header sent with the handshake request (e.g. Authorization:Bearer xyz). Repeat the flag to send multiple headers. Use - to send no additional headers.",
+            "-",
+            move |val: String| match val.as_str() {
+                "-" => Ok(Param::Headers(vec![])),
+                _ => Ok(Param::Headers(vec![parse_header(&val)?])),
+            },
+        )
+        .multiple(),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-initial-delay",
+            "Delay before the first attempt to reconnect to the WebSockets upstream (e.g. `100ms`, `1s`).",
+            "100ms",
+            move |val: String| upstream::config::parse_duration(&val).map(Param::ReconnectInitialDelay),
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-backoff-factor",
+            "Multiplier of the delay applied with every next attempt to reconnect to the WebSockets upstream.",
+            "2",
+            move |val: String| {
+                let factor: f64 = val
+                    .parse()
+                    .map_err(|e| format!("Invalid reconnect backoff factor {}: {:?}", val, e))?;
+                if !factor.is_finite() || factor < 1.0 {
+                    return Err(format!("Reconnect backoff factor has to be at least 1, got: {}", val));
+                }
+                Ok(Param::ReconnectBackoffFactor(factor))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-max-delay",
+            "Maximal delay between attempts to reconnect to the WebSockets upstream (e.g. `30s`, `1m`).",
+            "30s",
+            move |val: String| upstream::config::parse_duration(&val).map(Param::ReconnectMaxDelay),
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-max-attempts",
+            "Number of consecutive failed attempts to reconnect to the WebSockets upstream after which the proxy stops reconnecting and reports the upstream as unhealthy.",
+            "unlimited",
+            move |val: String| {
+                if val == "unlimited" {
+                    return Ok(Param::ReconnectMaxAttempts(None));
+                }
+                val.parse()
+                    .map(|attempts| Param::ReconnectMaxAttempts(Some(attempts)))
+                    .map_err(|e| format!("Invalid reconnect max attempts {}: {:?}", val, e))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-jitter",
+            "Add a random delay of up to the initial delay to every attempt to reconnect to the WebSockets upstream.",
+            "true",
+            move |val: String| {
+                val.parse()
+                    .map(Param::ReconnectJitter)
+                    .map_err(|e| format!("Invalid value of upstream-ws-reconnect-jitter {}: {:?}", val, e))
+            },
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-probe",
+            "Subscription used to verify that the reconnected WebSockets upstream delivers notifications, given as `subscribe,unsubscribe,notification[,param...]` (e.g. eth_subscribe,eth_unsubscribe,eth_subscription,newHeads). The upstream is reported as degraded if no notification arrives. Use - to skip the verification.",
+            "-",
+            move |val: String| parse_probe(&val).map(Param::ReconnectProbe),
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-reconnect-probe-timeout",
+            "How long to wait for the first notification of the reconnect probe (e.g. `5s`).",
+            "5s",
+            move |val: String| upstream::config::parse_duration(&val).map(Param::ReconnectProbeTimeout),
+        ),
+        cli_params::Param::new(
+            "WebSockets upstream",
+            "upstream-ws-resubscribe",
+            "What to do with active subscriptions after reconnecting to the WebSockets upstream: `ignore` them or `replay` the subscribe calls, so that the clients keep receiving notifications.",
+            "ignore",
+            move |val: String| match val.as_str() {
+                "ignore" => Ok(Param::ResubscribePolicy(ResubscribePolicy::Ignore)),
+                "replay" => Ok(Param::ResubscribePolicy(ResubscribePolicy::Replay)),
+                _ => Err(format!("Invalid resubscribe policy {}: expected `ignore` or `replay`", val)),
+            },
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(param: &cli_params::Param<Param>, val: &str) -> Result<Vec<(String, String)>, String> {
+        match param.parse(Some(val.into()))? {
+            Param::Headers(headers) => Ok(headers),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn should_parse_headers() {
+        // given
+        let params = params();
+        let param = params.iter().find(|p| p.name == "upstream-ws-header").unwrap();
+
+        // when
+        let authorization = headers(param, "Authorization: Bearer xyz").unwrap();
+        let api_key = headers(param, "X-Api-Key:abc:def").unwrap();
+
+        // then
+        assert!(param.multiple);
+        assert_eq!(
+            authorization,
+            vec![("Authorization".to_owned(), "Bearer xyz".to_owned())]
+        );
+        assert_eq!(api_key, vec![("X-Api-Key".to_owned(), "abc:def".to_owned())]);
+        assert!(headers(param, "-").unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_parse_reconnect_probe() {
+        // given
+        let val = "eth_subscribe,eth_unsubscribe,eth_subscription,newHeads";
+
+        // when
+        let (subscription, params) = parse_probe(val).unwrap().unwrap();
+
+        // then
+        assert_eq!(subscription.subscribe, "eth_subscribe");
+        assert_eq!(subscription.unsubscribe, "eth_unsubscribe");
+        assert_eq!(subscription.name, "eth_subscription");
+        assert_eq!(params, jsonrpc_core::Params::Array(vec!["newHeads".into()]));
+        assert!(parse_probe("-").unwrap().is_none());
+        assert!(parse_probe("eth_subscribe,eth_unsubscribe").is_err());
+    }
+
+    #[test]
+    fn should_reject_invalid_headers() {
+        assert!(parse_header("Authorization").is_err());
+        assert!(parse_header(":value").is_err());
+        assert!(parse_header("X Api:value").is_err());
+        assert!(parse_header("X-Api-Key:abc,def:ghi").is_ok());
+    }
 }
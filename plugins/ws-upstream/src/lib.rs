@@ -21,6 +21,7 @@
 #![warn(missing_docs)]
 
 pub mod config;
+pub mod reconnect;
 
 use jsonrpc_core::futures::{
     self,
@@ -28,21 +29,83 @@ use jsonrpc_core::futures::{
     future::{self, Either},
     Future, FutureExt, StreamExt, TryFutureExt,
 };
-use std::sync::{atomic, Arc};
+use std::{
+    pin::Pin,
+    sync::{atomic, Arc, Mutex},
+    time::Duration,
+};
 use upstream::{
     helpers,
-    shared::{PendingKind, Shared},
+    shared::{Detached, PendingGuard, PendingKind, Shared},
     Subscription,
 };
-use websocket::OwnedMessage;
+use websocket::{
+    client::r#async::{Client, TcpStream},
+    OwnedMessage,
+};
+
+/// Default maximal size of a message received from the upstream (10 MB).
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
 
 struct WebSocketHandler {
     shared: Arc<Shared>,
     write_sender: mpsc::UnboundedSender<OwnedMessage>,
+    max_response_bytes: usize,
+    log_responses: bool,
+}
+
+/// Returns the size of the message payload.
+fn message_len(message: &OwnedMessage) -> usize {
+    match *message {
+        OwnedMessage::Text(ref t) => t.len(),
+        OwnedMessage::Binary(ref d) | OwnedMessage::Ping(ref d) | OwnedMessage::Pong(ref d) => d.len(),
+        OwnedMessage::Close(_) => 0,
+    }
 }
 
 impl WebSocketHandler {
+    /// Discards a message exceeding `max_response_bytes` and responds with a parse error instead.
+    #[tracing::instrument(skip(self, message), fields(limit = self.max_response_bytes))]
+    fn discard_oversized(&self, message: String) -> Result<(), String> {
+        let id = match helpers::peek_response_id(message.as_bytes()) {
+            Some(id) => id,
+            None => {
+                tracing::warn!(
+                    "Discarding oversized message without id ({} bytes, limit: {})",
+                    message.len(),
+                    self.max_response_bytes
+                );
+                return Ok(());
+            }
+        };
+
+        tracing::warn!(
+            "Discarding oversized response (id: {:?}, {} bytes, limit: {})",
+            id,
+            message.len(),
+            self.max_response_bytes
+        );
+        self.shared.fail_pending(
+            &id,
+            jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ParseError,
+                message: "Upstream response is too large.".into(),
+                data: None,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Handles a message received from the upstream.
+    ///
+    /// Processing happens within a `ws_message` span, with a `subscription_notify`
+    /// or `pending_response` child span for text messages.
     pub fn process_message(&self, message: OwnedMessage) -> impl Future<Output = Result<(), String>> {
+        let span = tracing::info_span!("ws_message", message_len = %message_len(&message));
+        let _enter = span.enter();
+        tracing::trace!("Message received: {:?}", message);
+
         future::ready(match message {
             OwnedMessage::Close(e) => self
                 .write_sender
@@ -53,43 +116,24 @@ impl WebSocketHandler {
                 .unbounded_send(OwnedMessage::Pong(d))
                 .map_err(|e| format!("Error sending pong message: {:?}", e)),
             OwnedMessage::Text(t) => {
-                // First check if it's a notification for a subscription
-                if let Some(id) = helpers::peek_subscription_id(t.as_bytes()) {
-                    return future::ready(self.shared.notify_subscription(&id, t).unwrap_or_else(|| {
-                        log::warn!("Got notification for unknown subscription (id: {:?})", id);
-                        Ok(())
-                    }));
+                if self.log_responses {
+                    tracing::trace!("Upstream response: {}", t);
+                }
+                if t.len() > self.max_response_bytes {
+                    return future::ready(self.discard_oversized(t));
                 }
 
-                // then check if it's one of the pending calls
-                if let Some(id) = helpers::peek_id(t.as_bytes()) {
-                    if let Some((sink, kind)) = self.shared.remove_pending(&id) {
-                        match kind {
-                            // Just a regular call, don't do anything else.
-                            PendingKind::Regular => {}
-                            // We have a subscription ID, register subscription.
-                            PendingKind::Subscribe(session, unsubscribe) => {
-                                let subscription_id = helpers::peek_result(t.as_bytes())
-                                    .as_ref()
-                                    .and_then(jsonrpc_pubsub::SubscriptionId::parse_value);
-                                if let Some(subscription_id) = subscription_id {
-                                    self.shared.add_subscription(subscription_id, session, unsubscribe);
-                                }
-                            }
-                        }
-
-                        log::trace!("Responding to (id: {:?}) with {:?}", id, t);
-                        if let Err(err) = sink.send(t) {
-                            log::warn!("Sending a response to deallocated channel: {:?}", err);
-                        }
-                    } else {
-                        log::warn!("Got response for unknown request (id: {:?})", id);
-                    }
+                if helpers::peek_subscription_id(t.as_bytes()).is_some() {
+                    tracing::info_span!("subscription_notify").in_scope(|| {
+                        tracing::trace!("Forwarding notification");
+                        self.shared.process_text(t)
+                    })
                 } else {
-                    log::warn!("Got unexpected notification: {:?}", t);
+                    tracing::info_span!("pending_response").in_scope(|| {
+                        tracing::trace!("Routing response");
+                        self.shared.process_text(t)
+                    })
                 }
-
-                Ok(())
             }
             _ => Ok(()),
         })
@@ -110,6 +154,99 @@ impl<F: Fn(Spawnable) + Send + Sync> Spawn for F {
     }
 }
 
+/// Parameters of the connection to the upstream.
+struct Connector {
+    url: url::Url,
+    headers: Vec<(String, String)>,
+    subprotocol: Option<String>,
+    timeout: Duration,
+}
+
+impl Connector {
+    /// Connects to the upstream, fails if the connection can't be established within the timeout.
+    async fn connect(&self) -> Result<Client<TcpStream>, String> {
+        let connect = {
+            use futures::compat::Future01CompatExt;
+            let mut headers = websocket::header::Headers::new();
+            for (name, value) in &self.headers {
+                headers.set_raw(name.clone(), vec![value.clone().into_bytes()]);
+            }
+            let mut builder = websocket::ClientBuilder::from_url(&self.url).custom_headers(&headers);
+            if let Some(ref protocol) = self.subprotocol {
+                builder = builder.add_protocol(protocol.clone());
+            }
+            builder.async_connect_insecure().compat()
+        };
+        let (duplex, _) = tokio::time::timeout(self.timeout, connect)
+            .await
+            .map_err(|_| format!("Timeout connecting to {} after {:?}", self.url, self.timeout))?
+            .map_err(|e| format!("Unable to connect to {}: {:?}", self.url, e))?;
+
+        Ok(duplex)
+    }
+}
+
+/// Passes messages between the upstream and the transport until either of them closes the connection.
+async fn run_connection(
+    duplex: Client<TcpStream>,
+    handler: WebSocketHandler,
+    write_receiver: &mut mpsc::UnboundedReceiver<OwnedMessage>,
+    log_requests: bool,
+) -> Result<(), String> {
+    use futures::{compat::Future01CompatExt, TryStreamExt};
+    use futures01::{Future, Sink, Stream};
+
+    let write_receiver = write_receiver
+        .map(move |msg| {
+            tracing::trace!("Sending request: {:?}", msg);
+            if log_requests {
+                if let OwnedMessage::Text(ref t) = msg {
+                    tracing::trace!("Upstream request: {}", t);
+                }
+            }
+            msg
+        })
+        .map(|x| Ok(x) as Result<_, websocket::WebSocketError>)
+        .compat();
+    let (sink, stream) = duplex.split();
+    let reader = stream
+        .map_err(|e| format!("{:?}", e))
+        .for_each(move |message| handler.process_message(message).compat());
+
+    let writer = sink
+        .send_all(write_receiver)
+        .map_err(|e| format!("{:?}", e))
+        .map(|_| ());
+
+    match future::select(reader.compat(), writer.compat()).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result,
+    }
+}
+
+/// Fails all requests awaiting a response from the upstream.
+fn fail_pending(shared: &Shared) {
+    shared.fail_all_pending(jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::InternalError,
+        message: "Upstream connection closed.".into(),
+        data: None,
+    });
+}
+
+/// Replaces the subscription id of given unsubscribe call.
+fn with_unsubscribe_id(mut call: jsonrpc_core::Call, id: jsonrpc_pubsub::SubscriptionId) -> jsonrpc_core::Call {
+    let params = match call {
+        jsonrpc_core::Call::MethodCall(ref mut method_call) => &mut method_call.params,
+        jsonrpc_core::Call::Notification(ref mut notification) => &mut notification.params,
+        jsonrpc_core::Call::Invalid { .. } => return call,
+    };
+    if let jsonrpc_core::Params::Array(ref mut params) = *params {
+        if let Some(first) = params.first_mut() {
+            *first = id.into();
+        }
+    }
+    call
+}
+
 /// WebSocket transport
 #[derive(Clone)]
 pub struct WebSocket {
@@ -118,6 +255,8 @@ pub struct WebSocket {
     shared: Arc<Shared>,
     spawn: Arc<dyn Spawn>,
     write_sender: mpsc::UnboundedSender<OwnedMessage>,
+    reconnect: reconnect::ReconnectConfig,
+    state: Arc<Mutex<reconnect::ConnectionState>>,
 }
 
 impl std::fmt::Debug for WebSocket {
@@ -126,99 +265,251 @@ impl std::fmt::Debug for WebSocket {
             .field("id", &self.id)
             .field("url", &self.url)
             .field("shared", &self.shared)
+            .field("state", &self.connection_state())
             .finish()
     }
 }
 
 impl WebSocket {
     /// Create new WebSocket transport within existing Event Loop.
-    pub fn new(params: Vec<config::Param>, spawn_tasks: impl Spawn + 'static) -> Result<Self, String> {
-        let mut url = "ws://127.0.0.1:9944".parse().expect("Valid address given.");
+    ///
+    /// Fails if the connection to the upstream can't be established within the connect timeout.
+    /// Once established, the connection is re-established whenever it's lost (see `reconnect::ReconnectConfig`).
+    pub async fn new(params: Vec<config::Param>, spawn_tasks: impl Spawn + 'static) -> Result<Self, String> {
+        let mut connector = Connector {
+            url: "ws://127.0.0.1:9944".parse().expect("Valid address given."),
+            headers: vec![],
+            subprotocol: None,
+            timeout: config::DEFAULT_CONNECT_TIMEOUT,
+        };
+        let mut max_response_bytes = DEFAULT_MAX_RESPONSE_BYTES;
+        let mut log_requests = false;
+        let mut log_responses = false;
+        let mut max_pending = usize::MAX;
+        let mut reconnect = reconnect::ReconnectConfig::default();
+        let mut probe = None;
+        let mut probe_timeout = upstream::SUBSCRIPTION_PROBE_TIMEOUT;
+        let mut resubscribe = reconnect::ResubscribePolicy::default();
 
         for p in params {
             match p {
                 config::Param::Url(new_url) => {
-                    url = new_url;
+                    connector.url = new_url;
+                }
+                config::Param::MaxResponseBytes(max) => {
+                    max_response_bytes = max;
+                }
+                config::Param::ConnectTimeout(timeout) => {
+                    connector.timeout = timeout;
+                }
+                config::Param::Subprotocol(protocol) => {
+                    connector.subprotocol = protocol;
+                }
+                config::Param::Headers(new_headers) => {
+                    connector.headers.extend(new_headers);
+                }
+                config::Param::LogRequests(log) => {
+                    log_requests = log;
+                }
+                config::Param::LogResponses(log) => {
+                    log_responses = log;
+                }
+                config::Param::MaxPendingRequests(max) => {
+                    max_pending = max;
+                }
+                config::Param::ReconnectInitialDelay(delay) => {
+                    reconnect.initial_delay_ms = delay.as_millis() as u64;
+                }
+                config::Param::ReconnectBackoffFactor(factor) => {
+                    reconnect.backoff_factor = factor;
+                }
+                config::Param::ReconnectMaxDelay(delay) => {
+                    reconnect.max_delay_ms = delay.as_millis() as u64;
+                }
+                config::Param::ReconnectMaxAttempts(attempts) => {
+                    reconnect.max_attempts = attempts;
+                }
+                config::Param::ReconnectJitter(jitter) => {
+                    reconnect.jitter = jitter;
+                }
+                config::Param::ReconnectProbe(new_probe) => {
+                    probe = new_probe;
+                }
+                config::Param::ReconnectProbeTimeout(timeout) => {
+                    probe_timeout = timeout;
+                }
+                config::Param::ResubscribePolicy(policy) => {
+                    resubscribe = policy;
                 }
             }
         }
 
-        println!("[WS] Connecting to: {:?}", url);
+        println!("[WS] Connecting to: {:?}", connector.url);
 
-        let (write_sender, write_receiver) = mpsc::unbounded();
-        let shared = Arc::new(Shared::default());
+        let duplex = connector.connect().await?;
 
-        let ws_future = {
-            use futures::{compat::Future01CompatExt, TryStreamExt};
-            use futures01::{Future, Sink, Stream};
-
-            let handler = WebSocketHandler {
-                shared: shared.clone(),
-                write_sender: write_sender.clone(),
-            };
-
-            let write_receiver = write_receiver
-                .map(|msg| {
-                    log::trace!("Sending request: {:?}", msg);
-                    msg
-                })
-                .map(|x| Ok(x) as Result<_, websocket::WebSocketError>)
-                .compat();
-            websocket::ClientBuilder::from_url(&url)
-                .async_connect_insecure()
-                .map(|(duplex, _)| duplex.split())
-                .map_err(|e| format!("{:?}", e))
-                .and_then(move |(sink, stream)| {
-                    let reader = stream.map_err(|e| format!("{:?}", e)).for_each(move |message| {
-                        log::trace!("Message received: {:?}", message);
-                        handler.process_message(message).compat()
-                    });
-
-                    let writer = sink
-                        .send_all(write_receiver)
-                        .map_err(|e| format!("{:?}", e))
-                        .map(|_| ());
-
-                    reader.join(writer)
-                })
-                .compat()
-        };
-
-        spawn_tasks.spawn(Box::new(
-            ws_future
-                .map_err(|err| {
-                    log::error!("WebSocketError: {:?}", err);
-                })
-                .map(|_| ()),
-        ));
-
-        Ok(Self {
+        let url = connector.url.clone();
+        let (write_sender, mut write_receiver) = mpsc::unbounded();
+        let ws = Self {
             id: Arc::new(atomic::AtomicUsize::new(1)),
             url,
-            shared,
+            shared: Arc::new(Shared::default().with_max_pending(max_pending)),
             spawn: Arc::new(spawn_tasks),
             write_sender,
-        })
+            reconnect,
+            state: Arc::new(Mutex::new(reconnect::ConnectionState::Connected)),
+        };
+
+        let ws_future = {
+            let ws = ws.clone();
+
+            async move {
+                let mut duplex = duplex;
+                let mut reconnected = false;
+                loop {
+                    let handler = WebSocketHandler {
+                        shared: ws.shared.clone(),
+                        write_sender: ws.write_sender.clone(),
+                        max_response_bytes,
+                        log_responses,
+                    };
+                    let connection = run_connection(duplex, handler, &mut write_receiver, log_requests);
+                    // Mark the connection as established, or degraded if the reconnected upstream doesn't deliver notifications.
+                    let verification = async {
+                        if reconnected && resubscribe == reconnect::ResubscribePolicy::Replay {
+                            ws.resubscribe().await;
+                        }
+                        let mut state = reconnect::ConnectionState::Connected;
+                        if let (true, Some((subscription, params))) = (reconnected, probe.as_ref()) {
+                            if !upstream::probe_subscriptions(&ws, subscription, params.clone(), probe_timeout).await {
+                                tracing::warn!(
+                                    "Reconnected upstream does not deliver notifications of {}.",
+                                    subscription
+                                );
+                                state = reconnect::ConnectionState::Degraded;
+                            }
+                        }
+                        reconnect::set_state(&ws.state, state);
+                        future::pending::<()>().await
+                    };
+                    if let Either::Left((Err(err), _)) =
+                        future::select(Box::pin(connection), Box::pin(verification)).await
+                    {
+                        tracing::error!("WebSocketError: {:?}", err);
+                    }
+                    tracing::warn!("WebSocket upstream connection closed.");
+                    fail_pending(&ws.shared);
+
+                    duplex = match reconnect::reconnect(&ws.reconnect, &ws.state, || connector.connect()).await {
+                        Some(duplex) => duplex,
+                        None => break,
+                    };
+                    reconnected = true;
+                }
+
+                write_receiver.close();
+                fail_pending(&ws.shared);
+            }
+        };
+
+        ws.spawn.spawn(Box::new(Box::pin(ws_future)));
+
+        Ok(ws)
+    }
+
+    /// Create new WebSocket transport sending given headers with the handshake request.
+    ///
+    /// Useful for providers requiring authentication (e.g. `Authorization` header).
+    /// The headers override the ones with the same name given in `params`.
+    pub async fn with_custom_headers(
+        mut params: Vec<config::Param>,
+        headers: Vec<(String, String)>,
+        spawn_tasks: impl Spawn + 'static,
+    ) -> Result<Self, String> {
+        params.push(config::Param::Headers(headers));
+        Self::new(params, spawn_tasks).await
+    }
+
+    /// Returns the URL of the upstream server.
+    pub fn url(&self) -> &url::Url {
+        &self.url
+    }
+
+    /// Returns `true` if the upstream connection is established (even if degraded).
+    pub fn is_connected(&self) -> bool {
+        match self.connection_state() {
+            reconnect::ConnectionState::Connected | reconnect::ConnectionState::Degraded => true,
+            reconnect::ConnectionState::Reconnecting | reconnect::ConnectionState::Failed => false,
+        }
+    }
+
+    /// Returns the state of the upstream connection.
+    pub fn connection_state(&self) -> reconnect::ConnectionState {
+        *self.state.lock().expect("Connection state is never poisoned.")
     }
 
+    /// Returns the backoff parameters of reconnection attempts.
+    pub fn reconnect_config(&self) -> &reconnect::ReconnectConfig {
+        &self.reconnect
+    }
+
+    /// Re-issues subscribe calls of all active subscriptions to the (reconnected) upstream.
+    ///
+    /// Notifications of the new subscriptions are forwarded to the sessions with the original ids.
+    async fn resubscribe(&self) {
+        let calls = self.shared.subscribe_calls();
+        tracing::info!("Re-issuing {} subscriptions to the upstream.", calls.len());
+
+        let resubscribe = calls.into_iter().map(|(subscription_id, mut call)| {
+            call.id = jsonrpc_core::Id::Str(format!(
+                "proxy_resubscribe_{}",
+                self.id.fetch_add(1, atomic::Ordering::SeqCst)
+            ));
+            let rx = self
+                .shared
+                .add_pending(Some(&call.id), PendingKind::Resubscribe(subscription_id.clone()));
+            self.write_and_wait(jsonrpc_core::Call::MethodCall(call), rx)
+                .map(move |res| match res {
+                    Ok(Some(jsonrpc_core::Output::Success(_))) => {}
+                    res => tracing::warn!("Unable to re-issue subscription {:?}: {:?}", subscription_id, res),
+                })
+        });
+        future::join_all(resubscribe).await;
+    }
+
+    #[tracing::instrument(skip_all)]
     fn write_and_wait(
         &self,
         call: jsonrpc_core::Call,
         response: Option<oneshot::Receiver<String>>,
     ) -> impl Future<Output = Result<Option<jsonrpc_core::Output>, String>> {
+        if response.is_none() && helpers::get_id(&call).is_some() {
+            return Either::Left(future::ready(Err(upstream::shared::QUEUE_FULL_ERROR.to_owned())));
+        }
+
+        // The request is no longer pending if it can't be written or the caller stops waiting.
+        let guard = helpers::get_id(&call).map(|id| PendingGuard::new(self.shared.clone(), id.clone()));
         let request = jsonrpc_core::types::to_string(&call).expect("jsonrpc-core are infallible");
         let result = self
             .write_sender
             .unbounded_send(OwnedMessage::Text(request))
             .map_err(|e| format!("Error sending request: {:?}", e));
 
-        future::ready(result).and_then(|_| match response {
-            None => Either::Left(future::ready(Ok(None))),
-            Some(res) => res
-                .map_ok(|out| serde_json::from_str(&out).ok())
-                .map_err(|e| format!("{:?}", e))
-                .right_future(),
-        })
+        Either::Right(future::ready(result).and_then(move |_| {
+            match response {
+                None => Either::Left(future::ready(Ok(None))),
+                Some(res) => res
+                    .map(move |res| {
+                        if let Some(guard) = guard {
+                            guard.disarm();
+                        }
+                        res
+                    })
+                    .map_ok(|out| serde_json::from_str(&out).ok())
+                    .map_err(|e| format!("{:?}", e))
+                    .right_future(),
+            }
+        }))
     }
 }
 
@@ -230,7 +521,7 @@ impl upstream::Transport for WebSocket {
     type Future = Box<dyn Future<Output = Result<Option<jsonrpc_core::Output>, Self::Error>> + Send + Unpin>;
 
     fn send(&self, call: jsonrpc_core::Call) -> Self::Future {
-        log::trace!("Calling: {:?}", call);
+        tracing::trace!("Calling: {}", helpers::format_call(&call));
 
         // TODO [ToDr] Mangle ids per sender or just ensure atomicity
         let rx = {
@@ -254,7 +545,15 @@ impl upstream::Transport for WebSocket {
             }
         };
 
-        log::trace!("Subscribing to {:?}: {:?}", subscription, call);
+        tracing::trace!("Subscribing to {}: {}", subscription, helpers::format_call(&call));
+
+        if let Some(rx) = self.shared.join_subscribe(&call, session.clone()) {
+            tracing::trace!("Joining identical subscribe call to {}", subscription);
+            return Box::new(
+                rx.map_ok(|out| serde_json::from_str(&out).ok())
+                    .map_err(|e| format!("{:?}", e)),
+            );
+        }
 
         // TODO [ToDr] Mangle ids per sender or just ensure atomicity
         let rx = {
@@ -272,13 +571,11 @@ impl upstream::Transport for WebSocket {
                             method: subscription.unsubscribe.clone(),
                             params: jsonrpc_core::Params::Array(vec![subs_id.into()]).into(),
                         });
-                        let name = subscription.name.clone();
-                        let fut = ws
-                            .unsubscribe(call, subscription.clone())
-                            .map_err(move |e| {
-                                log::warn!("Unable to auto-unsubscribe from '{}': {:?}", name, e);
-                            })
-                            .map(|_| ());
+                        let fut = helpers::auto_unsubscribe(
+                            ws.unsubscribe(call, None, subscription.clone()),
+                            subscription.name.clone(),
+                            helpers::AUTO_UNSUBSCRIBE_TIMEOUT,
+                        );
 
                         ws.spawn.spawn(Box::new(fut));
                     }),
@@ -289,15 +586,605 @@ impl upstream::Transport for WebSocket {
         Box::new(self.write_and_wait(call, rx))
     }
 
-    fn unsubscribe(&self, call: jsonrpc_core::Call, subscription: Subscription) -> Self::Future {
-        log::trace!("Unsubscribing from {:?}: {:?}", subscription, call);
+    fn unsubscribe(
+        &self,
+        call: jsonrpc_core::Call,
+        session: Option<Arc<jsonrpc_pubsub::Session>>,
+        subscription: Subscription,
+    ) -> Self::Future {
+        tracing::trace!("Unsubscribing from {}: {}", subscription, helpers::format_call(&call));
 
-        // Remove the subscription id
-        if let Some(subscription_id) = helpers::get_unsubscribe_id(&call) {
-            self.shared.remove_subscription(&subscription_id);
-        }
+        // Detach the session, the subscription is cancelled upstream once the last one leaves
+        // (the upstream might know it under a different id after reconnecting).
+        let call = match helpers::get_unsubscribe_id(&call) {
+            Some(subscription_id) => match self.shared.detach_session(&subscription_id, session.as_ref()) {
+                Detached::Unknown => call,
+                Detached::Last(upstream_id) => with_unsubscribe_id(call, upstream_id),
+                Detached::Remaining => return Box::new(future::ok(helpers::build_response(&call, true.into()))),
+                Detached::NotSubscribed => return Box::new(future::ok(helpers::build_response(&call, false.into()))),
+            },
+            None => call,
+        };
 
         // It's a regular RPC, so just send it
         self.send(call)
     }
+
+    fn health_check(&self) -> Pin<Box<dyn Future<Output = bool> + Send>> {
+        // Don't wait for the timeout if the connection is already closed.
+        if !self.is_connected() {
+            return Box::pin(future::ready(false));
+        }
+
+        upstream::health_check(upstream::Transport::probe(self), upstream::HEALTH_CHECK_TIMEOUT)
+    }
+
+    fn probe_subscriptions<'a>(
+        &'a self,
+        subscription: &'a Subscription,
+        params: jsonrpc_core::Params,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        // Don't wait for the timeout if reconnecting has been abandoned.
+        if self.connection_state() == reconnect::ConnectionState::Failed {
+            return Box::pin(future::ready(false));
+        }
+
+        upstream::probe_subscriptions(self, subscription, params, upstream::SUBSCRIPTION_PROBE_TIMEOUT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    fn handler(max_response_bytes: usize) -> WebSocketHandler {
+        let (write_sender, _write_receiver) = mpsc::unbounded();
+        WebSocketHandler {
+            shared: Default::default(),
+            write_sender,
+            max_response_bytes,
+            log_responses: false,
+        }
+    }
+
+    fn response(id: u64, result: &str) -> String {
+        format!(r#"{{"jsonrpc":"2.0","id":{},"result":"{}"}}"#, id, result)
+    }
+
+    #[tokio::test]
+    async fn should_fail_if_upstream_does_not_respond_within_connect_timeout() {
+        // given
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap()).parse().unwrap();
+        let params = vec![
+            config::Param::Url(url),
+            config::Param::ConnectTimeout(std::time::Duration::from_millis(100)),
+        ];
+        let start = std::time::Instant::now();
+
+        // when
+        let res = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await;
+
+        // then
+        assert!(res.is_err());
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    /// Starts a mock server accepting only connections negotiating given subprotocol.
+    fn mock_upstream(protocol: &'static str) -> url::Url {
+        let server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", server.local_addr().unwrap()).parse().unwrap();
+        std::thread::spawn(move || {
+            let mut clients = vec![];
+            for request in server.filter_map(Result::ok) {
+                if request.protocols().iter().any(|p| p == protocol) {
+                    clients.push(request.use_protocol(protocol).accept().unwrap());
+                } else {
+                    let _ = request.reject();
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn should_connect_with_required_subprotocol() {
+        // given
+        let params = vec![
+            config::Param::Url(mock_upstream("json-rpc/2.0")),
+            config::Param::Subprotocol(Some("json-rpc/2.0".into())),
+        ];
+
+        // when
+        let res = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await;
+
+        // then
+        assert!(res.is_ok());
+    }
+
+    /// Starts a mock server accepting only connections with given `Authorization` header.
+    fn mock_authorized_upstream(authorization: &'static str) -> url::Url {
+        let server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", server.local_addr().unwrap()).parse().unwrap();
+        std::thread::spawn(move || {
+            let mut clients = vec![];
+            for request in server.filter_map(Result::ok) {
+                let authorized = request
+                    .request
+                    .headers
+                    .get_raw("Authorization")
+                    .is_some_and(|values| values == [authorization.as_bytes().to_vec()]);
+                if authorized {
+                    clients.push(request.accept().unwrap());
+                } else {
+                    let _ = request.reject();
+                }
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn should_send_custom_headers_during_handshake() {
+        // given
+        let url = mock_authorized_upstream("Bearer xyz");
+        let headers = vec![("Authorization".to_owned(), "Bearer xyz".to_owned())];
+
+        // when
+        let unauthorized = WebSocket::new(vec![config::Param::Url(url.clone())], |fut| drop(tokio::spawn(fut))).await;
+        let authorized = WebSocket::new(vec![config::Param::Url(url), config::Param::Headers(headers)], |fut| {
+            drop(tokio::spawn(fut))
+        })
+        .await;
+
+        // then
+        assert!(unauthorized.is_err());
+        assert!(authorized.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_connect_with_custom_headers() {
+        // given
+        let url = mock_authorized_upstream("Bearer xyz");
+        let params = || vec![config::Param::Url(url.clone())];
+
+        // when
+        let missing = WebSocket::with_custom_headers(params(), vec![], |fut| drop(tokio::spawn(fut))).await;
+        let invalid =
+            WebSocket::with_custom_headers(params(), vec![("Authorization".into(), "Bearer abc".into())], |fut| {
+                drop(tokio::spawn(fut))
+            })
+            .await;
+        let valid =
+            WebSocket::with_custom_headers(params(), vec![("Authorization".into(), "Bearer xyz".into())], |fut| {
+                drop(tokio::spawn(fut))
+            })
+            .await;
+
+        // then
+        assert!(missing.is_err());
+        assert!(invalid.is_err());
+        assert!(valid.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_expose_upstream_url() {
+        // given
+        let url = mock_upstream("json-rpc/2.0");
+        let params = vec![
+            config::Param::Url(url.clone()),
+            config::Param::Subprotocol(Some("json-rpc/2.0".into())),
+        ];
+
+        // when
+        let ws = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await.unwrap();
+
+        // then
+        assert_eq!(ws.url(), &url);
+        assert!(ws.is_connected());
+    }
+
+    #[tokio::test]
+    async fn should_stop_reconnecting_after_max_attempts() {
+        // given
+        let server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+        let url: url::Url = format!("ws://{}", server.local_addr().unwrap()).parse().unwrap();
+        let (accepted_tx, accepted_rx) = std::sync::mpsc::channel();
+        let (close_tx, close_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let mut server = server;
+            let client = server.accept().ok().unwrap().accept().unwrap();
+            accepted_tx.send(()).unwrap();
+            // close both the connection and the listener, so that reconnecting fails.
+            let _ = close_rx.recv();
+            drop(client);
+            drop(server);
+        });
+        let params = vec![
+            config::Param::Url(url),
+            config::Param::ReconnectInitialDelay(std::time::Duration::from_millis(10)),
+            config::Param::ReconnectMaxAttempts(Some(3)),
+            config::Param::ReconnectJitter(false),
+        ];
+        let ws = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await.unwrap();
+        accepted_rx.recv().unwrap();
+        assert_eq!(ws.connection_state(), reconnect::ConnectionState::Connected);
+
+        // when
+        close_tx.send(()).unwrap();
+        let failed = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while ws.connection_state() != reconnect::ConnectionState::Failed {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+
+        // then
+        assert!(failed.is_ok());
+        assert!(!ws.is_connected());
+        assert!(!upstream::Transport::health_check(&ws).await);
+        assert_eq!(ws.reconnect_config().max_attempts, Some(3));
+    }
+
+    /// Waits for the next message sent to the session.
+    async fn next_message(rx: &mut mpsc::UnboundedReceiver<String>) -> serde_json::Value {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), rx.next())
+            .await
+            .unwrap()
+            .unwrap();
+        serde_json::from_str(&message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_replay_subscriptions_after_reconnecting() {
+        // given
+        let server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+        let url: url::Url = format!("ws://{}", server.local_addr().unwrap()).parse().unwrap();
+        let (restart_tx, restart_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let mut server = server;
+            // every connection gets a different subscription id, just like a restarted upstream would assign.
+            for upstream_id in &["0x1", "0x2"] {
+                let mut client = server.accept().ok().unwrap().accept().unwrap();
+                let call = match client.recv_message().unwrap() {
+                    OwnedMessage::Text(call) => serde_json::from_str::<jsonrpc_core::MethodCall>(&call).unwrap(),
+                    message => panic!("Unexpected message: {:?}", message),
+                };
+                let response = jsonrpc_core::Output::from(Ok((*upstream_id).into()), call.id, call.jsonrpc);
+                let notification = format!(
+                    r#"{{"jsonrpc":"2.0","method":"eth_subscription","params":{{"subscription":"{}","result":"{}"}}}}"#,
+                    upstream_id, upstream_id
+                );
+                client
+                    .send_message(&OwnedMessage::Text(serde_json::to_string(&response).unwrap()))
+                    .unwrap();
+                client.send_message(&OwnedMessage::Text(notification)).unwrap();
+                let _ = restart_rx.recv();
+            }
+        });
+        let params = vec![
+            config::Param::Url(url),
+            config::Param::ReconnectInitialDelay(std::time::Duration::from_millis(10)),
+            config::Param::ReconnectJitter(false),
+            config::Param::ResubscribePolicy(reconnect::ResubscribePolicy::Replay),
+        ];
+        let ws = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded();
+        let session = Arc::new(jsonrpc_pubsub::Session::new(tx));
+        let subscription = Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        };
+        let subscribe = jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+            jsonrpc: Some(jsonrpc_core::Version::V2),
+            id: jsonrpc_core::Id::Num(1),
+            method: "eth_subscribe".into(),
+            params: jsonrpc_core::Params::Array(vec!["newHeads".into()]),
+        });
+        let res = upstream::Transport::subscribe(&ws, subscribe, Some(session.clone()), subscription).await;
+        assert_eq!(
+            res,
+            Ok(Some(jsonrpc_core::Output::from(
+                Ok("0x1".into()),
+                jsonrpc_core::Id::Num(1),
+                Some(jsonrpc_core::Version::V2)
+            )))
+        );
+        assert_eq!(next_message(&mut rx).await["params"]["subscription"], "0x1");
+
+        // when
+        restart_tx.send(()).unwrap();
+        let resubscribed = next_message(&mut rx).await;
+        let notification = next_message(&mut rx).await;
+
+        // then
+        assert_eq!(resubscribed["method"], upstream::shared::RESUBSCRIBED_NOTIFICATION);
+        assert_eq!(resubscribed["params"]["subscription"], "0x1");
+        assert_eq!(notification["params"]["subscription"], "0x1");
+        assert_eq!(notification["params"]["result"], "0x2");
+    }
+
+    #[tokio::test]
+    async fn should_keep_degraded_connection_if_reconnected_upstream_does_not_notify() {
+        // given
+        let server = websocket::sync::Server::bind("127.0.0.1:0").unwrap();
+        let url: url::Url = format!("ws://{}", server.local_addr().unwrap()).parse().unwrap();
+        let (restart_tx, restart_rx) = std::sync::mpsc::channel::<()>();
+        std::thread::spawn(move || {
+            let mut server = server;
+            let client = server.accept().ok().unwrap().accept().unwrap();
+            let _ = restart_rx.recv();
+            drop(client);
+            // the reconnected upstream responds to all calls, but never sends notifications.
+            let mut client = server.accept().ok().unwrap().accept().unwrap();
+            while let Ok(OwnedMessage::Text(call)) = client.recv_message() {
+                let call = serde_json::from_str::<jsonrpc_core::MethodCall>(&call).unwrap();
+                let response = jsonrpc_core::Output::from(Ok(call.method.into()), call.id, call.jsonrpc);
+                client
+                    .send_message(&OwnedMessage::Text(serde_json::to_string(&response).unwrap()))
+                    .unwrap();
+            }
+        });
+        let subscription = Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        };
+        let params = vec![
+            config::Param::Url(url),
+            config::Param::ReconnectInitialDelay(std::time::Duration::from_millis(10)),
+            config::Param::ReconnectJitter(false),
+            config::Param::ReconnectProbe(Some((
+                subscription,
+                jsonrpc_core::Params::Array(vec!["newHeads".into()]),
+            ))),
+            config::Param::ReconnectProbeTimeout(std::time::Duration::from_millis(100)),
+        ];
+        let ws = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await.unwrap();
+
+        // when
+        restart_tx.send(()).unwrap();
+        let degraded = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while ws.connection_state() != reconnect::ConnectionState::Degraded {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        let res = upstream::Transport::send(
+            &ws,
+            jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(1),
+                method: "eth_chainId".into(),
+                params: jsonrpc_core::Params::None,
+            }),
+        )
+        .await;
+
+        // then
+        assert!(degraded.is_ok());
+        assert!(ws.is_connected());
+        assert_eq!(
+            res,
+            Ok(Some(jsonrpc_core::Output::from(
+                Ok("eth_chainId".into()),
+                jsonrpc_core::Id::Num(1),
+                Some(jsonrpc_core::Version::V2)
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_calls_to_ws_upstream() {
+        // given
+        let upstream = mock_upstream::MockWsUpstream::new(|call| match call {
+            jsonrpc_core::Call::MethodCall(call) => {
+                jsonrpc_core::Output::from(Ok(call.method.into()), call.id, call.jsonrpc)
+            }
+            _ => unreachable!(),
+        })
+        .await;
+        let ws = WebSocket::new(vec![config::Param::Url(upstream.url())], |fut| drop(tokio::spawn(fut)))
+            .await
+            .unwrap();
+
+        // when
+        let res = upstream::Transport::send(
+            &ws,
+            jsonrpc_core::Call::MethodCall(jsonrpc_core::MethodCall {
+                jsonrpc: Some(jsonrpc_core::Version::V2),
+                id: jsonrpc_core::Id::Num(1),
+                method: "eth_chainId".into(),
+                params: jsonrpc_core::Params::None,
+            }),
+        )
+        .await;
+
+        // then
+        assert_eq!(
+            res,
+            Ok(Some(jsonrpc_core::Output::from(
+                Ok("eth_chainId".into()),
+                jsonrpc_core::Id::Num(1),
+                Some(jsonrpc_core::Version::V2)
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_fail_subscription_probe_if_upstream_does_not_notify() {
+        // given
+        let upstream = mock_upstream::MockWsUpstream::new(|call| match call {
+            jsonrpc_core::Call::MethodCall(call) => jsonrpc_core::Output::from(Ok("0x1".into()), call.id, call.jsonrpc),
+            _ => unreachable!(),
+        })
+        .await;
+        let ws = WebSocket::new(vec![config::Param::Url(upstream.url())], |fut| drop(tokio::spawn(fut)))
+            .await
+            .unwrap();
+        let subscription = Subscription {
+            subscribe: "eth_subscribe".into(),
+            unsubscribe: "eth_unsubscribe".into(),
+            name: "eth_subscription".into(),
+        };
+
+        // when
+        let res = upstream::probe_subscriptions(
+            &ws,
+            &subscription,
+            jsonrpc_core::Params::Array(vec!["newHeads".into()]),
+            std::time::Duration::from_millis(100),
+        )
+        .await;
+
+        // then
+        assert!(!res);
+    }
+
+    #[tokio::test]
+    async fn should_fail_if_required_subprotocol_is_not_negotiated() {
+        // given
+        let params = vec![
+            config::Param::Url(mock_upstream("json-rpc/2.0")),
+            config::Param::ConnectTimeout(std::time::Duration::from_secs(5)),
+        ];
+
+        // when
+        let res = WebSocket::new(params, |fut| drop(tokio::spawn(fut))).await;
+
+        // then
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn should_respond_with_parse_error_if_response_is_too_large() {
+        // given
+        let handler = handler(64);
+        let id = jsonrpc_core::Id::Num(1);
+        let mut rx = handler.shared.add_pending(Some(&id), PendingKind::Regular).unwrap();
+
+        // when
+        let res =
+            futures::executor::block_on(handler.process_message(OwnedMessage::Text(response(1, &"x".repeat(100)))));
+
+        // then
+        assert_eq!(res, Ok(()));
+        let output: jsonrpc_core::Output = serde_json::from_str(&rx.try_recv().unwrap().unwrap()).unwrap();
+        match output {
+            jsonrpc_core::Output::Failure(failure) => {
+                assert_eq!(failure.id, id);
+                assert_eq!(failure.error.code, jsonrpc_core::ErrorCode::ParseError);
+            }
+            other => panic!("Unexpected output: {:?}", other),
+        }
+        assert!(handler.shared.remove_pending(&id).is_none());
+    }
+
+    #[test]
+    fn should_pass_responses_within_limit() {
+        // given
+        let handler = handler(64);
+        let id = jsonrpc_core::Id::Num(1);
+        let mut rx = handler.shared.add_pending(Some(&id), PendingKind::Regular).unwrap();
+
+        // when
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(response(1, "x")))).unwrap();
+
+        // then
+        assert_eq!(rx.try_recv().unwrap(), Some(response(1, "x")));
+    }
+
+    #[test]
+    fn should_route_batch_responses_to_callers() {
+        // given
+        let handler = handler(1024);
+        let mut rx1 = handler
+            .shared
+            .add_pending(Some(&jsonrpc_core::Id::Num(1)), PendingKind::Regular)
+            .unwrap();
+        let mut rx2 = handler
+            .shared
+            .add_pending(Some(&jsonrpc_core::Id::Num(2)), PendingKind::Regular)
+            .unwrap();
+
+        // when
+        let batch = format!("[{},{}]", response(2, "b"), response(1, "a"));
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(batch))).unwrap();
+
+        // then
+        let json = |s: String| serde_json::from_str::<serde_json::Value>(&s).unwrap();
+        assert_eq!(json(rx1.try_recv().unwrap().unwrap()), json(response(1, "a")));
+        assert_eq!(json(rx2.try_recv().unwrap().unwrap()), json(response(2, "b")));
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_log_upstream_response_body() {
+        // given
+        let handler = WebSocketHandler {
+            log_responses: true,
+            ..handler(1024)
+        };
+        let _rx = handler
+            .shared
+            .add_pending(Some(&jsonrpc_core::Id::Num(1)), PendingKind::Regular);
+
+        // when
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(response(1, "0x2a")))).unwrap();
+
+        // then
+        assert!(logs_contain(
+            r#"Upstream response: {"jsonrpc":"2.0","id":1,"result":"0x2a"}"#
+        ));
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_not_log_upstream_response_body_by_default() {
+        // given
+        let handler = handler(1024);
+
+        // when
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(response(1, "0x2a")))).unwrap();
+
+        // then
+        assert!(!logs_contain("Upstream response"));
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_trace_pending_response_within_message_span() {
+        // given
+        let handler = handler(1024);
+        let _rx = handler
+            .shared
+            .add_pending(Some(&jsonrpc_core::Id::Num(1)), PendingKind::Regular);
+
+        // when
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(response(1, "x")))).unwrap();
+
+        // then
+        assert!(logs_contain("ws_message"));
+        assert!(logs_contain("pending_response"));
+        assert!(!logs_contain("subscription_notify"));
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_trace_subscription_notification_within_message_span() {
+        // given
+        let handler = handler(1024);
+        let notification =
+            r#"{"jsonrpc":"2.0","method":"eth_subscription","params":{"subscription":"0x1","result":1}}"#;
+
+        // when
+        futures::executor::block_on(handler.process_message(OwnedMessage::Text(notification.into()))).unwrap();
+
+        // then
+        assert!(logs_contain("ws_message"));
+        assert!(logs_contain("subscription_notify"));
+        assert!(!logs_contain("pending_response"));
+    }
 }
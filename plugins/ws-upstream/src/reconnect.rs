@@ -0,0 +1,229 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Reconnection to the WebSocket upstream.
+
+use jsonrpc_core::futures::Future;
+use rand::Rng;
+use std::{sync::Mutex, time::Duration};
+
+/// State of the connection to the upstream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// The connection is established.
+    Connected,
+    /// The connection is established, but the reconnected upstream doesn't deliver notifications of the probed subscription.
+    Degraded,
+    /// The connection was lost, attempting to reconnect.
+    Reconnecting,
+    /// All reconnection attempts failed, the transport is unusable.
+    Failed,
+}
+
+/// Handling of active subscriptions once the connection to the upstream is re-established.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResubscribePolicy {
+    /// Keep the subscriptions as they are, the sessions stop receiving notifications.
+    #[default]
+    Ignore,
+    /// Re-issue the subscribe calls and forward notifications of the new subscriptions with the original ids.
+    Replay,
+}
+
+/// Jittered exponential backoff parameters of reconnection attempts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectConfig {
+    /// Delay before the first attempt (in milliseconds).
+    pub initial_delay_ms: u64,
+    /// Multiplier of the delay applied with every next attempt.
+    pub backoff_factor: f64,
+    /// Maximal delay between attempts, excluding jitter (in milliseconds).
+    pub max_delay_ms: u64,
+    /// Number of failed attempts after which reconnecting is abandoned (unlimited if `None`).
+    pub max_attempts: Option<u32>,
+    /// Add a random delay of up to `initial_delay_ms` to every attempt.
+    pub jitter: bool,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            initial_delay_ms: 100,
+            backoff_factor: 2.0,
+            max_delay_ms: 30_000,
+            max_attempts: None,
+            jitter: true,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Returns the delay before given (zero-based) reconnection attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay_with(attempt, &mut rand::thread_rng())
+    }
+
+    /// Returns the delay before given (zero-based) reconnection attempt using given source of jitter.
+    ///
+    /// The delay is `min(max_delay, initial_delay * backoff_factor^attempt) + random(0..initial_delay)`.
+    pub fn delay_with<R: Rng>(&self, attempt: u32, rng: &mut R) -> Duration {
+        let backoff = self.initial_delay_ms as f64 * self.backoff_factor.powf(attempt as f64);
+        let backoff = Duration::from_millis(backoff.min(self.max_delay_ms as f64) as u64);
+        if !self.jitter {
+            return backoff;
+        }
+
+        backoff + Duration::from_millis(self.initial_delay_ms).mul_f64(rng.gen::<f64>())
+    }
+}
+
+/// Attempts to connect until it succeeds or `max_attempts` consecutive attempts fail.
+///
+/// The `state` is set to `Reconnecting` (or `Failed` if all attempts failed, `None` is returned then).
+/// The caller marks the connection as `Connected` once it's verified to work.
+pub async fn reconnect<F, Fut, T>(config: &ReconnectConfig, state: &Mutex<ConnectionState>, mut connect: F) -> Option<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, String>>,
+{
+    set_state(state, ConnectionState::Reconnecting);
+
+    let mut attempt = 0u32;
+    loop {
+        let delay = config.delay(attempt);
+        tracing::info!("Reconnecting to the upstream in {:?} (attempt {}).", delay, attempt + 1);
+        tokio::time::sleep(delay).await;
+
+        match connect().await {
+            Ok(connection) => {
+                tracing::info!("Reconnected to the upstream.");
+                return Some(connection);
+            }
+            Err(e) => tracing::warn!("Unable to reconnect to the upstream: {}", e),
+        }
+
+        attempt = attempt.saturating_add(1);
+        if config.max_attempts.is_some_and(|max| attempt >= max) {
+            tracing::error!("Giving up reconnecting to the upstream after {} attempts.", attempt);
+            set_state(state, ConnectionState::Failed);
+            return None;
+        }
+    }
+}
+
+/// Updates the state of the connection.
+pub(crate) fn set_state(state: &Mutex<ConnectionState>, new_state: ConnectionState) {
+    *state.lock().expect("Connection state is never poisoned.") = new_state;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::mock::StepRng;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn should_multiply_the_delay_up_to_the_maximum() {
+        // given
+        let config = ReconnectConfig {
+            backoff_factor: 3.0,
+            jitter: false,
+            ..Default::default()
+        };
+
+        // when
+        let delays = (0..8).map(|attempt| config.delay(attempt)).collect::<Vec<_>>();
+
+        // then
+        assert_eq!(delays[0], Duration::from_millis(100));
+        assert_eq!(delays[1], Duration::from_millis(300));
+        assert_eq!(delays[5], Duration::from_millis(24_300));
+        assert_eq!(delays[6], Duration::from_secs(30));
+        assert_eq!(config.delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn should_add_jitter_below_initial_delay() {
+        // given
+        let config = ReconnectConfig::default();
+        let mut zero = StepRng::new(0, 0);
+        let mut max = StepRng::new(u64::MAX, 0);
+
+        // when
+        let min_delay = config.delay_with(3, &mut zero);
+        let max_delay = config.delay_with(3, &mut max);
+
+        // then
+        assert_eq!(min_delay, Duration::from_millis(800));
+        assert!(max_delay > Duration::from_millis(800) && max_delay <= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn should_stop_after_max_attempts() {
+        // given
+        let config = ReconnectConfig {
+            initial_delay_ms: 1,
+            max_attempts: Some(3),
+            jitter: false,
+            ..Default::default()
+        };
+        let state = Mutex::new(ConnectionState::Connected);
+        let attempts = AtomicU32::new(0);
+
+        // when
+        let res = reconnect(&config, &state, || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err::<(), _>("Connection refused".to_owned()) }
+        })
+        .await;
+
+        // then
+        assert_eq!(res, None);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(*state.lock().unwrap(), ConnectionState::Failed);
+    }
+
+    #[tokio::test]
+    async fn should_return_the_connection_once_reconnected() {
+        // given
+        let config = ReconnectConfig {
+            initial_delay_ms: 1,
+            max_attempts: Some(3),
+            jitter: false,
+            ..Default::default()
+        };
+        let state = Mutex::new(ConnectionState::Connected);
+        let attempts = AtomicU32::new(0);
+
+        // when
+        let res = reconnect(&config, &state, || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if attempt < 2 {
+                    Err("Connection refused".to_owned())
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+
+        // then
+        assert_eq!(res, Some(2));
+        assert_eq!(*state.lock().unwrap(), ConnectionState::Reconnecting);
+    }
+}
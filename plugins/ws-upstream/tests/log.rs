@@ -0,0 +1,53 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! The binaries only install a `log` logger, so `tracing` events have to reach it.
+
+use std::sync::Mutex;
+
+static LOGGER: Logger = Logger(Mutex::new(Vec::new()));
+
+struct Logger(Mutex<Vec<(log::Level, String)>>);
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn should_forward_tracing_events_to_log() {
+    // given
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    // when
+    tracing::warn!("WebSocket upstream connection closed.");
+
+    // then
+    assert!(LOGGER
+        .0
+        .lock()
+        .unwrap()
+        .contains(&(log::Level::Warn, "WebSocket upstream connection closed.".to_owned())));
+}
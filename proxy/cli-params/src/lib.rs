@@ -42,6 +42,9 @@ where
     }
 }
 
+/// Default prefix of environment variables used to look up parameter values.
+pub const DEFAULT_ENV_PREFIX: &str = "JSONRPC_PROXY";
+
 /// Describes a CLI parameter that should be present in the help.
 pub struct Param<Exec> {
     /// Parameters category
@@ -54,6 +57,16 @@ pub struct Param<Exec> {
     pub default_value: String,
     /// Parameter parser
     pub parser: Box<dyn Parser<Executor = Exec>>,
+    /// Prefix of the environment variable the value is read from if not given explicitly.
+    pub env_prefix: String,
+    /// Whether the parameter is omitted from the help output.
+    pub hidden: bool,
+    /// Whether the parameter can be given multiple times (every value is parsed separately).
+    pub multiple: bool,
+    /// Migration message printed if the deprecated parameter is used.
+    pub deprecated: Option<String>,
+    /// Former names of the parameter, still accepted but reported as deprecated.
+    pub deprecated_aliases: Vec<String>,
 }
 
 impl<X> Param<X> {
@@ -72,16 +85,131 @@ impl<X> Param<X> {
             description: description.into(),
             default_value: default_value.into(),
             parser: Box::new(parser),
+            env_prefix: DEFAULT_ENV_PREFIX.into(),
+            hidden: false,
+            multiple: false,
+            deprecated: None,
+            deprecated_aliases: vec![],
         }
     }
 
+    /// Hides the parameter from the help output.
+    ///
+    /// Meant for internal or advanced options, hidden parameters are still parsed.
+    pub fn hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    /// Allows the parameter to be given multiple times.
+    ///
+    /// Every value is parsed separately, yielding multiple executors.
+    pub fn multiple(mut self) -> Self {
+        self.multiple = true;
+        self
+    }
+
+    /// Marks the parameter as deprecated, given message is printed whenever it's used.
+    pub fn deprecated<T: Into<String>>(mut self, message: T) -> Self {
+        self.deprecated = Some(message.into());
+        self
+    }
+
+    /// Accepts the value under a former name of the parameter as well.
+    ///
+    /// Using the alias prints a warning pointing to the current name.
+    pub fn with_deprecated_alias<T: Into<String>>(mut self, alias: T) -> Self {
+        self.deprecated_aliases.push(alias.into());
+        self
+    }
+
+    /// Changes the prefix of the environment variable the value is read from.
+    ///
+    /// The variable is named `<prefix>_<PARAM_NAME_UPPER>`, e.g. `ETH_HTTP_PORT`.
+    pub fn with_env_prefix(mut self, prefix: &str) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    /// Name of the environment variable the value is read from.
+    pub fn env_var(&self) -> String {
+        format!("{}_{}", self.env_prefix, self.name.to_uppercase().replace('-', "_"))
+    }
+
     /// Parse given value and return `Executor` for given param.
+    ///
+    /// If the value is not given it's read from the environment variable (see `env_var`),
+    /// and the default value is used if the variable is not set either.
     pub fn parse(&self, value: Option<String>) -> Result<X, String> {
-        let default_value = self.default_value.clone();
-        let value = value.unwrap_or_else(|| default_value);
+        self.parse_with_env(value, |var| std::env::var(var).ok())
+    }
+
+    /// Parse given value and return `Executor` for given param.
+    ///
+    /// Same as `parse`, but the environment variables are looked up with given function.
+    pub fn parse_with_env<F>(&self, value: Option<String>, env: F) -> Result<X, String>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let value = value
+            .or_else(|| env(&self.env_var()))
+            .unwrap_or_else(|| self.default_value.clone());
 
         self.parser.parse(value)
     }
 }
 
 // TODO [ToDr] ParamsBuilder to have nicer API
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param() -> Param<String> {
+        Param::new(
+            "HTTP",
+            "test-http-port",
+            "Port",
+            "9934",
+            |val: String| -> Result<String, String> { Ok(val) },
+        )
+    }
+
+    #[test]
+    fn should_name_env_var_after_param() {
+        assert_eq!(param().env_var(), "JSONRPC_PROXY_TEST_HTTP_PORT");
+        assert_eq!(param().with_env_prefix("ETH").env_var(), "ETH_TEST_HTTP_PORT");
+    }
+
+    fn env(name: &'static str, value: &'static str) -> impl Fn(&str) -> Option<String> {
+        move |var| if var == name { Some(value.into()) } else { None }
+    }
+
+    #[test]
+    fn should_read_value_from_prefixed_env_var() {
+        // given
+        let param = param().with_env_prefix("PREFIXED");
+        let env = env("PREFIXED_TEST_HTTP_PORT", "8545");
+
+        // when
+        let from_env = param.parse_with_env(None, &env);
+        let explicit = param.parse_with_env(Some("9000".into()), &env);
+
+        // then
+        assert_eq!(from_env, Ok("8545".into()));
+        assert_eq!(explicit, Ok("9000".into()));
+        assert_eq!(param.parse_with_env(None, |_| None), Ok("9934".into()));
+    }
+
+    #[test]
+    fn should_ignore_unprefixed_env_var() {
+        // given
+        let param = param().with_env_prefix("OTHER");
+
+        // when
+        let value = param.parse_with_env(None, env("JSONRPC_PROXY_TEST_HTTP_PORT", "8545"));
+
+        // then
+        assert_eq!(value, Ok("9934".into()));
+    }
+}
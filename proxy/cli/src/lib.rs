@@ -31,20 +31,166 @@ pub fn configure_app<'a, 'b, Exec>(mut app: clap::App<'a, 'b>, params: &'a [para
                 .long(&p.name)
                 .takes_value(true)
                 .help(&p.description)
-                .default_value(&p.default_value),
-        )
+                .default_value(&p.default_value)
+                .hidden(p.hidden)
+                .multiple(p.multiple)
+                .number_of_values(1),
+        );
+        for alias in &p.deprecated_aliases {
+            app = app.arg(clap::Arg::with_name(alias).long(alias).takes_value(true).hidden(true));
+        }
     }
     app
 }
 
 /// Extract parameters from CLI matches and turn them into parameters executors, which can be used
 /// to configure particular transport or plugin.
+///
+/// Parameters not given explicitly are read from the environment (see `Param::env_var`).
+/// Every value of parameters given multiple times is parsed separately.
 pub fn parse_matches<Exec>(matches: &clap::ArgMatches, params: &[params::Param<Exec>]) -> Result<Vec<Exec>, String> {
-    params
-        .iter()
-        .map(|p| {
-            let val = matches.value_of(&p.name);
-            p.parse(val.map(str::to_owned))
-        })
-        .collect()
+    let mut executors = Vec::with_capacity(params.len());
+    for p in params {
+        let (val, warnings) = explicit_value(matches, p);
+        for warning in warnings {
+            eprintln!("{}", warning);
+        }
+        match matches.values_of(&p.name) {
+            Some(values) if p.multiple && matches.occurrences_of(&p.name) > 0 => {
+                for value in values {
+                    executors.push(p.parse(Some(value.to_owned()))?);
+                }
+            }
+            _ => executors.push(p.parse(val.map(str::to_owned))?),
+        }
+    }
+    Ok(executors)
+}
+
+/// Returns the value given explicitly (possibly with a deprecated alias) and deprecation warnings to print.
+fn explicit_value<'a, Exec>(matches: &'a clap::ArgMatches, p: &params::Param<Exec>) -> (Option<&'a str>, Vec<String>) {
+    let mut warnings = vec![];
+    let mut val = match matches.occurrences_of(&p.name) {
+        0 => None,
+        _ => matches.value_of(&p.name),
+    };
+
+    for alias in &p.deprecated_aliases {
+        if matches.occurrences_of(alias) == 0 {
+            continue;
+        }
+        warnings.push(format!("--{} is deprecated, use --{} instead", alias, p.name));
+        val = val.or_else(|| matches.value_of(alias));
+    }
+
+    if let (Some(_), Some(message)) = (val, p.deprecated.as_ref()) {
+        warnings.push(format!("--{} is deprecated: {}", p.name, message));
+    }
+
+    (val, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn param(name: &str) -> params::Param<String> {
+        params::Param::new(
+            "Test",
+            name,
+            "A test parameter.",
+            "default",
+            |value: String| -> Result<String, String> { Ok(value) },
+        )
+    }
+
+    #[test]
+    fn should_hide_params_from_help_but_parse_them() {
+        // given
+        let params = vec![param("test-visible"), param("test-hidden").hidden()];
+        let app = configure_app(clap::App::new("test"), &params);
+        let mut help = vec![];
+        app.clone().write_help(&mut help).unwrap();
+        let help = String::from_utf8(help).unwrap();
+
+        // when
+        let matches = app.get_matches_from(vec!["test", "--test-hidden", "value"]);
+        let values = parse_matches(&matches, &params);
+
+        // then
+        assert!(help.contains("--test-visible"));
+        assert!(!help.contains("--test-hidden"));
+        assert_eq!(values, Ok(vec!["default".to_owned(), "value".to_owned()]));
+    }
+
+    #[test]
+    fn should_parse_every_value_of_multiple_params() {
+        // given
+        let params = vec![param("test-header").multiple(), param("test-single")];
+        let app = configure_app(clap::App::new("test"), &params);
+
+        // when
+        let matches = app.clone().get_matches_from(vec![
+            "test",
+            "--test-header",
+            "a:1",
+            "--test-header",
+            "b:2",
+            "--test-single",
+            "value",
+        ]);
+        let values = parse_matches(&matches, &params);
+        let defaults = parse_matches(&app.get_matches_from(vec!["test"]), &params);
+
+        // then
+        assert_eq!(values, Ok(vec!["a:1".to_owned(), "b:2".to_owned(), "value".to_owned()]));
+        assert_eq!(defaults, Ok(vec!["default".to_owned(), "default".to_owned()]));
+    }
+
+    #[test]
+    fn should_accept_deprecated_alias_with_warning() {
+        // given
+        let params = vec![param("test-ws-upstream-url").with_deprecated_alias("test-upstream-ws")];
+        let app = configure_app(clap::App::new("test"), &params);
+        let mut help = vec![];
+        app.clone().write_help(&mut help).unwrap();
+        let help = String::from_utf8(help).unwrap();
+
+        // when
+        let matches = app.get_matches_from(vec!["test", "--test-upstream-ws", "ws://localhost:9944"]);
+        let (value, warnings) = explicit_value(&matches, &params[0]);
+
+        // then
+        assert!(!help.contains("--test-upstream-ws"));
+        assert_eq!(value, Some("ws://localhost:9944"));
+        assert_eq!(
+            warnings,
+            vec!["--test-upstream-ws is deprecated, use --test-ws-upstream-url instead".to_owned()]
+        );
+        assert_eq!(
+            parse_matches(&matches, &params),
+            Ok(vec!["ws://localhost:9944".to_owned()])
+        );
+    }
+
+    #[test]
+    fn should_warn_about_deprecated_param_only_if_used() {
+        // given
+        let params = vec![param("test-legacy").deprecated("the value is ignored")];
+        let app = configure_app(clap::App::new("test"), &params);
+
+        // when
+        let unused = app.clone().get_matches_from(vec!["test"]);
+        let used = app.get_matches_from(vec!["test", "--test-legacy", "value"]);
+
+        // then
+        assert_eq!(explicit_value(&unused, &params[0]), (None, vec![]));
+        assert_eq!(
+            explicit_value(&used, &params[0]),
+            (
+                Some("value"),
+                vec!["--test-legacy is deprecated: the value is ignored".to_owned()]
+            )
+        );
+    }
 }
@@ -0,0 +1,49 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Describes where the requests come from.
+//!
+//! Shared by the servers and the plugins, so that the latter don't depend on the servers.
+
+#![warn(missing_docs)]
+#![warn(unused_extern_crates)]
+
+use serde_derive::Deserialize;
+use std::net::IpAddr;
+
+/// Kind of the transport a request has been received with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransportKind {
+    /// HTTP server
+    Http,
+    /// WebSockets server
+    WebSocket,
+    /// TCP server
+    Tcp,
+    /// IPC server
+    Ipc,
+}
+
+/// Identifies the client making the request, e.g. to keep separate rate limits for every client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Client {
+    /// Address of the client.
+    Ip(IpAddr),
+    /// Connection of a client which address is not known, unique within the process.
+    Connection(u64),
+}
@@ -19,18 +19,91 @@
 
 use std::{
     io,
-    net::{Ipv4Addr, SocketAddr},
-    sync::Arc,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
+use crate::{Client, TransportKind};
 use jsonrpc_http_server as http;
 use params::Param;
-use pubsub;
-use rpc;
 
 const CATEGORY: &str = "HTTP Server";
 const PREFIX: &str = "http";
 
+/// Default maximal time to handle a single HTTP request.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// HTTP server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Listening address.
+    pub address: SocketAddr,
+    /// `Content-Security-Policy` header sent to browsers (omitted if `None`).
+    pub csp: Option<http::hyper::header::HeaderValue>,
+    /// Allowed CORS origin patterns, set only if wildcards are used.
+    pub cors_patterns: Option<Vec<String>>,
+    /// Path of the Prometheus metrics page (not served if `None`).
+    pub metrics_path: Option<String>,
+    /// Counters exposed on the metrics page (see `with_metrics`).
+    pub metrics: Metrics,
+    /// Read the client address from `X-Forwarded-For` header (set by a reverse proxy).
+    pub trust_forwarded_for: bool,
+    /// Refuse to start unless client addresses are known (see `require_client_address`).
+    pub require_client_address: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:9934".parse().unwrap(),
+            csp: None,
+            cors_patterns: None,
+            metrics_path: None,
+            metrics: Default::default(),
+            trust_forwarded_for: false,
+            require_client_address: false,
+        }
+    }
+}
+
+/// Counters exposed on the Prometheus metrics page.
+///
+/// The counters are shared with (and updated by) the middlewares handling the calls.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Number of handled calls.
+    pub requests_total: Arc<AtomicU64>,
+    /// Number of calls that resulted in an error.
+    pub errors_total: Arc<AtomicU64>,
+    /// Number of calls answered from the cache.
+    pub cache_hits_total: Arc<AtomicU64>,
+}
+
+impl Metrics {
+    /// Formats the counters in Prometheus text format.
+    pub fn render(&self) -> String {
+        [
+            ("requests_total", &self.requests_total),
+            ("errors_total", &self.errors_total),
+            ("cache_hits_total", &self.cache_hits_total),
+        ]
+        .iter()
+        .map(|(name, counter)| {
+            format!(
+                "# TYPE {} counter\n{} {}\n",
+                name,
+                name,
+                counter.load(Ordering::Relaxed)
+            )
+        })
+        .collect()
+    }
+}
+
 /// Returns CLI configuration options for the HTTP server.
 pub fn params<M, S>() -> Vec<Param<Box<dyn Configurator<M, S>>>>
 where
@@ -44,8 +117,8 @@ where
             let port: u16 = value
                 .parse()
                 .map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-            Ok(move |address: &mut SocketAddr, builder| {
-                address.set_port(port);
+            Ok(move |config: &mut Config, builder| {
+                config.address.set_port(port);
                 Ok(builder)
             })
         }),
@@ -53,8 +126,8 @@ where
             let ip: Ipv4Addr = value
                 .parse()
                 .map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-            Ok(move |address: &mut SocketAddr, builder| {
-                address.set_ip(ip.into());
+            Ok(move |config: &mut Config, builder| {
+                config.address.set_ip(ip.into());
                 Ok(builder)
             })
         }),
@@ -62,7 +135,7 @@ where
             let threads: usize = value
                 .parse()
                 .map_err(|e| format!("Invalid threads number {}: {}", value, e))?;
-            Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| Ok(builder.threads(threads)))
+            Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| Ok(builder.threads(threads)))
         }),
         param(
             "rest-api",
@@ -82,7 +155,7 @@ Possible options: "unsecure", "secure", "disabled"."#,
                     "unsecure" => http::RestApi::Unsecure,
                     _ => return Err(format!("Invalid value for rest-api: {}", value)),
                 };
-                Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| Ok(builder.rest_api(api)))
+                Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| Ok(builder.rest_api(api)))
             },
         ),
         param(
@@ -99,7 +172,7 @@ options: "all", "none"."#,
                     "*" | "all" | "any" => None,
                     _ => Some(value.split(',').map(Into::into).collect()),
                 };
-                Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| {
                     Ok(builder.allowed_hosts(hosts.clone().into()))
                 })
             },
@@ -109,15 +182,26 @@ options: "all", "none"."#,
             "none",
             r#"
 Specify CORS header for HTTP JSON-RPC API responses.
+Origins may contain `*` wildcards (e.g. "https://*.example.com").
 Special options: "all", "null", "none"."#,
             |value| {
-                let cors = match value.as_str() {
+                let origins = match value.as_str() {
                     "none" => Some(vec![]),
                     "*" | "all" | "any" => None,
-                    _ => Some(value.split(',').map(Into::into).collect()),
+                    _ => Some(value.split(',').map(String::from).collect::<Vec<_>>()),
+                };
+                // The server supports exact origins only, so patterns are matched by `CorsConfig`.
+                let patterns = origins
+                    .as_ref()
+                    .filter(|origins| origins.iter().any(|origin| origin.contains('*')))
+                    .cloned();
+                let cors: Option<Vec<http::AccessControlAllowOrigin>> = match patterns {
+                    Some(_) => None,
+                    None => origins.map(|origins| origins.into_iter().map(Into::into).collect()),
                 };
 
-                Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| {
+                Ok(move |config: &mut Config, builder: http::ServerBuilder<M, S>| {
+                    config.cors_patterns = patterns.clone();
                     Ok(builder.cors(cors.clone().into()))
                 })
             },
@@ -132,11 +216,31 @@ Informs the client that the preflight request is not required for the specified
                     .parse()
                     .map_err(|e| format!("Invalid cors max age {}: {}", value, e))?;
 
-                Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| {
                     Ok(builder.cors_max_age(if cors_max_age == 0 { None } else { Some(cors_max_age) }))
                 })
             },
         ),
+        param(
+            "csp",
+            "default-src 'none'",
+            r#"
+Content-Security-Policy header value sent to browsers
+loading the HTTP endpoint. Use "disabled" to omit the header."#,
+            |value| {
+                let csp = match value.as_str() {
+                    "disabled" | "off" | "no" => None,
+                    _ => Some(
+                        http::hyper::header::HeaderValue::from_str(&value)
+                            .map_err(|e| format!("Invalid Content-Security-Policy {}: {}", value, e))?,
+                    ),
+                };
+                Ok(move |config: &mut Config, builder: http::ServerBuilder<M, S>| {
+                    config.csp = csp.clone();
+                    Ok(builder)
+                })
+            },
+        ),
         param(
             "max-payload",
             "5",
@@ -145,33 +249,304 @@ Informs the client that the preflight request is not required for the specified
                 let max_payload: usize = value
                     .parse()
                     .map_err(|e| format!("Invalid maximal payload size ({}): {}", value, e))?;
-                Ok(move |_address: &mut SocketAddr, builder: http::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| {
                     Ok(builder.max_request_body_size(max_payload * 1024 * 1024))
                 })
             },
         ),
+        param(
+            "metrics-path",
+            "none",
+            r#"
+Path of the Prometheus metrics page (e.g. "/metrics").
+Use "none" to disable the page."#,
+            |value| {
+                let path = match value.as_str() {
+                    "none" | "disabled" | "off" => None,
+                    _ if value.starts_with('/') => Some(value),
+                    _ => return Err(format!("Invalid metrics path {}: has to start with `/`", value)),
+                };
+                Ok(move |config: &mut Config, builder: http::ServerBuilder<M, S>| {
+                    config.metrics_path = path.clone();
+                    Ok(builder)
+                })
+            },
+        ),
+        param(
+            "keep-alive-timeout",
+            "60",
+            r#"
+Idle timeout of HTTP keep-alive connections in seconds.
+Use 0 to disable keep-alive and close connections after every response.
+Note that the server doesn't enforce non-zero timeouts yet."#,
+            |value| {
+                let timeout: u64 = value
+                    .parse()
+                    .map_err(|e| format!("Invalid keep-alive timeout {}: {}", value, e))?;
+                // `jsonrpc_http_server` only allows to enable or disable keep-alive, idle connections are kept open.
+                Ok(move |_config: &mut Config, builder: http::ServerBuilder<M, S>| Ok(builder.keep_alive(timeout > 0)))
+            },
+        ),
+        param(
+            "trust-forwarded-for",
+            "false",
+            r#"
+Use the first address of the `X-Forwarded-For` header as the client address.
+Enable only if the server is behind a reverse proxy setting the header,
+otherwise clients can choose the address they are seen with.
+The address of the connection itself is not exposed by the server, so the proxy
+refuses to start with per-client rate limits unless this option is enabled."#,
+            |value| {
+                let trust: bool = value
+                    .parse()
+                    .map_err(|e| format!("Invalid value of trust-forwarded-for {}: {}", value, e))?;
+                Ok(move |config: &mut Config, builder: http::ServerBuilder<M, S>| {
+                    config.trust_forwarded_for = trust;
+                    Ok(builder)
+                })
+            },
+        ),
     ]
 }
 
+/// Returns CLI configuration option of the maximal time to handle a single HTTP request.
+///
+/// `jsonrpc_http_server` doesn't support request timeouts, so the option is parsed separately
+/// and has to be enforced by a middleware of the handler passed to `start` (`None` if unlimited).
+pub fn request_timeout_params() -> Vec<Param<Option<Duration>>> {
+    vec![Param {
+        category: CATEGORY.into(),
+        name: format!("{}-request-timeout", PREFIX),
+        description: "Maximal time to handle a single HTTP request in milliseconds. Timed out requests fail with `Timeout` error. Use 0 to disable.".into(),
+        default_value: DEFAULT_REQUEST_TIMEOUT.as_millis().to_string(),
+        parser: Box::new(|value: String| {
+            let timeout: u64 = value
+                .parse()
+                .map_err(|e| format!("Invalid request timeout {}: {}", value, e))?;
+            Ok(if timeout == 0 {
+                None
+            } else {
+                Some(Duration::from_millis(timeout))
+            })
+        }),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
+    }]
+}
+
+/// Returns a configurator exposing given counters on the metrics page (see `metrics-path` option).
+pub fn with_metrics<M, S>(metrics: Metrics) -> Box<dyn Configurator<M, S>>
+where
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+{
+    Box::new(move |config: &mut Config, builder| {
+        config.metrics = metrics.clone();
+        Ok(builder)
+    })
+}
+
+/// Returns a configurator making the server refuse to start unless client addresses are known.
+///
+/// Should be used if rate limits are kept per client, the server doesn't expose the address
+/// of the connection, so it's only known if read from `X-Forwarded-For` header (see `trust-forwarded-for` option).
+pub fn require_client_address<M, S>() -> Box<dyn Configurator<M, S>>
+where
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+{
+    Box::new(|config: &mut Config, builder| {
+        config.require_client_address = true;
+        Ok(builder)
+    })
+}
+
+/// Returns the HTTP request timeout if configured.
+pub fn request_timeout(params: &[Option<Duration>]) -> Option<Duration> {
+    params.iter().find_map(|timeout| *timeout)
+}
+
 /// Starts HTTP server on given handler.
 pub fn start<T, M, S>(params: Vec<Box<dyn Configurator<M, S>>>, io: T) -> io::Result<http::Server>
 where
     T: Into<rpc::MetaIoHandler<M, S>>,
-    M: rpc::Metadata + Default + From<Option<Arc<pubsub::Session>>>,
+    M: rpc::Metadata + From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)>,
     S: rpc::Middleware<M>,
     S::Future: Unpin,
     S::CallFuture: Unpin,
 {
-    let mut builder = http::ServerBuilder::new(io);
-    let mut address = "127.0.0.1:9934".parse().unwrap();
+    // The extractor is created before the configuration is known.
+    let trust_forwarded_for = Arc::new(AtomicBool::new(false));
+    let trust = trust_forwarded_for.clone();
+    let mut builder =
+        http::ServerBuilder::with_meta_extractor(io, move |request: &http::hyper::Request<http::hyper::Body>| {
+            // The server doesn't expose the address of the connection, only forwarded addresses are known.
+            let ip = if trust.load(Ordering::Relaxed) {
+                forwarded_for(request)
+            } else {
+                None
+            };
+            (TransportKind::Http, None, ip.map(Client::Ip)).into()
+        });
+    // should be overwritten by parameters anyway
+    let mut config = Config::default();
 
     // configure the server
     for p in params {
-        builder = p.configure(&mut address, builder)?;
+        builder = p.configure(&mut config, builder)?;
+    }
+    if config.require_client_address && !config.trust_forwarded_for {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Per-client rate limits of HTTP clients require their addresses to be read from X-Forwarded-For header.",
+        ));
+    }
+    trust_forwarded_for.store(config.trust_forwarded_for, Ordering::Relaxed);
+    println!("HTTP listening on {}", config.address);
+
+    // The server supports a single request middleware only.
+    let metrics = config.metrics;
+    let middleware = Middlewares {
+        metrics: config.metrics_path.map(|path| MetricsPage { path, metrics }),
+        cors: config.cors_patterns.map(CorsConfig),
+        csp: config.csp.map(ContentSecurityPolicy),
+    };
+    if middleware.metrics.is_some() || middleware.cors.is_some() || middleware.csp.is_some() {
+        builder = builder.request_middleware(middleware);
+    }
+
+    builder.start_http(&config.address)
+}
+
+/// Returns the first (i.e. the original client) address of `X-Forwarded-For` header.
+pub fn forwarded_for<T>(request: &http::hyper::Request<T>) -> Option<IpAddr> {
+    let header = request.headers().get("x-forwarded-for")?.to_str().ok()?;
+    header.split(',').next()?.trim().parse().ok()
+}
+
+/// Returns `true` if the origin matches given pattern.
+///
+/// `*` in the pattern matches any (possibly empty) sequence of characters.
+pub fn match_origin(pattern: &str, origin: &str) -> bool {
+    let mut parts = pattern.split('*').collect::<Vec<_>>();
+    let last = parts.pop().unwrap_or_default();
+    if parts.is_empty() {
+        return pattern == origin;
     }
-    println!("HTTP listening on {}", address);
 
-    builder.start_http(&address)
+    let mut rest = match origin.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    for part in &parts[1..] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Combines request middlewares configured for the server.
+struct Middlewares {
+    metrics: Option<MetricsPage>,
+    cors: Option<CorsConfig>,
+    csp: Option<ContentSecurityPolicy>,
+}
+
+impl http::RequestMiddleware for Middlewares {
+    fn on_request(&self, request: http::hyper::Request<http::hyper::Body>) -> http::RequestMiddlewareAction {
+        let request = match self.metrics {
+            Some(ref metrics) => match metrics.on_request(request) {
+                http::RequestMiddlewareAction::Proceed { request, .. } => request,
+                respond => return respond,
+            },
+            None => request,
+        };
+        let request = match self.cors {
+            Some(ref cors) => match cors.on_request(request) {
+                http::RequestMiddlewareAction::Proceed { request, .. } => request,
+                respond => return respond,
+            },
+            None => request,
+        };
+        match self.csp {
+            Some(ref csp) => csp.on_request(request),
+            None => request.into(),
+        }
+    }
+}
+
+/// Serves the Prometheus metrics page on `GET` requests to configured path.
+struct MetricsPage {
+    path: String,
+    metrics: Metrics,
+}
+
+impl http::RequestMiddleware for MetricsPage {
+    fn on_request(&self, request: http::hyper::Request<http::hyper::Body>) -> http::RequestMiddlewareAction {
+        if *request.method() != http::hyper::Method::GET || request.uri().path() != self.path {
+            return request.into();
+        }
+
+        let mut response = http::hyper::Response::new(self.metrics.render().into());
+        response.headers_mut().insert(
+            http::hyper::header::CONTENT_TYPE,
+            http::hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+        );
+        response.into()
+    }
+}
+
+/// Rejects requests from origins not matching any of the allowed patterns.
+///
+/// `jsonrpc_http_server` only supports exact origins, so the server is configured to allow any origin
+/// and the middleware responds to requests from other origins the same way the server would.
+/// Requests without `Origin` header don't come from browsers and are always allowed.
+struct CorsConfig(Vec<String>);
+
+impl http::RequestMiddleware for CorsConfig {
+    fn on_request(&self, request: http::hyper::Request<http::hyper::Body>) -> http::RequestMiddlewareAction {
+        let allowed = match request.headers().get(http::hyper::header::ORIGIN) {
+            Some(origin) => {
+                let origin = origin.to_str().unwrap_or_default();
+                self.0.iter().any(|pattern| match_origin(pattern, origin))
+            }
+            None => true,
+        };
+
+        if allowed {
+            request.into()
+        } else {
+            http::Response::invalid_allow_origin().into()
+        }
+    }
+}
+
+/// Adds `Content-Security-Policy` header to responses that can be rendered by the browser.
+///
+/// `jsonrpc_http_server` doesn't allow altering responses of the RPC handler, so the middleware
+/// responds to `GET` and `HEAD` requests on its own (with the same `405` response the server would return).
+/// JSON-RPC responses to `POST` requests are never rendered as documents, so the policy doesn't apply to them.
+struct ContentSecurityPolicy(http::hyper::header::HeaderValue);
+
+impl http::RequestMiddleware for ContentSecurityPolicy {
+    fn on_request(&self, request: http::hyper::Request<http::hyper::Body>) -> http::RequestMiddlewareAction {
+        match *request.method() {
+            http::hyper::Method::GET | http::hyper::Method::HEAD => {
+                let mut response: http::hyper::Response<http::hyper::Body> =
+                    http::Response::method_not_allowed().into();
+                response
+                    .headers_mut()
+                    .insert(http::hyper::header::CONTENT_SECURITY_POLICY, self.0.clone());
+                response.into()
+            }
+            _ => request.into(),
+        }
+    }
 }
 
 fn param<M, S, F, X>(
@@ -194,6 +569,11 @@ where
         description: description.replace('\n', ""),
         default_value: default_value.into(),
         parser: Box::new(move |val: String| Ok(Box::new(parser(val)?) as _)),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
     }
 }
 
@@ -206,22 +586,346 @@ where
     /// Configure the server.
     fn configure(
         &self,
-        address: &mut SocketAddr,
+        config: &mut Config,
         builder: http::ServerBuilder<M, S>,
     ) -> io::Result<http::ServerBuilder<M, S>>;
 }
 
 impl<F, M, S> Configurator<M, S> for F
 where
-    F: Fn(&mut SocketAddr, http::ServerBuilder<M, S>) -> io::Result<http::ServerBuilder<M, S>>,
+    F: Fn(&mut Config, http::ServerBuilder<M, S>) -> io::Result<http::ServerBuilder<M, S>>,
     M: rpc::Metadata,
     S: rpc::Middleware<M>,
 {
     fn configure(
         &self,
-        address: &mut SocketAddr,
+        config: &mut Config,
         builder: http::ServerBuilder<M, S>,
     ) -> io::Result<http::ServerBuilder<M, S>> {
-        (*self)(address, builder)
+        (*self)(config, builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_http_server::hyper::rt::Future;
+    use jsonrpc_http_server::RequestMiddleware;
+
+    fn request(method: http::hyper::Method) -> http::hyper::Request<http::hyper::Body> {
+        http::hyper::Request::builder()
+            .method(method)
+            .uri("/")
+            .body(http::hyper::Body::empty())
+            .unwrap()
+    }
+
+    fn csp() -> ContentSecurityPolicy {
+        ContentSecurityPolicy(http::hyper::header::HeaderValue::from_static("default-src 'none'"))
+    }
+
+    #[test]
+    fn should_add_content_security_policy_header() {
+        // when
+        let action = csp().on_request(request(http::hyper::Method::GET));
+
+        // then
+        match action {
+            http::RequestMiddlewareAction::Respond { response, .. } => {
+                let response = Future::wait(response).unwrap();
+                assert_eq!(response.status(), http::hyper::StatusCode::METHOD_NOT_ALLOWED);
+                assert_eq!(
+                    response.headers()[http::hyper::header::CONTENT_SECURITY_POLICY],
+                    "default-src 'none'"
+                );
+            }
+            _ => panic!("Expected the middleware to respond."),
+        }
+    }
+
+    #[test]
+    fn should_pass_rpc_requests_through() {
+        // when
+        let action = csp().on_request(request(http::hyper::Method::POST));
+
+        // then
+        match action {
+            http::RequestMiddlewareAction::Proceed { request, .. } => {
+                assert_eq!(*request.method(), http::hyper::Method::POST)
+            }
+            _ => panic!("Expected the request to proceed."),
+        }
+    }
+
+    fn cors() -> CorsConfig {
+        CorsConfig(vec!["https://*.example.com".into(), "http://localhost:*".into()])
+    }
+
+    fn request_from(origin: &str) -> http::hyper::Request<http::hyper::Body> {
+        http::hyper::Request::builder()
+            .method(http::hyper::Method::POST)
+            .uri("/")
+            .header(http::hyper::header::ORIGIN, origin)
+            .body(http::hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn should_match_origin_patterns() {
+        assert!(match_origin("https://*.example.com", "https://app.example.com"));
+        assert!(match_origin("https://*.example.com", "https://a.b.example.com"));
+        assert!(match_origin("http://localhost:*", "http://localhost:3000"));
+        assert!(match_origin("https://example.com", "https://example.com"));
+        assert!(!match_origin("https://*.example.com", "https://evil.com"));
+        assert!(!match_origin("https://*.example.com", "https://example.com.evil.com"));
+        assert!(!match_origin("https://*.example.com", "http://app.example.com"));
+        assert!(!match_origin("https://example.com", "https://app.example.com"));
+    }
+
+    #[test]
+    fn should_allow_requests_from_matching_origins() {
+        // when
+        let action = cors().on_request(request_from("https://app.example.com"));
+
+        // then
+        match action {
+            http::RequestMiddlewareAction::Proceed { .. } => {}
+            _ => panic!("Expected the request to proceed."),
+        }
+    }
+
+    #[test]
+    fn should_reject_requests_from_other_origins() {
+        // when
+        let action = cors().on_request(request_from("https://evil.com"));
+
+        // then
+        match action {
+            http::RequestMiddlewareAction::Respond { response, .. } => {
+                let response = Future::wait(response).unwrap();
+                assert_eq!(response.status(), http::hyper::StatusCode::FORBIDDEN);
+            }
+            _ => panic!("Expected the middleware to respond."),
+        }
+    }
+
+    #[test]
+    fn should_serve_metrics_page() {
+        // given
+        let metrics = Metrics::default();
+        metrics.requests_total.fetch_add(3, Ordering::Relaxed);
+        metrics.cache_hits_total.fetch_add(1, Ordering::Relaxed);
+        let page = MetricsPage {
+            path: "/metrics".into(),
+            metrics,
+        };
+        let metrics_request = http::hyper::Request::builder()
+            .method(http::hyper::Method::GET)
+            .uri("/metrics")
+            .body(http::hyper::Body::empty())
+            .unwrap();
+
+        // when
+        let action = page.on_request(metrics_request);
+        let rpc_action = page.on_request(request(http::hyper::Method::POST));
+
+        // then
+        match action {
+            http::RequestMiddlewareAction::Respond { response, .. } => {
+                let response = Future::wait(response).unwrap();
+                assert_eq!(response.status(), http::hyper::StatusCode::OK);
+                assert_eq!(
+                    response.headers()[http::hyper::header::CONTENT_TYPE],
+                    "text/plain; version=0.0.4"
+                );
+                let body = Future::wait(http::hyper::rt::Stream::concat2(response.into_body())).unwrap();
+                assert_eq!(
+                    String::from_utf8(body.to_vec()).unwrap(),
+                    "# TYPE requests_total counter\nrequests_total 3\n\
+                     # TYPE errors_total counter\nerrors_total 0\n\
+                     # TYPE cache_hits_total counter\ncache_hits_total 1\n"
+                );
+            }
+            _ => panic!("Expected the middleware to respond."),
+        }
+        match rpc_action {
+            http::RequestMiddlewareAction::Proceed { .. } => {}
+            _ => panic!("Expected the request to proceed."),
+        }
+    }
+
+    #[test]
+    fn should_reject_invalid_policy() {
+        // given
+        let param = params::<(), rpc::middleware::Noop>()
+            .into_iter()
+            .find(|p| p.name == "http-csp")
+            .unwrap();
+
+        // then
+        assert!(param.parse(Some("default-src\n'none'".into())).is_err());
+        assert!(param.parse(Some("disabled".into())).is_ok());
+    }
+
+    #[derive(Clone, Default)]
+    struct Meta(Option<IpAddr>);
+    impl rpc::Metadata for Meta {}
+    impl From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)> for Meta {
+        fn from((_, _, client): (TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)) -> Self {
+            match client {
+                Some(Client::Ip(ip)) => Meta(Some(ip)),
+                _ => Meta(None),
+            }
+        }
+    }
+
+    /// Sends given request to the server and returns the response, the connection is closed by the server.
+    fn send(server: &http::Server, request: &str) -> String {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(server.address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn client_ip_server(trust_forwarded_for: &str) -> http::Server {
+        client_ip_server_with(trust_forwarded_for, vec![]).unwrap()
+    }
+
+    fn client_ip_server_with(
+        trust_forwarded_for: &str,
+        extra: Vec<Box<dyn Configurator<Meta, rpc::middleware::Noop>>>,
+    ) -> io::Result<http::Server> {
+        let mut params = params::<Meta, rpc::middleware::Noop>()
+            .into_iter()
+            .map(|p| match p.name.as_str() {
+                "http-port" => p.parse(Some("0".into())),
+                "http-keep-alive-timeout" => p.parse(Some("0".into())),
+                "http-trust-forwarded-for" => p.parse(Some(trust_forwarded_for.into())),
+                _ => p.parse(None),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        params.extend(extra);
+        let mut io = rpc::MetaIoHandler::<Meta>::default();
+        io.add_method_with_meta("client_ip", |_, meta: Meta| {
+            rpc::futures::future::ready(Ok(meta.0.map_or(rpc::Value::Null, |ip| ip.to_string().into())))
+        });
+        start(params, io)
+    }
+
+    fn client_ip_request(server: &http::Server) -> String {
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"client_ip"}"#;
+        format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nX-Forwarded-For: 1.2.3.4, 10.0.0.1\r\nContent-Length: {}\r\n\r\n{}",
+            server.address(),
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn should_read_client_ip_from_forwarded_for_header() {
+        // given
+        let server = client_ip_server("true");
+
+        // when
+        let response = send(&server, &client_ip_request(&server));
+
+        // then
+        assert!(response.contains(r#""result":"1.2.3.4""#), "{}", response);
+        server.close();
+    }
+
+    #[test]
+    fn should_ignore_forwarded_for_header_by_default() {
+        // given
+        let server = client_ip_server("false");
+
+        // when
+        let response = send(&server, &client_ip_request(&server));
+
+        // then
+        assert!(response.contains(r#""result":null"#), "{}", response);
+        server.close();
+    }
+
+    #[test]
+    fn should_refuse_to_start_without_client_addresses_if_required() {
+        // when
+        let untrusted = client_ip_server_with("false", vec![require_client_address()]);
+        let trusted = client_ip_server_with("true", vec![require_client_address()]);
+
+        // then
+        assert_eq!(untrusted.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidInput));
+        trusted.unwrap().close();
+    }
+
+    #[test]
+    fn should_parse_first_forwarded_for_address() {
+        let request = |value: &str| {
+            http::hyper::Request::builder()
+                .header("X-Forwarded-For", value)
+                .body(())
+                .unwrap()
+        };
+
+        assert_eq!(forwarded_for(&request("1.2.3.4")), Some([1, 2, 3, 4].into()));
+        assert_eq!(forwarded_for(&request(" ::1 , 1.2.3.4")), Some("::1".parse().unwrap()));
+        assert_eq!(forwarded_for(&request("unknown")), None);
+        assert_eq!(forwarded_for(&http::hyper::Request::new(())), None);
+    }
+
+    #[test]
+    fn should_close_connections_if_keep_alive_disabled() {
+        use std::io::{Read, Write};
+
+        // given
+        let params = params::<Meta, rpc::middleware::Noop>()
+            .into_iter()
+            .map(|p| match p.name.as_str() {
+                "http-port" => p.parse(Some("0".into())),
+                "http-keep-alive-timeout" => p.parse(Some("0".into())),
+                _ => p.parse(None),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let server = start(params, rpc::MetaIoHandler::<Meta>::default()).unwrap();
+        let mut stream = std::net::TcpStream::connect(server.address()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // when
+        let body = r#"{"jsonrpc":"2.0","id":1,"method":"ping"}"#;
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            server.address(),
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        let read = stream.read_to_string(&mut response);
+
+        // then
+        assert!(read.is_ok(), "Connection not closed: {:?}", read);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        server.close();
+    }
+
+    #[test]
+    fn should_parse_request_timeout() {
+        // given
+        let param = request_timeout_params().pop().unwrap();
+
+        // then
+        assert_eq!(param.name, "http-request-timeout");
+        assert_eq!(param.parse(None), Ok(Some(DEFAULT_REQUEST_TIMEOUT)));
+        assert_eq!(param.parse(Some("100".into())), Ok(Some(Duration::from_millis(100))));
+        assert_eq!(param.parse(Some("0".into())), Ok(None));
+        assert!(param.parse(Some("1s".into())).is_err());
     }
 }
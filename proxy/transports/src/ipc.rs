@@ -19,10 +19,9 @@
 
 use std::{io, sync::Arc};
 
+use crate::{Client, TransportKind};
 use jsonrpc_ipc_server as ipc;
 use params::Param;
-use pubsub;
-use rpc;
 
 const CATEGORY: &str = "IPC Server";
 const PREFIX: &str = "ipc";
@@ -61,13 +60,19 @@ where
 pub fn start<T, M, S>(params: Vec<Box<dyn Configurator<M, S>>>, io: T) -> io::Result<ipc::Server>
 where
     T: Into<rpc::MetaIoHandler<M, S>>,
-    M: rpc::Metadata + Default + From<Option<Arc<pubsub::Session>>>,
+    M: rpc::Metadata + From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)>,
     S: rpc::Middleware<M>,
     S::Future: Unpin,
     S::CallFuture: Unpin,
 {
+    // Local clients have no address, so they are told apart by the connection.
     let mut builder = ipc::ServerBuilder::with_meta_extractor(io, |context: &ipc::RequestContext| {
-        Some(Arc::new(pubsub::Session::new(context.sender.clone()))).into()
+        (
+            TransportKind::Ipc,
+            Some(Arc::new(pubsub::Session::new(context.sender.clone()))),
+            Some(crate::next_connection()),
+        )
+            .into()
     });
     // should be overwritten by parameters anyway
     let mut path = "./jsonrpc.ipc".to_owned();
@@ -122,5 +127,10 @@ where
         description: description.replace('\n', " "),
         default_value: default_value.into(),
         parser: Box::new(move |val: String| Ok(Box::new(parser(val)?) as _)),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
     }
 }
@@ -24,12 +24,19 @@ extern crate cli_params as params;
 extern crate jsonrpc_core as rpc;
 extern crate jsonrpc_pubsub as pubsub;
 
-extern crate jsonrpc_http_server;
-extern crate jsonrpc_ipc_server;
-extern crate jsonrpc_tcp_server;
-extern crate jsonrpc_ws_server;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub mod http;
 pub mod ipc;
 pub mod tcp;
+#[cfg(unix)]
+pub mod unix;
 pub mod ws;
+
+pub use request_meta::{Client, TransportKind};
+
+/// Identifies a new connection of a client which address is not known.
+fn next_connection() -> Client {
+    static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(0);
+    Client::Connection(NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed))
+}
@@ -18,19 +18,65 @@
 //! TCP server for the proxy.
 
 use std::{
+    future::Future,
     io,
-    net::{Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    num::NonZeroU32,
     sync::Arc,
+    time::Duration,
 };
 
+use crate::{Client, TransportKind};
+use dashmap::DashMap;
 use jsonrpc_tcp_server as tcp;
 use params::Param;
-use pubsub;
-use rpc;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::{Mutex, Semaphore},
+    time,
+};
 
 const CATEGORY: &str = "TCP Server";
 const PREFIX: &str = "tcp";
 
+/// Default maximal number of connections waiting to be accepted when the accept rate is limited.
+pub const DEFAULT_ACCEPT_QUEUE_DEPTH: usize = 128;
+
+/// Time to wait before accepting again after a failure (e.g. too many open files).
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+/// Time to discard remaining data of a connection closed because of an oversized request.
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Response sent before closing a connection with an oversized request.
+const PARSE_ERROR: &str = r#"{"jsonrpc":"2.0","error":{"code":-32700,"message":"Parse error"},"id":null}"#;
+
+/// TCP server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Listening address.
+    pub address: SocketAddr,
+    /// Maximal number of connections accepted per second (unlimited if `None`).
+    pub max_accept_rate: Option<NonZeroU32>,
+    /// Maximal number of connections waiting to be accepted, the ones above are dropped.
+    pub accept_queue_depth: usize,
+    /// Byte separating requests (`None` if the boundaries are detected by the parser).
+    pub request_separator: Option<u8>,
+    /// Maximal size of a single request in bytes (unlimited if `None`).
+    pub max_payload: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:9955".parse().unwrap(),
+            max_accept_rate: None,
+            accept_queue_depth: DEFAULT_ACCEPT_QUEUE_DEPTH,
+            request_separator: Some(b'\n'),
+            max_payload: None,
+        }
+    }
+}
+
 /// Returns CLI configuration options for the TCP server.
 pub fn params<M, S>() -> Vec<Param<Box<dyn Configurator<M, S>>>>
 where
@@ -42,15 +88,15 @@ where
     vec![
         param("port", "9955", "Configures TCP server listening port.", |value| {
             let port: u16 = value.parse().map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-            Ok(move |address: &mut SocketAddr, builder| {
-                address.set_port(port);
+            Ok(move |config: &mut Config, builder| {
+                config.address.set_port(port);
                 Ok(builder)
             })
         }),
         param("ip", "127.0.0.1", "Configures TCP server interface.", |value| {
             let ip: Ipv4Addr = value.parse().map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-            Ok(move |address: &mut SocketAddr, builder| {
-                address.set_ip(ip.into());
+            Ok(move |config: &mut Config, builder| {
+                config.address.set_ip(ip.into());
                 Ok(builder)
             })
         }),
@@ -61,36 +107,264 @@ where
                     "none" => tcp::Separator::Empty,
                     _ => tcp::Separator::Byte(value.parse().map_err(|e| format!("Invalid separator code {}: {}", value, e))?),
                 };
-                Ok(move |_address: &mut SocketAddr, builder: tcp::ServerBuilder<M, S>| {
+                Ok(move |config: &mut Config, builder: tcp::ServerBuilder<M, S>| {
+                    config.request_separator = match separator {
+                        tcp::Separator::Byte(byte) => Some(byte),
+                        tcp::Separator::Empty => None,
+                    };
                     Ok(builder.request_separators(separator.clone(), separator.clone()))
                 })
             }
         ),
+        param("max-accept-rate", "unlimited",
+            "Configures maximal number of TCP connections accepted per second. If limited, the connections are forwarded to the server over a local port, which is not limited and can be reached by other local processes.",
+            |value| {
+                let rate = match value.as_str() {
+                    "unlimited" => None,
+                    _ => Some(value.parse::<NonZeroU32>().map_err(|e| format!("Invalid accept rate {}: {}", value, e))?),
+                };
+                Ok(move |config: &mut Config, builder| {
+                    config.max_accept_rate = rate;
+                    Ok(builder)
+                })
+            }
+        ),
+        param("accept-queue-depth", "128",
+            "Configures maximal number of TCP connections waiting to be accepted if the accept rate is limited. Connections above that are dropped.",
+            |value| {
+                let depth: usize = value.parse().map_err(|e| format!("Invalid queue depth {}: {}", value, e))?;
+                Ok(move |config: &mut Config, builder| {
+                    config.accept_queue_depth = depth;
+                    Ok(builder)
+                })
+            }
+        ),
+        param("max-payload", "unlimited",
+            "Maximal size of a single TCP request in Megabytes. Connections sending larger requests are closed with a parse error. If limited, the connections are forwarded to the server over a local port, which is not limited and can be reached by other local processes.",
+            |value| {
+                let max_payload = match value.as_str() {
+                    "unlimited" => None,
+                    _ => Some(value.parse::<usize>().map_err(|e| format!("Invalid maximal payload size ({}): {}", value, e))?),
+                };
+                Ok(move |config: &mut Config, builder| {
+                    config.max_payload = max_payload.map(|mb| mb * 1024 * 1024);
+                    Ok(builder)
+                })
+            }
+        ),
     ]
 }
 
 /// Starts TCP server on given handler.
+///
+/// If the accept rate or the payload size is limited the limiter runs on the current tokio runtime.
+///
+/// The limited connections are forwarded to the actual server listening on a random port of `127.0.0.1`,
+/// the server still sees the addresses of the original clients.
+/// That port is not limited, so it should not be reachable by untrusted local processes. It's picked right
+/// before the server starts, so starting fails if another process takes it in between.
 pub fn start<T, M, S>(params: Vec<Box<dyn Configurator<M, S>>>, io: T) -> io::Result<tcp::Server>
 where
     T: Into<rpc::MetaIoHandler<M, S>>,
-    M: rpc::Metadata + Default + From<Option<Arc<pubsub::Session>>>,
+    M: rpc::Metadata + From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)>,
     S: rpc::Middleware<M>,
     S::Future: Unpin,
     S::CallFuture: Unpin,
 {
-    let mut builder = tcp::ServerBuilder::with_meta_extractor(io, |context: &tcp::RequestContext| {
-        Some(Arc::new(pubsub::Session::new(context.sender.clone()))).into()
+    // Addresses of clients of the forwarded connections, keyed by the local address they are forwarded from.
+    let peers = Arc::new(DashMap::<SocketAddr, IpAddr>::new());
+    let mut builder = tcp::ServerBuilder::with_meta_extractor(io, {
+        let peers = peers.clone();
+        move |context: &tcp::RequestContext| {
+            let ip = peers
+                .get(&context.peer_addr)
+                .map_or_else(|| context.peer_addr.ip(), |ip| *ip);
+            (
+                TransportKind::Tcp,
+                Some(Arc::new(pubsub::Session::new(context.sender.clone()))),
+                Some(Client::Ip(ip)),
+            )
+                .into()
+        }
     });
     // should be overwritten by parameters anyway
-    let mut address = "127.0.0.1:9955".parse().unwrap();
+    let mut config = Config::default();
     // configure the server
     for p in params {
-        builder = p.configure(&mut address, builder)?;
+        builder = p.configure(&mut config, builder)?;
     }
 
-    println!("TCP listening on {}", address);
+    println!("TCP listening on {}", config.address);
+
+    if config.max_accept_rate.is_none() && config.max_payload.is_none() {
+        return builder.start(&config.address);
+    }
+
+    // `jsonrpc_tcp_server` doesn't allow to intercept incoming connections nor limit the size of requests,
+    // so the server listens on a local port and accepted connections are forwarded to it.
+    let runtime = tokio::runtime::Handle::try_current().map_err(io::Error::other)?;
+    let listener = std::net::TcpListener::bind(config.address)?;
+    listener.set_nonblocking(true)?;
+    let server_address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server = builder.start(&server_address)?;
+    log::info!("TCP connections are forwarded to the server at {}", server_address);
+    let listener = {
+        let _guard = runtime.enter();
+        TcpListener::from_std(listener)?
+    };
+
+    // requests are split on new lines if the parser detects their boundaries
+    let separator = config.request_separator.unwrap_or(b'\n');
+    let max_payload = config.max_payload;
+    runtime.spawn(accept_limited(
+        listener,
+        config.max_accept_rate,
+        config.accept_queue_depth,
+        move |mut inbound| {
+            let peers = peers.clone();
+            async move {
+                let mut outbound = match connect_as(&inbound, server_address, &peers).await {
+                    Ok(outbound) => outbound,
+                    Err(e) => {
+                        log::debug!("Unable to forward TCP connection: {:?}", e);
+                        return;
+                    }
+                };
+                let local_address = outbound.local_addr();
+                let result = match max_payload {
+                    Some(max_payload) => forward_limited(inbound, outbound, max_payload, separator).await,
+                    None => tokio::io::copy_bidirectional(&mut inbound, &mut outbound)
+                        .await
+                        .map(|_| ()),
+                };
+                if let Ok(address) = local_address {
+                    peers.remove(&address);
+                }
+                if let Err(e) = result {
+                    log::debug!("TCP connection closed: {:?}", e);
+                }
+            }
+        },
+    ));
+
+    Ok(server)
+}
 
-    builder.start(&address)
+/// Connects to the server on behalf of the client of `inbound` connection.
+///
+/// The client address is recorded in `peers` under the local address of the new connection
+/// before connecting, so that it's known once the server accepts the connection.
+async fn connect_as(
+    inbound: &TcpStream,
+    server_address: SocketAddr,
+    peers: &DashMap<SocketAddr, IpAddr>,
+) -> io::Result<TcpStream> {
+    let peer = inbound.peer_addr()?;
+    let socket = TcpSocket::new_v4()?;
+    socket.bind((Ipv4Addr::LOCALHOST, 0).into())?;
+    let local_address = socket.local_addr()?;
+    peers.insert(local_address, peer.ip());
+    socket.connect(server_address).await.inspect_err(|_| {
+        peers.remove(&local_address);
+    })
+}
+
+/// Accepts connections at most `max_accept_rate` per second (if limited) and passes them to `handle`.
+///
+/// Up to `queue_depth` connections wait for their turn, connections above that are dropped.
+async fn accept_limited<F, R>(listener: TcpListener, max_accept_rate: Option<NonZeroU32>, queue_depth: usize, handle: F)
+where
+    F: Fn(TcpStream) -> R + Clone + Send + 'static,
+    R: Future<Output = ()> + Send + 'static,
+{
+    let queue = Arc::new(Semaphore::new(queue_depth));
+    let interval = max_accept_rate.map(|max_accept_rate| {
+        let mut interval = time::interval(Duration::from_secs(1) / max_accept_rate.get());
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        Arc::new(Mutex::new(interval))
+    });
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::warn!("Unable to accept TCP connection: {:?}", e);
+                // Errors like running out of file descriptors persist for a while, don't spin.
+                time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+
+        let permit = match queue.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log::warn!("TCP accept queue is full, dropping connection from {}", peer);
+                continue;
+            }
+        };
+
+        let interval = interval.clone();
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            if let Some(interval) = interval {
+                interval.lock().await.tick().await;
+            }
+            drop(permit);
+            handle(stream).await
+        });
+    }
+}
+
+/// Forwards data between the connections, closing them with a parse error once a single request
+/// (bytes between `separator`s) sent over `inbound` exceeds `max_payload` bytes.
+async fn forward_limited(inbound: TcpStream, outbound: TcpStream, max_payload: usize, separator: u8) -> io::Result<()> {
+    let (mut inbound_read, inbound_write) = inbound.into_split();
+    let (mut outbound_read, mut outbound_write) = outbound.into_split();
+    let inbound_write = Arc::new(Mutex::new(inbound_write));
+
+    let responses = tokio::spawn({
+        let inbound_write = inbound_write.clone();
+        async move {
+            let mut buf = vec![0; 8 * 1024];
+            loop {
+                let read = outbound_read.read(&mut buf).await?;
+                if read == 0 {
+                    return inbound_write.lock().await.shutdown().await;
+                }
+                inbound_write.lock().await.write_all(&buf[..read]).await?;
+            }
+        }
+    });
+
+    let mut buf = vec![0; 8 * 1024];
+    let mut request_size = 0;
+    loop {
+        let read = inbound_read.read(&mut buf).await?;
+        if read == 0 {
+            outbound_write.shutdown().await?;
+            return responses.await.map_err(io::Error::other)?;
+        }
+
+        for byte in &buf[..read] {
+            request_size = if *byte == separator { 0 } else { request_size + 1 };
+            if request_size > max_payload {
+                log::warn!("TCP request exceeds {} bytes, closing connection.", max_payload);
+                responses.abort();
+                let mut inbound_write = inbound_write.lock().await;
+                inbound_write.write_all(PARSE_ERROR.as_bytes()).await?;
+                inbound_write.write_all(&[separator]).await?;
+                inbound_write.shutdown().await?;
+                // closing the connection with unread data would reset it before the client reads the error
+                let _ = time::timeout(
+                    LINGER_TIMEOUT,
+                    tokio::io::copy(&mut inbound_read, &mut tokio::io::sink()),
+                )
+                .await;
+                return Ok(());
+            }
+        }
+        outbound_write.write_all(&buf[..read]).await?;
+    }
 }
 
 /// Configures the TCP server.
@@ -100,25 +374,22 @@ where
     S: rpc::Middleware<M>,
 {
     /// Configure the server.
-    fn configure(
-        &self,
-        address: &mut SocketAddr,
-        builder: tcp::ServerBuilder<M, S>,
-    ) -> io::Result<tcp::ServerBuilder<M, S>>;
+    fn configure(&self, config: &mut Config, builder: tcp::ServerBuilder<M, S>)
+        -> io::Result<tcp::ServerBuilder<M, S>>;
 }
 
 impl<F, M, S> Configurator<M, S> for F
 where
-    F: Fn(&mut SocketAddr, tcp::ServerBuilder<M, S>) -> io::Result<tcp::ServerBuilder<M, S>>,
+    F: Fn(&mut Config, tcp::ServerBuilder<M, S>) -> io::Result<tcp::ServerBuilder<M, S>>,
     M: rpc::Metadata,
     S: rpc::Middleware<M>,
 {
     fn configure(
         &self,
-        address: &mut SocketAddr,
+        config: &mut Config,
         builder: tcp::ServerBuilder<M, S>,
     ) -> io::Result<tcp::ServerBuilder<M, S>> {
-        (*self)(address, builder)
+        (*self)(config, builder)
     }
 }
 
@@ -142,5 +413,113 @@ where
         description: description.replace('\n', " "),
         default_value: default_value.into(),
         parser: Box::new(move |val: String| Ok(Box::new(parser(val)?) as _)),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::AsyncBufReadExt;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn should_limit_accept_rate() {
+        // given
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let accepted2 = accepted.clone();
+        tokio::spawn(accept_limited(listener, NonZeroU32::new(100), 10, move |_stream| {
+            accepted2.fetch_add(1, Ordering::SeqCst);
+            async {}
+        }));
+        let start = std::time::Instant::now();
+
+        // when
+        let mut connected = 0;
+        for _ in 0..1000 {
+            if TcpStream::connect(address).await.is_ok() {
+                connected += 1;
+            }
+        }
+        time::sleep(Duration::from_millis(200)).await;
+        let elapsed = start.elapsed();
+        let accepted = accepted.load(Ordering::SeqCst);
+
+        // then
+        let max_accepted = (elapsed.as_secs_f64() * 100.0) as usize + 1;
+        assert_eq!(connected, 1000);
+        assert!(accepted > 0);
+        assert!(
+            accepted <= max_accepted,
+            "Accepted {} connections within {:?}, expected at most {}.",
+            accepted,
+            elapsed,
+            max_accepted
+        );
+    }
+
+    #[tokio::test]
+    async fn should_record_client_address_of_forwarded_connection() {
+        // given
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (inbound, _) = listener.accept().await.unwrap();
+        let peers = DashMap::new();
+
+        // when
+        let _outbound = connect_as(&inbound, server.local_addr().unwrap(), &peers)
+            .await
+            .unwrap();
+        let (_, forwarded_from) = server.accept().await.unwrap();
+
+        // then
+        assert_eq!(
+            peers.get(&forwarded_from).map(|ip| *ip),
+            Some(client.local_addr().unwrap().ip())
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn should_close_connection_sending_oversized_request() {
+        // given
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_address = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            // echoes complete requests back
+            let (stream, _) = server.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = tokio::io::BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                write.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (inbound, _) = listener.accept().await.unwrap();
+            let outbound = TcpStream::connect(server_address).await.unwrap();
+            forward_limited(inbound, outbound, 1024, b'\n').await.unwrap();
+        });
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let request = format!("{}\n", "x".repeat(1024));
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut echo = vec![0; request.len()];
+        client.read_exact(&mut echo).await.unwrap();
+
+        // when
+        let _ = client.write_all("y".repeat(2048).as_bytes()).await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+
+        // then
+        assert_eq!(echo, request.as_bytes());
+        assert_eq!(response, format!("{}\n", PARSE_ERROR));
     }
 }
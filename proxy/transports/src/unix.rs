@@ -0,0 +1,329 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Unix domain socket server for the proxy.
+//!
+//! Runs the same server as `ipc`, but additionally restricts access to the socket file
+//! by setting its permissions and group ownership right after binding.
+
+use std::{ffi::CString, io, sync::Arc};
+
+use crate::{Client, TransportKind};
+use jsonrpc_ipc_server as ipc;
+use params::Param;
+
+const CATEGORY: &str = "Unix Socket Server";
+const PREFIX: &str = "unix-socket";
+
+/// Socket file options.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Options {
+    /// Socket path, the server is not started if `None`.
+    pub path: Option<String>,
+    /// Permissions of the socket file.
+    pub mode: Option<libc::mode_t>,
+    /// Group owning the socket file.
+    pub group: Option<libc::gid_t>,
+}
+
+/// Returns CLI configuration options for the Unix socket server.
+pub fn params<M, S>() -> Vec<Param<Box<dyn Configurator<M, S>>>>
+where
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+    S::Future: Unpin,
+    S::CallFuture: Unpin,
+{
+    vec![
+        param(
+            "path",
+            "-",
+            "Configures Unix domain socket server path. The server is disabled if \"-\".",
+            |value| {
+                let path = match value.as_str() {
+                    "-" => None,
+                    _ => Some(value),
+                };
+                Ok(move |options: &mut Options, builder| {
+                    options.path = path.clone();
+                    Ok(builder)
+                })
+            },
+        ),
+        param(
+            "mode",
+            "-",
+            "Permissions of the socket file as an octal mode string, e.g. \"0660\". Umask defaults are kept if \"-\".",
+            |value| {
+                let mode = match value.as_str() {
+                    "-" => None,
+                    _ => Some(parse_mode(&value)?),
+                };
+                Ok(move |options: &mut Options, builder| {
+                    options.mode = mode;
+                    Ok(builder)
+                })
+            },
+        ),
+        param(
+            "group",
+            "-",
+            "Group (name or numeric id) owning the socket file. Ownership is not changed if \"-\".",
+            |value| {
+                let group = match value.as_str() {
+                    "-" => None,
+                    _ => Some(parse_group(&value)?),
+                };
+                Ok(move |options: &mut Options, builder| {
+                    options.group = group;
+                    Ok(builder)
+                })
+            },
+        ),
+    ]
+}
+
+fn parse_mode(value: &str) -> Result<libc::mode_t, String> {
+    let mode = libc::mode_t::from_str_radix(value, 8).map_err(|e| format!("Invalid socket mode {}: {}", value, e))?;
+    if mode > 0o7777 {
+        return Err(format!("Invalid socket mode {}: out of range", value));
+    }
+    Ok(mode)
+}
+
+fn parse_group(value: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = value.parse() {
+        return Ok(gid);
+    }
+
+    let name = CString::new(value).map_err(|e| format!("Invalid group name {}: {}", value, e))?;
+    // Only called while parsing CLI parameters, so the static buffer of `getgrnam` is not shared.
+    let group = unsafe { libc::getgrnam(name.as_ptr()) };
+    if group.is_null() {
+        return Err(format!("Unknown group: {}", value));
+    }
+    Ok(unsafe { (*group).gr_gid })
+}
+
+/// Starts Unix socket server on given handler.
+///
+/// Returns `None` if the socket path is not configured.
+pub fn start<T, M, S>(params: Vec<Box<dyn Configurator<M, S>>>, io: T) -> io::Result<Option<ipc::Server>>
+where
+    T: Into<rpc::MetaIoHandler<M, S>>,
+    M: rpc::Metadata + From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)>,
+    S: rpc::Middleware<M>,
+    S::Future: Unpin,
+    S::CallFuture: Unpin,
+{
+    // Local clients have no address, so they are told apart by the connection.
+    let mut builder = ipc::ServerBuilder::with_meta_extractor(io, |context: &ipc::RequestContext| {
+        (
+            TransportKind::Ipc,
+            Some(Arc::new(pubsub::Session::new(context.sender.clone()))),
+            Some(crate::next_connection()),
+        )
+            .into()
+    });
+    let mut options = Options::default();
+    // configure the server
+    for p in params {
+        builder = p.configure(&mut options, builder)?;
+    }
+
+    let path = match options.path {
+        Some(ref path) => path.clone(),
+        None => return Ok(None),
+    };
+    let server = builder.start(&path)?;
+    if let Err(e) = set_permissions(&path, &options) {
+        server.close();
+        return Err(e);
+    }
+
+    println!("Unix socket listening at {}", path);
+
+    Ok(Some(server))
+}
+
+fn set_permissions(path: &str, options: &Options) -> io::Result<()> {
+    let path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    if let Some(mode) = options.mode {
+        if unsafe { libc::chmod(path.as_ptr(), mode) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(group) = options.group {
+        // `-1` leaves the owner unchanged.
+        if unsafe { libc::chown(path.as_ptr(), libc::uid_t::MAX, group) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Configures the Unix socket server.
+pub trait Configurator<M, S>
+where
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+{
+    /// Configure the server.
+    fn configure(
+        &self,
+        options: &mut Options,
+        builder: ipc::ServerBuilder<M, S>,
+    ) -> io::Result<ipc::ServerBuilder<M, S>>;
+}
+
+impl<F, M, S> Configurator<M, S> for F
+where
+    F: Fn(&mut Options, ipc::ServerBuilder<M, S>) -> io::Result<ipc::ServerBuilder<M, S>>,
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+{
+    fn configure(
+        &self,
+        options: &mut Options,
+        builder: ipc::ServerBuilder<M, S>,
+    ) -> io::Result<ipc::ServerBuilder<M, S>> {
+        (*self)(options, builder)
+    }
+}
+
+fn param<M, S, F, X>(
+    name: &str,
+    default_value: &str,
+    description: &str,
+    parser: F,
+) -> Param<Box<dyn Configurator<M, S>>>
+where
+    F: Fn(String) -> Result<X, String> + 'static,
+    X: Configurator<M, S> + 'static,
+    M: rpc::Metadata,
+    S: rpc::Middleware<M>,
+    S::Future: Unpin,
+    S::CallFuture: Unpin,
+{
+    Param {
+        category: CATEGORY.into(),
+        name: format!("{}-{}", PREFIX, name),
+        description: description.replace('\n', " "),
+        default_value: default_value.into(),
+        parser: Box::new(move |val: String| Ok(Box::new(parser(val)?) as _)),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[derive(Clone, Default)]
+    struct Meta;
+    impl rpc::Metadata for Meta {}
+    impl From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)> for Meta {
+        fn from(_: (TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)) -> Self {
+            Meta
+        }
+    }
+
+    fn configure(values: &[(&str, &str)]) -> Vec<Box<dyn Configurator<Meta, rpc::NoopMiddleware>>> {
+        params()
+            .iter()
+            .map(|p| {
+                let value = values
+                    .iter()
+                    .find(|(name, _)| p.name == format!("{}-{}", PREFIX, name))
+                    .map(|(_, value)| value.to_string());
+                p.parse(value).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_parse_octal_mode() {
+        assert_eq!(parse_mode("0660"), Ok(0o660));
+        assert_eq!(parse_mode("600"), Ok(0o600));
+        assert!(parse_mode("0980").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn should_not_start_without_path() {
+        // when
+        let server = start(configure(&[]), rpc::MetaIoHandler::<Meta>::default()).unwrap();
+
+        // then
+        assert!(server.is_none());
+    }
+
+    #[test]
+    fn should_create_socket_with_given_permissions() {
+        // given
+        let path = ::std::env::temp_dir().join(format!("unix-socket-{}.ipc", ::std::process::id()));
+        let path = path.display().to_string();
+        let gid = unsafe { libc::getgid() }.to_string();
+        let params = configure(&[("path", &path), ("mode", "0640"), ("group", &gid)]);
+
+        // when
+        let server = start(params, rpc::MetaIoHandler::<Meta>::default()).unwrap().unwrap();
+
+        // then
+        let metadata = ::std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(metadata.gid().to_string(), gid);
+        server.close();
+    }
+
+    #[test]
+    fn should_respond_to_calls_over_socket() {
+        use std::io::{BufRead, Write};
+
+        // given
+        let path = ::std::env::temp_dir().join(format!("unix-socket-call-{}.ipc", ::std::process::id()));
+        let path = path.display().to_string();
+        let mut io = rpc::MetaIoHandler::<Meta>::default();
+        io.add_method("hello", |_| {
+            rpc::futures::future::ready(Ok(rpc::Value::String("world".into())))
+        });
+        let server = start(configure(&[("path", &path)]), io).unwrap().unwrap();
+        let mut client = ::std::os::unix::net::UnixStream::connect(&path).unwrap();
+        client
+            .set_read_timeout(Some(::std::time::Duration::from_secs(5)))
+            .unwrap();
+
+        // when
+        client
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"hello\"}\n")
+            .unwrap();
+        let mut response = String::new();
+        ::std::io::BufReader::new(&client).read_line(&mut response).unwrap();
+
+        // then
+        assert_eq!(response.trim_end(), r#"{"jsonrpc":"2.0","result":"world","id":1}"#);
+        server.close();
+    }
+}
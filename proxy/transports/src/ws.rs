@@ -20,16 +20,38 @@
 use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
+    time::{Duration, Instant},
 };
 
+use crate::{Client, TransportKind};
+use dashmap::DashMap;
 use jsonrpc_ws_server as ws;
 use params::Param;
-use pubsub;
-use rpc;
 
 const CATEGORY: &str = "WebSockets Server";
 const PREFIX: &str = "websockets";
 
+/// WebSockets server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Listening address.
+    pub address: SocketAddr,
+    /// Maximal number of connections opened by a single origin within `origin_rate_limit_window`.
+    pub max_connections_per_origin: usize,
+    /// Period in which connections of every origin are counted.
+    pub origin_rate_limit_window: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: "127.0.0.1:9945".parse().unwrap(),
+            max_connections_per_origin: 50,
+            origin_rate_limit_window: Duration::from_secs(1),
+        }
+    }
+}
+
 /// Returns CLI configuration options for the WS server.
 pub fn params<M, S>() -> Vec<Param<Box<dyn Configurator<M, S>>>>
 where
@@ -47,8 +69,8 @@ where
                 let port: u16 = value
                     .parse()
                     .map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-                Ok(move |address: &mut SocketAddr, builder| {
-                    address.set_port(port);
+                Ok(move |config: &mut Config, builder| {
+                    config.address.set_port(port);
                     Ok(builder)
                 })
             },
@@ -57,8 +79,8 @@ where
             let ip: Ipv4Addr = value
                 .parse()
                 .map_err(|e| format!("Invalid port number {}: {}", value, e))?;
-            Ok(move |address: &mut SocketAddr, builder| {
-                address.set_ip(ip.into());
+            Ok(move |config: &mut Config, builder| {
+                config.address.set_ip(ip.into());
                 Ok(builder)
             })
         }),
@@ -76,7 +98,7 @@ options: "all", "none"."#,
                     "*" | "all" | "any" => None,
                     _ => Some(value.split(',').map(Into::into).collect()),
                 };
-                Ok(move |_address: &mut SocketAddr, builder: ws::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: ws::ServerBuilder<M, S>| {
                     Ok(builder.allowed_hosts(hosts.clone().into()))
                 })
             },
@@ -94,7 +116,7 @@ options: "all", "none". "#,
                     _ => Some(value.split(',').map(Into::into).collect()),
                 };
 
-                Ok(move |_address: &mut SocketAddr, builder: ws::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: ws::ServerBuilder<M, S>| {
                     Ok(builder.allowed_origins(origins.clone().into()))
                 })
             },
@@ -107,11 +129,52 @@ options: "all", "none". "#,
                 let max_connections: usize = value
                     .parse()
                     .map_err(|e| format!("Invalid number of connections {}: {}", value, e))?;
-                Ok(move |_address: &mut SocketAddr, builder: ws::ServerBuilder<M, S>| {
+                Ok(move |_config: &mut Config, builder: ws::ServerBuilder<M, S>| {
                     Ok(builder.max_connections(max_connections))
                 })
             },
         ),
+        param(
+            "max-payload",
+            "10",
+            "Maximal size of a WebSockets message in Megabytes. Connections sending larger messages are closed.",
+            |value| {
+                let max_payload: usize = value
+                    .parse()
+                    .map_err(|e| format!("Invalid maximal payload size ({}): {}", value, e))?;
+                Ok(move |_config: &mut Config, builder: ws::ServerBuilder<M, S>| {
+                    Ok(builder.max_payload(max_payload * 1024 * 1024))
+                })
+            },
+        ),
+        param(
+            "max-connections-per-origin",
+            "50",
+            "Maximal number of WebSockets connections a single origin can open within the rate limit window. Further connections are rejected with 429 status.",
+            |value| {
+                let max_connections: usize = value
+                    .parse()
+                    .map_err(|e| format!("Invalid number of connections {}: {}", value, e))?;
+                Ok(move |config: &mut Config, builder| {
+                    config.max_connections_per_origin = max_connections;
+                    Ok(builder)
+                })
+            },
+        ),
+        param(
+            "origin-rate-limit-window",
+            "1000",
+            "Period (in milliseconds) in which WebSockets connections of every origin are counted.",
+            |value| {
+                let window: u64 = value
+                    .parse()
+                    .map_err(|e| format!("Invalid rate limit window {}: {}", value, e))?;
+                Ok(move |config: &mut Config, builder| {
+                    config.origin_rate_limit_window = Duration::from_millis(window);
+                    Ok(builder)
+                })
+            },
+        ),
     ]
 }
 
@@ -119,24 +182,95 @@ options: "all", "none". "#,
 pub fn start<T, M, S>(params: Vec<Box<dyn Configurator<M, S>>>, io: T) -> ws::Result<ws::Server>
 where
     T: Into<rpc::MetaIoHandler<M, S>>,
-    M: rpc::Metadata + Default + From<Option<Arc<pubsub::Session>>>,
+    M: rpc::Metadata + From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)>,
     S: rpc::Middleware<M>,
     S::Future: Unpin,
     S::CallFuture: Unpin,
 {
+    // The server doesn't expose the address of the client, so it's told apart by the connection.
     let mut builder = ws::ServerBuilder::with_meta_extractor(io, |context: &ws::RequestContext| {
-        Some(Arc::new(pubsub::Session::new(context.sender()))).into()
+        (
+            TransportKind::WebSocket,
+            Some(Arc::new(pubsub::Session::new(context.sender()))),
+            Some(crate::next_connection()),
+        )
+            .into()
     });
     // should be overwritten by parameters anyway
-    let mut address = "127.0.0.1:9945".parse().unwrap();
+    let mut config = Config::default();
     // configure the server
     for p in params {
-        builder = p.configure(&mut address, builder)?;
+        builder = p.configure(&mut config, builder)?;
+    }
+
+    println!("WS listening on {}", config.address);
+
+    builder
+        .request_middleware(OriginRateLimit::new(
+            config.max_connections_per_origin,
+            config.origin_rate_limit_window,
+        ))
+        .start(&config.address)
+}
+
+/// Rejects WebSocket handshakes of origins that opened too many connections recently.
+///
+/// Connections are counted per `Origin` header within fixed windows, the count is reset
+/// with the first connection after the window elapses. Handshakes without `Origin` header
+/// don't come from browsers and are not limited.
+struct OriginRateLimit {
+    max_connections: usize,
+    window: Duration,
+    origins: DashMap<String, (usize, Instant)>,
+}
+
+impl OriginRateLimit {
+    fn new(max_connections: usize, window: Duration) -> Self {
+        OriginRateLimit {
+            max_connections,
+            window,
+            origins: Default::default(),
+        }
     }
 
-    println!("WS listening on {}", address);
+    /// Records a connection of given origin, returns `false` if the limit is exceeded.
+    fn check(&self, origin: &str, now: Instant) -> bool {
+        if !self.origins.contains_key(origin) {
+            // Forget origins that didn't connect within the window to keep the map bounded.
+            let window = self.window;
+            self.origins
+                .retain(|_, (_, start)| now.saturating_duration_since(*start) < window);
+        }
 
-    builder.start(&address)
+        let mut entry = self.origins.entry(origin.to_owned()).or_insert((0, now));
+        let (ref mut count, ref mut start) = *entry;
+        if now.saturating_duration_since(*start) >= self.window {
+            *count = 0;
+            *start = now;
+        }
+        *count += 1;
+        *count <= self.max_connections
+    }
+}
+
+impl ws::RequestMiddleware for OriginRateLimit {
+    fn process(&self, request: &ws::ws::Request) -> ws::MiddlewareAction {
+        let origin = match request.header("origin") {
+            Some(origin) => String::from_utf8_lossy(origin),
+            None => return ws::MiddlewareAction::Proceed,
+        };
+
+        if self.check(&origin, Instant::now()) {
+            ws::MiddlewareAction::Proceed
+        } else {
+            log::warn!("Too many WebSockets connections from {}, rejecting.", origin);
+            ws::MiddlewareAction::Respond {
+                response: ws::ws::Response::new(429, "Too Many Requests", b"Too many connections.\n".to_vec()),
+                validate_origin: true,
+                validate_hosts: true,
+            }
+        }
+    }
 }
 
 /// Configures the WS server.
@@ -146,25 +280,17 @@ where
     S: rpc::Middleware<M>,
 {
     /// Configure the server.
-    fn configure(
-        &self,
-        address: &mut SocketAddr,
-        builder: ws::ServerBuilder<M, S>,
-    ) -> ws::Result<ws::ServerBuilder<M, S>>;
+    fn configure(&self, config: &mut Config, builder: ws::ServerBuilder<M, S>) -> ws::Result<ws::ServerBuilder<M, S>>;
 }
 
 impl<F, M, S> Configurator<M, S> for F
 where
-    F: Fn(&mut SocketAddr, ws::ServerBuilder<M, S>) -> ws::Result<ws::ServerBuilder<M, S>>,
+    F: Fn(&mut Config, ws::ServerBuilder<M, S>) -> ws::Result<ws::ServerBuilder<M, S>>,
     M: rpc::Metadata,
     S: rpc::Middleware<M>,
 {
-    fn configure(
-        &self,
-        address: &mut SocketAddr,
-        builder: ws::ServerBuilder<M, S>,
-    ) -> ws::Result<ws::ServerBuilder<M, S>> {
-        (*self)(address, builder)
+    fn configure(&self, config: &mut Config, builder: ws::ServerBuilder<M, S>) -> ws::Result<ws::ServerBuilder<M, S>> {
+        (*self)(config, builder)
     }
 }
 
@@ -188,5 +314,117 @@ where
         description: description.replace('\n', " "),
         default_value: default_value.into(),
         parser: Box::new(move |val: String| Ok(Box::new(parser(val)?) as _)),
+        env_prefix: params::DEFAULT_ENV_PREFIX.into(),
+        hidden: false,
+        multiple: false,
+        deprecated: None,
+        deprecated_aliases: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use websocket::{ClientBuilder, OwnedMessage};
+
+    #[derive(Clone, Default)]
+    struct Meta;
+    impl rpc::Metadata for Meta {}
+    impl From<(TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)> for Meta {
+        fn from(_: (TransportKind, Option<Arc<pubsub::Session>>, Option<Client>)) -> Self {
+            Meta
+        }
+    }
+
+    fn configure(values: &[(&str, &str)]) -> Vec<Box<dyn Configurator<Meta, rpc::NoopMiddleware>>> {
+        params()
+            .iter()
+            .map(|p| {
+                let value = values
+                    .iter()
+                    .find(|(name, _)| p.name == format!("{}-{}", PREFIX, name))
+                    .map(|(_, value)| value.to_string());
+                p.parse(value).unwrap()
+            })
+            .collect()
+    }
+
+    fn call(size: usize) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"echo","params":["{}"]}}"#,
+            "x".repeat(size)
+        )
+    }
+
+    #[test]
+    fn should_close_connection_sending_oversized_message() {
+        // given
+        let mut io = rpc::MetaIoHandler::<Meta>::default();
+        io.add_method("echo", |_params| {
+            rpc::futures::future::ready(Ok(rpc::Value::Bool(true)))
+        });
+        let server = start(configure(&[("port", "0"), ("hosts", "all"), ("max-payload", "1")]), io).unwrap();
+        let url = format!("ws://{}", server.addr());
+        let mut client = ClientBuilder::new(&url).unwrap().connect_insecure().unwrap();
+        client.send_message(&OwnedMessage::Text(call(1024))).unwrap();
+        let response = client.recv_message().unwrap();
+
+        // when
+        let _ = client.send_message(&OwnedMessage::Text(call(2 * 1024 * 1024)));
+        let oversized_response = client.recv_message();
+
+        // then
+        assert_eq!(
+            response,
+            OwnedMessage::Text(r#"{"jsonrpc":"2.0","result":true,"id":1}"#.into())
+        );
+        if let Ok(OwnedMessage::Text(text)) = oversized_response {
+            panic!("Oversized message processed: {}", text);
+        }
+        server.close();
+    }
+
+    #[test]
+    fn should_reject_connections_exceeding_origin_limit() {
+        // given
+        let io = rpc::MetaIoHandler::<Meta>::default();
+        let server = start(configure(&[("port", "0"), ("hosts", "all"), ("origins", "all")]), io).unwrap();
+        let url = format!("ws://{}", server.addr());
+        let connect = |origin: &str| {
+            ClientBuilder::new(&url)
+                .unwrap()
+                .origin(origin.into())
+                .connect_insecure()
+        };
+        let clients = (0..50)
+            .map(|_| connect("https://example.com").unwrap())
+            .collect::<Vec<_>>();
+
+        // when
+        let rejected = connect("https://example.com");
+        let other = connect("https://other.com");
+
+        // then
+        assert_eq!(clients.len(), 50);
+        assert!(rejected.is_err());
+        assert!(other.is_ok());
+        server.close();
+    }
+
+    #[test]
+    fn should_reset_origin_count_after_window() {
+        // given
+        let limit = OriginRateLimit::new(1, Duration::from_secs(1));
+        let now = Instant::now();
+
+        // when
+        let first = limit.check("https://example.com", now);
+        let second = limit.check("https://example.com", now + Duration::from_millis(500));
+        let third = limit.check("https://example.com", now + Duration::from_secs(1));
+
+        // then
+        assert!(first);
+        assert!(!second);
+        assert!(third);
     }
 }
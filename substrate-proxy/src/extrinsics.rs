@@ -0,0 +1,281 @@
+// Copyright (c) 2018-2020 jsonrpc-proxy contributors.
+//
+// This file is part of jsonrpc-proxy
+// (see https://github.com/tomusdrw/jsonrpc-proxy).
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+//! Tracking of in-flight extrinsics.
+//!
+//! Watches status notifications of `author_submitAndWatchExtrinsic` subscriptions
+//! and exposes the current state of every extrinsic via `proxy_extrinsicStatus` method.
+
+use jsonrpc_core as rpc;
+use jsonrpc_pubsub::{Session, SubscriptionId};
+use parking_lot::RwLock;
+use rpc::futures::{
+    future::{self, Either},
+    Future, StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// Method used to submit and watch extrinsics.
+pub const SUBMIT_AND_WATCH: &str = "author_submitAndWatchExtrinsic";
+/// Method returning the state of all in-flight extrinsics.
+pub const EXTRINSIC_STATUS: &str = "proxy_extrinsicStatus";
+
+/// Status of an extrinsic as reported by the node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExtrinsicStatus {
+    /// Extrinsic is part of the future queue.
+    Future,
+    /// Extrinsic is part of the ready queue.
+    Ready,
+    /// Extrinsic has been broadcast to given peers.
+    Broadcast(Vec<String>),
+    /// Extrinsic has been included in block with given hash.
+    InBlock(String),
+    /// The block this extrinsic was included in has been retracted.
+    Retracted(String),
+    /// Maximum number of finality watchers has been reached.
+    FinalityTimeout(String),
+    /// Extrinsic has been finalized in block with given hash.
+    Finalized(String),
+    /// Extrinsic has been replaced in the pool by other extrinsic.
+    Usurped(String),
+    /// Extrinsic has been dropped from the pool.
+    Dropped,
+    /// Extrinsic is no longer valid.
+    Invalid,
+}
+
+impl ExtrinsicStatus {
+    /// Returns `true` if no more notifications are expected after this status.
+    pub fn is_final(&self) -> bool {
+        !matches!(
+            *self,
+            ExtrinsicStatus::Future
+                | ExtrinsicStatus::Ready
+                | ExtrinsicStatus::Broadcast(_)
+                | ExtrinsicStatus::InBlock(_)
+                | ExtrinsicStatus::Retracted(_)
+        )
+    }
+}
+
+/// Keeps the latest status of every in-flight extrinsic.
+#[derive(Debug, Clone, Default)]
+pub struct ExtrinsicTracker {
+    statuses: Arc<RwLock<HashMap<SubscriptionId, ExtrinsicStatus>>>,
+}
+
+impl ExtrinsicTracker {
+    /// Updates the tracker with a status notification.
+    ///
+    /// Extrinsics reaching a final status (e.g. `Finalized`) are removed.
+    /// Returns the subscription id the notification was for.
+    pub fn update(&self, notification: &str) -> Option<SubscriptionId> {
+        let notification: rpc::Notification = serde_json::from_str(notification).ok()?;
+        let params = match notification.params {
+            rpc::Params::Map(params) => params,
+            _ => return None,
+        };
+        let id = SubscriptionId::parse_value(params.get("subscription")?)?;
+        let status: ExtrinsicStatus = serde_json::from_value(params.get("result")?.clone()).ok()?;
+
+        if status.is_final() {
+            self.statuses.write().remove(&id);
+        } else {
+            self.statuses.write().insert(id.clone(), status);
+        }
+        Some(id)
+    }
+
+    /// Stops tracking given subscription.
+    pub fn remove(&self, id: &SubscriptionId) {
+        self.statuses.write().remove(id);
+    }
+
+    /// Returns the status of given subscription.
+    #[cfg(test)]
+    pub fn get(&self, id: &SubscriptionId) -> Option<ExtrinsicStatus> {
+        self.statuses.read().get(id).cloned()
+    }
+
+    /// Returns the statuses of all in-flight extrinsics keyed by subscription id.
+    pub fn status(&self) -> rpc::Value {
+        let statuses = self
+            .statuses
+            .read()
+            .iter()
+            .map(|(id, status)| {
+                let id = match id {
+                    SubscriptionId::Number(id) => id.to_string(),
+                    SubscriptionId::String(id) => id.clone(),
+                };
+                (
+                    id,
+                    serde_json::to_value(status).expect("ExtrinsicStatus serialization is infallible."),
+                )
+            })
+            .collect();
+        rpc::Value::Object(statuses)
+    }
+}
+
+/// Middleware tracking watched extrinsics.
+///
+/// Intercepts the session of `author_submitAndWatchExtrinsic` calls to inspect
+/// status notifications before they are forwarded to the client.
+#[derive(Debug, Clone)]
+pub struct Middleware {
+    tracker: ExtrinsicTracker,
+    runtime: tokio::runtime::Handle,
+}
+
+impl Middleware {
+    /// Creates new middleware spawning notification forwarding on given runtime.
+    pub fn new(runtime: tokio::runtime::Handle) -> Self {
+        Middleware {
+            tracker: Default::default(),
+            runtime,
+        }
+    }
+
+    /// Returns a session that updates the tracker and forwards notifications to `session`.
+    fn track(&self, session: Arc<Session>) -> Arc<Session> {
+        let (sender, mut receiver) = rpc::futures::channel::mpsc::unbounded();
+        let tracked = Arc::new(Session::new(sender));
+        let client = Arc::downgrade(&session);
+        let tracker = self.tracker.clone();
+
+        // The upstream only keeps a weak reference, so tie the lifetime to the client session.
+        let keep_alive = tracked.clone();
+        session.on_drop(move || drop(keep_alive));
+
+        self.runtime.spawn(async move {
+            let mut ids = HashSet::new();
+            while let Some(notification) = receiver.next().await {
+                ids.extend(tracker.update(&notification));
+                if let Some(client) = client.upgrade() {
+                    if let Err(e) = client.sender().unbounded_send(notification) {
+                        log::warn!("Unable to forward extrinsic notification: {:?}", e);
+                    }
+                }
+            }
+            for id in ids {
+                tracker.remove(&id);
+            }
+        });
+
+        tracked
+    }
+}
+
+impl rpc::Middleware<generic_proxy::ProxyMetadata> for Middleware {
+    type Future = rpc::middleware::NoopFuture;
+    type CallFuture = future::Ready<Option<rpc::Output>>;
+
+    fn on_call<F, X>(&self, call: rpc::Call, meta: generic_proxy::ProxyMetadata, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: FnOnce(rpc::Call, generic_proxy::ProxyMetadata) -> X + Send,
+        X: Future<Output = Option<rpc::Output>> + Send + 'static,
+    {
+        match call {
+            rpc::Call::MethodCall(ref request) if request.method == EXTRINSIC_STATUS => {
+                let output = rpc::Output::from(Ok(self.tracker.status()), request.id.clone(), request.jsonrpc);
+                Either::Left(future::ready(Some(output)))
+            }
+            rpc::Call::MethodCall(ref request) if request.method == SUBMIT_AND_WATCH => {
+                let meta = generic_proxy::ProxyMetadata {
+                    session: meta.session.map(|session| self.track(session)),
+                    ..meta
+                };
+                Either::Right(next(call, meta))
+            }
+            _ => Either::Right(next(call, meta)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(id: &str, result: &str) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","method":"author_extrinsicUpdate","params":{{"subscription":"{}","result":{}}}}}"#,
+            id, result
+        )
+    }
+
+    #[test]
+    fn should_track_extrinsic_until_finalized() {
+        // given
+        let tracker = ExtrinsicTracker::default();
+        let id = SubscriptionId::String("a".into());
+
+        // when
+        tracker.update(&notification("a", r#""ready""#));
+        assert_eq!(tracker.get(&id), Some(ExtrinsicStatus::Ready));
+        tracker.update(&notification("a", r#"{"broadcast":["peer1"]}"#));
+        assert_eq!(tracker.get(&id), Some(ExtrinsicStatus::Broadcast(vec!["peer1".into()])));
+        tracker.update(&notification("a", r#"{"inBlock":"0x01"}"#));
+        assert_eq!(tracker.get(&id), Some(ExtrinsicStatus::InBlock("0x01".into())));
+        tracker.update(&notification("a", r#"{"finalized":"0x01"}"#));
+
+        // then
+        assert_eq!(tracker.get(&id), None);
+        assert_eq!(tracker.status(), serde_json::json!({}));
+    }
+
+    #[test]
+    fn should_report_status_of_all_extrinsics() {
+        // given
+        let tracker = ExtrinsicTracker::default();
+
+        // when
+        tracker.update(&notification("a", r#"{"inBlock":"0x01"}"#));
+        tracker.update(&notification("b", r#""future""#));
+        tracker.update(&notification("c", r#""invalid""#));
+
+        // then
+        assert_eq!(
+            tracker.status(),
+            serde_json::json!({
+                "a": { "inBlock": "0x01" },
+                "b": "future",
+            })
+        );
+    }
+
+    #[test]
+    fn should_ignore_unknown_notifications() {
+        // given
+        let tracker = ExtrinsicTracker::default();
+
+        // when
+        let res1 = tracker.update(&notification("a", r#""unknown""#));
+        let res2 = tracker.update(r#"{"jsonrpc":"2.0","method":"author_extrinsicUpdate","params":["ready"]}"#);
+
+        // then
+        assert_eq!(res1, None);
+        assert_eq!(res2, None);
+        assert_eq!(tracker.status(), serde_json::json!({}));
+    }
+}
@@ -17,14 +17,22 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 //! JSON-RPC proxy suitable for Substrate nodes.
 //!
-//! The proxy contains a pre-configured list of cacheable methods and upstream subscriptions.
+//! The proxy contains a pre-configured list of cacheable methods and upstream subscriptions
+//! (see `substrate.yml`).
 
 #![warn(missing_docs)]
 
+mod extrinsics;
+
 #[tokio::main]
 async fn main() {
     let yml = clap::load_yaml!("./cli.yml");
     let app = clap::App::from_yaml(yml).set_term_width(80);
+    let new_head_subscription = upstream::Subscription {
+        subscribe: "chain_subscribeNewHead".into(),
+        unsubscribe: "chain_unsubscribeNewHead".into(),
+        name: "chain_newHead".into(),
+    };
 
     generic_proxy::run_app(
         app,
@@ -47,29 +55,27 @@ async fn main() {
             cache("state_getStorageSize"),
             cache("state_getStorageSizeAt"),
             cache("state_queryStorage"),
+            // Metadata only changes with runtime upgrades, so keep just the latest one.
+            simple_cache::Method::new("state_getMetadata", simple_cache::CacheEviction::Block)
+                .with_max_entries(1)
+                .metadata_version_aware(true),
             // system
             cache("system_name"),
             cache("system_version"),
             cache("system_chain"),
         ],
-        vec![
-            upstream::Subscription {
-                subscribe: "author_submitAndWatchExtrinsic".into(),
-                unsubscribe: "author_unwatchExtrinsic".into(),
-                name: "author_extrinsicUpdate".into(),
-            },
-            upstream::Subscription {
-                subscribe: "chain_subscribeNewHead".into(),
-                unsubscribe: "chain_unsubscribeNewHead".into(),
-                name: "chain_newHead".into(),
-            },
-            upstream::Subscription {
-                subscribe: "state_subscribeStorage".into(),
-                unsubscribe: "state_unsubscribeStorage".into(),
-                name: "state_storage".into(),
-            },
-        ],
-        (),
+        upstream::config::parse_subscriptions(&include_bytes!("../substrate.yml")[..])
+            .expect("Built-in subscriptions are valid; qed"),
+        Some(generic_proxy::BlockSubscription {
+            subscription: new_head_subscription,
+            params: jsonrpc_core::Params::None,
+            runtime_version: Some(upstream::Subscription {
+                subscribe: "state_subscribeRuntimeVersion".into(),
+                unsubscribe: "state_unsubscribeRuntimeVersion".into(),
+                name: "state_runtimeVersion".into(),
+            }),
+        }),
+        Extension,
     )
 }
 
@@ -79,3 +85,17 @@ fn cache(name: &str) -> simple_cache::Method {
         simple_cache::CacheEviction::Time(::std::time::Duration::from_secs(3)),
     )
 }
+
+struct Extension;
+
+impl generic_proxy::Extension for Extension {
+    type Middleware = extrinsics::Middleware;
+
+    fn configure_app<'a, 'b>(&'a mut self, app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+        app
+    }
+
+    fn parse_matches(_matches: &clap::ArgMatches, _upstream: impl upstream::Transport) -> Self::Middleware {
+        extrinsics::Middleware::new(tokio::runtime::Handle::current())
+    }
+}